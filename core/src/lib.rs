@@ -3,6 +3,7 @@ pub mod error;
 pub mod data;
 pub mod math;
 pub mod network;
+pub mod rng;
 pub mod text;
 
 pub use error::{Error, Result};
@@ -18,7 +19,7 @@ pub use serde_json;
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Id(String);
 
@@ -1,10 +1,11 @@
-use std::collections::HashMap;
-
 use async_trait::async_trait;
 
 use crate::Result;
 
-use super::{Id, Lobby, NetworkEvent, Player, RequestStatus};
+use super::{
+    ClientState, Id, Lobby, LobbyFilter, LobbyPrivacy, LobbyRegistry, LobbySettings, LobbyState,
+    NetworkEvent, Player, RequestStatus,
+};
 
 static mut API_INSTANCE: Option<Api> = None;
 
@@ -23,11 +24,26 @@ impl Api {
     }
 
     pub async fn init<T: 'static + ApiBackend + Default>(token: &str) -> Result<()> {
-        let backend = Box::new(T::default());
+        Self::init_with(T::default(), token).await
+    }
+
+    /// Init the api with an already constructed backend, like one that has to be given the
+    /// address of a service
+    pub async fn init_with<T: 'static + ApiBackend>(backend: T, token: &str) -> Result<()> {
+        let mut api = Api {
+            backend: Box::new(backend),
+        };
 
-        let mut api = Api { backend };
+        api.backend.init(token).await?;
 
-        api.backend.init(token).await
+        unsafe { API_INSTANCE = Some(api) };
+
+        Ok(())
+    }
+
+    /// Returns `true` if the api has been initialized
+    pub fn is_initialized() -> bool {
+        Self::try_get_instance().is_some()
     }
 
     pub async fn close() -> Result<()> {
@@ -48,7 +64,82 @@ impl Api {
         api.backend.get_lobby(id).await
     }
 
-    pub fn poll_events() -> Vec<NetworkEvent> {
+    /// The id of the player that the api was initialized for
+    pub fn local_player_id() -> Option<Id> {
+        let api = Self::get_instance();
+
+        api.backend.local_player_id()
+    }
+
+    pub async fn list_lobbies(filter: &LobbyFilter) -> Result<Vec<Lobby>> {
+        let api = Self::get_instance();
+
+        api.backend.list_lobbies(filter).await
+    }
+
+    pub async fn create_lobby(name: &str, privacy: LobbyPrivacy, capacity: i32) -> Result<Lobby> {
+        let api = Self::get_instance();
+
+        api.backend.create_lobby(name, privacy, capacity).await
+    }
+
+    pub async fn join_lobby(id: &Id) -> Result<Lobby> {
+        let api = Self::get_instance();
+
+        api.backend.join_lobby(id).await
+    }
+
+    pub async fn leave_lobby(id: &Id) -> Result<()> {
+        let api = Self::get_instance();
+
+        api.backend.leave_lobby(id).await
+    }
+
+    pub async fn set_ready(id: &Id, is_ready: bool) -> Result<()> {
+        let api = Self::get_instance();
+
+        api.backend.set_ready(id, is_ready).await
+    }
+
+    pub async fn set_character(id: &Id, character: &str) -> Result<()> {
+        let api = Self::get_instance();
+
+        api.backend.set_character(id, character).await
+    }
+
+    pub async fn kick_player(lobby_id: &Id, player_id: &Id) -> Result<()> {
+        let api = Self::get_instance();
+
+        api.backend.kick_player(lobby_id, player_id).await
+    }
+
+    pub async fn start_game(id: &Id) -> Result<()> {
+        let api = Self::get_instance();
+
+        api.backend.start_game(id).await
+    }
+
+    pub async fn update_lobby_settings(id: &Id, settings: LobbySettings) -> Result<()> {
+        let api = Self::get_instance();
+
+        api.backend.update_lobby_settings(id, settings).await
+    }
+
+    pub async fn send_chat_message(id: &Id, text: &str) -> Result<()> {
+        let api = Self::get_instance();
+
+        api.backend.send_chat_message(id, text).await
+    }
+
+    pub async fn set_player_muted(lobby_id: &Id, player_id: &Id, is_muted: bool) -> Result<()> {
+        let api = Self::get_instance();
+
+        api.backend
+            .set_player_muted(lobby_id, player_id, is_muted)
+            .await
+    }
+
+    pub fn poll_events() -> Result<Vec<NetworkEvent>> {
         let api = Self::get_instance();
 
         api.backend.poll_events()
@@ -66,36 +157,117 @@ pub trait ApiBackend {
     async fn get_player(&mut self, id: &Id) -> Result<Player>;
     /// Get `Lobby` with the specified `id`
     async fn get_lobby(&mut self, id: &Id) -> Result<Lobby>;
-    /// Get the next event in the event queue
-    fn poll_events(&mut self) -> Vec<NetworkEvent>;
+    /// Get the id of the player that the backend was initialized for
+    fn local_player_id(&self) -> Option<Id>;
+    /// Get all lobbies that match `filter`
+    async fn list_lobbies(&mut self, filter: &LobbyFilter) -> Result<Vec<Lobby>>;
+    /// Create a lobby, with the local player as its admin. If the local player is in another
+    /// lobby, it will leave that first.
+    async fn create_lobby(
+        &mut self,
+        name: &str,
+        privacy: LobbyPrivacy,
+        capacity: i32,
+    ) -> Result<Lobby>;
+    /// Join the `Lobby` with the specified `id`
+    async fn join_lobby(&mut self, id: &Id) -> Result<Lobby>;
+    /// Leave the `Lobby` with the specified `id`
+    async fn leave_lobby(&mut self, id: &Id) -> Result<()>;
+    /// Mark the local player as ready, or not ready, in the `Lobby` with the specified `id`
+    async fn set_ready(&mut self, id: &Id, is_ready: bool) -> Result<()>;
+    /// Select the character that the local player will play as, in the `Lobby` with the
+    /// specified `id`
+    async fn set_character(&mut self, id: &Id, character: &str) -> Result<()>;
+    /// Remove a player from a lobby. Only the admin of the lobby can do this.
+    async fn kick_player(&mut self, lobby_id: &Id, player_id: &Id) -> Result<()>;
+    /// Start the game of a lobby, once all players are ready. Only the admin of the lobby can do
+    /// this.
+    async fn start_game(&mut self, id: &Id) -> Result<()>;
+    /// Change the map and rules of a lobby. Only the admin of the lobby can do this.
+    async fn update_lobby_settings(&mut self, id: &Id, settings: LobbySettings) -> Result<()>;
+    /// Send a chat message to the players in the `Lobby` with the specified `id`. It is received
+    /// by the local player as well, as a `NetworkEvent::ChatMessage`. Messages are subject to
+    /// `MAX_CHAT_MESSAGE_LEN` and `CHAT_RATE_LIMIT`.
+    async fn send_chat_message(&mut self, id: &Id, text: &str) -> Result<()>;
+    /// Mute, or unmute, a player in a lobby. Only the admin of the lobby can do this.
+    async fn set_player_muted(
+        &mut self,
+        lobby_id: &Id,
+        player_id: &Id,
+        is_muted: bool,
+    ) -> Result<()>;
+    /// Take the events that have been received since this was last called. An error is returned
+    /// if the connection to the service is lost, or if an invalid event is received.
+    fn poll_events(&mut self) -> Result<Vec<NetworkEvent>>;
 }
 
 /// This is used as a placeholder for when no external backend implementation is available.
 /// Will be removed once we have a backend that can be freely redistributed (Steam, probably)
-#[allow(dead_code)]
+///
+/// Lobbies are held in memory, by a `LobbyRegistry`, so all requests behave like they would with
+/// an actual service. To be able to go through a whole lobby without another client, the other
+/// mock player will join any lobby that the local player creates, and mark itself as ready.
 pub struct MockApiBackend {
-    players: Vec<Player>,
-    lobbies: Vec<Lobby>,
-    sessions: HashMap<String, Id>,
+    registry: LobbyRegistry,
+    player_id: Option<Id>,
 }
 
 impl MockApiBackend {
     pub fn new() -> Self {
-        let players = vec![
-            Player::new(&Id::from("1"), "Player One"),
-            Player::new(&Id::from("2"), "Player Two"),
-        ];
+        let mut registry = LobbyRegistry::default();
 
-        let mut sessions = HashMap::new();
-
-        sessions.insert("player_one_token".to_string(), players[0].id.clone());
-        sessions.insert("player_two_token".to_string(), players[1].id.clone());
+        registry.add_player("player_one_token", "Player One");
+        registry.add_player("player_two_token", "Player Two");
 
         MockApiBackend {
-            players,
-            lobbies: Vec::new(),
-            sessions,
+            registry,
+            player_id: None,
+        }
+    }
+
+    fn authenticated_id(&self) -> Result<&Id> {
+        self.player_id
+            .as_ref()
+            .ok_or_else(|| RequestStatus::Unauthorized.into())
+    }
+
+    /// The id of the mock player that is not the local player
+    fn other_player_id(&self) -> Option<Id> {
+        let other_id = if self.player_id == Some(Id::from("1")) {
+            Id::from("2")
+        } else {
+            Id::from("1")
+        };
+
+        self.registry
+            .get_player(&other_id)
+            .ok()
+            .map(|player| player.id)
+    }
+
+    /// Advance the lobby of the local player, the way the service and the other player would
+    fn update_lobby(&mut self) {
+        let (lobby, other_id) = match (&self.player_id, self.other_player_id()) {
+            (Some(player_id), Some(other_id)) => match self.registry.current_lobby(player_id) {
+                Some(lobby) => (lobby.clone(), other_id),
+                None => return,
+            },
+            _ => return,
+        };
+
+        if lobby.state == LobbyState::Starting {
+            let _ = self.registry.set_game_running(&lobby.id);
+        } else if lobby.state.is_joinable()
+            && lobby
+                .players
+                .iter()
+                .any(|player| player.id == other_id && player.state == ClientState::Joined)
+        {
+            let _ = self.registry.set_ready(&other_id, &lobby.id, true);
         }
+
+        // The other player is not polling for its events
+        self.registry.take_events(&other_id);
     }
 }
 
@@ -107,31 +279,154 @@ impl Default for MockApiBackend {
 
 #[async_trait]
 impl ApiBackend for MockApiBackend {
-    async fn init(&mut self, _token: &str) -> Result<()> {
+    async fn init(&mut self, token: &str) -> Result<()> {
+        let player_id = self.registry.authenticate(token)?;
+
+        self.player_id = Some(player_id);
+
         Ok(())
     }
 
     async fn close(&mut self) -> Result<()> {
+        if let Some(player_id) = self.player_id.take() {
+            self.registry.leave_current_lobby(&player_id)?;
+        }
+
         Ok(())
     }
 
     async fn get_player(&mut self, id: &Id) -> Result<Player> {
-        if let Some(player) = self.players.iter().find(|&player| player.id == *id) {
-            Ok(player.clone())
-        } else {
-            Err(RequestStatus::NotFound.into())
-        }
+        let player = self.registry.get_player(id)?;
+
+        Ok(player)
     }
 
     async fn get_lobby(&mut self, id: &Id) -> Result<Lobby> {
-        if let Some(lobby) = self.lobbies.iter().find(|&lobby| lobby.id == *id) {
-            Ok(lobby.clone())
-        } else {
-            Err(RequestStatus::NotFound.into())
+        let lobby = self.registry.get_lobby(id)?;
+
+        Ok(lobby)
+    }
+
+    fn local_player_id(&self) -> Option<Id> {
+        self.player_id.clone()
+    }
+
+    async fn list_lobbies(&mut self, filter: &LobbyFilter) -> Result<Vec<Lobby>> {
+        Ok(self.registry.list_lobbies(filter))
+    }
+
+    async fn create_lobby(
+        &mut self,
+        name: &str,
+        privacy: LobbyPrivacy,
+        capacity: i32,
+    ) -> Result<Lobby> {
+        let player_id = self.authenticated_id()?.clone();
+
+        let lobby = self
+            .registry
+            .create_lobby(&player_id, name, privacy, capacity)?;
+
+        if let Some(other_id) = self.other_player_id() {
+            if lobby.capacity > 1 {
+                self.registry.join_lobby(&other_id, &lobby.id)?;
+            }
         }
+
+        let lobby = self.registry.get_lobby(&lobby.id)?;
+
+        Ok(lobby)
+    }
+
+    async fn join_lobby(&mut self, id: &Id) -> Result<Lobby> {
+        let player_id = self.authenticated_id()?.clone();
+
+        let lobby = self.registry.join_lobby(&player_id, id)?;
+
+        Ok(lobby)
+    }
+
+    async fn leave_lobby(&mut self, id: &Id) -> Result<()> {
+        let player_id = self.authenticated_id()?.clone();
+
+        self.registry.leave_lobby(&player_id, id)?;
+
+        Ok(())
+    }
+
+    async fn set_ready(&mut self, id: &Id, is_ready: bool) -> Result<()> {
+        let player_id = self.authenticated_id()?.clone();
+
+        self.registry.set_ready(&player_id, id, is_ready)?;
+
+        Ok(())
     }
 
-    fn poll_events(&mut self) -> Vec<NetworkEvent> {
-        Vec::new()
+    async fn set_character(&mut self, id: &Id, character: &str) -> Result<()> {
+        let player_id = self.authenticated_id()?.clone();
+
+        self.registry.set_character(&player_id, id, character)?;
+
+        Ok(())
+    }
+
+    async fn kick_player(&mut self, lobby_id: &Id, player_id: &Id) -> Result<()> {
+        let admin_id = self.authenticated_id()?.clone();
+
+        self.registry.kick_player(&admin_id, lobby_id, player_id)?;
+
+        Ok(())
+    }
+
+    async fn start_game(&mut self, id: &Id) -> Result<()> {
+        let player_id = self.authenticated_id()?.clone();
+
+        self.registry.start_game(&player_id, id)?;
+
+        Ok(())
+    }
+
+    async fn update_lobby_settings(&mut self, id: &Id, settings: LobbySettings) -> Result<()> {
+        let player_id = self.authenticated_id()?.clone();
+
+        self.registry
+            .update_lobby_settings(&player_id, id, settings)?;
+
+        Ok(())
+    }
+
+    async fn send_chat_message(&mut self, id: &Id, text: &str) -> Result<()> {
+        let player_id = self.authenticated_id()?.clone();
+
+        self.registry.send_chat_message(&player_id, id, text)?;
+
+        Ok(())
+    }
+
+    async fn set_player_muted(
+        &mut self,
+        lobby_id: &Id,
+        player_id: &Id,
+        is_muted: bool,
+    ) -> Result<()> {
+        let admin_id = self.authenticated_id()?.clone();
+
+        self.registry
+            .set_muted(&admin_id, lobby_id, player_id, is_muted)?;
+
+        Ok(())
+    }
+
+    fn poll_events(&mut self) -> Result<Vec<NetworkEvent>> {
+        let player_id = match self.player_id.clone() {
+            Some(player_id) => player_id,
+            None => return Ok(Vec::new()),
+        };
+
+        let res = self.registry.take_events(&player_id);
+
+        self.update_lobby();
+
+        Ok(res)
     }
 }
@@ -0,0 +1,93 @@
+//! The limits that apply to chat messages, both in lobbies and in network games
+
+use std::collections::VecDeque;
+
+/// The maximum length of a chat message, in characters. Longer messages are truncated.
+pub const MAX_CHAT_MESSAGE_LEN: usize = 120;
+
+/// The maximum amount of chat messages that a player can send within `CHAT_RATE_WINDOW`
+pub const CHAT_RATE_LIMIT: usize = 5;
+
+/// The duration, in seconds, of the window that `CHAT_RATE_LIMIT` applies to
+pub const CHAT_RATE_WINDOW: f64 = 10.0;
+
+/// Remove control characters and surrounding whitespace from `text` and truncate it to
+/// `MAX_CHAT_MESSAGE_LEN`. Returns `None` if nothing is left.
+pub fn sanitize_chat_message(text: &str) -> Option<String> {
+    let res = text
+        .chars()
+        .filter(|c| !c.is_control())
+        .collect::<String>()
+        .trim()
+        .chars()
+        .take(MAX_CHAT_MESSAGE_LEN)
+        .collect::<String>();
+
+    if res.is_empty() {
+        None
+    } else {
+        Some(res)
+    }
+}
+
+/// Keeps track of the chat messages sent by a single player, to enforce `CHAT_RATE_LIMIT`
+#[derive(Debug, Clone, Default)]
+pub struct ChatRateLimiter {
+    /// The times that messages within the current window were sent at
+    sent: VecDeque<f64>,
+}
+
+impl ChatRateLimiter {
+    pub fn new() -> Self {
+        ChatRateLimiter {
+            sent: VecDeque::new(),
+        }
+    }
+
+    /// Returns `true`, and counts the message, if a message can be sent at `now`
+    pub fn try_send(&mut self, now: f64) -> bool {
+        while matches!(self.sent.front(), Some(&time) if now - time >= CHAT_RATE_WINDOW) {
+            self.sent.pop_front();
+        }
+
+        if self.sent.len() >= CHAT_RATE_LIMIT {
+            return false;
+        }
+
+        self.sent.push_back(now);
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chat_rate_limit() {
+        let mut limiter = ChatRateLimiter::new();
+
+        for i in 0..CHAT_RATE_LIMIT {
+            assert!(limiter.try_send(i as f64 * 0.1));
+        }
+
+        assert!(
+            !limiter.try_send(1.0),
+            "Message should exceed the rate limit"
+        );
+        assert!(limiter.try_send(CHAT_RATE_WINDOW + 0.05));
+    }
+
+    #[test]
+    fn test_sanitize_chat_message() {
+        assert_eq!(sanitize_chat_message("  \n\t "), None);
+        assert_eq!(sanitize_chat_message(" gg\u{7} "), Some("gg".to_string()));
+
+        let long = "a".repeat(MAX_CHAT_MESSAGE_LEN * 2);
+        assert_eq!(
+            sanitize_chat_message(&long).map(|text| text.len()),
+            Some(MAX_CHAT_MESSAGE_LEN)
+        );
+    }
+}
@@ -4,6 +4,7 @@ use serde::{Deserialize, Serialize};
 use crate::network::Lobby;
 use crate::Id;
 
+#[derive(Debug, Clone)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum NetworkEvent {
     LobbyCreated {
@@ -21,16 +22,34 @@ pub enum NetworkEvent {
     PlayerJoined {
         player_id: Id,
         username: String,
+        /// The port the player accepts game traffic on, or `0` if it is not yet known
         port: u16,
     },
     PlayerLeft {
         player_id: Id,
     },
+    /// The player was removed from the lobby by its admin
+    PlayerKicked {
+        player_id: Id,
+    },
     PlayerReconnecting {
         player_id: Id,
     },
+    /// A chat message, sent to the lobby by one of its players
+    ChatMessage {
+        player_id: Id,
+        username: String,
+        text: String,
+    },
+    /// A message from the lobby service, like when a player has joined or left the lobby
+    SystemMessage {
+        text: String,
+    },
     GameStarted {
         lobby_id: Id,
+        /// The token that the host registers the game with on the relay, which is only sent to
+        /// the admin of the lobby
+        relay_host_token: Option<u64>,
     },
     GameEnded {
         lobby_id: Id,
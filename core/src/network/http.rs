@@ -0,0 +1,529 @@
+//! An `ApiBackend` that talks to a lobby service, like the one run by `fishfight-lobby-server`.
+//! Requests are sent over HTTP, with JSON bodies, and are authenticated by a bearer token.
+//! Events are pushed by the service over a TCP connection, as lines of JSON, after the client
+//! has sent its token, followed by a newline.
+//!
+//! | Method | Path                      | Body                  | Response     |
+//! |--------|---------------------------|-----------------------|--------------|
+//! | GET    | `/server`                 |                       | `Server`     |
+//! | GET    | `/players/me`             |                       | `Player`     |
+//! | GET    | `/players/{id}`           |                       | `Player`     |
+//! | POST   | `/lobbies/search`         | `LobbyFilter`         | `Vec<Lobby>` |
+//! | POST   | `/lobbies`                | `CreateLobbyRequest`  | `Lobby`      |
+//! | GET    | `/lobbies/{id}`           |                       | `Lobby`      |
+//! | POST   | `/lobbies/{id}/join`      |                       | `Lobby`      |
+//! | POST   | `/lobbies/{id}/leave`     |                       |              |
+//! | POST   | `/lobbies/{id}/ready`     | `SetReadyRequest`     |              |
+//! | POST   | `/lobbies/{id}/character` | `SetCharacterRequest` |              |
+//! | POST   | `/lobbies/{id}/kick`      | `KickPlayerRequest`   |              |
+//! | POST   | `/lobbies/{id}/start`     |                       |              |
+//! | PUT    | `/lobbies/{id}/settings`  | `LobbySettings`       |              |
+//! | POST   | `/lobbies/{id}/chat`      | `SendChatRequest`     |              |
+//! | POST   | `/lobbies/{id}/mute`      | `MutePlayerRequest`   |              |
+//!
+//! Requests are blocking, so this should only be used with a service on a fast connection, like
+//! one running on the local network.
+
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, ErrorKind};
+use crate::{formaterr, Result};
+
+use super::{
+    ApiBackend, Id, Lobby, LobbyFilter, LobbyPrivacy, LobbySettings, NetworkEvent, Player,
+    RequestStatus, Server,
+};
+
+/// The default port of the HTTP API of a lobby service. The TCP event stream and the UDP socket
+/// of the service are on the following ports, by default.
+pub const DEFAULT_LOBBY_PORT: u16 = 9080;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateLobbyRequest {
+    pub name: String,
+    pub privacy: LobbyPrivacy,
+    pub capacity: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetReadyRequest {
+    pub is_ready: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetCharacterRequest {
+    pub character: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KickPlayerRequest {
+    pub player_id: Id,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SendChatRequest {
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MutePlayerRequest {
+    pub player_id: Id,
+    pub is_muted: bool,
+}
+
+pub struct HttpApiBackend {
+    /// The address of the HTTP API of the service
+    address: SocketAddr,
+    token: String,
+    server: Option<Server>,
+    player: Option<Player>,
+    events: Option<TcpStream>,
+    /// Received event data that does not yet hold a full line
+    event_buf: Vec<u8>,
+}
+
+impl HttpApiBackend {
+    pub fn new(address: SocketAddr) -> Self {
+        HttpApiBackend {
+            address,
+            token: String::new(),
+            server: None,
+            player: None,
+            events: None,
+            event_buf: Vec::new(),
+        }
+    }
+
+    /// The addresses of the service, once initialized
+    pub fn server(&self) -> Option<&Server> {
+        self.server.as_ref()
+    }
+
+    /// The player that is authenticated by the token passed to `init`
+    pub fn player(&self) -> Option<&Player> {
+        self.player.as_ref()
+    }
+
+    fn request<T, B>(&self, method: &str, path: &str, body: Option<&B>) -> Result<T>
+    where
+        T: DeserializeOwned,
+        B: Serialize,
+    {
+        let body = match body {
+            Some(body) => serde_json::to_vec(body)?,
+            None => Vec::new(),
+        };
+
+        let mut stream = TcpStream::connect_timeout(&self.address, REQUEST_TIMEOUT)
+            .map_err(|err| Error::new(ErrorKind::Network, err))?;
+
+        stream
+            .set_read_timeout(Some(REQUEST_TIMEOUT))
+            .map_err(|err| Error::new(ErrorKind::Network, err))?;
+
+        let head = format!(
+            "{} {} HTTP/1.1\r\n\
+             Host: {}\r\n\
+             Authorization: Bearer {}\r\n\
+             Content-Type: application/json\r\n\
+             Content-Length: {}\r\n\
+             Connection: close\r\n\r\n",
+            method,
+            path,
+            self.address,
+            self.token,
+            body.len()
+        );
+
+        let mut res = Vec::new();
+
+        stream
+            .write_all(head.as_bytes())
+            .and_then(|_| stream.write_all(&body))
+            .and_then(|_| stream.read_to_end(&mut res))
+            .map_err(|err| Error::new(ErrorKind::Network, err))?;
+
+        let (status, body) = parse_response(&res)?;
+
+        if status != RequestStatus::Ok {
+            return Err(status.into());
+        }
+
+        let res = serde_json::from_slice(body)?;
+
+        Ok(res)
+    }
+
+    fn connect_events(&mut self, server: &Server) -> Result<()> {
+        // A service that is bound to all interfaces will report an unspecified address
+        let mut address = server.tcp;
+        if address.ip().is_unspecified() {
+            address.set_ip(self.address.ip());
+        }
+
+        let mut stream = TcpStream::connect_timeout(&address, REQUEST_TIMEOUT)
+            .map_err(|err| Error::new(ErrorKind::Network, err))?;
+
+        stream
+            .write_all(format!("{}\n", self.token).as_bytes())
+            .and_then(|_| stream.set_nonblocking(true))
+            .map_err(|err| Error::new(ErrorKind::Network, err))?;
+
+        self.events = Some(stream);
+
+        Ok(())
+    }
+}
+
+impl Default for HttpApiBackend {
+    fn default() -> Self {
+        Self::new((std::net::Ipv4Addr::LOCALHOST, DEFAULT_LOBBY_PORT).into())
+    }
+}
+
+#[async_trait]
+impl ApiBackend for HttpApiBackend {
+    async fn init(&mut self, token: &str) -> Result<()> {
+        self.token = token.to_string();
+
+        let server: Server = self.request("GET", "/server", None::<&()>)?;
+        let player: Player = self.request("GET", "/players/me", None::<&()>)?;
+
+        self.connect_events(&server)?;
+
+        self.server = Some(server);
+        self.player = Some(player);
+
+        Ok(())
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        if let Some(stream) = self.events.take() {
+            let _ = stream.shutdown(std::net::Shutdown::Both);
+        }
+
+        Ok(())
+    }
+
+    async fn get_player(&mut self, id: &Id) -> Result<Player> {
+        self.request("GET", &format!("/players/{}", id.as_str()), None::<&()>)
+    }
+
+    async fn get_lobby(&mut self, id: &Id) -> Result<Lobby> {
+        self.request("GET", &format!("/lobbies/{}", id.as_str()), None::<&()>)
+    }
+
+    fn local_player_id(&self) -> Option<Id> {
+        self.player.as_ref().map(|player| player.id.clone())
+    }
+
+    async fn list_lobbies(&mut self, filter: &LobbyFilter) -> Result<Vec<Lobby>> {
+        self.request("POST", "/lobbies/search", Some(filter))
+    }
+
+    async fn create_lobby(
+        &mut self,
+        name: &str,
+        privacy: LobbyPrivacy,
+        capacity: i32,
+    ) -> Result<Lobby> {
+        let req = CreateLobbyRequest {
+            name: name.to_string(),
+            privacy,
+            capacity,
+        };
+
+        self.request("POST", "/lobbies", Some(&req))
+    }
+
+    async fn join_lobby(&mut self, id: &Id) -> Result<Lobby> {
+        self.request(
+            "POST",
+            &format!("/lobbies/{}/join", id.as_str()),
+            None::<&()>,
+        )
+    }
+
+    async fn leave_lobby(&mut self, id: &Id) -> Result<()> {
+        self.request(
+            "POST",
+            &format!("/lobbies/{}/leave", id.as_str()),
+            None::<&()>,
+        )
+    }
+
+    async fn set_ready(&mut self, id: &Id, is_ready: bool) -> Result<()> {
+        let req = SetReadyRequest { is_ready };
+
+        self.request(
+            "POST",
+            &format!("/lobbies/{}/ready", id.as_str()),
+            Some(&req),
+        )
+    }
+
+    async fn set_character(&mut self, id: &Id, character: &str) -> Result<()> {
+        let req = SetCharacterRequest {
+            character: character.to_string(),
+        };
+
+        self.request(
+            "POST",
+            &format!("/lobbies/{}/character", id.as_str()),
+            Some(&req),
+        )
+    }
+
+    async fn kick_player(&mut self, lobby_id: &Id, player_id: &Id) -> Result<()> {
+        let req = KickPlayerRequest {
+            player_id: player_id.clone(),
+        };
+
+        self.request(
+            "POST",
+            &format!("/lobbies/{}/kick", lobby_id.as_str()),
+            Some(&req),
+        )
+    }
+
+    async fn start_game(&mut self, id: &Id) -> Result<()> {
+        self.request(
+            "POST",
+            &format!("/lobbies/{}/start", id.as_str()),
+            None::<&()>,
+        )
+    }
+
+    async fn update_lobby_settings(&mut self, id: &Id, settings: LobbySettings) -> Result<()> {
+        self.request(
+            "PUT",
+            &format!("/lobbies/{}/settings", id.as_str()),
+            Some(&settings),
+        )
+    }
+
+    async fn send_chat_message(&mut self, id: &Id, text: &str) -> Result<()> {
+        let req = SendChatRequest {
+            text: text.to_string(),
+        };
+
+        self.request(
+            "POST",
+            &format!("/lobbies/{}/chat", id.as_str()),
+            Some(&req),
+        )
+    }
+
+    async fn set_player_muted(
+        &mut self,
+        lobby_id: &Id,
+        player_id: &Id,
+        is_muted: bool,
+    ) -> Result<()> {
+        let req = MutePlayerRequest {
+            player_id: player_id.clone(),
+            is_muted,
+        };
+
+        self.request(
+            "POST",
+            &format!("/lobbies/{}/mute", lobby_id.as_str()),
+            Some(&req),
+        )
+    }
+
+    fn poll_events(&mut self) -> Result<Vec<NetworkEvent>> {
+        let mut buf = [0; 4096];
+
+        if let Some(stream) = &mut self.events {
+            loop {
+                let err = match stream.read(&mut buf) {
+                    Ok(0) => formaterr!(
+                        ErrorKind::Network,
+                        "The lobby service closed the event stream"
+                    ),
+                    Ok(len) => {
+                        self.event_buf.extend_from_slice(&buf[..len]);
+                        continue;
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(err) => Error::new(ErrorKind::Network, err),
+                };
+
+                self.events = None;
+                self.event_buf.clear();
+
+                return Err(err);
+            }
+        }
+
+        take_events(&mut self.event_buf)
+    }
+}
+
+/// Take all complete lines of `buf` and parse them as events. Incomplete lines are left in
+/// `buf`, to be completed by data that is received later. If a line is not a valid event, the
+/// lines before it are discarded as well, and the lines after it are left in `buf`.
+fn take_events(buf: &mut Vec<u8>) -> Result<Vec<NetworkEvent>> {
+    let mut res = Vec::new();
+
+    while let Some(i) = buf.iter().position(|&byte| byte == b'\n') {
+        let line = buf.drain(..=i).collect::<Vec<_>>();
+
+        // Empty lines are sent by the service to detect disconnected clients
+        if line.iter().all(u8::is_ascii_whitespace) {
+            continue;
+        }
+
+        let event = serde_json::from_slice(&line).map_err(|err| {
+            formaterr!(
+                ErrorKind::Network,
+                "Invalid event from lobby service: {}",
+                err
+            )
+        })?;
+
+        res.push(event);
+    }
+
+    Ok(res)
+}
+
+/// Returns the status and the body of a HTTP response
+fn parse_response(res: &[u8]) -> Result<(RequestStatus, &[u8])> {
+    let i = res
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or_else(|| formaterr!(ErrorKind::Network, "Invalid HTTP response"))?;
+
+    let head = String::from_utf8_lossy(&res[..i]);
+
+    let code = head
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse::<u16>().ok())
+        .ok_or_else(|| formaterr!(ErrorKind::Network, "Invalid HTTP status line"))?;
+
+    Ok((code.into(), &res[i + 4..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use super::*;
+
+    fn event_line(text: &str) -> Vec<u8> {
+        let event = NetworkEvent::SystemMessage {
+            text: text.to_string(),
+        };
+
+        let mut line = serde_json::to_vec(&event).unwrap();
+        line.push(b'\n');
+        line
+    }
+
+    fn event_text(event: &NetworkEvent) -> &str {
+        match event {
+            NetworkEvent::SystemMessage { text } => text,
+            _ => panic!("Unexpected event {:?}", event),
+        }
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let (status, body) =
+            parse_response(b"HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\nnull").unwrap();
+        assert_eq!(status, RequestStatus::Ok);
+        assert_eq!(body, b"null");
+
+        let (status, body) = parse_response(b"HTTP/1.1 409 conflict\r\n\r\n").unwrap();
+        assert_eq!(status, RequestStatus::Conflict);
+        assert!(body.is_empty());
+
+        let (status, _) = parse_response(b"HTTP/1.1 418 I'm a teapot\r\n\r\n").unwrap();
+        assert_eq!(status, RequestStatus::Unknown);
+
+        assert!(
+            parse_response(b"HTTP/1.1 200 OK\r\n").is_err(),
+            "Response without the end of its head should be invalid"
+        );
+        assert!(
+            parse_response(b"HTTP/1.1 OK\r\n\r\n").is_err(),
+            "Response without a status code should be invalid"
+        );
+    }
+
+    #[test]
+    fn test_take_events() {
+        let line = event_line("first");
+        let (start, end) = line.split_at(line.len() / 2);
+
+        let mut buf = start.to_vec();
+        assert!(take_events(&mut buf).unwrap().is_empty());
+        assert_eq!(buf, start, "Partial line should be kept");
+
+        buf.extend_from_slice(end);
+        buf.extend_from_slice(b"\n \r\n");
+        buf.extend_from_slice(&event_line("second"));
+        buf.extend_from_slice(b"\n");
+
+        let events = take_events(&mut buf).unwrap();
+        assert_eq!(
+            events.iter().map(event_text).collect::<Vec<_>>(),
+            ["first", "second"]
+        );
+        assert!(buf.is_empty(), "Keep-alive lines should be discarded");
+
+        buf.extend_from_slice(b"{\"Invalid\":null}\n");
+        buf.extend_from_slice(&event_line("third"));
+
+        assert!(take_events(&mut buf).is_err());
+
+        let events = take_events(&mut buf).unwrap();
+        assert_eq!(
+            events.iter().map(event_text).collect::<Vec<_>>(),
+            ["third"],
+            "Events after an invalid line should be kept"
+        );
+    }
+
+    #[test]
+    fn test_poll_events_closed() {
+        let listener = TcpListener::bind((std::net::Ipv4Addr::LOCALHOST, 0)).unwrap();
+
+        let mut backend = HttpApiBackend::default();
+
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        stream.set_nonblocking(true).unwrap();
+        backend.events = Some(stream);
+
+        let (mut service, _) = listener.accept().unwrap();
+        service.write_all(b"\n").unwrap();
+        service.shutdown(std::net::Shutdown::Both).unwrap();
+
+        let err = loop {
+            if let Err(err) = backend.poll_events() {
+                break err;
+            }
+
+            std::thread::sleep(Duration::from_millis(1));
+        };
+
+        assert!(err.to_string().contains("closed"), "{}", err);
+        assert!(backend.events.is_none());
+        assert!(
+            backend.poll_events().unwrap().is_empty(),
+            "Closed stream should only be reported once"
+        );
+    }
+}
@@ -1,9 +1,29 @@
 mod api;
+mod chat;
 mod event;
+#[cfg(feature = "serde_json")]
+mod http;
+mod protocol;
+mod registry;
+mod relay;
 mod status;
 
 pub use api::{Api, ApiBackend, MockApiBackend};
+pub use chat::{
+    sanitize_chat_message, ChatRateLimiter, CHAT_RATE_LIMIT, CHAT_RATE_WINDOW, MAX_CHAT_MESSAGE_LEN,
+};
 pub use event::NetworkEvent;
+#[cfg(feature = "serde_json")]
+pub use http::{
+    CreateLobbyRequest, HttpApiBackend, KickPlayerRequest, MutePlayerRequest, SendChatRequest,
+    SetCharacterRequest, SetReadyRequest, DEFAULT_LOBBY_PORT,
+};
+pub use protocol::{
+    fnv_hash, DisconnectReason, HandshakeInfo, Message, MAX_INPUTS_PER_MESSAGE, MAX_SETTINGS_SIZE,
+    MAX_STATE_CHUNK_SIZE, MAX_STRING_LEN, PROTOCOL_VERSION,
+};
+pub use registry::{LobbyRegistry, RegistryResult};
+pub use relay::{RelayPacket, MAX_RELAY_OVERHEAD, RELAY_MAGIC};
 pub use status::RequestStatus;
 
 use std::net::SocketAddr;
@@ -43,6 +63,63 @@ pub struct Lobby {
     pub privacy: LobbyPrivacy,
     pub state: LobbyState,
     pub players: Vec<Player>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub settings: LobbySettings,
+    /// The players whose chat messages are not delivered, as decided by the admin
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub muted_player_ids: Vec<Id>,
+}
+
+impl Lobby {
+    /// The id of the session of the game of the lobby, on the relay of its server
+    pub fn relay_session(&self) -> u64 {
+        fnv_hash(self.id.as_str().as_bytes())
+    }
+}
+
+/// The settings of the match that will be played in a lobby. These are decided by the admin of
+/// the lobby.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LobbySettings {
+    /// The path of the map, relative to the assets, or mod, directory that it is loaded from
+    pub map: Option<String>,
+    /// The rules of the match, serialized as JSON. These are opaque to the lobby service.
+    pub rules: Option<String>,
+    /// The address that the admin hosts the game on, as reachable by the other players
+    pub address: Option<SocketAddr>,
+}
+
+/// Filters applied by `Api::list_lobbies`. Private lobbies are never listed.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LobbyFilter {
+    /// Only list lobbies with a name that contains this, ignoring case
+    pub name: Option<String>,
+    /// List lobbies that are at capacity
+    pub include_full: bool,
+    /// List lobbies where the game has started
+    pub include_started: bool,
+}
+
+impl LobbyFilter {
+    pub fn matches(&self, lobby: &Lobby) -> bool {
+        if lobby.privacy != LobbyPrivacy::Public {
+            return false;
+        }
+
+        if let Some(name) = &self.name {
+            if !lobby.name.to_lowercase().contains(&name.to_lowercase()) {
+                return false;
+            }
+        }
+
+        if !self.include_full && lobby.player_count >= lobby.capacity {
+            return false;
+        }
+
+        self.include_started || lobby.state.is_joinable()
+    }
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -56,12 +133,22 @@ pub enum LobbyState {
     Ended,
 }
 
+impl LobbyState {
+    /// Returns `true` if players can join, leave and change their ready state
+    pub fn is_joinable(&self) -> bool {
+        matches!(self, LobbyState::NotStarted | LobbyState::LobbyReady)
+    }
+}
+
 #[derive(Debug, Clone)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Player {
     pub id: Id,
     pub username: String,
     pub state: ClientState,
+    /// The id of the character that the player has selected, while in a lobby
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub character: Option<String>,
 }
 
 impl Player {
@@ -70,6 +157,7 @@ impl Player {
             id: id.clone(),
             username: username.to_string(),
             state: ClientState::None,
+            character: None,
         }
     }
 }
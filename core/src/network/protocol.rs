@@ -0,0 +1,708 @@
+//! The peer-to-peer protocol of network games, and its binary encoding.
+//! Every message is sent as a single datagram, starting with a header that holds the protocol
+//! version, as a `u16`, followed by the message type, as a `u8`. All multi-byte values are
+//! encoded as little endian and strings are prefixed with their length, in bytes, as a `u8`.
+//!
+//! A connection starts with the client sending `Message::Hello` to the host, until it is
+//! answered by `Message::Welcome`, holding the settings of the match, or refused by
+//! `Message::Disconnect`. Spectators start with
+//! `Message::Spectate` in stead, and are answered in the same way. Addresses are encoded as
+//! the IP version, as a `u8`, followed by the octets of the IP and the port. Any change to the
+//! encoding must increment `PROTOCOL_VERSION`. The header and `Message::Disconnect` must keep
+//! their encoding across versions, so that a peer running another version can always be told
+//! why it was refused.
+
+use std::fmt::{self, Display, Formatter};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use crate::error::ErrorKind;
+use crate::Result;
+
+/// The version of the protocol. Peers will only talk to peers with the same version.
+pub const PROTOCOL_VERSION: u16 = 6;
+
+/// The maximum amount of inputs that can be held by a single `Message::Input`
+pub const MAX_INPUTS_PER_MESSAGE: usize = u8::MAX as usize;
+
+/// The maximum amount of bytes held by a single `Message::StateChunk`, or
+/// `Message::SnapshotChunk`
+pub const MAX_STATE_CHUNK_SIZE: usize = 960;
+
+/// The maximum amount of bytes of match settings held by a single `Message::Welcome`
+pub const MAX_SETTINGS_SIZE: usize = 960;
+
+/// The maximum length, in bytes, of strings in messages. Longer strings are truncated.
+pub const MAX_STRING_LEN: usize = u8::MAX as usize;
+
+const MESSAGE_TYPE_DISCONNECT: u8 = 0;
+const MESSAGE_TYPE_HELLO: u8 = 1;
+const MESSAGE_TYPE_WELCOME: u8 = 2;
+const MESSAGE_TYPE_INPUT: u8 = 3;
+const MESSAGE_TYPE_ACK: u8 = 4;
+const MESSAGE_TYPE_PING: u8 = 5;
+const MESSAGE_TYPE_PONG: u8 = 6;
+const MESSAGE_TYPE_CHECKSUM: u8 = 7;
+const MESSAGE_TYPE_STATE_CHUNK: u8 = 8;
+const MESSAGE_TYPE_CHAT: u8 = 9;
+const MESSAGE_TYPE_CHAT_ACK: u8 = 10;
+const MESSAGE_TYPE_SNAPSHOT_CHUNK: u8 = 11;
+const MESSAGE_TYPE_SNAPSHOT_ACK: u8 = 12;
+const MESSAGE_TYPE_SPECTATE: u8 = 13;
+const MESSAGE_TYPE_SLOT_REQUEST: u8 = 14;
+const MESSAGE_TYPE_SLOT_GRANTED: u8 = 15;
+const MESSAGE_TYPE_SUCCESSOR: u8 = 16;
+
+/// Computes a 64 bit FNV-1a hash of `bytes`. This is used for all hashes that are compared
+/// between peers, as it is stable across platforms and builds.
+pub fn fnv_hash(bytes: &[u8]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+
+    bytes.iter().fold(OFFSET_BASIS, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(PRIME)
+    })
+}
+
+/// The information that is exchanged when connecting, to make sure that both peers will run
+/// identical simulations
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct HandshakeInfo {
+    pub game_version: String,
+    /// A hash of the ids and versions of the loaded mods, in load order
+    pub mods_hash: u64,
+    /// A hash of the map that will be played
+    pub map_hash: u64,
+    /// The seed of the match. This is `None` on a client that has yet to receive the settings of
+    /// the match from the host, in `Message::Welcome`.
+    pub seed: Option<u64>,
+    /// A hash of the settings of the match, as they are sent in `Message::Welcome`. Like the
+    /// seed, this is `None` until the settings have been received.
+    pub settings_hash: Option<u64>,
+}
+
+impl HandshakeInfo {
+    /// Returns the reason to refuse a peer with the info in `remote`, if it differs from the
+    /// local info in a way that would make the simulations diverge
+    pub fn check(&self, remote: &HandshakeInfo) -> Option<DisconnectReason> {
+        if self.game_version != remote.game_version {
+            Some(DisconnectReason::GameVersionMismatch)
+        } else if self.mods_hash != remote.mods_hash {
+            Some(DisconnectReason::ModsMismatch)
+        } else if self.map_hash != remote.map_hash {
+            Some(DisconnectReason::MapMismatch)
+        } else if is_mismatch(self.seed, remote.seed)
+            || is_mismatch(self.settings_hash, remote.settings_hash)
+        {
+            Some(DisconnectReason::SettingsMismatch)
+        } else {
+            None
+        }
+    }
+}
+
+/// Returns `true` if both values are known, and differ
+fn is_mismatch(local: Option<u64>, remote: Option<u64>) -> bool {
+    matches!((local, remote), (Some(local), Some(remote)) if local != remote)
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum DisconnectReason {
+    /// The remote peer left the game
+    Quit,
+    ProtocolMismatch,
+    GameVersionMismatch,
+    ModsMismatch,
+    MapMismatch,
+    SettingsMismatch,
+    /// The host is already connected to another peer
+    SessionFull,
+    Unknown,
+}
+
+impl DisconnectReason {
+    pub fn as_code(&self) -> u8 {
+        match *self {
+            DisconnectReason::Quit => 0,
+            DisconnectReason::ProtocolMismatch => 1,
+            DisconnectReason::GameVersionMismatch => 2,
+            DisconnectReason::ModsMismatch => 3,
+            DisconnectReason::MapMismatch => 4,
+            DisconnectReason::SessionFull => 5,
+            DisconnectReason::SettingsMismatch => 6,
+            DisconnectReason::Unknown => u8::MAX,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            DisconnectReason::Quit => "the remote peer left the game",
+            DisconnectReason::ProtocolMismatch => "the network protocol versions differ",
+            DisconnectReason::GameVersionMismatch => "the game versions differ",
+            DisconnectReason::ModsMismatch => "the loaded mods differ",
+            DisconnectReason::MapMismatch => "the maps differ",
+            DisconnectReason::SessionFull => "the game is full",
+            DisconnectReason::SettingsMismatch => "the match settings differ",
+            DisconnectReason::Unknown => "unknown reason",
+        }
+    }
+}
+
+impl From<u8> for DisconnectReason {
+    fn from(code: u8) -> Self {
+        match code {
+            0 => DisconnectReason::Quit,
+            1 => DisconnectReason::ProtocolMismatch,
+            2 => DisconnectReason::GameVersionMismatch,
+            3 => DisconnectReason::ModsMismatch,
+            4 => DisconnectReason::MapMismatch,
+            5 => DisconnectReason::SessionFull,
+            6 => DisconnectReason::SettingsMismatch,
+            _ => DisconnectReason::Unknown,
+        }
+    }
+}
+
+impl Display for DisconnectReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    /// Sent when leaving a game, or to refuse a connection
+    Disconnect { reason: DisconnectReason },
+    /// Sent by a client, until it is answered by the host
+    Hello(HandshakeInfo),
+    /// Sent by the host, to accept a client, with the settings of the match, serialized by the
+    /// game. These are opaque to the protocol, and must not exceed `MAX_SETTINGS_SIZE` bytes, for
+    /// the message to fit in a datagram, which the sender has to check.
+    Welcome { settings: Vec<u8> },
+    /// The input of a local player of the sending peer, for every tick from `start_tick` and
+    /// forward, with each input packed into the bits of a `u8`. All input that has not yet been
+    /// acknowledged is sent again, with every message, so that input that is lost on the way is
+    /// recovered by later messages.
+    Input {
+        player: u8,
+        start_tick: u64,
+        inputs: Vec<u8>,
+    },
+    /// Acknowledges that the input of all the players of the receiving peer has been received,
+    /// for all ticks before `tick`
+    Ack { tick: u64 },
+    /// Sent periodically to measure round trip time. `time` is the local time of the sender.
+    Ping { time: f64 },
+    /// The response to a `Message::Ping`, holding the time of the ping that is responded to
+    Pong { time: f64 },
+    /// The checksum of the simulation state at the start of `tick`
+    Checksum { tick: u64, checksum: u64 },
+    /// A part of the simulation state at the start of `tick`, sent when a desync is detected, so
+    /// that the states of both peers can be compared
+    StateChunk {
+        tick: u64,
+        index: u16,
+        chunk_cnt: u16,
+        data: Vec<u8>,
+    },
+    /// A chat message from the player with the index `player`. Chat messages are numbered by
+    /// `seq`, for each peer, and are sent again until they are acknowledged.
+    Chat { seq: u32, player: u8, text: String },
+    /// Acknowledges that all chat messages before `seq` have been received
+    ChatAck { seq: u32 },
+    /// A part of the snapshot of the game at the start of `tick`, sent by the host to a client
+    /// that has reconnected, for the client to continue from
+    SnapshotChunk {
+        tick: u64,
+        index: u16,
+        chunk_cnt: u16,
+        data: Vec<u8>,
+    },
+    /// Acknowledges that all chunks of the snapshot of `tick`, before `index`, have been received
+    SnapshotAck { tick: u64, index: u16 },
+    /// Sent by a client that wants to watch the match, in stead of `Message::Hello`. Spectators
+    /// are sent a snapshot of the game, followed by the input of all players, but send no input.
+    Spectate(HandshakeInfo),
+    /// Sent by a spectator that wants to take the slot of a player that has left the match
+    SlotRequest,
+    /// Tells a spectator that it has been given the slots of the players in `players`, and that
+    /// it will be sent a snapshot to continue from, as a player
+    SlotGranted { players: Vec<u8> },
+    /// Tells a spectator the address of the peer that will take over as host, if the host
+    /// leaves the match
+    Successor { addr: SocketAddr },
+}
+
+impl Message {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+
+        bytes.extend_from_slice(&PROTOCOL_VERSION.to_le_bytes());
+
+        match self {
+            Message::Disconnect { reason } => {
+                bytes.push(MESSAGE_TYPE_DISCONNECT);
+                bytes.push(reason.as_code());
+            }
+            Message::Hello(info) => {
+                bytes.push(MESSAGE_TYPE_HELLO);
+                write_handshake(&mut bytes, info);
+            }
+            Message::Spectate(info) => {
+                bytes.push(MESSAGE_TYPE_SPECTATE);
+                write_handshake(&mut bytes, info);
+            }
+            Message::SlotRequest => {
+                bytes.push(MESSAGE_TYPE_SLOT_REQUEST);
+            }
+            Message::SlotGranted { players } => {
+                let player_cnt = players.len().min(u8::MAX as usize);
+
+                bytes.push(MESSAGE_TYPE_SLOT_GRANTED);
+                bytes.push(player_cnt as u8);
+                bytes.extend_from_slice(&players[..player_cnt]);
+            }
+            Message::Successor { addr } => {
+                bytes.push(MESSAGE_TYPE_SUCCESSOR);
+                write_addr(&mut bytes, addr);
+            }
+            Message::Welcome { settings } => {
+                bytes.push(MESSAGE_TYPE_WELCOME);
+                bytes.extend_from_slice(&(settings.len() as u16).to_le_bytes());
+                bytes.extend_from_slice(settings);
+            }
+            Message::Input {
+                player,
+                start_tick,
+                inputs,
+            } => {
+                let input_cnt = inputs.len().min(MAX_INPUTS_PER_MESSAGE);
+
+                bytes.push(MESSAGE_TYPE_INPUT);
+                bytes.push(*player);
+                bytes.extend_from_slice(&start_tick.to_le_bytes());
+                bytes.push(input_cnt as u8);
+                bytes.extend_from_slice(&inputs[..input_cnt]);
+            }
+            Message::Ack { tick } => {
+                bytes.push(MESSAGE_TYPE_ACK);
+                bytes.extend_from_slice(&tick.to_le_bytes());
+            }
+            Message::Ping { time } => {
+                bytes.push(MESSAGE_TYPE_PING);
+                bytes.extend_from_slice(&time.to_le_bytes());
+            }
+            Message::Pong { time } => {
+                bytes.push(MESSAGE_TYPE_PONG);
+                bytes.extend_from_slice(&time.to_le_bytes());
+            }
+            Message::Checksum { tick, checksum } => {
+                bytes.push(MESSAGE_TYPE_CHECKSUM);
+                bytes.extend_from_slice(&tick.to_le_bytes());
+                bytes.extend_from_slice(&checksum.to_le_bytes());
+            }
+            Message::StateChunk {
+                tick,
+                index,
+                chunk_cnt,
+                data,
+            } => {
+                let len = data.len().min(MAX_STATE_CHUNK_SIZE);
+
+                bytes.push(MESSAGE_TYPE_STATE_CHUNK);
+                bytes.extend_from_slice(&tick.to_le_bytes());
+                bytes.extend_from_slice(&index.to_le_bytes());
+                bytes.extend_from_slice(&chunk_cnt.to_le_bytes());
+                bytes.extend_from_slice(&(len as u16).to_le_bytes());
+                bytes.extend_from_slice(&data[..len]);
+            }
+            Message::Chat { seq, player, text } => {
+                bytes.push(MESSAGE_TYPE_CHAT);
+                bytes.extend_from_slice(&seq.to_le_bytes());
+                bytes.push(*player);
+                write_str(&mut bytes, text);
+            }
+            Message::ChatAck { seq } => {
+                bytes.push(MESSAGE_TYPE_CHAT_ACK);
+                bytes.extend_from_slice(&seq.to_le_bytes());
+            }
+            Message::SnapshotChunk {
+                tick,
+                index,
+                chunk_cnt,
+                data,
+            } => {
+                let len = data.len().min(MAX_STATE_CHUNK_SIZE);
+
+                bytes.push(MESSAGE_TYPE_SNAPSHOT_CHUNK);
+                bytes.extend_from_slice(&tick.to_le_bytes());
+                bytes.extend_from_slice(&index.to_le_bytes());
+                bytes.extend_from_slice(&chunk_cnt.to_le_bytes());
+                bytes.extend_from_slice(&(len as u16).to_le_bytes());
+                bytes.extend_from_slice(&data[..len]);
+            }
+            Message::SnapshotAck { tick, index } => {
+                bytes.push(MESSAGE_TYPE_SNAPSHOT_ACK);
+                bytes.extend_from_slice(&tick.to_le_bytes());
+                bytes.extend_from_slice(&index.to_le_bytes());
+            }
+        }
+
+        bytes
+    }
+
+    /// Decode a message. This will fail if the message was encoded with another protocol
+    /// version, unless it is a `Message::Disconnect`.
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader { bytes };
+
+        let version = reader.read_u16()?;
+        let message_type = reader.read_u8()?;
+
+        if version != PROTOCOL_VERSION && message_type != MESSAGE_TYPE_DISCONNECT {
+            return Err(formaterr!(
+                ErrorKind::Network,
+                "Unsupported protocol version {} (expected {})",
+                version,
+                PROTOCOL_VERSION
+            ));
+        }
+
+        let message = match message_type {
+            MESSAGE_TYPE_DISCONNECT => Message::Disconnect {
+                reason: reader.read_u8()?.into(),
+            },
+            MESSAGE_TYPE_HELLO => Message::Hello(reader.read_handshake()?),
+            MESSAGE_TYPE_SPECTATE => Message::Spectate(reader.read_handshake()?),
+            MESSAGE_TYPE_SLOT_REQUEST => Message::SlotRequest,
+            MESSAGE_TYPE_SLOT_GRANTED => {
+                let player_cnt = reader.read_u8()? as usize;
+
+                Message::SlotGranted {
+                    players: reader.read_bytes(player_cnt)?.to_vec(),
+                }
+            }
+            MESSAGE_TYPE_SUCCESSOR => Message::Successor {
+                addr: reader.read_addr()?,
+            },
+            MESSAGE_TYPE_WELCOME => {
+                let len = reader.read_u16()? as usize;
+
+                Message::Welcome {
+                    settings: reader.read_bytes(len)?.to_vec(),
+                }
+            }
+            MESSAGE_TYPE_INPUT => {
+                let player = reader.read_u8()?;
+                let start_tick = reader.read_u64()?;
+                let input_cnt = reader.read_u8()? as usize;
+
+                Message::Input {
+                    player,
+                    start_tick,
+                    inputs: reader.read_bytes(input_cnt)?.to_vec(),
+                }
+            }
+            MESSAGE_TYPE_ACK => Message::Ack {
+                tick: reader.read_u64()?,
+            },
+            MESSAGE_TYPE_PING => Message::Ping {
+                time: reader.read_f64()?,
+            },
+            MESSAGE_TYPE_PONG => Message::Pong {
+                time: reader.read_f64()?,
+            },
+            MESSAGE_TYPE_CHECKSUM => Message::Checksum {
+                tick: reader.read_u64()?,
+                checksum: reader.read_u64()?,
+            },
+            MESSAGE_TYPE_STATE_CHUNK => {
+                let tick = reader.read_u64()?;
+                let index = reader.read_u16()?;
+                let chunk_cnt = reader.read_u16()?;
+                let len = reader.read_u16()? as usize;
+
+                Message::StateChunk {
+                    tick,
+                    index,
+                    chunk_cnt,
+                    data: reader.read_bytes(len)?.to_vec(),
+                }
+            }
+            MESSAGE_TYPE_CHAT => Message::Chat {
+                seq: reader.read_u32()?,
+                player: reader.read_u8()?,
+                text: reader.read_str()?,
+            },
+            MESSAGE_TYPE_CHAT_ACK => Message::ChatAck {
+                seq: reader.read_u32()?,
+            },
+            MESSAGE_TYPE_SNAPSHOT_CHUNK => {
+                let tick = reader.read_u64()?;
+                let index = reader.read_u16()?;
+                let chunk_cnt = reader.read_u16()?;
+                let len = reader.read_u16()? as usize;
+
+                Message::SnapshotChunk {
+                    tick,
+                    index,
+                    chunk_cnt,
+                    data: reader.read_bytes(len)?.to_vec(),
+                }
+            }
+            MESSAGE_TYPE_SNAPSHOT_ACK => Message::SnapshotAck {
+                tick: reader.read_u64()?,
+                index: reader.read_u16()?,
+            },
+            message_type => {
+                return Err(formaterr!(
+                    ErrorKind::Network,
+                    "Invalid message type '{}'",
+                    message_type
+                ));
+            }
+        };
+
+        Ok(message)
+    }
+
+    /// Returns the protocol version that `bytes` was encoded with, if it holds a full header
+    pub fn protocol_version(bytes: &[u8]) -> Option<u16> {
+        Reader { bytes }.read_u16().ok()
+    }
+}
+
+/// Write `value`, truncated to `MAX_STRING_LEN` bytes, at a character boundary
+fn write_str(bytes: &mut Vec<u8>, value: &str) {
+    let mut len = value.len().min(MAX_STRING_LEN);
+    while !value.is_char_boundary(len) {
+        len -= 1;
+    }
+
+    bytes.push(len as u8);
+    bytes.extend_from_slice(&value.as_bytes()[..len]);
+}
+
+fn write_handshake(bytes: &mut Vec<u8>, info: &HandshakeInfo) {
+    write_str(bytes, &info.game_version);
+    bytes.extend_from_slice(&info.mods_hash.to_le_bytes());
+    bytes.extend_from_slice(&info.map_hash.to_le_bytes());
+    write_opt_u64(bytes, info.seed);
+    write_opt_u64(bytes, info.settings_hash);
+}
+
+/// Write a flag, as a `u8`, that is `1` if `value` is set, followed by the value, if it is
+fn write_opt_u64(bytes: &mut Vec<u8>, value: Option<u64>) {
+    match value {
+        Some(value) => {
+            bytes.push(1);
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        None => bytes.push(0),
+    }
+}
+
+pub(super) fn write_addr(bytes: &mut Vec<u8>, addr: &SocketAddr) {
+    match addr.ip() {
+        IpAddr::V4(ip) => {
+            bytes.push(4);
+            bytes.extend_from_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            bytes.push(6);
+            bytes.extend_from_slice(&ip.octets());
+        }
+    }
+
+    bytes.extend_from_slice(&addr.port().to_le_bytes());
+}
+
+pub(super) struct Reader<'a> {
+    pub(super) bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() < len {
+            return Err(formaterr!(ErrorKind::Network, "Message is truncated"));
+        }
+
+        let (res, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+
+        Ok(res)
+    }
+
+    pub(super) fn read_u8(&mut self) -> Result<u8> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_u16(&mut self) -> Result<u16> {
+        let mut buf = [0; 2];
+        buf.copy_from_slice(self.read_bytes(2)?);
+        Ok(u16::from_le_bytes(buf))
+    }
+
+    fn read_u32(&mut self) -> Result<u32> {
+        let mut buf = [0; 4];
+        buf.copy_from_slice(self.read_bytes(4)?);
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    pub(super) fn read_u64(&mut self) -> Result<u64> {
+        let mut buf = [0; 8];
+        buf.copy_from_slice(self.read_bytes(8)?);
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    fn read_f64(&mut self) -> Result<f64> {
+        let mut buf = [0; 8];
+        buf.copy_from_slice(self.read_bytes(8)?);
+        Ok(f64::from_le_bytes(buf))
+    }
+
+    fn read_opt_u64(&mut self) -> Result<Option<u64>> {
+        match self.read_u8()? {
+            0 => Ok(None),
+            _ => Ok(Some(self.read_u64()?)),
+        }
+    }
+
+    fn read_str(&mut self) -> Result<String> {
+        let len = self.read_u8()? as usize;
+
+        String::from_utf8(self.read_bytes(len)?.to_vec())
+            .map_err(|_| formaterr!(ErrorKind::Network, "Message holds an invalid string"))
+    }
+
+    pub(super) fn read_addr(&mut self) -> Result<SocketAddr> {
+        let ip = match self.read_u8()? {
+            4 => {
+                let mut octets = [0; 4];
+                octets.copy_from_slice(self.read_bytes(4)?);
+                IpAddr::V4(Ipv4Addr::from(octets))
+            }
+            6 => {
+                let mut octets = [0; 16];
+                octets.copy_from_slice(self.read_bytes(16)?);
+                IpAddr::V6(Ipv6Addr::from(octets))
+            }
+            version => {
+                return Err(formaterr!(
+                    ErrorKind::Network,
+                    "Invalid IP version '{}'",
+                    version
+                ));
+            }
+        };
+
+        Ok(SocketAddr::new(ip, self.read_u16()?))
+    }
+
+    fn read_handshake(&mut self) -> Result<HandshakeInfo> {
+        Ok(HandshakeInfo {
+            game_version: self.read_str()?,
+            mods_hash: self.read_u64()?,
+            map_hash: self.read_u64()?,
+            seed: self.read_opt_u64()?,
+            settings_hash: self.read_opt_u64()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_round_trip() {
+        let messages = [
+            Message::Disconnect {
+                reason: DisconnectReason::ModsMismatch,
+            },
+            Message::Hello(HandshakeInfo {
+                game_version: "0.4.0".to_string(),
+                mods_hash: 0x0123456789abcdef,
+                map_hash: 42,
+                seed: Some(1234),
+                settings_hash: Some(u64::MAX),
+            }),
+            Message::Welcome {
+                settings: b"{\"seed\":1234}".to_vec(),
+            },
+            Message::Spectate(HandshakeInfo {
+                game_version: "0.4.0".to_string(),
+                mods_hash: 7,
+                map_hash: 42,
+                seed: None,
+                settings_hash: None,
+            }),
+            Message::SlotRequest,
+            Message::SlotGranted {
+                players: vec![1, 3],
+            },
+            Message::Successor {
+                addr: "192.168.1.20:9000".parse().unwrap(),
+            },
+            Message::Successor {
+                addr: "[::1]:9001".parse().unwrap(),
+            },
+            Message::Input {
+                player: 1,
+                start_tick: 300,
+                inputs: vec![0, 3, 255],
+            },
+            Message::Checksum {
+                tick: 60,
+                checksum: u64::MAX,
+            },
+            Message::Chat {
+                seq: 7,
+                player: 0,
+                text: "gg 🐟".to_string(),
+            },
+            Message::ChatAck { seq: 8 },
+            Message::SnapshotChunk {
+                tick: 1200,
+                index: 3,
+                chunk_cnt: 4,
+                data: vec![1, 2, 3],
+            },
+            Message::SnapshotAck {
+                tick: 1200,
+                index: 4,
+            },
+        ];
+
+        for message in messages {
+            assert_eq!(Message::decode(&message.encode()).unwrap(), message);
+        }
+    }
+
+    #[test]
+    fn test_message_version_mismatch() {
+        let mut bytes = Message::Welcome {
+            settings: Vec::new(),
+        }
+        .encode();
+        bytes[..2].copy_from_slice(&(PROTOCOL_VERSION + 1).to_le_bytes());
+
+        assert!(Message::decode(&bytes).is_err());
+        assert_eq!(
+            Message::protocol_version(&bytes),
+            Some(PROTOCOL_VERSION + 1)
+        );
+
+        let mut bytes = Message::Disconnect {
+            reason: DisconnectReason::ProtocolMismatch,
+        }
+        .encode();
+        bytes[..2].copy_from_slice(&(PROTOCOL_VERSION + 1).to_le_bytes());
+
+        assert_eq!(
+            Message::decode(&bytes).unwrap(),
+            Message::Disconnect {
+                reason: DisconnectReason::ProtocolMismatch
+            }
+        );
+    }
+}
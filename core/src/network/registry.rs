@@ -0,0 +1,877 @@
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+
+use macroquad::miniquad::date;
+
+use super::{
+    sanitize_chat_message, ChatRateLimiter, ClientState, Id, Lobby, LobbyFilter, LobbyPrivacy,
+    LobbySettings, LobbyState, NetworkEvent, Player, RequestStatus, Server,
+};
+
+/// Errors are returned as the `RequestStatus` that a lobby service should respond with
+pub type RegistryResult<T> = std::result::Result<T, RequestStatus>;
+
+/// The maximum amount of events that are queued for a player, before the oldest are dropped
+const MAX_QUEUED_EVENTS: usize = 256;
+
+/// This holds the players and lobbies of a lobby service and implements the transitions of
+/// `LobbyState` and `ClientState`. Every change is reported to the affected players as a
+/// `NetworkEvent`, that is queued until taken with `take_events`.
+#[derive(Debug)]
+pub struct LobbyRegistry {
+    server: Option<Server>,
+    players: Vec<Player>,
+    lobbies: Vec<Lobby>,
+    /// Player ids, by session token
+    sessions: HashMap<String, Id>,
+    events: HashMap<Id, Vec<NetworkEvent>>,
+    chat_limiters: HashMap<Id, ChatRateLimiter>,
+    /// The tokens that the hosts of the games of lobbies register with the relay, by lobby id
+    relay_host_tokens: HashMap<Id, u64>,
+    next_player_id: u64,
+    next_lobby_id: u64,
+}
+
+impl LobbyRegistry {
+    /// Create a registry where lobbies are hosted on `server`, if specified
+    pub fn new(server: Option<Server>) -> Self {
+        LobbyRegistry {
+            server,
+            players: Vec::new(),
+            lobbies: Vec::new(),
+            sessions: HashMap::new(),
+            events: HashMap::new(),
+            chat_limiters: HashMap::new(),
+            relay_host_tokens: HashMap::new(),
+            next_player_id: 1,
+            next_lobby_id: 1,
+        }
+    }
+
+    pub fn server(&self) -> Option<&Server> {
+        self.server.as_ref()
+    }
+
+    /// The amount of players that have been added
+    pub fn player_cnt(&self) -> usize {
+        self.players.len()
+    }
+
+    /// Add a player with the session `token`
+    pub fn add_player(&mut self, token: &str, username: &str) -> Player {
+        let id = Id::from(self.next_player_id.to_string());
+        self.next_player_id += 1;
+
+        let player = Player::new(&id, username);
+
+        self.players.push(player.clone());
+        self.sessions.insert(token.to_string(), id);
+
+        player
+    }
+
+    /// Returns the id of the player with the session `token`
+    pub fn authenticate(&self, token: &str) -> RegistryResult<Id> {
+        self.sessions
+            .get(token)
+            .cloned()
+            .ok_or(RequestStatus::Unauthorized)
+    }
+
+    pub fn get_player(&self, id: &Id) -> RegistryResult<Player> {
+        self.players
+            .iter()
+            .find(|player| player.id == *id)
+            .cloned()
+            .ok_or(RequestStatus::NotFound)
+    }
+
+    pub fn get_lobby(&self, id: &Id) -> RegistryResult<Lobby> {
+        self.lobbies
+            .iter()
+            .find(|lobby| lobby.id == *id)
+            .cloned()
+            .ok_or(RequestStatus::NotFound)
+    }
+
+    /// Returns all lobbies that match `filter`
+    pub fn list_lobbies(&self, filter: &LobbyFilter) -> Vec<Lobby> {
+        self.lobbies
+            .iter()
+            .filter(|lobby| filter.matches(lobby))
+            .cloned()
+            .collect()
+    }
+
+    /// Create a lobby with the player `player_id` as its admin. The player will leave any
+    /// lobby that it is already in.
+    pub fn create_lobby(
+        &mut self,
+        player_id: &Id,
+        name: &str,
+        privacy: LobbyPrivacy,
+        capacity: i32,
+    ) -> RegistryResult<Lobby> {
+        if name.is_empty() || capacity < 1 {
+            return Err(RequestStatus::BadRequest);
+        }
+
+        let mut player = self.get_player(player_id)?;
+
+        self.leave_current_lobby(player_id)?;
+
+        let id = Id::from(format!("lobby-{}", self.next_lobby_id));
+        self.next_lobby_id += 1;
+
+        player.state = ClientState::Joined;
+
+        let lobby = Lobby {
+            id: id.clone(),
+            name: name.to_string(),
+            creator_player_id: player_id.clone(),
+            admin_player_id: player_id.clone(),
+            player_count: 1,
+            capacity,
+            server: self.server.clone(),
+            privacy,
+            state: LobbyState::NotStarted,
+            players: vec![player],
+            settings: LobbySettings::default(),
+            muted_player_ids: Vec::new(),
+        };
+
+        self.lobbies.push(lobby.clone());
+        self.set_player_state(player_id, ClientState::Joined);
+
+        self.push_event(player_id, NetworkEvent::LobbyCreated { lobby_id: id });
+        self.push_event(
+            player_id,
+            NetworkEvent::LobbyChanged {
+                lobby: lobby.clone(),
+            },
+        );
+
+        Ok(lobby)
+    }
+
+    /// Add the player `player_id` to the lobby `lobby_id`. The player will leave any lobby that
+    /// it is already in. This fails with `RequestStatus::Conflict` if the lobby is full, or if
+    /// its game has started.
+    pub fn join_lobby(&mut self, player_id: &Id, lobby_id: &Id) -> RegistryResult<Lobby> {
+        let mut player = self.get_player(player_id)?;
+
+        {
+            let lobby = self.lobby_mut(lobby_id)?;
+
+            if lobby.players.iter().any(|other| other.id == *player_id) {
+                return Ok(lobby.clone());
+            }
+
+            if lobby.player_count >= lobby.capacity || !lobby.state.is_joinable() {
+                return Err(RequestStatus::Conflict);
+            }
+        }
+
+        self.leave_current_lobby(player_id)?;
+
+        player.state = ClientState::Joined;
+        self.set_player_state(player_id, ClientState::Joined);
+
+        let lobby = self.lobby_mut(lobby_id)?;
+
+        lobby.players.push(player.clone());
+        lobby.player_count = lobby.players.len() as i32;
+
+        // A new player is not ready, so the lobby is no longer ready either
+        lobby.state = LobbyState::NotStarted;
+
+        let lobby = lobby.clone();
+
+        self.push_lobby_event(
+            &lobby,
+            NetworkEvent::PlayerJoined {
+                player_id: player_id.clone(),
+                username: player.username.clone(),
+                port: 0,
+            },
+        );
+
+        self.push_system_message(&lobby, format!("{} joined", player.username));
+        self.push_lobby_changed(&lobby);
+
+        Ok(lobby)
+    }
+
+    /// Remove the player `player_id` from the lobby `lobby_id`. If the player is the admin of the
+    /// lobby, the player that has been in the lobby the longest becomes the admin, and the lobby
+    /// is removed when its last player leaves.
+    pub fn leave_lobby(&mut self, player_id: &Id, lobby_id: &Id) -> RegistryResult<()> {
+        let event = NetworkEvent::PlayerLeft {
+            player_id: player_id.clone(),
+        };
+
+        self.remove_player(player_id, lobby_id, event, "left")
+    }
+
+    /// Remove the player `player_id` from the lobby `lobby_id`, on behalf of `admin_id`, which
+    /// must be the admin of the lobby
+    pub fn kick_player(
+        &mut self,
+        admin_id: &Id,
+        lobby_id: &Id,
+        player_id: &Id,
+    ) -> RegistryResult<()> {
+        self.check_admin(admin_id, lobby_id)?;
+
+        if admin_id == player_id {
+            return Err(RequestStatus::BadRequest);
+        }
+
+        let event = NetworkEvent::PlayerKicked {
+            player_id: player_id.clone(),
+        };
+
+        self.remove_player(player_id, lobby_id, event, "was kicked")
+    }
+
+    /// Remove the player `player_id` from the lobby `lobby_id`, notifying the player and the
+    /// remaining players with `event`, and the remaining players with a system message, made up
+    /// of the name of the player and `action`
+    fn remove_player(
+        &mut self,
+        player_id: &Id,
+        lobby_id: &Id,
+        event: NetworkEvent,
+        action: &str,
+    ) -> RegistryResult<()> {
+        let (lobby, player) = {
+            let lobby = self.lobby_mut(lobby_id)?;
+
+            let i = lobby
+                .players
+                .iter()
+                .position(|player| player.id == *player_id)
+                .ok_or(RequestStatus::NotFound)?;
+
+            let player = lobby.players.remove(i);
+            lobby.muted_player_ids.retain(|id| id != player_id);
+            lobby.player_count = lobby.players.len() as i32;
+
+            if lobby.admin_player_id == *player_id {
+                if let Some(player) = lobby.players.first() {
+                    lobby.admin_player_id = player.id.clone();
+                }
+            }
+
+            update_ready_state(lobby);
+
+            (lobby.clone(), player)
+        };
+
+        self.set_player_state(player_id, ClientState::Left);
+
+        self.push_event(player_id, event.clone());
+
+        if lobby.players.is_empty() {
+            self.lobbies.retain(|other| other.id != *lobby_id);
+            self.relay_host_tokens.remove(lobby_id);
+        } else {
+            self.push_lobby_event(&lobby, event);
+            self.push_system_message(&lobby, format!("{} {}", player.username, action));
+            self.push_lobby_changed(&lobby);
+        }
+
+        Ok(())
+    }
+
+    /// Mark the player `player_id` as ready, or not ready, in the lobby `lobby_id`. The lobby
+    /// becomes `LobbyState::LobbyReady` when it holds more than one player and all of them are
+    /// ready.
+    pub fn set_ready(
+        &mut self,
+        player_id: &Id,
+        lobby_id: &Id,
+        is_ready: bool,
+    ) -> RegistryResult<()> {
+        let state = if is_ready {
+            ClientState::Ready
+        } else {
+            ClientState::Joined
+        };
+
+        let lobby = {
+            let lobby = self.lobby_mut(lobby_id)?;
+
+            if !lobby.state.is_joinable() {
+                return Err(RequestStatus::Conflict);
+            }
+
+            let player = lobby
+                .players
+                .iter_mut()
+                .find(|player| player.id == *player_id)
+                .ok_or(RequestStatus::NotFound)?;
+
+            player.state = state;
+
+            update_ready_state(lobby);
+
+            lobby.clone()
+        };
+
+        self.set_player_state(player_id, state);
+
+        let event = if is_ready {
+            NetworkEvent::PlayerMarkedReady {
+                player_id: player_id.clone(),
+            }
+        } else {
+            NetworkEvent::PlayerMarkedNotReady {
+                player_id: player_id.clone(),
+            }
+        };
+
+        self.push_lobby_event(&lobby, event);
+        self.push_lobby_changed(&lobby);
+
+        Ok(())
+    }
+
+    /// Send a chat message from the player `player_id` to all the players in the lobby
+    /// `lobby_id`, including the sender. This fails with `RequestStatus::Forbidden` if the player has been muted, and
+    /// with `RequestStatus::TooManyRequests` if the player exceeds `CHAT_RATE_LIMIT`.
+    pub fn send_chat_message(
+        &mut self,
+        player_id: &Id,
+        lobby_id: &Id,
+        text: &str,
+    ) -> RegistryResult<()> {
+        let text = sanitize_chat_message(text).ok_or(RequestStatus::BadRequest)?;
+
+        let lobby = self.get_lobby(lobby_id)?;
+
+        let player = lobby
+            .players
+            .iter()
+            .find(|player| player.id == *player_id)
+            .ok_or(RequestStatus::NotFound)?;
+
+        if lobby.muted_player_ids.contains(player_id) {
+            return Err(RequestStatus::Forbidden);
+        }
+
+        let is_allowed = self
+            .chat_limiters
+            .entry(player_id.clone())
+            .or_default()
+            .try_send(date::now());
+
+        if !is_allowed {
+            return Err(RequestStatus::TooManyRequests);
+        }
+
+        let event = NetworkEvent::ChatMessage {
+            player_id: player_id.clone(),
+            username: player.username.clone(),
+            text,
+        };
+
+        self.push_lobby_event(&lobby, event);
+
+        Ok(())
+    }
+
+    /// Mute, or unmute, the player `player_id` in the lobby `lobby_id`, on behalf of `admin_id`,
+    /// which must be the admin of the lobby
+    pub fn set_muted(
+        &mut self,
+        admin_id: &Id,
+        lobby_id: &Id,
+        player_id: &Id,
+        is_muted: bool,
+    ) -> RegistryResult<()> {
+        self.check_admin(admin_id, lobby_id)?;
+
+        if admin_id == player_id {
+            return Err(RequestStatus::BadRequest);
+        }
+
+        let (lobby, username) = {
+            let lobby = self.lobby_mut(lobby_id)?;
+
+            let username = lobby
+                .players
+                .iter()
+                .find(|player| player.id == *player_id)
+                .map(|player| player.username.clone())
+                .ok_or(RequestStatus::NotFound)?;
+
+            lobby.muted_player_ids.retain(|id| id != player_id);
+
+            if is_muted {
+                lobby.muted_player_ids.push(player_id.clone());
+            }
+
+            (lobby.clone(), username)
+        };
+
+        let action = if is_muted { "was muted" } else { "was unmuted" };
+
+        self.push_system_message(&lobby, format!("{} {}", username, action));
+        self.push_lobby_changed(&lobby);
+
+        Ok(())
+    }
+
+    /// Select the character that the player `player_id` will play as, in the lobby `lobby_id`
+    pub fn set_character(
+        &mut self,
+        player_id: &Id,
+        lobby_id: &Id,
+        character: &str,
+    ) -> RegistryResult<()> {
+        let lobby = {
+            let lobby = self.lobby_mut(lobby_id)?;
+
+            if !lobby.state.is_joinable() {
+                return Err(RequestStatus::Conflict);
+            }
+
+            let player = lobby
+                .players
+                .iter_mut()
+                .find(|player| player.id == *player_id)
+                .ok_or(RequestStatus::NotFound)?;
+
+            player.character = Some(character.to_string());
+
+            lobby.clone()
+        };
+
+        self.push_lobby_changed(&lobby);
+
+        Ok(())
+    }
+
+    /// Change the settings of the lobby `lobby_id`, on behalf of `player_id`, which must be the
+    /// admin of the lobby. As the players agreed to the previous settings, they are all marked as
+    /// not ready, except for the admin.
+    pub fn update_lobby_settings(
+        &mut self,
+        player_id: &Id,
+        lobby_id: &Id,
+        settings: LobbySettings,
+    ) -> RegistryResult<()> {
+        self.check_admin(player_id, lobby_id)?;
+
+        let (lobby, not_ready) = {
+            let lobby = self.lobby_mut(lobby_id)?;
+
+            if !lobby.state.is_joinable() {
+                return Err(RequestStatus::Conflict);
+            }
+
+            lobby.settings = settings;
+
+            let mut not_ready = Vec::new();
+
+            for player in &mut lobby.players {
+                if player.state == ClientState::Ready && player.id != *player_id {
+                    player.state = ClientState::Joined;
+                    not_ready.push(player.id.clone());
+                }
+            }
+
+            update_ready_state(lobby);
+
+            (lobby.clone(), not_ready)
+        };
+
+        for id in not_ready {
+            self.set_player_state(&id, ClientState::Joined);
+            self.push_lobby_event(&lobby, NetworkEvent::PlayerMarkedNotReady { player_id: id });
+        }
+
+        self.push_lobby_changed(&lobby);
+
+        Ok(())
+    }
+
+    /// Start the game of the lobby `lobby_id`, on behalf of `player_id`, which must be the admin
+    /// of the lobby. The lobby must be `LobbyState::LobbyReady`, and it will be
+    /// `LobbyState::Starting` until `set_game_running` is called. The admin is given a new relay
+    /// host token, with `NetworkEvent::GameStarted`, as it hosts the game.
+    pub fn start_game(&mut self, player_id: &Id, lobby_id: &Id) -> RegistryResult<()> {
+        self.check_admin(player_id, lobby_id)?;
+
+        let lobby = {
+            let lobby = self.lobby_mut(lobby_id)?;
+
+            if lobby.state != LobbyState::LobbyReady {
+                return Err(RequestStatus::Conflict);
+            }
+
+            lobby.state = LobbyState::Starting;
+
+            for player in &mut lobby.players {
+                player.state = ClientState::Playing;
+            }
+
+            lobby.clone()
+        };
+
+        // The token is drawn from the random keys of the hasher, so that it can not be guessed
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write(lobby_id.as_str().as_bytes());
+        hasher.write_u64(date::now().to_bits());
+        let relay_host_token = hasher.finish();
+
+        self.relay_host_tokens
+            .insert(lobby_id.clone(), relay_host_token);
+
+        for player in &lobby.players {
+            self.set_player_state(&player.id, ClientState::Playing);
+
+            self.push_event(
+                &player.id,
+                NetworkEvent::GameStarted {
+                    lobby_id: lobby_id.clone(),
+                    relay_host_token: (player.id == lobby.admin_player_id)
+                        .then_some(relay_host_token),
+                },
+            );
+        }
+
+        self.push_lobby_changed(&lobby);
+
+        Ok(())
+    }
+
+    /// Mark the game of the lobby `lobby_id` as running, once it has started
+    pub fn set_game_running(&mut self, lobby_id: &Id) -> RegistryResult<()> {
+        self.set_game_state(lobby_id, LobbyState::Starting, LobbyState::Running)
+    }
+
+    /// End the game of the lobby `lobby_id`. The lobby will be removed once all players have
+    /// left it.
+    pub fn end_game(&mut self, lobby_id: &Id) -> RegistryResult<()> {
+        self.set_game_state(lobby_id, LobbyState::Running, LobbyState::Ended)?;
+
+        let lobby = {
+            let lobby = self.lobby_mut(lobby_id)?;
+
+            for player in &mut lobby.players {
+                player.state = ClientState::Done;
+            }
+
+            lobby.clone()
+        };
+
+        for player in &lobby.players {
+            self.set_player_state(&player.id, ClientState::Done);
+        }
+
+        self.push_lobby_event(
+            &lobby,
+            NetworkEvent::GameEnded {
+                lobby_id: lobby_id.clone(),
+            },
+        );
+
+        self.push_lobby_changed(&lobby);
+
+        Ok(())
+    }
+
+    /// Returns the relay host token of the game of the lobby with the relay session `session`, if
+    /// its game has been started
+    pub fn relay_host_token(&self, session: u64) -> Option<u64> {
+        self.lobbies
+            .iter()
+            .find(|lobby| lobby.relay_session() == session)
+            .and_then(|lobby| self.relay_host_tokens.get(&lobby.id))
+            .copied()
+    }
+
+    /// Returns the lobby that the player `player_id` is in, if any
+    pub fn current_lobby(&self, player_id: &Id) -> Option<&Lobby> {
+        self.lobbies
+            .iter()
+            .find(|lobby| lobby.players.iter().any(|player| player.id == *player_id))
+    }
+
+    /// Remove the player `player_id` from the lobby that it is in, if any, like when it
+    /// disconnects from the service
+    pub fn leave_current_lobby(&mut self, player_id: &Id) -> RegistryResult<()> {
+        let lobby_id = self.current_lobby(player_id).map(|lobby| lobby.id.clone());
+
+        if let Some(lobby_id) = lobby_id {
+            self.leave_lobby(player_id, &lobby_id)?;
+        }
+
+        Ok(())
+    }
+
+    /// Take the events that have been queued for the player `player_id`
+    pub fn take_events(&mut self, player_id: &Id) -> Vec<NetworkEvent> {
+        self.events.remove(player_id).unwrap_or_default()
+    }
+
+    fn set_game_state(
+        &mut self,
+        lobby_id: &Id,
+        from: LobbyState,
+        to: LobbyState,
+    ) -> RegistryResult<()> {
+        let lobby = self.lobby_mut(lobby_id)?;
+
+        if lobby.state != from {
+            return Err(RequestStatus::Conflict);
+        }
+
+        lobby.state = to;
+
+        let lobby = lobby.clone();
+        self.push_lobby_changed(&lobby);
+
+        Ok(())
+    }
+
+    fn check_admin(&self, player_id: &Id, lobby_id: &Id) -> RegistryResult<()> {
+        let lobby = self.get_lobby(lobby_id)?;
+
+        if lobby.admin_player_id != *player_id {
+            return Err(RequestStatus::Forbidden);
+        }
+
+        Ok(())
+    }
+
+    fn lobby_mut(&mut self, id: &Id) -> RegistryResult<&mut Lobby> {
+        self.lobbies
+            .iter_mut()
+            .find(|lobby| lobby.id == *id)
+            .ok_or(RequestStatus::NotFound)
+    }
+
+    fn set_player_state(&mut self, id: &Id, state: ClientState) {
+        if let Some(player) = self.players.iter_mut().find(|player| player.id == *id) {
+            player.state = state;
+        }
+    }
+
+    fn push_event(&mut self, player_id: &Id, event: NetworkEvent) {
+        let events = self.events.entry(player_id.clone()).or_default();
+
+        events.push(event);
+
+        if events.len() > MAX_QUEUED_EVENTS {
+            events.remove(0);
+        }
+    }
+
+    /// Queue `event` for all the players in `lobby`
+    fn push_lobby_event(&mut self, lobby: &Lobby, event: NetworkEvent) {
+        for player in &lobby.players {
+            self.push_event(&player.id, event.clone());
+        }
+    }
+
+    fn push_system_message(&mut self, lobby: &Lobby, text: String) {
+        self.push_lobby_event(lobby, NetworkEvent::SystemMessage { text });
+    }
+
+    fn push_lobby_changed(&mut self, lobby: &Lobby) {
+        self.push_lobby_event(
+            lobby,
+            NetworkEvent::LobbyChanged {
+                lobby: lobby.clone(),
+            },
+        );
+    }
+}
+
+impl Default for LobbyRegistry {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+/// Set the state of `lobby` to `LobbyState::LobbyReady` if all its players are ready, and back
+/// to `LobbyState::NotStarted` if not
+fn update_ready_state(lobby: &mut Lobby) {
+    if !lobby.state.is_joinable() {
+        return;
+    }
+
+    let is_ready = lobby.players.len() > 1
+        && lobby
+            .players
+            .iter()
+            .all(|player| player.state == ClientState::Ready);
+
+    lobby.state = if is_ready {
+        LobbyState::LobbyReady
+    } else {
+        LobbyState::NotStarted
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::CHAT_RATE_LIMIT;
+
+    #[test]
+    fn test_lobby_lifecycle() {
+        let mut registry = LobbyRegistry::default();
+
+        let admin = registry.add_player("admin", "Admin").id;
+        let other = registry.add_player("other", "Other").id;
+
+        let lobby_id = registry
+            .create_lobby(&admin, "Lobby", LobbyPrivacy::Public, 4)
+            .unwrap()
+            .id;
+
+        registry.join_lobby(&other, &lobby_id).unwrap();
+        assert_eq!(
+            registry.list_lobbies(&LobbyFilter::default()).len(),
+            1,
+            "Lobby should be listed"
+        );
+
+        assert_eq!(
+            registry.start_game(&admin, &lobby_id),
+            Err(RequestStatus::Conflict),
+            "Game should not start before all players are ready"
+        );
+
+        registry.set_ready(&admin, &lobby_id, true).unwrap();
+        registry.set_ready(&other, &lobby_id, true).unwrap();
+        assert_eq!(
+            registry.get_lobby(&lobby_id).unwrap().state,
+            LobbyState::LobbyReady
+        );
+
+        assert_eq!(
+            registry.start_game(&other, &lobby_id),
+            Err(RequestStatus::Forbidden),
+            "Only the admin should be able to start the game"
+        );
+
+        registry
+            .update_lobby_settings(&admin, &lobby_id, LobbySettings::default())
+            .unwrap();
+        assert_eq!(
+            registry.get_lobby(&lobby_id).unwrap().state,
+            LobbyState::NotStarted,
+            "Changing the settings should reset the ready state of the other players"
+        );
+
+        registry.take_events(&other);
+
+        registry.kick_player(&admin, &lobby_id, &other).unwrap();
+        assert!(registry.current_lobby(&other).is_none());
+        assert!(registry.take_events(&other).iter().any(
+            |event| matches!(event, NetworkEvent::PlayerKicked { player_id } if *player_id == other)
+        ));
+    }
+
+    #[test]
+    fn test_relay_host_token() {
+        let mut registry = LobbyRegistry::default();
+
+        let admin = registry.add_player("admin", "Admin").id;
+        let other = registry.add_player("other", "Other").id;
+
+        let lobby = registry
+            .create_lobby(&admin, "Lobby", LobbyPrivacy::Public, 2)
+            .unwrap();
+
+        registry.join_lobby(&other, &lobby.id).unwrap();
+        assert_eq!(registry.relay_host_token(lobby.relay_session()), None);
+
+        registry.set_ready(&admin, &lobby.id, true).unwrap();
+        registry.set_ready(&other, &lobby.id, true).unwrap();
+        registry.start_game(&admin, &lobby.id).unwrap();
+
+        let relay_host_token = |events: Vec<NetworkEvent>| {
+            events.into_iter().find_map(|event| match event {
+                NetworkEvent::GameStarted {
+                    relay_host_token, ..
+                } => Some(relay_host_token),
+                _ => None,
+            })
+        };
+
+        let token = relay_host_token(registry.take_events(&admin)).unwrap();
+        assert!(token.is_some(), "The admin should be given the token");
+        assert_eq!(registry.relay_host_token(lobby.relay_session()), token);
+
+        assert_eq!(
+            relay_host_token(registry.take_events(&other)),
+            Some(None),
+            "Only the admin should be given the token"
+        );
+    }
+
+    #[test]
+    fn test_chat() {
+        let mut registry = LobbyRegistry::default();
+
+        let admin = registry.add_player("admin", "Admin").id;
+        let other = registry.add_player("other", "Other").id;
+
+        let lobby_id = registry
+            .create_lobby(&admin, "Lobby", LobbyPrivacy::Public, 2)
+            .unwrap()
+            .id;
+
+        registry.join_lobby(&other, &lobby_id).unwrap();
+        registry.take_events(&admin);
+
+        registry
+            .send_chat_message(&other, &lobby_id, "hello")
+            .unwrap();
+        assert!(registry.take_events(&admin).iter().any(
+            |event| matches!(event, NetworkEvent::ChatMessage { text, .. } if text == "hello")
+        ));
+
+        assert_eq!(
+            registry.send_chat_message(&other, &lobby_id, " \n "),
+            Err(RequestStatus::BadRequest),
+            "Empty messages should be rejected"
+        );
+
+        assert_eq!(
+            registry.set_muted(&other, &lobby_id, &admin, true),
+            Err(RequestStatus::Forbidden),
+            "Only the admin should be able to mute players"
+        );
+
+        registry.set_muted(&admin, &lobby_id, &other, true).unwrap();
+        assert_eq!(
+            registry.send_chat_message(&other, &lobby_id, "hello"),
+            Err(RequestStatus::Forbidden),
+            "Muted players should not be able to send messages"
+        );
+
+        registry
+            .set_muted(&admin, &lobby_id, &other, false)
+            .unwrap();
+
+        // One message has already been sent
+        for _ in 1..CHAT_RATE_LIMIT {
+            registry
+                .send_chat_message(&other, &lobby_id, "spam")
+                .unwrap();
+        }
+
+        assert_eq!(
+            registry.send_chat_message(&other, &lobby_id, "spam"),
+            Err(RequestStatus::TooManyRequests)
+        );
+    }
+}
@@ -0,0 +1,222 @@
+//! The encoding of the datagrams exchanged with a relay server, which forwards the datagrams of
+//! a network game between peers that can not reach each other directly.
+//! Every relay packet starts with `RELAY_MAGIC`, followed by the packet type, as a `u8`, so that
+//! relay packets can be told apart from the messages in `protocol`, which start with the protocol
+//! version. The payload of a packet is not prefixed with its length, as it always runs to the end
+//! of the datagram. Other values are encoded like they are in `protocol`.
+//!
+//! The host of a session sends `RelayPacket::Register` to the relay at an interval, with the host
+//! token of the session, and the other peers send their messages to the host with
+//! `RelayPacket::Send`, which also makes them members of the session. The relay passes payloads on
+//! as `RelayPacket::Forward`, so that the receiver knows which peer sent them.
+//! Latency to the relay is measured with `RelayPacket::Ping`, which the relay answers with a
+//! `RelayPacket::Pong` of the same size.
+
+use std::net::SocketAddr;
+
+use super::protocol::{write_addr, Reader};
+use crate::error::ErrorKind;
+use crate::Result;
+
+/// The bytes that every relay packet starts with
+pub const RELAY_MAGIC: [u8; 4] = *b"FFRL";
+
+/// The maximum amount of bytes that a relay packet adds to the payload that it holds
+pub const MAX_RELAY_OVERHEAD: usize = 40;
+
+const PACKET_TYPE_REGISTER: u8 = 0;
+const PACKET_TYPE_SEND: u8 = 1;
+const PACKET_TYPE_FORWARD: u8 = 2;
+const PACKET_TYPE_PING: u8 = 3;
+const PACKET_TYPE_PONG: u8 = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RelayPacket {
+    /// Register the sender as the host of `session`, if `token` is the host token of the
+    /// session. This replaces any earlier host.
+    Register {
+        session: u64,
+        token: u64,
+    },
+    /// Forward `payload` to the member `to` of `session`, or to the host of `session`, if `to` is
+    /// `None`
+    Send {
+        session: u64,
+        to: Option<SocketAddr>,
+        payload: Vec<u8>,
+    },
+    /// A payload forwarded by the relay, that was sent by `from`
+    Forward {
+        from: SocketAddr,
+        is_from_host: bool,
+        payload: Vec<u8>,
+    },
+    /// Ask the relay for a `RelayPacket::Pong`, holding the same `nonce`
+    Ping {
+        nonce: u64,
+    },
+    Pong {
+        nonce: u64,
+    },
+}
+
+impl RelayPacket {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = RELAY_MAGIC.to_vec();
+
+        match self {
+            RelayPacket::Register { session, token } => {
+                bytes.push(PACKET_TYPE_REGISTER);
+                bytes.extend_from_slice(&session.to_le_bytes());
+                bytes.extend_from_slice(&token.to_le_bytes());
+            }
+            RelayPacket::Send {
+                session,
+                to,
+                payload,
+            } => {
+                bytes.push(PACKET_TYPE_SEND);
+                bytes.extend_from_slice(&session.to_le_bytes());
+
+                match to {
+                    Some(to) => {
+                        bytes.push(1);
+                        write_addr(&mut bytes, to);
+                    }
+                    None => bytes.push(0),
+                }
+
+                bytes.extend_from_slice(payload);
+            }
+            RelayPacket::Forward {
+                from,
+                is_from_host,
+                payload,
+            } => {
+                bytes.push(PACKET_TYPE_FORWARD);
+                write_addr(&mut bytes, from);
+                bytes.push(*is_from_host as u8);
+                bytes.extend_from_slice(payload);
+            }
+            RelayPacket::Ping { nonce } => {
+                bytes.push(PACKET_TYPE_PING);
+                bytes.extend_from_slice(&nonce.to_le_bytes());
+            }
+            RelayPacket::Pong { nonce } => {
+                bytes.push(PACKET_TYPE_PONG);
+                bytes.extend_from_slice(&nonce.to_le_bytes());
+            }
+        }
+
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        if !Self::is_relay_packet(bytes) {
+            return Err(formaterr!(ErrorKind::Network, "Not a relay packet"));
+        }
+
+        let mut reader = Reader {
+            bytes: &bytes[RELAY_MAGIC.len()..],
+        };
+
+        let packet = match reader.read_u8()? {
+            PACKET_TYPE_REGISTER => RelayPacket::Register {
+                session: reader.read_u64()?,
+                token: reader.read_u64()?,
+            },
+            PACKET_TYPE_SEND => {
+                let session = reader.read_u64()?;
+
+                let to = match reader.read_u8()? {
+                    0 => None,
+                    _ => Some(reader.read_addr()?),
+                };
+
+                RelayPacket::Send {
+                    session,
+                    to,
+                    payload: reader.bytes.to_vec(),
+                }
+            }
+            PACKET_TYPE_FORWARD => {
+                let from = reader.read_addr()?;
+                let is_from_host = reader.read_u8()? != 0;
+
+                RelayPacket::Forward {
+                    from,
+                    is_from_host,
+                    payload: reader.bytes.to_vec(),
+                }
+            }
+            PACKET_TYPE_PING => RelayPacket::Ping {
+                nonce: reader.read_u64()?,
+            },
+            PACKET_TYPE_PONG => RelayPacket::Pong {
+                nonce: reader.read_u64()?,
+            },
+            packet_type => {
+                return Err(formaterr!(
+                    ErrorKind::Network,
+                    "Unknown relay packet type '{}'",
+                    packet_type
+                ));
+            }
+        };
+
+        Ok(packet)
+    }
+
+    /// Returns `true` if `bytes` starts with `RELAY_MAGIC`
+    pub fn is_relay_packet(bytes: &[u8]) -> bool {
+        bytes.starts_with(&RELAY_MAGIC)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relay_packet_round_trip() {
+        let packets = [
+            RelayPacket::Register {
+                session: 42,
+                token: 7,
+            },
+            RelayPacket::Send {
+                session: u64::MAX,
+                to: None,
+                payload: vec![5, 0, 2],
+            },
+            RelayPacket::Send {
+                session: 7,
+                to: Some("[::1]:9001".parse().unwrap()),
+                payload: Vec::new(),
+            },
+            RelayPacket::Forward {
+                from: "192.168.1.20:9000".parse().unwrap(),
+                is_from_host: true,
+                payload: vec![1, 2, 3],
+            },
+            RelayPacket::Ping { nonce: 3 },
+            RelayPacket::Pong { nonce: 3 },
+        ];
+
+        for packet in packets {
+            let bytes = packet.encode();
+
+            assert!(bytes.len() <= packet_payload(&packet).len() + MAX_RELAY_OVERHEAD);
+            assert_eq!(RelayPacket::decode(&bytes).unwrap(), packet);
+        }
+    }
+
+    fn packet_payload(packet: &RelayPacket) -> &[u8] {
+        match packet {
+            RelayPacket::Register { .. } | RelayPacket::Ping { .. } | RelayPacket::Pong { .. } => {
+                &[]
+            }
+            RelayPacket::Send { payload, .. } | RelayPacket::Forward { payload, .. } => payload,
+        }
+    }
+}
@@ -6,9 +6,13 @@ use serde::{Deserialize, Serialize};
 #[cfg_attr(feature = "serde_json", serde(rename_all = "snake_case"))]
 pub enum RequestStatus {
     Ok,
+    BadRequest,
     Unauthorized,
+    Forbidden,
     NotFound,
     RequestTimeout,
+    Conflict,
+    TooManyRequests,
     InternalServerError,
     Unknown,
 }
@@ -17,9 +21,13 @@ impl RequestStatus {
     pub fn as_code(&self) -> u16 {
         match *self {
             RequestStatus::Ok => 200,
+            RequestStatus::BadRequest => 400,
             RequestStatus::Unauthorized => 401,
+            RequestStatus::Forbidden => 403,
             RequestStatus::NotFound => 404,
             RequestStatus::RequestTimeout => 408,
+            RequestStatus::Conflict => 409,
+            RequestStatus::TooManyRequests => 429,
             RequestStatus::InternalServerError => 500,
             RequestStatus::Unknown => 0,
         }
@@ -28,9 +36,13 @@ impl RequestStatus {
     pub fn as_str(&self) -> &'static str {
         match *self {
             RequestStatus::Ok => "ok",
+            RequestStatus::BadRequest => "bad request",
             RequestStatus::Unauthorized => "unauthorized",
+            RequestStatus::Forbidden => "forbidden",
             RequestStatus::NotFound => "not found",
             RequestStatus::RequestTimeout => "request timeout",
+            RequestStatus::Conflict => "conflict",
+            RequestStatus::TooManyRequests => "too many requests",
             RequestStatus::InternalServerError => "internal server error",
             RequestStatus::Unknown => "unknown",
         }
@@ -41,9 +53,13 @@ impl From<u16> for RequestStatus {
     fn from(code: u16) -> Self {
         match code {
             200 => RequestStatus::Ok,
+            400 => RequestStatus::BadRequest,
             401 => RequestStatus::Unauthorized,
+            403 => RequestStatus::Forbidden,
             404 => RequestStatus::NotFound,
             408 => RequestStatus::RequestTimeout,
+            409 => RequestStatus::Conflict,
+            429 => RequestStatus::TooManyRequests,
             500 => RequestStatus::InternalServerError,
             _ => RequestStatus::Unknown,
         }
@@ -0,0 +1,148 @@
+//! This implements a small, seedable pseudo random number generator (PCG32), that should be used
+//! by all gameplay code, in stead of the global generator in `macroquad::rand`.
+//! As the output of a `Rng` only depends on its seed and on the number of values drawn from it,
+//! two peers, or a replay, that start from the same seed will produce identical outcomes.
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+const MULTIPLIER: u64 = 6364136223846793005;
+const DEFAULT_STREAM: u64 = 1442695040888963407;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Rng {
+    state: u64,
+    inc: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        let mut rng = Rng {
+            state: 0,
+            inc: DEFAULT_STREAM | 1,
+        };
+
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+
+        rng
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old_state = self.state;
+
+        self.state = old_state.wrapping_mul(MULTIPLIER).wrapping_add(self.inc);
+
+        let xor_shifted = (((old_state >> 18) ^ old_state) >> 27) as u32;
+        let rot = (old_state >> 59) as u32;
+
+        xor_shifted.rotate_right(rot)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        ((self.next_u32() as u64) << 32) | self.next_u32() as u64
+    }
+
+    /// Returns a value in the range `0.0..1.0`
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / (1u32 << 24) as f32
+    }
+
+    /// Returns a value in the range `low..high`. If `high` is not greater than `low`, `low` is
+    /// returned.
+    pub fn gen_range<T: RandomRange>(&mut self, low: T, high: T) -> T {
+        T::gen_range(self, low, high)
+    }
+}
+
+impl Default for Rng {
+    fn default() -> Self {
+        Rng::new(0)
+    }
+}
+
+/// This is implemented for types that can be passed to `Rng::gen_range`
+pub trait RandomRange {
+    fn gen_range(rng: &mut Rng, low: Self, high: Self) -> Self;
+}
+
+impl RandomRange for f32 {
+    fn gen_range(rng: &mut Rng, low: Self, high: Self) -> Self {
+        if high <= low {
+            return low;
+        }
+
+        low + (high - low) * rng.next_f32()
+    }
+}
+
+impl RandomRange for u32 {
+    fn gen_range(rng: &mut Rng, low: Self, high: Self) -> Self {
+        if high <= low {
+            return low;
+        }
+
+        let range = (high - low) as u64;
+        low + ((rng.next_u32() as u64 * range) >> 32) as u32
+    }
+}
+
+impl RandomRange for i32 {
+    fn gen_range(rng: &mut Rng, low: Self, high: Self) -> Self {
+        if high <= low {
+            return low;
+        }
+
+        let range = (high as i64 - low as i64) as u64;
+        (low as i64 + ((rng.next_u32() as u64 * range) >> 32) as i64) as i32
+    }
+}
+
+impl RandomRange for usize {
+    fn gen_range(rng: &mut Rng, low: Self, high: Self) -> Self {
+        if high <= low {
+            return low;
+        }
+
+        let range = (high - low) as u128;
+        low + ((rng.next_u64() as u128 * range) >> 64) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rng_same_seed_same_sequence() {
+        let mut a = Rng::new(1234);
+        let mut b = Rng::new(1234);
+
+        for _ in 0..100 {
+            assert_eq!(a.next_u32(), b.next_u32());
+        }
+
+        let mut c = Rng::new(4321);
+        assert_ne!(a.next_u64(), c.next_u64());
+    }
+
+    #[test]
+    fn test_rng_gen_range_bounds() {
+        let mut rng = Rng::new(42);
+
+        for _ in 0..1000 {
+            let f = rng.gen_range(-1.5f32, 1.5);
+            assert!((-1.5..1.5).contains(&f));
+
+            let i = rng.gen_range(-3i32, 3);
+            assert!((-3..3).contains(&i));
+
+            let u = rng.gen_range(2usize, 5);
+            assert!((2..5).contains(&u));
+        }
+
+        assert_eq!(rng.gen_range(7u32, 7), 7);
+    }
+}
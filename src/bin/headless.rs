@@ -0,0 +1,359 @@
+//! This runs a match without a window, for the specified amount of ticks, and prints the state of
+//! all players when done. No input is applied, unless a replay is played back, so, in that case,
+//! this mostly serves as a smoke test for the simulation.
+//!
+//! Usage: `fishfight-headless [--map <name>] [--players <count>] [--ticks <count>] [--seed <seed>]
+//! [--replay <path>] [--record <path>] [--host <port> | --join <address>] [--rollback <ticks>]
+//! [--relay <address>] [--trace <path>]`
+//!
+//! If `--replay` is specified, the map, players and seed are read from the replay, and it is
+//! played back for as many ticks as were recorded, unless `--ticks` is specified.
+//! If `--record` is specified, a replay of the match is saved to that path when done.
+//! If `--trace` is specified, the time that every system takes is recorded, and saved as a Chrome
+//! trace, to that path, when done.
+//!
+//! If `--host` or `--join` is specified, a two player network game is played, where the host
+//! controls the first player and the client the second. The local player is fed scripted input,
+//! derived from the seed, so running a host and a client on loopback, with the same arguments,
+//! should print identical player states and state checksums, if the simulation stays in sync:
+//!
+//! `fishfight-headless --seed 1 --host 9000` and `fishfight-headless --seed 1 --join 127.0.0.1:9000`
+//!
+//! If `--rollback` is specified, rollback is used, in stead of delayed lockstep, with the specified
+//! maximum amount of ticks to roll back. Clients adopt the settings of the host, so this only has
+//! an effect when hosting.
+//!
+//! If `--relay` is specified, the peers use the relay server at that address. The client sends
+//! everything through the relay, so a relay run by `fishfight-relay-server` can be tested locally:
+//!
+//! `fishfight-relay-server --port 9100`, `fishfight-headless --seed 1 --host 9000 --relay
+//! 127.0.0.1:9100` and `fishfight-headless --seed 1 --join 127.0.0.1:9000 --relay 127.0.0.1:9100`
+
+use std::env;
+use std::net::SocketAddr;
+use std::thread;
+use std::time::Duration;
+
+use macroquad::experimental::collections::storage;
+
+use fishfight::game::{
+    checksum, init_headless, simulation_state, GameMode, HeadlessGame, MatchSettings, NetcodeMode,
+    Replay,
+};
+use fishfight::network::RelayConfig;
+use fishfight::player::{Player, PlayerControllerKind, PlayerParams};
+use fishfight::profiler;
+use fishfight::{GameInput, Resources, Transform, ASSETS_DIR_ENV_VAR, MODS_DIR_ENV_VAR};
+
+use core::error::ErrorKind;
+use core::rng::Rng;
+use core::{formaterr, Id, Result};
+
+const DEFAULT_PLAYER_CNT: u8 = 2;
+const DEFAULT_TICK_CNT: u64 = 60 * 60;
+
+/// The amount of ticks that scripted input is held for, in a network game
+const SCRIPTED_INPUT_INTERVAL: u64 = 20;
+
+/// The id of the session on the relay, if `--relay` is specified
+const RELAY_SESSION: u64 = 0;
+
+/// The token that the host registers the session with, if `--relay` is specified. A relay that
+/// runs on its own is claimed by the first host to register a session, so this only has to be the
+/// same for every run.
+const RELAY_HOST_TOKEN: u64 = 0;
+
+struct Args {
+    map: Option<String>,
+    player_cnt: u8,
+    tick_cnt: Option<u64>,
+    seed: u64,
+    replay: Option<String>,
+    record: Option<String>,
+    trace: Option<String>,
+    mode: GameMode,
+    max_rollback_ticks: Option<u64>,
+    relay: Option<SocketAddr>,
+}
+
+impl Args {
+    fn parse() -> Result<Self> {
+        let mut res = Args {
+            map: None,
+            player_cnt: DEFAULT_PLAYER_CNT,
+            tick_cnt: None,
+            seed: 0,
+            replay: None,
+            record: None,
+            trace: None,
+            mode: GameMode::Local,
+            max_rollback_ticks: None,
+            relay: None,
+        };
+
+        let mut args = env::args().skip(1);
+
+        while let Some(arg) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| formaterr!(ErrorKind::Input, "Missing value for '{}'", &arg))?;
+
+            match arg.as_str() {
+                "--map" => res.map = Some(value),
+                "--players" => {
+                    res.player_cnt = value.parse().map_err(|_| {
+                        formaterr!(ErrorKind::Input, "Invalid player count '{}'", &value)
+                    })?
+                }
+                "--ticks" => {
+                    let tick_cnt = value.parse().map_err(|_| {
+                        formaterr!(ErrorKind::Input, "Invalid tick count '{}'", &value)
+                    })?;
+
+                    res.tick_cnt = Some(tick_cnt);
+                }
+                "--seed" => {
+                    res.seed = value
+                        .parse()
+                        .map_err(|_| formaterr!(ErrorKind::Input, "Invalid seed '{}'", &value))?
+                }
+                "--replay" => res.replay = Some(value),
+                "--record" => res.record = Some(value),
+                "--trace" => res.trace = Some(value),
+                "--host" => {
+                    let port = value
+                        .parse()
+                        .map_err(|_| formaterr!(ErrorKind::Input, "Invalid port '{}'", &value))?;
+
+                    res.mode = GameMode::NetworkHost { port, relay: None };
+                }
+                "--join" => {
+                    let host = value.parse().map_err(|_| {
+                        formaterr!(ErrorKind::Input, "Invalid address '{}'", &value)
+                    })?;
+
+                    res.mode = GameMode::NetworkClient { host, relay: None };
+                }
+                "--rollback" => {
+                    let max_rollback_ticks = value.parse().map_err(|_| {
+                        formaterr!(ErrorKind::Input, "Invalid tick count '{}'", &value)
+                    })?;
+
+                    res.max_rollback_ticks = Some(max_rollback_ticks);
+                }
+                "--relay" => {
+                    let server = value.parse().map_err(|_| {
+                        formaterr!(ErrorKind::Input, "Invalid address '{}'", &value)
+                    })?;
+
+                    res.relay = Some(server);
+                }
+                _ => return Err(formaterr!(ErrorKind::Input, "Unknown argument '{}'", &arg)),
+            }
+        }
+
+        if let Some(server) = res.relay {
+            let config = RelayConfig {
+                server,
+                session: RELAY_SESSION,
+                is_forced: true,
+                host_token: None,
+            };
+
+            match &mut res.mode {
+                GameMode::NetworkHost { relay, .. } => {
+                    *relay = Some(RelayConfig {
+                        host_token: Some(RELAY_HOST_TOKEN),
+                        ..config
+                    })
+                }
+                GameMode::NetworkClient { relay, .. } => *relay = Some(config),
+                _ => {
+                    return Err(formaterr!(
+                        ErrorKind::Input,
+                        "A relay can only be used by network games"
+                    ))
+                }
+            }
+        }
+
+        Ok(res)
+    }
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse()?;
+
+    let assets_dir = env::var(ASSETS_DIR_ENV_VAR).unwrap_or_else(|_| "./assets".to_string());
+    let mods_dir = env::var(MODS_DIR_ENV_VAR).unwrap_or_else(|_| "./mods".to_string());
+
+    init_headless(&assets_dir, &mods_dir)?;
+
+    let local_player = match args.mode {
+        // Spectating is not supported by the headless runner
+        GameMode::Local | GameMode::NetworkSpectator { .. } => None,
+        GameMode::NetworkHost { .. } => Some(0),
+        GameMode::NetworkClient { .. } => Some(1),
+    };
+
+    if local_player.is_some() && (args.player_cnt != 2 || args.replay.is_some()) {
+        return Err(formaterr!(
+            ErrorKind::Input,
+            "Network games must have two players and can not play back a replay"
+        ));
+    }
+
+    if args.trace.is_some() {
+        profiler::start_trace();
+    }
+
+    let (mut game, tick_cnt) = if let Some(path) = &args.replay {
+        let replay = Replay::load(path)?;
+        let tick_cnt = args.tick_cnt.unwrap_or(replay.tick_cnt);
+
+        (HeadlessGame::from_replay(replay)?, tick_cnt)
+    } else {
+        let (map, player_params) = {
+            let resources = storage::get::<Resources>();
+
+            let map_resource = match &args.map {
+                Some(name) => resources.maps.iter().find(|res| res.meta.name == *name),
+                None => resources.maps.first(),
+            }
+            .ok_or_else(|| formaterr!(ErrorKind::General, "Unable to find the requested map"))?;
+
+            let mut character_ids = resources.player_characters.keys().collect::<Vec<_>>();
+            character_ids.sort();
+
+            if character_ids.is_empty() {
+                return Err(formaterr!(
+                    ErrorKind::General,
+                    "No player characters loaded"
+                ));
+            }
+
+            let player_params = (0..args.player_cnt)
+                .map(|index| {
+                    let id = character_ids[index as usize % character_ids.len()];
+
+                    let controller = match local_player {
+                        Some(local_player) if local_player != index => {
+                            PlayerControllerKind::Network(Id::from(index.to_string()))
+                        }
+                        _ => PlayerControllerKind::External,
+                    };
+
+                    PlayerParams {
+                        index,
+                        controller,
+                        character: resources.player_characters.get(id).cloned().unwrap(),
+                    }
+                })
+                .collect::<Vec<_>>();
+
+            (map_resource.map.clone(), player_params)
+        };
+
+        let mut settings = MatchSettings::new(args.seed);
+
+        if let Some(max_rollback_ticks) = args.max_rollback_ticks {
+            settings.netcode = NetcodeMode::Rollback;
+            settings.max_rollback_ticks = max_rollback_ticks;
+        }
+
+        let game = HeadlessGame::with_mode(args.mode.clone(), map, &player_params, settings)?;
+
+        (game, args.tick_cnt.unwrap_or(DEFAULT_TICK_CNT))
+    };
+
+    if let Some(local_player) = local_player {
+        let mut rng = Rng::new(args.seed.wrapping_add(local_player as u64));
+        let mut input = GameInput::default();
+
+        while game.tick() < tick_cnt {
+            if game.tick() % SCRIPTED_INPUT_INTERVAL == 0 {
+                input = random_input(&mut rng);
+            }
+
+            let is_simulated = game.step(&[(local_player, input)]);
+
+            // The game continues without the players of the remote peer, once it has
+            // disconnected, but the run is only meaningful with both peers
+            let network = game.network().unwrap();
+
+            if network.is_disconnected() {
+                let reason = network
+                    .disconnect_reason()
+                    .map(|reason| reason.as_str())
+                    .unwrap_or("timed out");
+
+                return Err(formaterr!(
+                    ErrorKind::Network,
+                    "Remote peer disconnected at tick {}: {}",
+                    game.tick(),
+                    reason
+                ));
+            }
+
+            if !is_simulated {
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+
+        game.flush_network()?;
+
+        let network = game.network().unwrap();
+
+        println!(
+            "Input delay: {} ticks, stalled ticks: {}, rollbacks: {}",
+            network.input_delay(),
+            network.stalled_tick_cnt(),
+            game.rollback_cnt(),
+        );
+    } else {
+        for _ in 0..tick_cnt {
+            game.step(&[]);
+        }
+    }
+
+    if let Some(path) = &args.record {
+        game.replay().save(path)?;
+    }
+
+    if let Some(path) = &args.trace {
+        profiler::save_trace(path)?;
+    }
+
+    println!("Simulated {} ticks", game.tick());
+
+    let state = simulation_state(&game.snapshot())?;
+    println!("State checksum: {:016x}", checksum(&state));
+
+    for &entity in game.players() {
+        let player = game.world().get::<Player>(entity).unwrap();
+        let transform = game.world().get::<Transform>(entity).unwrap();
+
+        println!(
+            "Player {}: position: ({:.2}, {:.2}), state: {:?}",
+            player.index, transform.position.x, transform.position.y, player.state,
+        );
+    }
+
+    Ok(())
+}
+
+fn random_input(rng: &mut Rng) -> GameInput {
+    let bits = rng.next_u32();
+    let is_set = |i: u32| bits & (1 << i) != 0;
+
+    GameInput {
+        left: is_set(0),
+        right: is_set(1),
+        down: is_set(2) && is_set(3),
+        jump: is_set(4),
+        float: is_set(5),
+        pickup: is_set(6),
+        fire: is_set(7),
+        slide: is_set(8) && is_set(9),
+    }
+}
@@ -0,0 +1,72 @@
+//! This runs a lobby service, that the game can use for online lobbies, when its address is set
+//! as `lobby_server` in the config file.
+//!
+//! Usage: `fishfight-lobby-server [--address <ip>] [--port <port>]`
+//!
+//! The HTTP API is bound to the specified port, which defaults to `DEFAULT_LOBBY_PORT`, and the
+//! TCP event stream and the UDP socket to the two following ports. The address defaults to the
+//! loopback address, so the service has to be bound to `0.0.0.0` to be reachable from other hosts.
+
+use std::env;
+use std::net::{IpAddr, Ipv4Addr};
+
+use fishfight::network::LobbyServer;
+
+use core::error::ErrorKind;
+use core::network::DEFAULT_LOBBY_PORT;
+use core::{formaterr, Result};
+
+struct Args {
+    address: IpAddr,
+    port: u16,
+}
+
+impl Args {
+    fn parse() -> Result<Self> {
+        let mut res = Args {
+            address: Ipv4Addr::LOCALHOST.into(),
+            port: DEFAULT_LOBBY_PORT,
+        };
+
+        let mut args = env::args().skip(1);
+
+        while let Some(arg) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| formaterr!(ErrorKind::Input, "Missing value for '{}'", &arg))?;
+
+            match arg.as_str() {
+                "--address" => {
+                    res.address = value
+                        .parse()
+                        .map_err(|_| formaterr!(ErrorKind::Input, "Invalid address '{}'", &value))?
+                }
+                "--port" => {
+                    res.port = value
+                        .parse()
+                        .map_err(|_| formaterr!(ErrorKind::Input, "Invalid port '{}'", &value))?
+                }
+                _ => return Err(formaterr!(ErrorKind::Input, "Unknown argument '{}'", &arg)),
+            }
+        }
+
+        Ok(res)
+    }
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse()?;
+
+    let server = LobbyServer::bind(args.address, args.port)?;
+
+    {
+        let addresses = server.server();
+
+        println!("Lobby service running");
+        println!("HTTP: {}", addresses.http);
+        println!("TCP:  {}", addresses.tcp);
+        println!("UDP:  {}", addresses.udp);
+    }
+
+    server.run()
+}
@@ -0,0 +1,67 @@
+//! This runs a relay server, that forwards the datagrams of network games between peers that can
+//! not reach each other directly. The lobby service runs a relay on its UDP socket, so this is only
+//! needed when the games of online lobbies should use another relay, set as `relay_server` in the
+//! config file, or to test the relay locally, with `fishfight-headless`.
+//!
+//! Usage: `fishfight-relay-server [--address <ip>] [--port <port>]`
+//!
+//! The port defaults to `DEFAULT_RELAY_PORT`. The address defaults to the loopback address, so the
+//! relay has to be bound to `0.0.0.0` to be reachable from other hosts.
+
+use std::env;
+use std::net::{IpAddr, Ipv4Addr};
+
+use fishfight::network::RelayServer;
+
+use core::error::ErrorKind;
+use core::{formaterr, Result};
+
+const DEFAULT_RELAY_PORT: u16 = 9100;
+
+struct Args {
+    address: IpAddr,
+    port: u16,
+}
+
+impl Args {
+    fn parse() -> Result<Self> {
+        let mut res = Args {
+            address: Ipv4Addr::LOCALHOST.into(),
+            port: DEFAULT_RELAY_PORT,
+        };
+
+        let mut args = env::args().skip(1);
+
+        while let Some(arg) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| formaterr!(ErrorKind::Input, "Missing value for '{}'", &arg))?;
+
+            match arg.as_str() {
+                "--address" => {
+                    res.address = value
+                        .parse()
+                        .map_err(|_| formaterr!(ErrorKind::Input, "Invalid address '{}'", &value))?
+                }
+                "--port" => {
+                    res.port = value
+                        .parse()
+                        .map_err(|_| formaterr!(ErrorKind::Input, "Invalid port '{}'", &value))?
+                }
+                _ => return Err(formaterr!(ErrorKind::Input, "Unknown argument '{}'", &arg)),
+            }
+        }
+
+        Ok(res)
+    }
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse()?;
+
+    let server = RelayServer::bind(args.address, args.port)?;
+
+    println!("Relay running on {}", server.local_addr()?);
+
+    server.run()
+}
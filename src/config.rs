@@ -1,15 +1,34 @@
 use std::fs;
+use std::net::SocketAddr;
 use std::path::Path;
 
 use serde::{Deserialize, Serialize};
 
 use core::Error;
 
+use crate::json;
+
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Config {
     pub fullscreen: bool,
     pub high_dpi: bool,
     pub resolution: Resolution,
+    /// The address of the HTTP API of the lobby service to use, like one run by
+    /// `fishfight-lobby-server`. If this is not set, online lobbies are not available.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lobby_server: Option<SocketAddr>,
+    /// The token used to authenticate with the lobby service. A token is generated on startup,
+    /// if this is not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lobby_token: Option<String>,
+    /// The address of the relay server used by the games of online lobbies, like one run by
+    /// `fishfight-relay-server`. If this is not set, the UDP socket of the lobby service is used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub relay_server: Option<SocketAddr>,
+    /// If this is set, online games are always played through the relay, in stead of only when
+    /// the host can not be reached directly
+    #[serde(default, skip_serializing_if = "json::is_false")]
+    pub force_relay: bool,
 }
 
 impl Config {
@@ -11,9 +11,10 @@ use hecs::World;
 
 use serde::{Deserialize, Serialize};
 
+use crate::ecs::RunContext;
 use crate::{json, Drawable, DrawableKind, Resources, Transform};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Animation {
     pub id: String,
     pub row: u32,
@@ -73,20 +74,28 @@ impl From<AnimatedSpriteMetadata> for AnimatedSpriteParams {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub enum QueuedAnimationAction {
     Play(String),
     PlayIndex(usize),
     Deactivate,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct AnimatedSprite {
+    pub texture_id: String,
+    /// This is resolved from `texture_id`. It is not serialized, so it must be restored by
+    /// calling `AnimatedSprite::resolve_texture`, after deserialization.
+    #[serde(skip, default = "Texture2D::empty")]
     pub texture: Texture2D,
+    #[serde(with = "json::vec2_def")]
     pub frame_size: Vec2,
     pub scale: f32,
+    #[serde(with = "json::vec2_def")]
     pub offset: Vec2,
+    #[serde(with = "json::vec2_opt")]
     pub pivot: Option<Vec2>,
+    #[serde(with = "json::ColorDef")]
     pub tint: Color,
     pub animations: Vec<Animation>,
     pub current_index: usize,
@@ -131,6 +140,7 @@ impl AnimatedSprite {
             .unwrap_or_else(|| texture_res.frame_size());
 
         AnimatedSprite {
+            texture_id: texture_id.to_string(),
             texture: texture_res.texture,
             frame_size,
             animations,
@@ -149,6 +159,16 @@ impl AnimatedSprite {
         }
     }
 
+    pub fn resolve_texture(&mut self) {
+        let resources = storage::get::<Resources>();
+        let texture_res = resources
+            .textures
+            .get(&self.texture_id)
+            .unwrap_or_else(|| panic!("AnimatedSprite: Invalid texture ID '{}'", &self.texture_id));
+
+        self.texture = texture_res.texture;
+    }
+
     pub fn get_animation(&self, id: &str) -> Option<&Animation> {
         self.animations.iter().find(|&a| a.id == *id)
     }
@@ -206,16 +226,16 @@ impl AnimatedSprite {
     }
 }
 
-pub fn update_animated_sprites(world: &mut World) {
-    for (_, drawable) in world.query_mut::<&mut Drawable>() {
+pub fn update_animated_sprites(world: &World, context: &RunContext) {
+    for (_, drawable) in world.query::<&mut Drawable>().iter() {
         match drawable.kind.borrow_mut() {
             DrawableKind::AnimatedSprite(sprite) => {
-                update_one_animated_sprite(sprite);
+                update_one_animated_sprite(sprite, context.frame_time);
             }
             DrawableKind::AnimatedSpriteSet(sprite_set) => {
                 for key in &sprite_set.draw_order {
                     let sprite = sprite_set.map.get_mut(key).unwrap();
-                    update_one_animated_sprite(sprite);
+                    update_one_animated_sprite(sprite, context.frame_time);
                 }
             }
             _ => {}
@@ -223,7 +243,7 @@ pub fn update_animated_sprites(world: &mut World) {
     }
 }
 
-pub fn update_one_animated_sprite(sprite: &mut AnimatedSprite) {
+pub fn update_one_animated_sprite(sprite: &mut AnimatedSprite, delta_time: f32) {
     if !sprite.is_deactivated && sprite.is_playing {
         let (is_last_frame, is_looping) = {
             let animation = sprite.animations.get(sprite.current_index).unwrap();
@@ -257,7 +277,7 @@ pub fn update_one_animated_sprite(sprite: &mut AnimatedSprite) {
         };
 
         if sprite.is_playing {
-            sprite.frame_timer += get_frame_time();
+            sprite.frame_timer += delta_time;
 
             if sprite.frame_timer > 1.0 / fps as f32 {
                 sprite.current_frame += 1;
@@ -299,7 +319,7 @@ pub fn debug_draw_one_animated_sprite(position: Vec2, sprite: &AnimatedSprite) {
     }
 }
 
-#[derive(Default)]
+#[derive(Default, Clone, Serialize, Deserialize)]
 pub struct AnimatedSpriteSet {
     pub draw_order: Vec<String>,
     pub map: HashMap<String, AnimatedSprite>,
@@ -9,10 +9,13 @@ use macroquad::prelude::*;
 
 use hecs::World;
 
+use serde::{Deserialize, Serialize};
+
 use crate::Transform;
 
 /// This is a wrapper type for all the different types of drawable sprites, used so that we can
 /// access them all in one query and draw them, ordered, in one pass, according to `draw_order`.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Drawable {
     /// This is used to specify draw order on a sprite
     /// This will be used, primarily, by `Player` to draw equipped items in the right order, relative
@@ -65,6 +68,25 @@ impl Drawable {
         }
     }
 
+    /// Resolve the textures of all sprites, from their texture ids.
+    /// This must be called after a `Drawable` has been deserialized.
+    pub fn resolve_textures(&mut self) {
+        match self.kind.borrow_mut() {
+            DrawableKind::Sprite(sprite) => sprite.resolve_texture(),
+            DrawableKind::SpriteSet(sprite_set) => {
+                for sprite in sprite_set.map.values_mut() {
+                    sprite.resolve_texture();
+                }
+            }
+            DrawableKind::AnimatedSprite(sprite) => sprite.resolve_texture(),
+            DrawableKind::AnimatedSpriteSet(sprite_set) => {
+                for sprite in sprite_set.map.values_mut() {
+                    sprite.resolve_texture();
+                }
+            }
+        }
+    }
+
     pub fn get_sprite(&self) -> Option<&Sprite> {
         match self.kind.borrow() {
             DrawableKind::Sprite(sprite) => Some(sprite),
@@ -122,6 +144,7 @@ impl Drawable {
     }
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub enum DrawableKind {
     Sprite(Sprite),
     SpriteSet(SpriteSet),
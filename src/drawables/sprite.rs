@@ -71,13 +71,21 @@ impl Default for SpriteMetadata {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Sprite {
+    pub texture_id: String,
+    /// This is resolved from `texture_id`. It is not serialized, so it must be restored by
+    /// calling `Sprite::resolve_texture`, after deserialization.
+    #[serde(skip, default = "Texture2D::empty")]
     pub texture: Texture2D,
+    #[serde(with = "json::RectDef")]
     pub source_rect: Rect,
+    #[serde(with = "json::ColorDef")]
     pub tint: Color,
     pub scale: f32,
+    #[serde(with = "json::vec2_def")]
     pub offset: Vec2,
+    #[serde(with = "json::vec2_opt")]
     pub pivot: Option<Vec2>,
     pub is_flipped_x: bool,
     pub is_flipped_y: bool,
@@ -123,6 +131,7 @@ impl Sprite {
         let tint = params.tint.unwrap_or(color::WHITE);
 
         Sprite {
+            texture_id: texture_id.to_string(),
             texture: texture_res.texture,
             source_rect,
             tint,
@@ -139,6 +148,16 @@ impl Sprite {
         self.source_rect.size() * self.scale
     }
 
+    pub fn resolve_texture(&mut self) {
+        let resources = storage::get::<Resources>();
+        let texture_res = resources
+            .textures
+            .get(&self.texture_id)
+            .unwrap_or_else(|| panic!("Sprite: Invalid texture ID '{}'", &self.texture_id));
+
+        self.texture = texture_res.texture;
+    }
+
     pub fn set_scale(&mut self, scale: f32) {
         self.scale = scale;
     }
@@ -226,7 +245,7 @@ pub fn debug_draw_one_sprite(position: Vec2, sprite: &Sprite) {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SpriteSet {
     pub draw_order: Vec<String>,
     pub map: HashMap<String, Sprite>,
@@ -1,51 +1,440 @@
-use hecs::{Entity, World};
+use std::any::{self, TypeId};
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use std::thread;
+
+use hecs::{Component, Entity, World};
+
+use serde::{Deserialize, Serialize};
+
+use crate::profiler::{self, ExecutionProfile, SchedulerProfile, SystemTiming};
 
 pub type SystemFn = fn(&mut World);
 
+/// A system that can be executed in parallel with other systems, on a worker thread
+pub type ParallelSystemFn = fn(&World, &RunContext);
+
 /// This is used as a component to signify ownership
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub struct Owner(pub Entity);
 
-/// Placeholder until we implement threading
-#[derive(Default)]
+impl MapEntities for Owner {
+    fn map_entities(&mut self, entity_map: &EntityMap) {
+        entity_map.map(&mut self.0);
+    }
+}
+
+/// This maps entities from one world to entities in another world, like when a world is restored
+/// from a snapshot, where the entities of the restored world will have new handles.
+#[derive(Debug, Default, Clone)]
+pub struct EntityMap {
+    map: HashMap<Entity, Entity>,
+}
+
+impl EntityMap {
+    pub fn new() -> Self {
+        EntityMap {
+            map: HashMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, from: Entity, to: Entity) {
+        self.map.insert(from, to);
+    }
+
+    pub fn get(&self, entity: Entity) -> Option<Entity> {
+        self.map.get(&entity).copied()
+    }
+
+    /// Map `entity` in place. If `entity` is not in the map, it is left unchanged.
+    pub fn map(&self, entity: &mut Entity) {
+        if let Some(mapped) = self.get(*entity) {
+            *entity = mapped;
+        }
+    }
+
+    /// Map an optional entity in place. If the entity is not in the map, it is set to `None`, as
+    /// it no longer exists.
+    pub fn map_opt(&self, entity: &mut Option<Entity>) {
+        *entity = entity.and_then(|entity| self.get(entity));
+    }
+}
+
+/// This is implemented by components that hold handles to other entities, so that these can be
+/// remapped with an `EntityMap`.
+pub trait MapEntities {
+    fn map_entities(&mut self, entity_map: &EntityMap);
+}
+
+/// The stages that the systems of a `Scheduler` are grouped in. Stages are executed in the order
+/// that they are declared in, and systems are only ordered by their constraints relative to other
+/// systems in the same stage.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Stage {
+    /// Systems that apply input, before it is simulated
+    Input,
+    /// The systems that advance the simulation
+    Simulation,
+    /// Systems that react to the simulated state
+    PostSimulation,
+    /// Systems that present the state, like animations and drawing
+    Presentation,
+}
+
+/// A label that systems can be given, so that other systems can be ordered before, or after,
+/// them. Any number of systems can share a label.
+pub type SystemLabel = &'static str;
+
+/// The state that run conditions are checked against, when a `Scheduler` is executed. This is
+/// also passed to parallel systems.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct RunContext {
+    /// The game is paused, like when the game menu is open in a local game
+    pub is_paused: bool,
+    /// This is the authority of the game, which is any local game, or the host of a network game
+    pub is_authority: bool,
+    /// The game runs without a window
+    pub is_headless: bool,
+    /// The time, in seconds, that the last frame took. Parallel systems must use this, in stead of
+    /// `get_frame_time`, as macroquad can only be accessed from the main thread.
+    pub frame_time: f32,
+}
+
+/// A condition that has to be met for a system to run, which is checked every time that its
+/// scheduler is executed
+#[derive(Debug, Copy, Clone)]
+pub enum RunCondition {
+    NotPaused,
+    /// All peers of a network game run the complete simulation, so this should not be used for
+    /// systems that change the simulated state
+    Authority,
+    /// Only in debug builds
+    Debug,
+    NotHeadless,
+    /// Only when the function returns `true`
+    If(fn() -> bool),
+}
+
+impl RunCondition {
+    pub fn is_met(&self, context: &RunContext) -> bool {
+        match self {
+            RunCondition::NotPaused => !context.is_paused,
+            RunCondition::Authority => context.is_authority,
+            RunCondition::Debug => cfg!(debug_assertions),
+            RunCondition::NotHeadless => !context.is_headless,
+            RunCondition::If(f) => f(),
+        }
+    }
+}
+
+/// The components that a parallel system reads and writes. Systems whose access does not
+/// conflict can be executed at the same time. hecs checks borrows at runtime, so a system that
+/// accesses components that it did not declare will panic, in stead of causing a data race.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Access {
+    reads: Vec<TypeId>,
+    writes: Vec<TypeId>,
+}
+
+impl Access {
+    pub fn new() -> Self {
+        Access {
+            reads: Vec::new(),
+            writes: Vec::new(),
+        }
+    }
+
+    #[must_use]
+    pub fn read<T: Component>(mut self) -> Self {
+        self.reads.push(TypeId::of::<T>());
+        self
+    }
+
+    #[must_use]
+    pub fn write<T: Component>(mut self) -> Self {
+        self.writes.push(TypeId::of::<T>());
+        self
+    }
+
+    /// Returns `true` if a system with this access can not be executed at the same time as a
+    /// system with the `other` access
+    pub fn is_conflicting(&self, other: &Access) -> bool {
+        self.writes
+            .iter()
+            .any(|id| other.reads.contains(id) || other.writes.contains(id))
+            || other.writes.iter().any(|id| self.reads.contains(id))
+    }
+}
+
+type BoxedParallelSystem = Box<dyn Fn(&World, &RunContext) + Send + Sync>;
+
+type BoxedThreadLocalSystem = Box<dyn Fn(&mut World, &RunContext)>;
+
+enum System {
+    Parallel(BoxedParallelSystem),
+    ThreadLocal(BoxedThreadLocalSystem),
+}
+
+/// A system, with the stage, labels, ordering constraints, run conditions and component access
+/// that it is added to a `Scheduler` with. This is created through the methods of
+/// `IntoSystemDescriptor`.
+pub struct SystemDescriptor {
+    system: System,
+    /// The name that the system is shown by, in profiles
+    name: &'static str,
+    /// If this is `None`, the default stage of the scheduler is used
+    stage: Option<Stage>,
+    labels: Vec<SystemLabel>,
+    before: Vec<SystemLabel>,
+    after: Vec<SystemLabel>,
+    conditions: Vec<RunCondition>,
+    access: Access,
+}
+
+impl SystemDescriptor {
+    fn new(name: &'static str, system: System) -> Self {
+        SystemDescriptor {
+            system,
+            name,
+            stage: None,
+            labels: Vec::new(),
+            before: Vec::new(),
+            after: Vec::new(),
+            conditions: Vec::new(),
+            access: Access::new(),
+        }
+    }
+
+    /// Returns `true` if this has to be executed before `other`
+    fn is_before(&self, other: &SystemDescriptor) -> bool {
+        self.before.iter().any(|label| other.labels.contains(label))
+            || other.after.iter().any(|label| self.labels.contains(label))
+    }
+
+    fn is_ordered_with(&self, other: &SystemDescriptor) -> bool {
+        self.is_before(other) || other.is_before(self)
+    }
+}
+
+/// This is implemented by systems, and by `SystemDescriptor`, so that the stage, labels,
+/// ordering constraints and run conditions of a system can be chained onto it:
+///
+/// `update_foo.in_stage(Stage::Input).label("foo").after("bar").run_if(RunCondition::NotPaused)`
+///
+/// Systems that take `&mut World` are thread-local, while systems that take `&World` and
+/// `&RunContext`, like `ParallelSystemFn`, can be executed on a worker thread. `Params` only
+/// tells these apart.
+pub trait IntoSystemDescriptor<Params>: Sized {
+    fn into_descriptor(self) -> SystemDescriptor;
+
+    #[must_use]
+    fn in_stage(self, stage: Stage) -> SystemDescriptor {
+        let mut res = self.into_descriptor();
+        res.stage = Some(stage);
+        res
+    }
+
+    #[must_use]
+    fn label(self, label: SystemLabel) -> SystemDescriptor {
+        let mut res = self.into_descriptor();
+        res.labels.push(label);
+        res
+    }
+
+    /// Execute the system before all systems with `label`, in the same stage
+    #[must_use]
+    fn before(self, label: SystemLabel) -> SystemDescriptor {
+        let mut res = self.into_descriptor();
+        res.before.push(label);
+        res
+    }
+
+    /// Execute the system after all systems with `label`, in the same stage
+    #[must_use]
+    fn after(self, label: SystemLabel) -> SystemDescriptor {
+        let mut res = self.into_descriptor();
+        res.after.push(label);
+        res
+    }
+
+    /// Only execute the system if `condition` is met. If this is called more than once, all
+    /// conditions have to be met.
+    #[must_use]
+    fn run_if(self, condition: RunCondition) -> SystemDescriptor {
+        let mut res = self.into_descriptor();
+        res.conditions.push(condition);
+        res
+    }
+}
+
+impl IntoSystemDescriptor<()> for SystemDescriptor {
+    fn into_descriptor(self) -> SystemDescriptor {
+        self
+    }
+}
+
+impl<F> IntoSystemDescriptor<fn(&mut World)> for F
+where
+    F: Fn(&mut World) + 'static,
+{
+    fn into_descriptor(self) -> SystemDescriptor {
+        SystemDescriptor::new(
+            system_name::<F>(),
+            System::ThreadLocal(Box::new(move |world: &mut World, _: &RunContext| {
+                self(world)
+            })),
+        )
+    }
+}
+
+impl<F> IntoSystemDescriptor<ParallelSystemFn> for F
+where
+    F: Fn(&World, &RunContext) + Send + Sync + 'static,
+{
+    fn into_descriptor(self) -> SystemDescriptor {
+        SystemDescriptor::new(system_name::<F>(), System::Parallel(Box::new(self)))
+    }
+}
+
+/// The name of a system function, without its module path. Closures keep the path, so that they
+/// can be told apart.
+fn system_name<F>() -> &'static str {
+    let name = any::type_name::<F>();
+
+    if name.ends_with("}}") {
+        name
+    } else {
+        name.rsplit("::").next().unwrap_or(name)
+    }
+}
+
 pub struct SchedulerBuilder {
-    steps: Vec<SystemFn>,
+    name: &'static str,
+    default_stage: Stage,
+    thread_cnt: usize,
+    systems: Vec<SystemDescriptor>,
 }
 
 impl SchedulerBuilder {
+    pub fn new() -> Self {
+        SchedulerBuilder {
+            name: "scheduler",
+            default_stage: Stage::Simulation,
+            thread_cnt: available_thread_cnt(),
+            systems: Vec::new(),
+        }
+    }
+
+    /// Set the name that the scheduler is shown by, in profiles
     #[must_use]
-    pub fn with_system(self, system: SystemFn) -> Self {
-        let mut steps = self.steps;
-        steps.push(system);
+    pub fn with_name(self, name: &'static str) -> Self {
+        SchedulerBuilder { name, ..self }
+    }
 
-        SchedulerBuilder { steps }
+    /// Set the stage that systems, that are not given a stage, are added to. This defaults to
+    /// `Stage::Simulation`.
+    #[must_use]
+    pub fn with_default_stage(self, stage: Stage) -> Self {
+        SchedulerBuilder {
+            default_stage: stage,
+            ..self
+        }
     }
 
-    pub fn add_system(&mut self, system: SystemFn) -> &mut Self {
-        self.steps.push(system);
-        self
+    /// Set the amount of threads that parallel systems are spread over, including the calling
+    /// thread. This defaults to the available parallelism, and is always `1` on wasm.
+    #[must_use]
+    pub fn with_thread_cnt(self, thread_cnt: usize) -> Self {
+        let thread_cnt = if cfg!(target_arch = "wasm32") {
+            1
+        } else {
+            thread_cnt.max(1)
+        };
+
+        SchedulerBuilder { thread_cnt, ..self }
     }
 
+    /// Add a system that accesses the components declared by `access`, which can be executed in
+    /// parallel with other systems. A system that takes `&mut World` is thread-local, regardless.
     #[must_use]
-    pub fn with_thread_local(self, system: SystemFn) -> Self {
-        let mut steps = self.steps;
-        steps.push(system);
+    pub fn with_system<P, S: IntoSystemDescriptor<P>>(mut self, system: S, access: Access) -> Self {
+        self.add_system(system, access);
+        self
+    }
+
+    pub fn add_system<P, S: IntoSystemDescriptor<P>>(
+        &mut self,
+        system: S,
+        access: Access,
+    ) -> &mut Self {
+        let mut system = system.into_descriptor();
+        system.access = access;
 
-        SchedulerBuilder { steps }
+        self.systems.push(system);
+        self
+    }
+
+    /// Add a system that is always executed on the main thread, like systems that draw, or that
+    /// access `storage`
+    #[must_use]
+    pub fn with_thread_local<P, S: IntoSystemDescriptor<P>>(mut self, system: S) -> Self {
+        self.add_thread_local(system);
+        self
     }
 
-    pub fn add_thread_local(&mut self, system: SystemFn) -> &mut Self {
-        self.steps.push(system);
+    pub fn add_thread_local<P, S: IntoSystemDescriptor<P>>(&mut self, system: S) -> &mut Self {
+        let mut system = system.into_descriptor();
+
+        if let System::Parallel(f) = system.system {
+            system.system = System::ThreadLocal(Box::new(move |world: &mut World, context| {
+                f(world, context)
+            }));
+        }
+
+        self.systems.push(system);
         self
     }
 
     pub fn build(self) -> Scheduler {
-        Scheduler { steps: self.steps }
+        let mut res = Scheduler {
+            name: self.name,
+            default_stage: self.default_stage,
+            thread_cnt: self.thread_cnt,
+            systems: Vec::new(),
+            order: Vec::new(),
+            profile: SchedulerProfile::new(),
+        };
+
+        for system in self.systems {
+            res.push(system);
+        }
+
+        res.update_order();
+
+        res
+    }
+}
+
+impl Default for SchedulerBuilder {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
-/// Placeholder until we implement threading
+/// Executes systems by stage, and by their ordering constraints. Parallel systems that are not
+/// ordered relative to each other, and whose component access does not conflict, are executed at
+/// the same time, on scoped threads, while thread-local systems are executed on the calling thread,
+/// when all systems before them have finished.
 pub struct Scheduler {
-    steps: Vec<SystemFn>,
+    name: &'static str,
+    default_stage: Stage,
+    thread_cnt: usize,
+    systems: Vec<SystemDescriptor>,
+    /// The indices of the systems, in the order that they are executed in
+    order: Vec<usize>,
+    /// The timings of the last executions, which are only recorded while the profiler is enabled
+    profile: SchedulerProfile,
 }
 
 impl Scheduler {
@@ -53,9 +442,462 @@ impl Scheduler {
         SchedulerBuilder::default()
     }
 
-    pub fn execute(&mut self, world: &mut World) {
-        for f in &mut self.steps {
-            f(world);
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn profile(&self) -> &SchedulerProfile {
+        &self.profile
+    }
+
+    /// Add a system after the scheduler has been built, like when a game mode, or a mod, adds
+    /// systems to a game. The system is ordered like it would have been if it was added to the
+    /// builder last.
+    pub fn add_system<P, S: IntoSystemDescriptor<P>>(
+        &mut self,
+        system: S,
+        access: Access,
+    ) -> &mut Self {
+        let mut system = system.into_descriptor();
+        system.access = access;
+
+        self.push(system);
+        self.update_order();
+        self
+    }
+
+    /// Add a thread-local system after the scheduler has been built
+    pub fn add_thread_local<P, S: IntoSystemDescriptor<P>>(&mut self, system: S) -> &mut Self {
+        let mut builder = SchedulerBuilder::new();
+        builder.add_thread_local(system);
+
+        for system in builder.systems {
+            self.push(system);
+        }
+
+        self.update_order();
+        self
+    }
+
+    /// Execute the systems whose run conditions are met in `context`. While the profiler is
+    /// enabled, or a trace is recorded, the time that every system takes is recorded.
+    pub fn execute(&mut self, world: &mut World, context: &RunContext) {
+        let is_profiling = profiler::is_profiler_enabled() || profiler::is_tracing();
+        let start = if is_profiling { profiler::now() } else { 0.0 };
+
+        let mut timings = Vec::new();
+
+        // The parallel systems that are executed together, once a system that can not be
+        // executed with them is reached
+        let mut batch: Vec<&SystemDescriptor> = Vec::new();
+
+        for &i in &self.order {
+            let system = &self.systems[i];
+
+            if !system
+                .conditions
+                .iter()
+                .all(|condition| condition.is_met(context))
+            {
+                continue;
+            }
+
+            let can_join_batch = matches!(system.system, System::Parallel(_))
+                && batch.iter().all(|other| {
+                    other.stage == system.stage
+                        && !other.is_ordered_with(system)
+                        && !other.access.is_conflicting(&system.access)
+                });
+
+            if !can_join_batch {
+                execute_batch(
+                    &batch,
+                    self.thread_cnt,
+                    world,
+                    context,
+                    is_profiling,
+                    &mut timings,
+                );
+                batch.clear();
+            }
+
+            match &system.system {
+                System::Parallel(_) => batch.push(system),
+                System::ThreadLocal(f) => {
+                    timings.extend(time_system(system.name, is_profiling, || f(world, context)));
+                }
+            }
+        }
+
+        execute_batch(
+            &batch,
+            self.thread_cnt,
+            world,
+            context,
+            is_profiling,
+            &mut timings,
+        );
+
+        if is_profiling {
+            let execution = ExecutionProfile {
+                start,
+                duration: profiler::now() - start,
+                systems: timings,
+            };
+
+            profiler::record_trace(self.name, &execution);
+
+            if profiler::is_profiler_enabled() {
+                self.profile.push(execution);
+            }
+        } else {
+            self.profile.clear();
+        }
+    }
+
+    fn push(&mut self, mut system: SystemDescriptor) {
+        system.stage = system.stage.or(Some(self.default_stage));
+        self.systems.push(system);
+    }
+
+    /// Sort the systems by stage, and then by their ordering constraints. Systems that are not
+    /// constrained relative to each other keep the order that they were added in.
+    fn update_order(&mut self) {
+        let mut stages = self
+            .systems
+            .iter()
+            .filter_map(|system| system.stage)
+            .collect::<Vec<_>>();
+
+        stages.sort_unstable();
+        stages.dedup();
+
+        self.order.clear();
+
+        for stage in stages {
+            let indices = (0..self.systems.len())
+                .filter(|&i| self.systems[i].stage == Some(stage))
+                .collect::<Vec<_>>();
+
+            // The amount of systems that have to be executed before each system, and the systems
+            // that have to be executed after each system, by their position in `indices`
+            let mut dependency_cnts = vec![0; indices.len()];
+            let mut dependents = vec![Vec::new(); indices.len()];
+
+            for (a, &i) in indices.iter().enumerate() {
+                for (b, &j) in indices.iter().enumerate() {
+                    if i != j && self.systems[i].is_before(&self.systems[j]) {
+                        dependents[a].push(b);
+                        dependency_cnts[b] += 1;
+                    }
+                }
+            }
+
+            let mut is_ordered = vec![false; indices.len()];
+
+            while let Some(a) =
+                (0..indices.len()).find(|&a| !is_ordered[a] && dependency_cnts[a] == 0)
+            {
+                is_ordered[a] = true;
+                self.order.push(indices[a]);
+
+                for &b in &dependents[a] {
+                    dependency_cnts[b] -= 1;
+                }
+            }
+
+            debug_assert!(
+                !is_ordered.contains(&false),
+                "The systems of stage {:?} have cyclic ordering constraints",
+                stage
+            );
+
+            // Systems in a cycle are executed in the order that they were added in
+            if is_ordered.contains(&false) {
+                for (a, &i) in indices.iter().enumerate() {
+                    if !is_ordered[a] {
+                        self.order.push(i);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Execute a batch of parallel systems, spread over as many threads as there are systems, up to
+/// `thread_cnt`. The calling thread executes systems as well, and this returns once
+/// all of them have finished, so they can borrow the world. If a system panics, this panics too.
+fn execute_batch(
+    batch: &[&SystemDescriptor],
+    thread_cnt: usize,
+    world: &World,
+    context: &RunContext,
+    is_profiling: bool,
+    timings: &mut Vec<SystemTiming>,
+) {
+    let thread_cnt = thread_cnt.min(batch.len());
+
+    if thread_cnt == 0 {
+        return;
+    }
+
+    let mut slots = vec![None; batch.len()];
+
+    // Every thread executes every `thread_cnt`th system, starting at its own index
+    let mut jobs = (0..thread_cnt).map(|_| Vec::new()).collect::<Vec<_>>();
+
+    for (i, (system, slot)) in batch.iter().zip(slots.iter_mut()).enumerate() {
+        if let System::Parallel(f) = &system.system {
+            jobs[i % thread_cnt].push((system.name, f, slot));
+        }
+    }
+
+    let execute = move |jobs: Vec<(&'static str, &BoxedParallelSystem, &mut Option<_>)>| {
+        for (name, f, slot) in jobs {
+            *slot = time_system(name, is_profiling, || f(world, context));
+        }
+    };
+
+    let mut jobs = jobs.into_iter();
+    let first = jobs.next().unwrap_or_default();
+
+    thread::scope(|scope| {
+        for (i, jobs) in jobs.enumerate() {
+            scope.spawn(move || {
+                THREAD_INDEX.with(|index| index.set(i + 1));
+                execute(jobs);
+            });
+        }
+
+        execute(first);
+    });
+
+    timings.extend(slots.into_iter().flatten());
+}
+
+/// Execute a system, returning the time that it took, if `is_profiling` is `true`
+fn time_system<F: FnOnce()>(name: &'static str, is_profiling: bool, f: F) -> Option<SystemTiming> {
+    if !is_profiling {
+        f();
+        return None;
+    }
+
+    let start = profiler::now();
+
+    f();
+
+    Some(SystemTiming {
+        name,
+        start,
+        duration: profiler::now() - start,
+        thread_index: THREAD_INDEX.with(Cell::get),
+    })
+}
+
+thread_local! {
+    /// The index of the thread that a batch of parallel systems is executed on, starting at `1`,
+    /// or `0` on the thread that executes the scheduler
+    static THREAD_INDEX: Cell<usize> = Cell::new(0);
+}
+
+/// The amount of threads that parallel systems are spread over, by default
+fn available_thread_cnt() -> usize {
+    static THREAD_CNT: OnceLock<usize> = OnceLock::new();
+
+    *THREAD_CNT.get_or_init(|| {
+        // Threads can not be spawned on wasm, so everything is executed on the main thread
+        if cfg!(target_arch = "wasm32") {
+            1
+        } else {
+            thread::available_parallelism()
+                .map(|cnt| cnt.get())
+                .unwrap_or(1)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    type Log = Vec<&'static str>;
+
+    fn log(world: &mut World, name: &'static str) {
+        for (_, log) in world.query_mut::<&mut Log>() {
+            log.push(name);
+        }
+    }
+
+    fn take_log(world: &mut World) -> Log {
+        world
+            .query_mut::<&mut Log>()
+            .into_iter()
+            .flat_map(|(_, log)| log.drain(..).collect::<Vec<_>>())
+            .collect()
+    }
+
+    #[test]
+    fn test_scheduler_order() {
+        let mut world = World::new();
+        world.spawn((Log::new(),));
+
+        let mut scheduler = Scheduler::builder()
+            .with_thread_local((|world: &mut World| log(world, "physics")).label("physics"))
+            .with_thread_local(
+                (|world: &mut World| log(world, "effects"))
+                    .label("effects")
+                    .after("physics"),
+            )
+            .with_thread_local((|world: &mut World| log(world, "input")).in_stage(Stage::Input))
+            .with_thread_local(
+                (|world: &mut World| log(world, "paused"))
+                    .in_stage(Stage::PostSimulation)
+                    .run_if(RunCondition::NotPaused),
+            )
+            .build();
+
+        // Added last, but constrained to run between the systems that it references
+        scheduler.add_thread_local(
+            (|world: &mut World| log(world, "mod"))
+                .after("physics")
+                .before("effects"),
+        );
+
+        let context = RunContext::default();
+        scheduler.execute(&mut world, &context);
+
+        assert_eq!(
+            take_log(&mut world),
+            ["input", "physics", "mod", "effects", "paused"]
+        );
+
+        let context = RunContext {
+            is_paused: true,
+            ..RunContext::default()
+        };
+        scheduler.execute(&mut world, &context);
+
+        assert_eq!(take_log(&mut world), ["input", "physics", "mod", "effects"]);
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Position(i32);
+
+    #[derive(Debug, PartialEq)]
+    struct Velocity(i32);
+
+    fn integrate(world: &World, _context: &RunContext) {
+        for (_, (position, velocity)) in world.query::<(&mut Position, &Velocity)>().iter() {
+            position.0 += velocity.0;
+        }
+    }
+
+    fn accelerate(world: &World, _context: &RunContext) {
+        for (_, velocity) in world.query::<&mut Velocity>().iter() {
+            velocity.0 += 1;
+        }
+    }
+
+    #[test]
+    fn test_scheduler_parallel() {
+        let mut world = World::new();
+
+        for _ in 0..64 {
+            world.spawn((Position(0), Velocity(0)));
+        }
+
+        // These conflict, so they are executed one after the other, in the order they were added
+        let mut scheduler = Scheduler::builder()
+            .with_system(accelerate, Access::new().write::<Velocity>())
+            .with_system(
+                integrate,
+                Access::new().read::<Velocity>().write::<Position>(),
+            )
+            .build();
+
+        for _ in 0..3 {
+            scheduler.execute(&mut world, &RunContext::default());
+        }
+
+        for (_, (position, velocity)) in world.query_mut::<(&Position, &Velocity)>() {
+            assert_eq!(*velocity, Velocity(3));
+            assert_eq!(*position, Position(1 + 2 + 3));
+        }
+
+        assert!(!Access::new()
+            .read::<Velocity>()
+            .is_conflicting(&Access::new().read::<Velocity>()));
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Rotation(i32);
+
+    #[test]
+    fn test_scheduler_concurrency() {
+        let mut world = World::new();
+
+        for _ in 0..64 {
+            world.spawn((Position(0), Velocity(1), Rotation(0)));
+        }
+
+        // Both systems wait for the other to start, which only happens in time if they are
+        // executed at the same time
+        let started_cnt = Arc::new(AtomicUsize::new(0));
+        let is_concurrent = Arc::new(AtomicBool::new(true));
+
+        let wait_for_other = {
+            let is_concurrent = is_concurrent.clone();
+
+            move || {
+                started_cnt.fetch_add(1, Ordering::SeqCst);
+
+                let start = Instant::now();
+
+                while started_cnt.load(Ordering::SeqCst) < 2 {
+                    if start.elapsed() > Duration::from_secs(1) {
+                        is_concurrent.store(false, Ordering::SeqCst);
+                        break;
+                    }
+
+                    thread::yield_now();
+                }
+            }
+        };
+
+        let wait_for_rotate = wait_for_other.clone();
+
+        let mut scheduler = Scheduler::builder()
+            .with_thread_cnt(2)
+            .with_system(
+                move |world: &World, context: &RunContext| {
+                    wait_for_other();
+                    integrate(world, context);
+                },
+                Access::new().read::<Velocity>().write::<Position>(),
+            )
+            .with_system(
+                move |world: &World, _context: &RunContext| {
+                    wait_for_rotate();
+
+                    for (_, rotation) in world.query::<&mut Rotation>().iter() {
+                        rotation.0 += 1;
+                    }
+                },
+                Access::new().write::<Rotation>(),
+            )
+            .build();
+
+        scheduler.execute(&mut world, &RunContext::default());
+
+        assert!(is_concurrent.load(Ordering::SeqCst));
+
+        for (_, (position, rotation)) in world.query_mut::<(&Position, &Rotation)>() {
+            assert_eq!(*position, Position(1));
+            assert_eq!(*rotation, Rotation(1));
         }
     }
 }
@@ -1,5 +1,6 @@
 use std::any::TypeId;
 use std::cmp::Ordering;
+use std::collections::BTreeMap;
 
 use macroquad::experimental::collections::storage;
 use macroquad::prelude::*;
@@ -8,6 +9,7 @@ use core::error::{Error, ErrorKind, Result};
 
 use crate::editor::gui::windows::Window;
 use crate::map::{MapBackgroundLayer, MapObject, MapObjectKind};
+use crate::player::PlayerParams;
 use crate::{
     map::{Map, MapLayer, MapLayerKind, MapTile, MapTileset},
     Resources,
@@ -133,6 +135,13 @@ pub enum EditorAction {
     SaveMap(Option<String>),
     OpenSaveMapWindow,
     DeleteMap(usize),
+    OpenPlaytestWindow,
+    /// Start a local match on the map being edited, with `players`, starting at the spawn points
+    /// in `spawn_point_overrides`, by player index, or at random spawn points
+    Playtest {
+        players: Vec<PlayerParams>,
+        spawn_point_overrides: BTreeMap<u8, usize>,
+    },
     ExitToMainMenu,
     QuitToDesktop,
 }
@@ -12,8 +12,9 @@ pub const EDITOR_MENU_RESULT_NEW: usize = 0;
 pub const EDITOR_MENU_RESULT_OPEN_IMPORT: usize = 1;
 pub const EDITOR_MENU_RESULT_SAVE: usize = 2;
 pub const EDITOR_MENU_RESULT_SAVE_AS: usize = 3;
-pub const EDITOR_MENU_RESULT_MAIN_MENU: usize = 4;
-pub const EDITOR_MENU_RESULT_QUIT: usize = 5;
+pub const EDITOR_MENU_RESULT_PLAYTEST: usize = 4;
+pub const EDITOR_MENU_RESULT_MAIN_MENU: usize = 5;
+pub const EDITOR_MENU_RESULT_QUIT: usize = 6;
 
 static mut EDITOR_MENU_INSTANCE: Option<Menu> = None;
 
@@ -45,6 +46,11 @@ pub fn open_editor_menu(ctx: &EditorContext) {
                         title: "Save As".to_string(),
                         ..Default::default()
                     },
+                    MenuEntry {
+                        index: EDITOR_MENU_RESULT_PLAYTEST,
+                        title: "Playtest".to_string(),
+                        ..Default::default()
+                    },
                     MenuEntry {
                         index: EDITOR_MENU_RESULT_MAIN_MENU,
                         title: "Main Menu".to_string(),
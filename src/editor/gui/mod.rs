@@ -13,7 +13,8 @@ pub use combobox::{ComboBoxBuilder, ComboBoxValue};
 pub use editor_menu::{
     close_editor_menu, draw_editor_menu, is_editor_menu_open, open_editor_menu, toggle_editor_menu,
     EDITOR_MENU_RESULT_MAIN_MENU, EDITOR_MENU_RESULT_NEW, EDITOR_MENU_RESULT_OPEN_IMPORT,
-    EDITOR_MENU_RESULT_QUIT, EDITOR_MENU_RESULT_SAVE, EDITOR_MENU_RESULT_SAVE_AS,
+    EDITOR_MENU_RESULT_PLAYTEST, EDITOR_MENU_RESULT_QUIT, EDITOR_MENU_RESULT_SAVE,
+    EDITOR_MENU_RESULT_SAVE_AS,
 };
 
 use macroquad::{
@@ -320,6 +321,10 @@ impl EditorGui {
                         let action = EditorAction::OpenSaveMapWindow;
                         res = Some(action);
                     }
+                    EDITOR_MENU_RESULT_PLAYTEST => {
+                        let action = EditorAction::OpenPlaytestWindow;
+                        res = Some(action);
+                    }
                     EDITOR_MENU_RESULT_MAIN_MENU => {
                         let action = EditorAction::ExitToMainMenu;
                         res = Some(action);
@@ -12,6 +12,7 @@ mod create_object;
 mod import;
 mod load_map;
 mod object_properties;
+mod playtest;
 mod save_map;
 mod tile_properties;
 mod tileset_properties;
@@ -25,6 +26,7 @@ pub use create_tileset::CreateTilesetWindow;
 pub use import::ImportWindow;
 pub use load_map::LoadMapWindow;
 pub use object_properties::ObjectPropertiesWindow;
+pub use playtest::PlaytestWindow;
 pub use save_map::SaveMapWindow;
 pub use tile_properties::TilePropertiesWindow;
 pub use tileset_properties::TilesetPropertiesWindow;
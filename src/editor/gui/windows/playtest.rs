@@ -0,0 +1,221 @@
+use std::collections::BTreeMap;
+
+use fishsticks::GamepadContext;
+
+use macroquad::{
+    experimental::collections::storage,
+    prelude::*,
+    ui::{hash, Ui},
+};
+
+use crate::editor::gui::combobox::ComboBoxVec;
+use crate::editor::gui::{ComboBoxBuilder, ComboBoxValue};
+use crate::map::Map;
+use crate::player::{PlayerCharacterMetadata, PlayerControllerKind, PlayerParams};
+use crate::{GameInputScheme, Resources};
+
+use super::{ButtonParams, EditorAction, EditorContext, Window, WindowParams};
+
+const MAX_PLAYER_CNT: usize = 4;
+
+/// The options of a player in the playtest window
+struct PlaytestPlayer {
+    controller: ComboBoxVec,
+    character: ComboBoxVec,
+    spawn_point: ComboBoxVec,
+}
+
+pub struct PlaytestWindow {
+    params: WindowParams,
+    player_cnt: ComboBoxVec,
+    players: Vec<PlaytestPlayer>,
+    /// The controllers that can be selected, in the order of the options of the controller combo
+    /// boxes
+    controllers: Vec<PlayerControllerKind>,
+    characters: Vec<PlayerCharacterMetadata>,
+}
+
+impl PlaytestWindow {
+    pub fn new(map: &Map) -> Self {
+        let params = WindowParams {
+            title: Some("Playtest".to_string()),
+            size: vec2(350.0, 500.0),
+            ..Default::default()
+        };
+
+        let mut controllers = vec![
+            PlayerControllerKind::LocalInput(GameInputScheme::KeyboardRight),
+            PlayerControllerKind::LocalInput(GameInputScheme::KeyboardLeft),
+        ];
+
+        let mut controller_options = vec![
+            "Keyboard (arrows)".to_string(),
+            "Keyboard (WASD)".to_string(),
+        ];
+
+        {
+            let gamepad_context = storage::get::<GamepadContext>();
+
+            for (i, (ix, _)) in gamepad_context.gamepads().enumerate() {
+                controllers.push(PlayerControllerKind::LocalInput(GameInputScheme::Gamepad(
+                    ix,
+                )));
+                controller_options.push(format!("Gamepad {}", i + 1));
+            }
+        }
+
+        controllers.push(PlayerControllerKind::Bot);
+        controller_options.push("Bot".to_string());
+
+        let characters = {
+            let resources = storage::get::<Resources>();
+
+            let mut res = resources
+                .player_characters
+                .values()
+                .cloned()
+                .collect::<Vec<_>>();
+
+            res.sort_by(|a, b| a.id.cmp(&b.id));
+
+            res
+        };
+
+        let character_options = characters
+            .iter()
+            .map(|character| character.name.clone())
+            .collect::<Vec<_>>();
+
+        let spawn_point_options = std::iter::once("Random".to_string())
+            .chain((0..map.spawn_points.len()).map(|i| format!("Spawn point {}", i + 1)))
+            .collect::<Vec<_>>();
+
+        // The first player is controlled from the keyboard, and all others are bots, by default
+        let players = (0..MAX_PLAYER_CNT)
+            .map(|i| {
+                let controller = if i == 0 { 0 } else { controllers.len() - 1 };
+
+                let mut character = ComboBoxVec::from(character_options.as_slice());
+                if !character_options.is_empty() {
+                    character.set_index(i % character_options.len());
+                }
+
+                PlaytestPlayer {
+                    controller: ComboBoxVec::new(
+                        controller,
+                        &controller_options
+                            .iter()
+                            .map(String::as_str)
+                            .collect::<Vec<_>>(),
+                    ),
+                    character,
+                    spawn_point: ComboBoxVec::from(spawn_point_options.as_slice()),
+                }
+            })
+            .collect();
+
+        PlaytestWindow {
+            params,
+            player_cnt: ComboBoxVec::new(1, &["1", "2", "3", "4"]),
+            players,
+            controllers,
+            characters,
+        }
+    }
+}
+
+impl Window for PlaytestWindow {
+    fn get_params(&self) -> &WindowParams {
+        &self.params
+    }
+
+    fn get_buttons(&self, map: &Map, _ctx: &EditorContext) -> Vec<ButtonParams> {
+        let mut res = Vec::new();
+
+        let player_cnt = self.player_cnt.get_index() + 1;
+
+        let mut action = None;
+
+        if !map.spawn_points.is_empty() && !self.characters.is_empty() {
+            let mut players = Vec::new();
+            let mut spawn_point_overrides = BTreeMap::new();
+
+            for (i, player) in self.players.iter().take(player_cnt).enumerate() {
+                let index = i as u8;
+
+                players.push(PlayerParams {
+                    index,
+                    controller: self.controllers[player.controller.get_index()].clone(),
+                    character: self.characters[player.character.get_index()].clone(),
+                });
+
+                // The first option is a random spawn point
+                let spawn_point = player.spawn_point.get_index();
+                if spawn_point > 0 && spawn_point <= map.spawn_points.len() {
+                    spawn_point_overrides.insert(index, spawn_point - 1);
+                }
+            }
+
+            let batch = self.get_close_action().then(EditorAction::Playtest {
+                players,
+                spawn_point_overrides,
+            });
+
+            action = Some(batch);
+        }
+
+        res.push(ButtonParams {
+            label: "Start",
+            action,
+            ..Default::default()
+        });
+
+        res.push(ButtonParams {
+            label: "Cancel",
+            action: Some(self.get_close_action()),
+            ..Default::default()
+        });
+
+        res
+    }
+
+    fn draw(
+        &mut self,
+        ui: &mut Ui,
+        _size: Vec2,
+        _map: &Map,
+        _ctx: &EditorContext,
+    ) -> Option<EditorAction> {
+        let id = hash!("playtest_window");
+
+        ComboBoxBuilder::new(hash!(id, "player_cnt_input"))
+            .with_label("Players")
+            .with_ratio(0.4)
+            .build(ui, &mut self.player_cnt);
+
+        let player_cnt = self.player_cnt.get_index() + 1;
+
+        for (i, player) in self.players.iter_mut().take(player_cnt).enumerate() {
+            ui.separator();
+
+            ui.label(None, &format!("Player {}", i + 1));
+
+            ComboBoxBuilder::new(hash!(id, "controller_input", i))
+                .with_label("Controller")
+                .with_ratio(0.6)
+                .build(ui, &mut player.controller);
+
+            ComboBoxBuilder::new(hash!(id, "character_input", i))
+                .with_label("Character")
+                .with_ratio(0.6)
+                .build(ui, &mut player.character);
+
+            ComboBoxBuilder::new(hash!(id, "spawn_point_input", i))
+                .with_label("Spawn")
+                .with_ratio(0.6)
+                .build(ui, &mut player.spawn_point);
+        }
+
+        None
+    }
+}
@@ -27,6 +27,7 @@ pub struct EditorInput {
     pub save_as: bool,
     pub load: bool,
     pub delete: bool,
+    pub playtest: bool,
 }
 
 pub fn collect_editor_input(scheme: EditorInputScheme) -> EditorInput {
@@ -90,6 +91,8 @@ pub fn collect_editor_input(scheme: EditorInputScheme) -> EditorInput {
                 input.toggle_disable_parallax = is_key_pressed(KeyCode::P);
 
                 input.delete = is_key_pressed(KeyCode::Delete);
+
+                input.playtest = is_key_pressed(KeyCode::F5);
             }
         }
         EditorInputScheme::Gamepad(ix) => {
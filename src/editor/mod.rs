@@ -1,6 +1,8 @@
 use std::any::TypeId;
 use std::path::Path;
 
+use crate::events::ApplicationEvent;
+use crate::game::MatchSettings;
 use crate::{exit_to_main_menu, quit_to_desktop, Resources};
 
 mod camera;
@@ -45,7 +47,7 @@ use crate::editor::actions::{
 };
 use crate::editor::gui::windows::{
     BackgroundPropertiesWindow, CreateMapWindow, ImportWindow, LoadMapWindow,
-    ObjectPropertiesWindow, SaveMapWindow, TilePropertiesWindow,
+    ObjectPropertiesWindow, PlaytestWindow, SaveMapWindow, TilePropertiesWindow,
 };
 use crate::editor::input::{collect_editor_input, EditorInput};
 use crate::editor::tools::SpawnPointPlacementTool;
@@ -117,6 +119,45 @@ enum DraggedObject {
 const SPAWN_POINT_COLLIDER_WIDTH: f32 = 38.0;
 const SPAWN_POINT_COLLIDER_HEIGHT: f32 = 49.0;
 
+/// The state of an editor that is kept while it is not in the scene, like while its map is
+/// playtested. This is created by `Editor::take_state`.
+pub struct EditorState {
+    map_resource: MapResource,
+    input_scheme: EditorInputScheme,
+    selected_tool: Option<TypeId>,
+    selected_layer: Option<String>,
+    selected_tileset: Option<String>,
+    selected_tile: Option<u32>,
+    history: EditorHistory,
+    camera_position: Vec2,
+    camera_scale: f32,
+    should_draw_grid: bool,
+    should_snap_to_grid: bool,
+    is_parallax_disabled: bool,
+}
+
+impl EditorState {
+    /// Add the editor, and its camera, to the scene, as they were when the state was taken
+    pub fn restore(self) {
+        let mut camera = EditorCamera::new(self.camera_position);
+        camera.scale = self.camera_scale;
+
+        let mut editor = Editor::new(self.input_scheme, self.map_resource);
+
+        editor.selected_tool = self.selected_tool;
+        editor.selected_layer = self.selected_layer;
+        editor.selected_tileset = self.selected_tileset;
+        editor.selected_tile = self.selected_tile;
+        editor.history = self.history;
+        editor.should_draw_grid = self.should_draw_grid;
+        editor.should_snap_to_grid = self.should_snap_to_grid;
+        editor.is_parallax_disabled = self.is_parallax_disabled;
+
+        scene::add_node(camera);
+        scene::add_node(editor);
+    }
+}
+
 pub struct Editor {
     map_resource: MapResource,
 
@@ -258,6 +299,30 @@ impl Editor {
         }
     }
 
+    /// Take the state of the editor, and of its camera, so that they can be restored, with
+    /// `EditorState::restore`, once the editor has been removed from the scene, like when its map
+    /// is playtested. This leaves the editor without any undo history.
+    pub fn take_state(&mut self) -> EditorState {
+        let (camera_position, camera_scale) = scene::find_node_by_type::<EditorCamera>()
+            .map(|camera| (camera.position, camera.scale))
+            .unwrap_or_else(|| (self.get_map().get_size() * 0.5, 1.0));
+
+        EditorState {
+            map_resource: self.map_resource.clone(),
+            input_scheme: self.input_scheme,
+            selected_tool: self.selected_tool,
+            selected_layer: self.selected_layer.clone(),
+            selected_tileset: self.selected_tileset.clone(),
+            selected_tile: self.selected_tile,
+            history: std::mem::replace(&mut self.history, EditorHistory::new()),
+            camera_position,
+            camera_scale,
+            should_draw_grid: self.should_draw_grid,
+            should_snap_to_grid: self.should_snap_to_grid,
+            is_parallax_disabled: self.is_parallax_disabled,
+        }
+    }
+
     #[allow(dead_code)]
     fn get_selected_tile(&self) -> Option<(String, u32)> {
         if let Some(tileset_id) = self.selected_tileset.clone() {
@@ -642,6 +707,30 @@ impl Editor {
                 let mut resources = storage::get_mut::<Resources>();
                 resources.delete_map(index).unwrap();
             }
+            EditorAction::OpenPlaytestWindow => {
+                if self.get_map().spawn_points.is_empty() {
+                    self.info_message = Some("Add a spawn point to playtest the map".to_string());
+                } else {
+                    let mut gui = storage::get_mut::<EditorGui>();
+                    gui.add_window(PlaytestWindow::new(self.get_map()));
+                }
+            }
+            EditorAction::Playtest {
+                players,
+                spawn_point_overrides,
+            } => {
+                let settings = MatchSettings {
+                    spawn_point_overrides,
+                    ..MatchSettings::with_random_seed()
+                };
+
+                ApplicationEvent::StartLocalGame {
+                    map: self.get_map().clone(),
+                    players,
+                    settings,
+                }
+                .dispatch();
+            }
             EditorAction::ExitToMainMenu => {
                 exit_to_main_menu();
             }
@@ -706,6 +795,11 @@ impl Node for Editor {
             node.apply_action(action);
         }
 
+        if node.input.playtest {
+            let action = EditorAction::OpenPlaytestWindow;
+            node.apply_action(action);
+        }
+
         if !node.input.action && node.double_click_timer < Self::DOUBLE_CLICK_THRESHOLD {
             node.double_click_timer =
                 (node.double_click_timer + dt).clamp(0.0, Self::DOUBLE_CLICK_THRESHOLD);
@@ -8,6 +8,7 @@ use macroquad::prelude::*;
 use serde::{Deserialize, Serialize};
 
 use core::math::{deg_to_rad, rotate_vector, IsZero};
+use core::rng::Rng;
 use core::Result;
 
 use crate::{json, Resources};
@@ -27,6 +28,10 @@ pub use projectiles::ProjectileKind;
 
 const COLLIDER_DEBUG_DRAW_TTL: f32 = 0.5;
 
+/// This marks the colliders that are spawned, in debug builds, to draw active effects. These are
+/// not part of the simulation, so they are left out of snapshots and checksums.
+pub struct DebugCollider;
+
 struct CircleCollider {
     r: f32,
     ttl_timer: f32,
@@ -51,9 +56,9 @@ pub fn spawn_active_effect(
 
     if let Some(id) = &params.sound_effect_id {
         let resources = storage::get::<Resources>();
-        let sound = resources.sounds.get(id).unwrap();
-
-        play_sound_once(*sound);
+        if let Some(sound) = resources.sounds.get(id) {
+            play_sound_once(*sound);
+        }
     }
 
     let mut damage = Vec::new();
@@ -71,6 +76,7 @@ pub fn spawn_active_effect(
             {
                 world.spawn((
                     Transform::new(origin, 0.0),
+                    DebugCollider,
                     CircleCollider {
                         r: radius,
                         ttl_timer: 0.0,
@@ -119,6 +125,7 @@ pub fn spawn_active_effect(
             {
                 world.spawn((
                     Transform::new(origin, 0.0),
+                    DebugCollider,
                     RectCollider {
                         w: rect.w,
                         h: rect.h,
@@ -166,7 +173,7 @@ pub fn spawn_active_effect(
 
             if spread != 0.0 {
                 let rad = deg_to_rad(spread);
-                let spread = rand::gen_range(-rad, rad);
+                let spread = storage::get_mut::<Rng>().gen_range(-rad, rad);
 
                 velocity = rotate_vector(velocity, spread);
             }
@@ -7,6 +7,7 @@ use macroquad_platformer::Tile;
 
 use serde::{Deserialize, Serialize};
 
+use crate::ecs::{EntityMap, MapEntities};
 use crate::effects::active::triggered::TriggeredEffect;
 use crate::effects::TriggeredEffectTrigger;
 use crate::particles::{ParticleEmitter, ParticleEmitterMetadata};
@@ -42,9 +43,11 @@ pub enum ProjectileKind {
     },
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Projectile {
     pub kind: ProjectileKind,
     pub owner: Entity,
+    #[serde(with = "json::vec2_def")]
     pub origin: Vec2,
     pub range: f32,
     pub is_lethal: bool,
@@ -71,6 +74,12 @@ impl Projectile {
     }
 }
 
+impl MapEntities for Projectile {
+    fn map_entities(&mut self, entity_map: &EntityMap) {
+        entity_map.map(&mut self.owner);
+    }
+}
+
 #[derive(Clone)]
 pub struct ProjectileParams {
     pub is_lethal: bool,
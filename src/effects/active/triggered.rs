@@ -8,7 +8,9 @@ use serde::{Deserialize, Serialize};
 use core::math::deg_to_rad;
 use core::Result;
 
+use crate::ecs::{EntityMap, MapEntities};
 use crate::effects::active::spawn_active_effect;
+use crate::game::get_delta_time;
 use crate::particles::{ParticleEmitter, ParticleEmitterMetadata};
 use crate::physics;
 use crate::player::{Player, PlayerState};
@@ -33,6 +35,7 @@ pub enum TriggeredEffectTrigger {
     Projectile,
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct TriggeredEffect {
     pub owner: Entity,
     pub trigger: Vec<TriggeredEffectTrigger>,
@@ -76,6 +79,13 @@ impl TriggeredEffect {
     }
 }
 
+impl MapEntities for TriggeredEffect {
+    fn map_entities(&mut self, entity_map: &EntityMap) {
+        entity_map.map(&mut self.owner);
+        entity_map.map_opt(&mut self.triggered_by);
+    }
+}
+
 pub fn spawn_triggered_effect(
     world: &mut World,
     owner: Entity,
@@ -157,7 +167,7 @@ const KICK_FORCE: f32 = 15.0;
 const KICK_DELAY: f32 = 0.22;
 
 pub fn fixed_update_triggered_effects(world: &mut World) {
-    let dt = get_frame_time();
+    let dt = get_delta_time();
 
     let mut to_trigger = Vec::new();
 
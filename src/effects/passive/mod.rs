@@ -8,6 +8,7 @@ use hecs::{Entity, World};
 
 mod turtle_shell;
 
+use crate::ecs::{EntityMap, MapEntities};
 use crate::player::PlayerEventKind;
 use crate::PlayerEvent;
 
@@ -42,8 +43,13 @@ pub fn init_passive_effects() {
     );
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct PassiveEffectInstance {
     pub name: String,
+    pub function_id: Option<String>,
+    /// This is resolved from `function_id`. It is not serialized, so it must be restored by
+    /// calling `PassiveEffectInstance::resolve_function`, after deserialization.
+    #[serde(skip)]
     pub function: Option<PassiveEffectFn>,
     pub activated_on: Vec<PlayerEventKind>,
     pub particle_effect_id: Option<String>,
@@ -58,10 +64,11 @@ pub struct PassiveEffectInstance {
 
 impl PassiveEffectInstance {
     pub fn new(item: Option<Entity>, meta: PassiveEffectMetadata) -> Self {
-        let function = meta.function_id.map(|id| *get_passive_effect(&id));
+        let function = meta.function_id.as_ref().map(|id| *get_passive_effect(id));
 
         PassiveEffectInstance {
             name: meta.name,
+            function_id: meta.function_id,
             function,
             activated_on: meta.activated_on,
             particle_effect_id: meta.particle_effect_id,
@@ -75,6 +82,10 @@ impl PassiveEffectInstance {
         }
     }
 
+    pub fn resolve_function(&mut self) {
+        self.function = self.function_id.as_ref().map(|id| *get_passive_effect(id));
+    }
+
     pub fn update(&mut self, dt: f32) {
         self.duration_timer += dt;
     }
@@ -96,6 +107,12 @@ impl PassiveEffectInstance {
     }
 }
 
+impl MapEntities for PassiveEffectInstance {
+    fn map_entities(&mut self, entity_map: &EntityMap) {
+        entity_map.map_opt(&mut self.item);
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct PassiveEffectMetadata {
     pub name: String,
@@ -1,24 +1,34 @@
-//! These events are meant to trigger actions in the main loop.
-//! This might seem excessive, for now, but in the future we want to be able to do things like
-//! jumping between game modes, for example, like starting a test game with a map we are editing
-//! in the editor, without having to exit to main menu, select game mode, select map, etc.
+//! These events are meant to trigger actions in the main loop, like jumping between game modes.
+//! Events are queued when dispatched, and handled by the main loop, in the order that they were
+//! dispatched, before the next frame, so a node that dispatches an event will finish its current
+//! update before it is removed from the scene.
 
-static mut APPLICATION_EVENTS: Option<Vec<ApplicationEvent>> = None;
+use std::collections::VecDeque;
+use std::sync::Mutex;
 
-unsafe fn get_event_queue() -> &'static mut Vec<ApplicationEvent> {
-    APPLICATION_EVENTS.get_or_insert(Vec::new())
-}
+use crate::editor::EditorInputScheme;
+use crate::game::MatchSettings;
+use crate::player::PlayerParams;
+use crate::resources::MapResource;
+use crate::Map;
+
+static APPLICATION_EVENTS: Mutex<VecDeque<ApplicationEvent>> = Mutex::new(VecDeque::new());
 
 pub fn dispatch_application_event(event: ApplicationEvent) {
-    unsafe { get_event_queue() }.push(event);
+    APPLICATION_EVENTS
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .push_back(event);
 }
 
+/// Iterate over, and remove, the queued events. Events that are dispatched while iterating are
+/// returned by the same iterator.
 pub fn iter_events() -> ApplicationEventIterator {
     ApplicationEventIterator::new()
 }
 
 /// This holds all the event types
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone)]
 pub enum ApplicationEvent {
     /// Reload resources
     ReloadResources,
@@ -26,6 +36,24 @@ pub enum ApplicationEvent {
     MainMenu,
     /// Quit to desktop
     Quit,
+    /// Start a local match on `map`, with `players`. If this is dispatched from the editor, the
+    /// match can return to it, with `ReturnToEditor`.
+    StartLocalGame {
+        map: Map,
+        players: Vec<PlayerParams>,
+        settings: MatchSettings,
+    },
+    /// Open `map` in the editor
+    OpenEditor {
+        input_scheme: EditorInputScheme,
+        map: MapResource,
+    },
+    /// Restart the current local match, with the same map, players and settings, and a new seed.
+    /// This must only be dispatched during a local match.
+    RestartMatch,
+    /// End the current local match and return to the editor that it was started from. This must
+    /// only be dispatched during a match that was started from the editor.
+    ReturnToEditor,
 }
 
 impl ApplicationEvent {
@@ -48,6 +76,9 @@ impl Iterator for ApplicationEventIterator {
     type Item = ApplicationEvent;
 
     fn next(&mut self) -> Option<Self::Item> {
-        unsafe { get_event_queue() }.pop()
+        APPLICATION_EVENTS
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .pop_front()
     }
 }
@@ -20,6 +20,18 @@ enum ShakeType {
     Rotational,
 }
 
+/// What the camera is focused on
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CameraMode {
+    /// Keep all players in view
+    #[default]
+    Players,
+    /// Follow the player with the specified index, or all players, if it is not in the game
+    FollowPlayer(u8),
+    /// Stay where it is moved to, with `GameCamera::move_free`, like when spectating
+    Free,
+}
+
 pub struct GameCamera {
     bounds: Rect,
     follow_buffer: Vec<(Vec2, f32)>,
@@ -27,8 +39,10 @@ pub struct GameCamera {
     noisegen: NoiseGenerator,
     noisegen_position: f32,
 
-    pub manual: Option<(Vec2, f32)>,
-    player_rects: Vec<Rect>,
+    mode: CameraMode,
+    /// The target and zoom of the camera in `CameraMode::Free`
+    free_view: (Vec2, f32),
+    player_rects: Vec<(u8, Rect)>,
 }
 
 impl GameCamera {
@@ -41,15 +55,46 @@ impl GameCamera {
             bounds,
             follow_buffer: vec![],
             shake: vec![],
-            manual: None,
             noisegen: NoiseGenerator::new(5),
             noisegen_position: 5.0,
+            mode: CameraMode::default(),
+            free_view: (bounds.point() + bounds.size() / 2.0, bounds.h),
             player_rects: Vec::new(),
         }
     }
 
-    pub fn add_player_rect(&mut self, rect: Rect) {
-        self.player_rects.push(rect);
+    /// Add the camera box of the player with the specified index, to be kept in view on the next
+    /// update, if the mode of the camera focuses on it
+    pub fn add_player_rect(&mut self, index: u8, rect: Rect) {
+        self.player_rects.push((index, rect));
+    }
+
+    pub fn mode(&self) -> CameraMode {
+        self.mode
+    }
+
+    /// Change what the camera is focused on. When switching to `CameraMode::Free`, the camera
+    /// starts out where it currently is.
+    pub fn set_mode(&mut self, mode: CameraMode) {
+        if mode == CameraMode::Free && self.mode != CameraMode::Free {
+            if let Some(&view) = self.follow_buffer.first() {
+                self.free_view = view;
+            }
+        }
+
+        self.mode = mode;
+    }
+
+    /// Move the camera by `offset`, and multiply its zoom by `zoom_scale`, in
+    /// `CameraMode::Free`. The camera is kept within the bounds of the map.
+    pub fn move_free(&mut self, offset: Vec2, zoom_scale: f32) {
+        let (target, zoom) = &mut self.free_view;
+
+        *zoom = (*zoom * zoom_scale).clamp(100.0, self.bounds.h.max(self.bounds.w));
+        *target = (*target + offset).clamp(
+            self.bounds.point(),
+            self.bounds.point() + self.bounds.size(),
+        );
     }
 }
 
@@ -174,17 +219,38 @@ impl GameCamera {
             let mut min = vec2(10000.0, 10000.0);
             let mut max = vec2(-10000.0, -10000.0);
 
-            let player_cnt = self.player_rects.len();
-            for rect in self.player_rects.drain(0..player_cnt) {
+            let is_followed_player_present = matches!(self.mode, CameraMode::FollowPlayer(index)
+                if self.player_rects.iter().any(|(other, _)| *other == index));
+
+            let mut player_cnt = 0;
+            for (index, rect) in self.player_rects.drain(..) {
+                if is_followed_player_present && self.mode != CameraMode::FollowPlayer(index) {
+                    continue;
+                }
+
                 let camera_pox_middle = rect.point() + rect.size() / 2.0;
                 //let k = if player.controller_id == 1 { 0.8 } else { 0.2 };
                 middle_point += camera_pox_middle; // * k;
 
                 min = min.min(camera_pox_middle);
                 max = max.max(camera_pox_middle);
+
+                player_cnt += 1;
             }
 
-            middle_point /= player_cnt as f32;
+            // With no players in the game, the camera stays where it is
+            if player_cnt == 0 {
+                let (target, _) = self
+                    .follow_buffer
+                    .first()
+                    .copied()
+                    .unwrap_or(self.free_view);
+                middle_point = target;
+                min = target;
+                max = target;
+            } else {
+                middle_point /= player_cnt as f32;
+            }
 
             let border_x = 150.0;
             let border_y = 200.0;
@@ -201,9 +267,8 @@ impl GameCamera {
                 middle_point.y = self.bounds.h - scale.y / 2.0;
             }
 
-            if let Some((override_target, override_zoom)) = self.manual {
-                middle_point = override_target;
-                zoom = override_zoom;
+            if self.mode == CameraMode::Free {
+                (middle_point, zoom) = self.free_view;
             }
 
             self.follow_buffer.insert(0, (middle_point, zoom));
@@ -0,0 +1,382 @@
+//! This implements checksums of the simulation state, used to detect desyncs.
+//! A checksum is computed over the state at the start of every tick that is a multiple of
+//! `MatchSettings::checksum_interval`. It is recorded in the replay of the match and, in network
+//! games, exchanged with the remote peer. If the checksums of the peers differ, both peers dump
+//! their state to disk, and send it to each other, so that a diff of the states can be written,
+//! pointing out what diverged. When a replay is played back, the checksums are compared with the
+//! ones that were recorded, and the state is dumped if they differ.
+//!
+//! The state is dumped to the directory specified by `DESYNC_DIR_ENV_VAR`, or to `./desync`.
+
+use std::collections::{BTreeSet, VecDeque};
+use std::env;
+use std::path::{Path, PathBuf};
+
+use serde_json::Value;
+
+use core::network::fnv_hash;
+use core::Result;
+
+use super::{Game, GameSnapshot};
+
+/// If this is set, desync dumps will be written to the specified directory
+pub const DESYNC_DIR_ENV_VAR: &str = "FISHFIGHT_DESYNC_DIR";
+
+const DEFAULT_DESYNC_DIR: &str = "desync";
+
+/// The amount of the most recent states that are kept, to be dumped on a desync
+const STATE_HISTORY_LEN: usize = 16;
+
+/// Returns the part of the state in `snapshot` that is covered by checksums, as JSON.
+/// Anything that is only used for presentation, and that might be updated outside of the fixed
+/// update, like drawables, particle emitters and camera boxes, is left out. Entities that are not
+/// part of the simulation, like debug colliders, are already left out of the snapshot, when it is
+/// captured. Entity handles are normalized, as they will differ between peers, after a rollback.
+pub fn simulation_state(snapshot: &GameSnapshot) -> Result<Value> {
+    let mut state = serde_json::to_value(snapshot.normalized())?;
+
+    if let Some(entities) = state
+        .pointer_mut("/world/entities")
+        .and_then(Value::as_array_mut)
+    {
+        for entity in entities.iter_mut().filter_map(Value::as_object_mut) {
+            entity.remove("drawable");
+            entity.remove("particle_emitters");
+
+            if let Some(player) = entity.get_mut("player").and_then(Value::as_object_mut) {
+                player.remove("camera_box");
+            }
+        }
+    }
+
+    Ok(state)
+}
+
+/// Compute a checksum of `state`, using 64 bit FNV-1a over its JSON representation.
+/// As the keys of JSON objects are sorted, this is deterministic.
+pub fn checksum(state: &Value) -> u64 {
+    fnv_hash(state.to_string().as_bytes())
+}
+
+/// Returns a line for every value that differs between `local` and `remote`, with the JSON
+/// pointer to the value, followed by both values
+pub fn diff_states(local: &Value, remote: &Value) -> Vec<String> {
+    let mut res = Vec::new();
+    diff_values("", local, remote, &mut res);
+    res
+}
+
+fn diff_values(path: &str, local: &Value, remote: &Value, res: &mut Vec<String>) {
+    match (local, remote) {
+        (Value::Object(local), Value::Object(remote)) => {
+            let keys = local.keys().chain(remote.keys()).collect::<BTreeSet<_>>();
+
+            for key in keys {
+                diff_values(
+                    &format!("{}/{}", path, key),
+                    local.get(key).unwrap_or(&Value::Null),
+                    remote.get(key).unwrap_or(&Value::Null),
+                    res,
+                );
+            }
+        }
+        (Value::Array(local), Value::Array(remote)) => {
+            for i in 0..local.len().max(remote.len()) {
+                diff_values(
+                    &format!("{}/{}", path, i),
+                    local.get(i).unwrap_or(&Value::Null),
+                    remote.get(i).unwrap_or(&Value::Null),
+                    res,
+                );
+            }
+        }
+        _ => {
+            if local != remote {
+                res.push(format!("{}: {} != {}", path, local, remote));
+            }
+        }
+    }
+}
+
+fn desync_dir() -> PathBuf {
+    env::var(DESYNC_DIR_ENV_VAR)
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from(DEFAULT_DESYNC_DIR))
+}
+
+#[cfg(any(target_family = "unix", target_family = "windows"))]
+fn write_dump<P: AsRef<Path>>(path: P, contents: &str) -> Result<()> {
+    use std::fs;
+
+    let path = path.as_ref();
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    fs::write(path, contents)?;
+
+    Ok(())
+}
+
+#[cfg(target_family = "wasm")]
+fn write_dump<P: AsRef<Path>>(_: P, _: &str) -> Result<()> {
+    Ok(())
+}
+
+/// Write the local state of `tick` and, if available, the state of the remote peer, or of the
+/// replay, as well as a diff of the two, to the desync directory
+pub fn dump_desync(tick: u64, local: &Value, remote: Option<&Value>) -> Result<PathBuf> {
+    let dir = desync_dir();
+
+    write_dump(
+        dir.join(format!("desync_{}_local.json", tick)),
+        &serde_json::to_string_pretty(local)?,
+    )?;
+
+    if let Some(remote) = remote {
+        write_dump(
+            dir.join(format!("desync_{}_remote.json", tick)),
+            &serde_json::to_string_pretty(remote)?,
+        )?;
+
+        let diff = diff_states(local, remote);
+
+        write_dump(
+            dir.join(format!("desync_{}.diff", tick)),
+            &format!("--- local\n+++ remote\n{}\n", diff.join("\n")),
+        )?;
+    }
+
+    Ok(dir)
+}
+
+/// This keeps the recent states that checksums were computed for, so that they can be dumped,
+/// if a desync is detected
+pub struct ChecksumState {
+    interval: u64,
+    states: VecDeque<(u64, Value)>,
+    /// This is set when the replay that is played back diverges, so that it is only reported once
+    is_replay_diverged: bool,
+    /// The tick of the desync that is waiting for the state of the remote peer to arrive
+    pending_desync: Option<u64>,
+    /// The state received from the remote peer, as it might arrive before the desync is
+    /// detected locally
+    remote_state: Option<(u64, Value)>,
+}
+
+impl ChecksumState {
+    pub fn new(interval: u64) -> Self {
+        ChecksumState {
+            interval,
+            states: VecDeque::new(),
+            is_replay_diverged: false,
+            pending_desync: None,
+            remote_state: None,
+        }
+    }
+
+    /// Returns `true` if a checksum should be computed for the state at the start of `tick`
+    pub fn is_checksum_tick(&self, tick: u64) -> bool {
+        self.interval > 0 && tick % self.interval == 0
+    }
+
+    /// Discard the states, and any desync that is being reported, like when the game continues
+    /// from a snapshot received from the host
+    pub(super) fn reset(&mut self) {
+        self.states.clear();
+        self.pending_desync = None;
+        self.remote_state = None;
+    }
+
+    fn get_state(&self, tick: u64) -> Option<&Value> {
+        self.states
+            .iter()
+            .find(|(other, _)| *other == tick)
+            .map(|(_, state)| state)
+    }
+}
+
+impl Game {
+    /// Compute the checksum of the state in `snapshot`, record it in the replay, compare it to
+    /// the checksum in the replay that is played back, if any, and send it to the remote peer,
+    /// in a network game
+    pub(super) fn update_checksum(&mut self, snapshot: &GameSnapshot) {
+        if let Err(err) = self.try_update_checksum(snapshot) {
+            println!(
+                "WARNING: Unable to update checksum for tick {}: {}",
+                snapshot.clock.tick(),
+                err
+            );
+        }
+    }
+
+    fn try_update_checksum(&mut self, snapshot: &GameSnapshot) -> Result<()> {
+        let tick = snapshot.clock.tick();
+
+        let state = simulation_state(snapshot)?;
+        let checksum = checksum(&state);
+
+        self.replay_recorder.record_checksum(tick, checksum);
+
+        if let Some(playback) = &self.replay_playback {
+            let is_diverged =
+                matches!(playback.get_checksum(tick), Some(recorded) if recorded != checksum);
+
+            if is_diverged && !self.checksums.is_replay_diverged {
+                self.checksums.is_replay_diverged = true;
+
+                let dir = dump_desync(tick, &state, None)?;
+
+                println!(
+                    "WARNING: Replay diverged from the recording at tick {}. State dumped to '{}'",
+                    tick,
+                    dir.display()
+                );
+            }
+        }
+
+        if let Some(network) = &mut self.network {
+            network.add_checksum(tick, checksum);
+        }
+
+        let checksums = &mut self.checksums;
+
+        checksums.states.push_back((tick, state));
+
+        while checksums.states.len() > STATE_HISTORY_LEN {
+            checksums.states.pop_front();
+        }
+
+        Ok(())
+    }
+
+    /// Handle desyncs reported by the network session, by dumping the local state and sending it
+    /// to the remote peer, and dump the state of the remote peer, with a diff, once received
+    pub(super) fn check_desync(&mut self) -> Result<()> {
+        let network = match &mut self.network {
+            Some(network) => network,
+            None => return Ok(()),
+        };
+
+        if let Some(tick) = network.take_desync() {
+            #[cfg(debug_assertions)]
+            println!("WARNING: Desync detected at tick {}", tick);
+
+            if let Some(state) = self.checksums.get_state(tick) {
+                let dir = dump_desync(tick, state, None)?;
+                network.send_state(tick, state.to_string().as_bytes())?;
+
+                self.checksums.pending_desync = Some(tick);
+
+                #[cfg(debug_assertions)]
+                println!("Local state dumped to '{}'", dir.display());
+            }
+        }
+
+        if let Some((tick, bytes)) = network.take_remote_state() {
+            let remote = serde_json::from_slice::<Value>(&bytes)?;
+            self.checksums.remote_state = Some((tick, remote));
+        }
+
+        let checksums = &mut self.checksums;
+
+        if let (Some(tick), Some((remote_tick, remote))) =
+            (checksums.pending_desync, &checksums.remote_state)
+        {
+            if tick == *remote_tick {
+                if let Some(local) = checksums.get_state(tick) {
+                    let dir = dump_desync(tick, local, Some(remote))?;
+
+                    #[cfg(debug_assertions)]
+                    println!("Remote state and diff dumped to '{}'", dir.display());
+                }
+
+                checksums.pending_desync = None;
+                checksums.remote_state = None;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use macroquad::prelude::vec2;
+
+    use hecs::World;
+
+    use crate::effects::active::DebugCollider;
+    use crate::game::headless::tests::{create_test_game, test_input};
+    use crate::game::{GameSnapshot, MatchSettings, WorldSnapshot};
+    use crate::Transform;
+
+    use super::{checksum, diff_states, simulation_state};
+
+    #[test]
+    fn test_checksum() {
+        let (_guard, mut game) = create_test_game(MatchSettings::new(9));
+
+        for tick in 0..60 {
+            game.step(&test_input(tick));
+        }
+
+        let snapshot = game.snapshot();
+        let state = simulation_state(&snapshot).unwrap();
+
+        // Restoring the snapshot allocates new entity handles, which must not affect the checksum
+        game.restore(&snapshot).unwrap();
+
+        let restored = simulation_state(&game.snapshot()).unwrap();
+        assert_eq!(checksum(&state), checksum(&restored));
+
+        let mut perturbed = snapshot.clone();
+
+        let transform = perturbed
+            .world
+            .entities
+            .iter_mut()
+            .find(|entity| entity.player.is_some())
+            .and_then(|entity| entity.transform.as_mut())
+            .unwrap();
+
+        transform.position.x += 1.0;
+
+        let perturbed = simulation_state(&perturbed).unwrap();
+        assert_ne!(checksum(&state), checksum(&perturbed));
+        assert!(!diff_states(&state, &perturbed).is_empty());
+    }
+
+    #[test]
+    fn test_checksum_debug_collider() {
+        let (_guard, game) = create_test_game(MatchSettings::new(3));
+
+        let snapshot = GameSnapshot {
+            players: Vec::new(),
+            ..game.snapshot()
+        };
+
+        let mut world = World::new();
+        world.spawn((Transform::new(vec2(16.0, 32.0), 0.0),));
+
+        let state = simulation_state(&GameSnapshot {
+            world: WorldSnapshot::capture(&world),
+            ..snapshot.clone()
+        })
+        .unwrap();
+
+        // Debug colliders are only spawned in debug builds, and are despawned when they are drawn
+        let mut debug_world = World::new();
+        debug_world.spawn((Transform::new(vec2(0.0, 0.0), 0.0), DebugCollider));
+        debug_world.spawn((Transform::new(vec2(16.0, 32.0), 0.0),));
+
+        let debug_state = simulation_state(&GameSnapshot {
+            world: WorldSnapshot::capture(&debug_world),
+            ..snapshot
+        })
+        .unwrap();
+
+        assert_eq!(checksum(&state), checksum(&debug_state));
+    }
+}
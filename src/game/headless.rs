@@ -0,0 +1,328 @@
+//! This implements a headless game, meaning a game that is simulated without a window, that has
+//! to be stepped manually, by calling `HeadlessGame::step`, with the input of every player.
+//! This can be used to run matches on machines without a GPU, like for automated tests of
+//! gameplay, bot training and dedicated hosting.
+
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use macroquad::experimental::collections::storage;
+
+use hecs::{Entity, World};
+
+use core::Result;
+
+use crate::effects::passive::init_passive_effects;
+use crate::network::LockstepSession;
+use crate::player::{PlayerController, PlayerParams};
+use crate::{GameInput, Map, Resources};
+
+use super::{Game, GameMode, GameSnapshot, MatchSettings, Replay, SimulationClock};
+
+/// This loads the resources required by a headless game and initializes everything that would
+/// otherwise be initialized by the main loop.
+/// Refer to `Resources::new_headless` for details on what is loaded.
+pub fn init_headless<P: AsRef<Path>>(assets_dir: P, mods_dir: P) -> Result<()> {
+    let resources = Resources::new_headless(assets_dir, mods_dir)?;
+    storage::store(resources);
+
+    init_passive_effects();
+
+    Ok(())
+}
+
+pub struct HeadlessGame {
+    game: Game,
+}
+
+impl HeadlessGame {
+    /// Create a new headless game. Resources must be initialized, by calling `init_headless`,
+    /// before this is called.
+    pub fn new(map: Map, player_params: &[PlayerParams], settings: MatchSettings) -> Result<Self> {
+        Self::with_mode(GameMode::Local, map, player_params, settings)
+    }
+
+    /// Create a new headless game with the specified mode. In a network game, the input of local
+    /// players must still be applied through `step`, using controllers of kind
+    /// `PlayerControllerKind::External`. Resources must be initialized, by calling
+    /// `init_headless`, before this is called.
+    pub fn with_mode(
+        mode: GameMode,
+        map: Map,
+        player_params: &[PlayerParams],
+        settings: MatchSettings,
+    ) -> Result<Self> {
+        let game = Game::create(mode, map, player_params, settings, None, true)?;
+
+        Ok(HeadlessGame { game })
+    }
+
+    /// Create a new headless game that plays back `replay`. Resources must be initialized, by
+    /// calling `init_headless`, before this is called.
+    pub fn from_replay(replay: Replay) -> Result<Self> {
+        let game = Game::create_from_replay(replay, true)?;
+
+        Ok(HeadlessGame { game })
+    }
+
+    /// The amount of ticks that has been simulated
+    pub fn tick(&self) -> u64 {
+        storage::get::<SimulationClock>().tick()
+    }
+
+    pub fn replay(&self) -> &Replay {
+        self.game.replay()
+    }
+
+    pub fn world(&self) -> &World {
+        self.game.world()
+    }
+
+    pub fn players(&self) -> &[Entity] {
+        self.game.players()
+    }
+
+    /// Capture the state of the simulation at the current tick
+    pub fn snapshot(&self) -> GameSnapshot {
+        self.game.snapshot()
+    }
+
+    /// Replace the state of the simulation with the state captured in `snapshot`
+    pub fn restore(&mut self, snapshot: &GameSnapshot) -> Result<()> {
+        self.game.restore(snapshot)
+    }
+
+    pub fn network(&self) -> Option<&LockstepSession> {
+        self.game.network()
+    }
+
+    /// The amount of times the game has been rolled back, when using `NetcodeMode::Rollback`
+    pub fn rollback_cnt(&self) -> u64 {
+        self.game
+            .rollback()
+            .map(|rollback| rollback.rollback_cnt())
+            .unwrap_or_default()
+    }
+
+    /// Keep exchanging messages with the remote peer, until it has received all the local input
+    /// that it needs to simulate up until the current tick, or until it is disconnected.
+    /// When using `NetcodeMode::Rollback`, this will also wait for the input of all simulated
+    /// ticks to be confirmed, rolling back if required, so that the state of the game is final.
+    /// This should be called when done stepping a network game, before it is dropped.
+    pub fn flush_network(&mut self) -> Result<()> {
+        let tick = self.tick();
+
+        if self.game.rollback.is_some() {
+            while !self.game.resolve_predictions()? {
+                if self.game.network.as_ref().unwrap().is_disconnected() {
+                    break;
+                }
+
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+
+        if let Some(network) = &mut self.game.network {
+            while !network.is_synced(tick) && !network.is_disconnected() {
+                network.poll()?;
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Simulate one tick, using the specified input, where each entry is a tuple of a player
+    /// index and the input of that player. Players that have no entry in `input` will have their
+    /// controller cleared for this tick, except for bots, which always decide their own input.
+    /// In a network game, this returns `false` if the tick could not be simulated, because remote
+    /// input is missing for it, in which case it should be called again, with the same input.
+    pub fn step(&mut self, input: &[(u8, GameInput)]) -> bool {
+        for (_, controller) in self.game.world.query_mut::<&mut PlayerController>() {
+            controller.clear();
+        }
+
+        for &(index, input) in input {
+            self.game.apply_player_input(index, input);
+        }
+
+        // Local players are given their input by `update_player_controllers`
+        self.game.local_inputs = input.iter().copied().collect();
+
+        let context = self.game.run_context();
+        self.game.updates.execute(&mut self.game.world, &context);
+
+        self.game.fixed_update()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::sync::{Mutex, MutexGuard};
+
+    use macroquad::experimental::collections::storage;
+    use macroquad::prelude::Vec2;
+
+    use crate::game::{
+        MatchPhase, MatchRules, MatchState, WinCondition, ROUND_END_DELAY, TICK_LENGTH,
+    };
+    use crate::player::{Player, PlayerControllerKind, PlayerParams};
+    use crate::{ApplicationEvent, GameInput, Resources, Transform};
+
+    use super::{init_headless, HeadlessGame, MatchSettings};
+
+    /// Headless games keep their state in the global storage, so tests that run them must not run
+    /// concurrently
+    static HEADLESS_LOCK: Mutex<()> = Mutex::new(());
+
+    /// This initializes the resources of a headless game and creates a two player game, on the
+    /// first map. The returned guard must be held for as long as the game is used.
+    pub(crate) fn create_test_game(
+        settings: MatchSettings,
+    ) -> (MutexGuard<'static, ()>, HeadlessGame) {
+        create_test_game_with_controllers(
+            settings,
+            [
+                PlayerControllerKind::External,
+                PlayerControllerKind::External,
+            ],
+        )
+    }
+
+    /// This creates a test game like `create_test_game`, where the players have the specified
+    /// controllers
+    pub(crate) fn create_test_game_with_controllers(
+        settings: MatchSettings,
+        controllers: [PlayerControllerKind; 2],
+    ) -> (MutexGuard<'static, ()>, HeadlessGame) {
+        let guard = HEADLESS_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+
+        init_headless("assets", "mods").unwrap();
+
+        let (map, player_params) = {
+            let resources = storage::get::<Resources>();
+
+            let mut characters = resources
+                .player_characters
+                .values()
+                .cloned()
+                .collect::<Vec<_>>();
+
+            characters.sort_by(|a, b| a.id.cmp(&b.id));
+
+            let player_params = controllers
+                .into_iter()
+                .enumerate()
+                .map(|(index, controller)| PlayerParams {
+                    index: index as u8,
+                    controller,
+                    character: characters[index % characters.len()].clone(),
+                })
+                .collect::<Vec<_>>();
+
+            (resources.maps[0].map.clone(), player_params)
+        };
+
+        let game = HeadlessGame::new(map, &player_params, settings).unwrap();
+
+        (guard, game)
+    }
+
+    /// Scripted input, that makes the players move, jump and shoot, so that tests exercise more of
+    /// the simulation than just idle players
+    pub(crate) fn test_input(tick: u64) -> Vec<(u8, GameInput)> {
+        (0..2)
+            .map(|index| {
+                let phase = (tick / 30 + index as u64) % 4;
+
+                let input = GameInput {
+                    left: phase == 0,
+                    right: phase == 2,
+                    jump: tick % 45 == 0,
+                    fire: phase == 1,
+                    ..GameInput::default()
+                };
+
+                (index, input)
+            })
+            .collect()
+    }
+
+    fn player_position(game: &HeadlessGame, index: u8) -> Vec2 {
+        game.world()
+            .query::<(&Player, &Transform)>()
+            .iter()
+            .find(|(_, (player, _))| player.index == index)
+            .map(|(_, (_, transform))| transform.position)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_headless_game() {
+        let (_guard, mut game) = create_test_game_with_controllers(
+            MatchSettings::new(1),
+            [PlayerControllerKind::External, PlayerControllerKind::Bot],
+        );
+
+        assert_eq!(game.players().len(), 2);
+
+        let bot_position = player_position(&game, 1);
+
+        // The bot decides its own input, so only the input of the other player is applied
+        for tick in 0..120 {
+            let input = test_input(tick)
+                .into_iter()
+                .filter(|(index, _)| *index == 0)
+                .collect::<Vec<_>>();
+
+            assert!(game.step(&input));
+        }
+
+        assert_eq!(game.tick(), 120);
+
+        assert_ne!(
+            player_position(&game, 1).x,
+            bot_position.x,
+            "Bot should move towards the other player"
+        );
+    }
+
+    #[test]
+    fn test_match_over() {
+        let (_guard, mut game) = create_test_game(MatchSettings {
+            rules: MatchRules::new(WinCondition::Kills { kills: 1 }, 1),
+            ..MatchSettings::new(4)
+        });
+
+        storage::get_mut::<MatchState>().on_player_killed(1, Some(0));
+        assert!(game.step(&[]));
+
+        assert!(matches!(
+            storage::get::<MatchState>().phase(),
+            MatchPhase::MatchOver {
+                winner: Some(0),
+                ..
+            }
+        ));
+
+        // The outcome is shown for a while, before the game moves on
+        assert!(game.game.match_over_event().is_none());
+
+        for _ in 0..(ROUND_END_DELAY / TICK_LENGTH) as u64 {
+            game.step(&[]);
+        }
+
+        assert!(matches!(
+            game.game.match_over_event(),
+            Some(ApplicationEvent::RestartMatch)
+        ));
+
+        game.game.set_playtest(true);
+
+        assert!(matches!(
+            game.game.match_over_event(),
+            Some(ApplicationEvent::ReturnToEditor)
+        ));
+    }
+}
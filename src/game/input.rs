@@ -7,17 +7,17 @@ use serde::{Deserialize, Serialize};
 
 use crate::json;
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum GameInputScheme {
-    /// Left side of the keyboard, around WASD
+    /// Right side of the keyboard, around Arrows
     KeyboardRight,
-    /// Left side of the keyboard, around Arrows
+    /// Left side of the keyboard, around WASD
     KeyboardLeft,
     /// Gamepad index
     Gamepad(fishsticks::GamepadId),
 }
 
-#[derive(Default, Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct GameInput {
     #[serde(default, skip_serializing_if = "json::is_false")]
     pub left: bool,
@@ -39,6 +39,60 @@ pub struct GameInput {
     pub slide: bool,
 }
 
+impl GameInput {
+    /// Pack the input into the bits of a `u8`, as it is sent over the network
+    pub fn to_bits(&self) -> u8 {
+        [
+            self.left,
+            self.right,
+            self.down,
+            self.jump,
+            self.float,
+            self.pickup,
+            self.fire,
+            self.slide,
+        ]
+        .iter()
+        .enumerate()
+        .fold(0, |bits, (i, &is_set)| bits | ((is_set as u8) << i))
+    }
+
+    /// Latch `input`, collected on a frame, into this, which holds the input collected since the
+    /// last simulated tick. Held buttons are taken from `input`, while presses, which are only set
+    /// on the frame that a button is pressed, are kept until `clear_presses` is called, so that
+    /// they are not lost on frames where no tick is simulated.
+    pub fn latch(&mut self, input: GameInput) {
+        *self = GameInput {
+            jump: self.jump || input.jump,
+            pickup: self.pickup || input.pickup,
+            slide: self.slide || input.slide,
+            ..input
+        };
+    }
+
+    /// Clear the presses, once the input has been applied to a tick
+    pub fn clear_presses(&mut self) {
+        self.jump = false;
+        self.pickup = false;
+        self.slide = false;
+    }
+
+    pub fn from_bits(bits: u8) -> Self {
+        let is_set = |i: u8| bits & (1 << i) != 0;
+
+        GameInput {
+            left: is_set(0),
+            right: is_set(1),
+            down: is_set(2),
+            jump: is_set(3),
+            float: is_set(4),
+            pickup: is_set(5),
+            fire: is_set(6),
+            slide: is_set(7),
+        }
+    }
+}
+
 pub fn collect_local_input(input_scheme: GameInputScheme) -> GameInput {
     let mut input = GameInput::default();
 
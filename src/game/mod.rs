@@ -1,8 +1,39 @@
 mod camera;
+mod checksum;
+mod headless;
 mod input;
 mod music;
+mod reconnect;
+mod replay;
+mod rollback;
+mod rules;
+mod settings;
+mod snapshot;
+mod spectator;
+mod time;
+
+pub use camera::{CameraMode, GameCamera};
+pub use checksum::{
+    checksum, diff_states, dump_desync, simulation_state, ChecksumState, DESYNC_DIR_ENV_VAR,
+};
+pub use headless::{init_headless, HeadlessGame};
+pub use replay::{
+    Replay, ReplayInput, ReplayPlayback, ReplayPlayer, ReplayRecorder, ReplayRemoval,
+};
+pub use rollback::RollbackState;
+pub use rules::{
+    draw_match_hud, update_match_state, MatchPhase, MatchRules, MatchState, PlayerScore,
+    WinCondition, ROUND_END_DELAY,
+};
+pub use settings::{
+    DisconnectRule, MatchSettings, NetcodeMode, DEFAULT_CHECKSUM_INTERVAL,
+    DEFAULT_MAX_ROLLBACK_TICKS, DEFAULT_RECONNECT_TIMEOUT,
+};
+pub use snapshot::{EntitySnapshot, GameSnapshot, PhysicsBodySnapshot, WorldSnapshot};
+pub use time::{get_delta_time, get_tick, SimulationClock, TICK_LENGTH};
 
-pub use camera::GameCamera;
+use std::collections::HashMap;
+use std::net::SocketAddr;
 
 use fishsticks::{Button, GamepadContext};
 
@@ -13,21 +44,28 @@ use macroquad::ui::root_ui;
 
 use hecs::{Entity, World};
 
+use core::rng::Rng;
 use core::Result;
 
 use crate::debug;
-use crate::ecs::Scheduler;
-use crate::gui::{self, GAME_MENU_RESULT_MAIN_MENU, GAME_MENU_RESULT_QUIT};
-use crate::physics::{debug_draw_physics_bodies, fixed_update_physics_bodies};
+use crate::ecs::{
+    Access, IntoSystemDescriptor, RunCondition, RunContext, Scheduler, Stage, SystemLabel,
+};
+use crate::gui::{
+    self, ChatLog, GAME_MENU_RESULT_MAIN_MENU, GAME_MENU_RESULT_QUIT, GAME_MENU_RESULT_RESTART,
+};
+use crate::physics::{debug_draw_physics_bodies, fixed_update_physics_bodies, PhysicsBody};
 use crate::player::{
-    draw_weapons_hud, spawn_player, update_player_animations, update_player_camera_box,
-    update_player_controllers, update_player_events, update_player_inventory,
-    update_player_passive_effects, update_player_states, PlayerParams,
+    collect_local_inputs, despawn_player, draw_weapons_hud, spawn_player, update_player_animations,
+    update_player_camera_box, update_player_controllers, update_player_events,
+    update_player_inventory, update_player_passive_effects, update_player_states, Player,
+    PlayerController, PlayerControllerKind, PlayerParams,
 };
 use crate::{
     create_collision_world, debug_draw_drawables, debug_draw_rigid_bodies, draw_drawables,
     exit_to_main_menu, fixed_update_rigid_bodies, is_gamepad_btn_pressed, quit_to_desktop,
-    update_animated_sprites, Map, MapLayerKind, MapObjectKind, Resources,
+    restart_match, return_to_editor, update_animated_sprites, ApplicationEvent, Drawable, Map,
+    MapLayerKind, MapObjectKind, Resources, Transform,
 };
 
 pub use input::{collect_local_input, GameInput, GameInputScheme};
@@ -38,157 +76,759 @@ use crate::effects::active::triggered::fixed_update_triggered_effects;
 use crate::items::spawn_item;
 use crate::map::{fixed_update_sproingers, spawn_decoration, spawn_sproinger};
 use crate::network::{
-    fixed_update_network_client, fixed_update_network_host, update_network_client,
-    update_network_host, NetworkClient, NetworkHost,
+    local_handshake, LanAnnouncement, LanAnnouncer, LockstepSession, PeerStatus, RelayConfig,
+};
+use crate::particles::{
+    draw_particles, spawn_emitted_particles, update_particle_emitters, ParticleEmitter,
 };
-use crate::particles::{draw_particles, update_particle_emitters};
 pub use music::{start_music, stop_music};
 
+/// The label of the player systems, in the fixed update scheduler of a game
+pub const PLAYER_LABEL: SystemLabel = "player";
+/// The label of the physics systems, which are executed after the player systems
+pub const PHYSICS_LABEL: SystemLabel = "physics";
+/// The label of the systems of active effects, which are executed after the physics systems
+pub const EFFECTS_LABEL: SystemLabel = "effects";
+/// The label of the systems of map objects, which are executed after the effect systems
+pub const MAP_LABEL: SystemLabel = "map";
+/// The label of the systems that keep the score, and end rounds, which are executed after all
+/// other systems in the fixed update scheduler
+pub const MATCH_LABEL: SystemLabel = "match";
+/// The label of the systems that update particle emitters, in the update scheduler of a game
+pub const PARTICLES_LABEL: SystemLabel = "particles";
+/// The label of the draw systems. Debug drawing is done after these.
+pub const DRAW_LABEL: SystemLabel = "draw";
+
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum GameMode {
     Local,
-    NetworkHost,
-    NetworkClient,
+    /// Host a network game, waiting for the remote peer on `port`, and through `relay`, if
+    /// specified
+    NetworkHost {
+        port: u16,
+        relay: Option<RelayConfig>,
+    },
+    /// Join the network game hosted at `host`, falling back to `relay`, if specified and the
+    /// host can not be reached directly
+    NetworkClient {
+        host: SocketAddr,
+        relay: Option<RelayConfig>,
+    },
+    /// Watch the network game hosted at `host`, as a spectator. If the spectator takes the slots
+    /// of a remote peer, those players are controlled with `input_scheme`.
+    NetworkSpectator {
+        host: SocketAddr,
+        relay: Option<RelayConfig>,
+        input_scheme: GameInputScheme,
+    },
 }
 
 pub struct Game {
+    mode: GameMode,
     world: World,
-    #[allow(dead_code)]
     players: Vec<Entity>,
+    /// The players that the match was started with
+    player_params: Vec<PlayerParams>,
+    /// The input of the local players, by player index, collected every frame, until it is
+    /// applied on the next tick
+    local_inputs: HashMap<u8, GameInput>,
+    replay_recorder: ReplayRecorder,
+    replay_playback: Option<ReplayPlayback>,
+    network: Option<LockstepSession>,
+    lan_announcer: Option<LanAnnouncer>,
+    /// The chat overlay, if this is a network game
+    chat: Option<ChatLog>,
+    /// This is used to show a message in the chat when the connection with the remote peer
+    /// changes
+    peer_status: PeerStatus,
+    rollback: Option<RollbackState>,
+    checksums: ChecksumState,
+    is_headless: bool,
+    /// If this is set, the match was started from the editor, which escape returns to
+    is_playtest: bool,
     updates: Scheduler,
     fixed_updates: Scheduler,
     draws: Scheduler,
-    #[cfg(debug_assertions)]
-    debug_draws: Scheduler,
 }
 
 impl Game {
-    pub fn new(mode: GameMode, map: Map, player_params: &[PlayerParams]) -> Result<Game> {
-        let mut world = World::default();
+    pub fn new(
+        mode: GameMode,
+        map: Map,
+        player_params: &[PlayerParams],
+        settings: MatchSettings,
+    ) -> Result<Game> {
+        Self::create(mode, map, player_params, settings, None, false)
+    }
+
+    /// Create a local game that plays back `replay`
+    pub fn from_replay(replay: Replay) -> Result<Game> {
+        Self::create_from_replay(replay, false)
+    }
+
+    fn create_from_replay(replay: Replay, is_headless: bool) -> Result<Game> {
+        let map = replay.map.clone();
+        let player_params = replay.player_params();
+        let settings = replay.settings.clone();
+
+        let playback = ReplayPlayback::new(replay);
+
+        Self::create(
+            GameMode::Local,
+            map,
+            &player_params,
+            settings,
+            Some(playback),
+            is_headless,
+        )
+    }
+
+    /// This creates the game and its schedulers. If `is_headless` is `true`, systems that collect
+    /// local input, or that depend on a window for drawing or a graphics context, are left out,
+    /// so that the game can be stepped by a `HeadlessGame`.
+    fn create(
+        mode: GameMode,
+        map: Map,
+        player_params: &[PlayerParams],
+        settings: MatchSettings,
+        replay_playback: Option<ReplayPlayback>,
+        is_headless: bool,
+    ) -> Result<Game> {
+        let network = match &mode {
+            GameMode::Local => None,
+            GameMode::NetworkHost { port, relay } => Some(LockstepSession::host(
+                *port,
+                *relay,
+                local_handshake(&map)?,
+                player_params,
+                &settings,
+            )?),
+            GameMode::NetworkClient { host, relay } => Some(LockstepSession::connect(
+                *host,
+                *relay,
+                local_handshake(&map)?,
+                player_params,
+                &settings,
+            )?),
+            GameMode::NetworkSpectator { host, relay, .. } => Some(LockstepSession::spectate(
+                *host,
+                *relay,
+                local_handshake(&map)?,
+                &settings,
+            )?),
+        };
+
+        // Spectators never roll back, as they only apply confirmed input
+        let rollback = match network.as_ref().map(LockstepSession::netcode) {
+            Some(NetcodeMode::Rollback) => Some(RollbackState::new()),
+            _ => None,
+        };
+
+        let checksums = ChecksumState::new(settings.checksum_interval);
+
+        let chat = if network.is_some() && !is_headless {
+            Some(ChatLog::new())
+        } else {
+            None
+        };
 
         {
             let camera = GameCamera::new(map.get_size());
             storage::store(camera);
+        }
+
+        let (world, players) = init_match(&map, player_params, &settings);
+
+        let replay_recorder = ReplayRecorder::new(settings, map.clone(), player_params);
+
+        storage::store(map);
+
+        // Systems that depend on a window are skipped when headless, so that the game can be
+        // stepped by a `HeadlessGame`
+        // Animations and particle emitters are updated in parallel, as they are the bulk of the
+        // work that is done every frame
+        let updates = Scheduler::builder()
+            .with_name("update")
+            .with_default_stage(Stage::Presentation)
+            .with_thread_local(update_player_camera_box.run_if(RunCondition::NotHeadless))
+            .with_system(
+                update_player_animations
+                    .run_if(RunCondition::NotHeadless)
+                    .run_if(RunCondition::NotPaused),
+                Access::new()
+                    .read::<Player>()
+                    .read::<PhysicsBody>()
+                    .write::<Drawable>(),
+            )
+            .with_system(
+                update_animated_sprites
+                    .run_if(RunCondition::NotHeadless)
+                    .run_if(RunCondition::NotPaused),
+                Access::new().write::<Drawable>(),
+            )
+            .with_system(
+                update_particle_emitters
+                    .label(PARTICLES_LABEL)
+                    .run_if(RunCondition::NotHeadless)
+                    .run_if(RunCondition::NotPaused),
+                Access::new()
+                    .read::<Transform>()
+                    .write::<ParticleEmitter>()
+                    .write::<Vec<ParticleEmitter>>(),
+            )
+            .with_thread_local(
+                spawn_emitted_particles
+                    .after(PARTICLES_LABEL)
+                    .run_if(RunCondition::NotHeadless),
+            )
+            .build();
+
+        // All peers in a network game run the complete simulation, as only input is exchanged
+        let fixed_updates = Scheduler::builder()
+            .with_name("fixed_update")
+            .with_thread_local(update_player_states.label(PLAYER_LABEL))
+            .with_thread_local(update_player_inventory.label(PLAYER_LABEL))
+            .with_thread_local(update_player_passive_effects.label(PLAYER_LABEL))
+            .with_thread_local(update_player_events.label(PLAYER_LABEL))
+            .with_thread_local(
+                fixed_update_physics_bodies
+                    .label(PHYSICS_LABEL)
+                    .after(PLAYER_LABEL),
+            )
+            .with_thread_local(
+                fixed_update_rigid_bodies
+                    .label(PHYSICS_LABEL)
+                    .after(PLAYER_LABEL),
+            )
+            .with_thread_local(
+                fixed_update_projectiles
+                    .label(EFFECTS_LABEL)
+                    .after(PHYSICS_LABEL),
+            )
+            .with_thread_local(
+                fixed_update_triggered_effects
+                    .label(EFFECTS_LABEL)
+                    .after(PHYSICS_LABEL),
+            )
+            .with_thread_local(
+                fixed_update_sproingers
+                    .label(MAP_LABEL)
+                    .after(EFFECTS_LABEL),
+            )
+            .with_thread_local(update_match_state.label(MATCH_LABEL).after(MAP_LABEL))
+            .build();
+
+        let draws = Scheduler::builder()
+            .with_name("draw")
+            .with_default_stage(Stage::Presentation)
+            .with_thread_local(draw_drawables.label(DRAW_LABEL))
+            .with_thread_local(draw_weapons_hud.label(DRAW_LABEL))
+            .with_thread_local(draw_particles.label(DRAW_LABEL))
+            .with_thread_local(draw_match_hud.after(DRAW_LABEL))
+            .with_thread_local(
+                debug_draw_drawables
+                    .after(DRAW_LABEL)
+                    .run_if(RunCondition::Debug)
+                    .run_if(RunCondition::If(debug::is_debug_draw_enabled)),
+            )
+            .with_thread_local(
+                debug_draw_physics_bodies
+                    .after(DRAW_LABEL)
+                    .run_if(RunCondition::Debug)
+                    .run_if(RunCondition::If(debug::is_debug_draw_enabled)),
+            )
+            .with_thread_local(
+                debug_draw_rigid_bodies
+                    .after(DRAW_LABEL)
+                    .run_if(RunCondition::Debug)
+                    .run_if(RunCondition::If(debug::is_debug_draw_enabled)),
+            )
+            .with_thread_local(
+                debug_draw_active_effects
+                    .after(DRAW_LABEL)
+                    .run_if(RunCondition::Debug)
+                    .run_if(RunCondition::If(debug::is_debug_draw_enabled)),
+            )
+            .build();
+
+        let res = Game {
+            mode,
+            world,
+            players,
+            player_params: player_params.to_vec(),
+            local_inputs: HashMap::new(),
+            replay_recorder,
+            replay_playback,
+            network,
+            lan_announcer: None,
+            chat,
+            peer_status: PeerStatus::Connecting,
+            rollback,
+            checksums,
+            is_headless,
+            is_playtest: false,
+            updates,
+            fixed_updates,
+            draws,
+        };
+
+        Ok(res)
+    }
+
+    pub fn mode(&self) -> &GameMode {
+        &self.mode
+    }
+
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+
+    pub fn players(&self) -> &[Entity] {
+        &self.players
+    }
 
-            let collision_world = create_collision_world(&map);
-            storage::store(collision_world);
+    /// Returns `true` if the game is paused, which is when the game menu is open in a local game.
+    /// Network games are never paused, as the remote peer keeps playing.
+    pub fn is_paused(&self) -> bool {
+        self.network.is_none() && !self.is_headless && gui::is_game_menu_open()
+    }
+
+    /// Returns `true` if the match can be restarted, with `ApplicationEvent::RestartMatch`, which
+    /// is only the case for local matches, that are not replays
+    pub fn can_restart(&self) -> bool {
+        self.mode == GameMode::Local && self.replay_playback.is_none()
+    }
+
+    /// The event that the game moves on with, once the match is finished. A playtest returns to
+    /// the editor, a local match is restarted, and network matches and replays exit to the main
+    /// menu, as they can not be restarted.
+    pub fn match_over_event(&self) -> Option<ApplicationEvent> {
+        let is_finished = storage::try_get::<MatchState>()
+            .map(|state| state.is_finished(get_tick()))
+            .unwrap_or(false);
+
+        if !is_finished {
+            None
+        } else if self.is_playtest {
+            Some(ApplicationEvent::ReturnToEditor)
+        } else if self.can_restart() {
+            Some(ApplicationEvent::RestartMatch)
+        } else {
+            Some(ApplicationEvent::MainMenu)
+        }
+    }
+
+    /// Set whether the match was started from the editor, in which case escape returns to the
+    /// editor, in stead of opening the game menu
+    pub fn set_playtest(&mut self, is_playtest: bool) {
+        self.is_playtest = is_playtest;
+    }
+
+    /// The context that the run conditions of systems are checked against
+    pub fn run_context(&self) -> RunContext {
+        RunContext {
+            is_paused: self.is_paused(),
+            is_authority: self
+                .network
+                .as_ref()
+                .map(LockstepSession::is_host)
+                .unwrap_or(true),
+            is_headless: self.is_headless,
+            frame_time: if self.is_headless {
+                0.0
+            } else {
+                get_frame_time()
+            },
+        }
+    }
+
+    /// The scheduler that is executed every frame, before the game is drawn. Game modes, and
+    /// mods, can add systems to the schedulers of a game, which are ordered by their stages and
+    /// by the labels of the systems added by the game, like `PLAYER_LABEL`.
+    pub fn updates_mut(&mut self) -> &mut Scheduler {
+        &mut self.updates
+    }
+
+    /// The scheduler that is executed every simulated tick. Systems added to this have to be
+    /// deterministic, and must be added by all peers of a network game.
+    pub fn fixed_updates_mut(&mut self) -> &mut Scheduler {
+        &mut self.fixed_updates
+    }
+
+    /// The scheduler that is executed every frame, to draw the game
+    pub fn draws_mut(&mut self) -> &mut Scheduler {
+        &mut self.draws
+    }
+
+    /// Capture the state of the simulation at the current tick
+    pub fn snapshot(&self) -> GameSnapshot {
+        GameSnapshot {
+            clock: *storage::get::<SimulationClock>(),
+            rng: storage::get::<Rng>().clone(),
+            match_state: storage::get::<MatchState>().clone(),
+            players: self.players.clone(),
+            world: WorldSnapshot::capture(&self.world),
+        }
+    }
+
+    /// Replace the state of the simulation with the state captured in `snapshot`.
+    /// Player controllers will keep their current kind, so that local input and replay playback
+    /// continues to work after the snapshot is restored.
+    pub fn restore(&mut self, snapshot: &GameSnapshot) -> Result<()> {
+        let controller_kinds = self
+            .world
+            .query_mut::<(&Player, &PlayerController)>()
+            .into_iter()
+            .map(|(_, (player, controller))| (player.index, controller.kind.clone()))
+            .collect::<HashMap<_, _>>();
+
+        let (mut world, collision_world, entity_map) = {
+            let map = storage::get::<Map>();
+            snapshot.world.restore(&map)
+        };
+
+        for (_, (player, controller)) in world.query_mut::<(&Player, &mut PlayerController)>() {
+            if let Some(kind) = controller_kinds.get(&player.index) {
+                controller.kind = kind.clone();
+            }
         }
 
-        spawn_map_objects(&mut world, &map).unwrap();
+        self.world = world;
+
+        self.players = snapshot
+            .players
+            .iter()
+            .filter_map(|&entity| entity_map.get(entity))
+            .collect();
+
+        storage::store(collision_world);
+        storage::store(snapshot.clock);
+        storage::store(snapshot.rng.clone());
+        storage::store(snapshot.match_state.clone());
+
+        Ok(())
+    }
+
+    /// The replay of the match, up until the current tick
+    pub fn replay(&self) -> &Replay {
+        self.replay_recorder.replay()
+    }
 
-        let mut players = Vec::new();
-        for PlayerParams {
-            index,
-            controller,
-            character,
-        } in player_params.iter().cloned()
+    /// Apply `input` to the controller of the player with the specified `index`.
+    /// This is used to feed input to players whose input is not collected by the
+    /// `update_player_controllers` system, like when the game is running headless.
+    pub fn apply_player_input(&mut self, index: u8, input: GameInput) {
+        for (_, (player, controller)) in self.world.query_mut::<(&Player, &mut PlayerController)>()
         {
-            let position = map.get_random_spawn_point();
-            let player = spawn_player(&mut world, index, position, controller, character);
+            if player.index == index {
+                controller.apply_input(input);
+            }
+        }
+    }
+
+    /// Remove the player with the specified `index` from the game, dropping its weapon and items
+    pub fn remove_player(&mut self, index: u8) {
+        let entity = self.players.iter().copied().find(|&entity| {
+            matches!(self.world.get::<Player>(entity), Ok(player) if player.index == index)
+        });
 
-            players.push(player);
+        if let Some(entity) = entity {
+            despawn_player(&mut self.world, entity);
+            self.players.retain(|&other| other != entity);
         }
+    }
 
-        storage::store(map);
+    /// The lockstep session, if this is a network game
+    pub fn network(&self) -> Option<&LockstepSession> {
+        self.network.as_ref()
+    }
+
+    /// Announce the game on the local network, for as long as it is running. This should only be
+    /// used when hosting a network game.
+    pub fn announce_on_lan(&mut self, announcement: LanAnnouncement) -> Result<()> {
+        self.lan_announcer = Some(LanAnnouncer::new(announcement)?);
+
+        Ok(())
+    }
+
+    /// The rollback state, if this is a network game using `NetcodeMode::Rollback`
+    pub fn rollback(&self) -> Option<&RollbackState> {
+        self.rollback.as_ref()
+    }
+
+    /// Execute the fixed update scheduler and advance the simulation clock by one tick.
+    /// In a network game, the tick is not simulated if input is missing for it, in which case
+    /// this returns `false`. When using `NetcodeMode::Rollback`, this might also simulate
+    /// previous ticks again, if the input of remote players was mispredicted.
+    fn fixed_update(&mut self) -> bool {
+        let tick = get_tick();
 
-        let mut updates_builder = Scheduler::builder();
+        // When headless, local input is not collected, but set by `HeadlessGame::step`
+        update_player_controllers(&mut self.world, &mut self.local_inputs);
 
-        let mut fixed_updates_builder = Scheduler::builder();
+        let removals = self
+            .replay_playback
+            .as_mut()
+            .map(|playback| playback.take_removals(tick))
+            .unwrap_or_default();
 
-        match mode {
-            GameMode::NetworkClient { .. } => {
-                let e = world.spawn(());
-                world.insert_one(e, NetworkClient::new())?;
+        for index in removals {
+            self.remove_player(index);
+        }
 
-                updates_builder.add_system(update_network_client);
+        if let Some(playback) = &mut self.replay_playback {
+            playback.advance_to(tick);
 
-                fixed_updates_builder.add_system(fixed_update_network_client);
+            for (_, (player, controller)) in
+                self.world.query_mut::<(&Player, &mut PlayerController)>()
+            {
+                if let PlayerControllerKind::Replay = controller.kind {
+                    controller.apply_input(playback.get_input(player.index));
+                }
             }
-            GameMode::NetworkHost { .. } => {
-                let e = world.spawn(());
-                world.insert_one(e, NetworkHost::new())?;
+        }
 
-                updates_builder.add_system(update_network_host);
+        if self.network.is_some() {
+            return self.network_fixed_update().unwrap_or_else(|_err| {
+                #[cfg(debug_assertions)]
+                println!("WARNING: Network error: {}", _err);
 
-                fixed_updates_builder.add_system(fixed_update_network_host);
+                false
+            });
+        }
+
+        self.simulate_confirmed_tick();
+
+        true
+    }
+
+    fn network_fixed_update(&mut self) -> Result<bool> {
+        self.update_reconnection()?;
+        self.update_spectators()?;
+
+        // A client simulates nothing until it is welcomed by the host, as it has to start the
+        // match over, with the settings that the host sends
+        let network = self.network.as_mut().unwrap();
+
+        if !network.is_host() && network.peer_status() == PeerStatus::Connecting {
+            network.poll()?;
+
+            if let Some(settings) = network.take_host_settings() {
+                self.restart_match(settings);
             }
-            _ => {}
+
+            return Ok(false);
         }
 
-        updates_builder
-            .add_system(update_player_controllers)
-            .add_system(update_player_camera_box);
-
-        if matches!(mode, GameMode::Local | GameMode::NetworkHost { .. }) {
-            updates_builder
-                .add_system(update_player_states)
-                .add_system(update_player_inventory)
-                .add_system(update_player_passive_effects)
-                .add_system(update_player_events);
-
-            fixed_updates_builder
-                .add_system(fixed_update_physics_bodies)
-                .add_system(fixed_update_rigid_bodies)
-                .add_system(fixed_update_projectiles)
-                .add_system(fixed_update_triggered_effects)
-                .add_system(fixed_update_sproingers);
+        // The tick changes, if a snapshot was restored
+        let tick = get_tick();
+
+        let is_simulated = if self.rollback.is_some() {
+            self.rollback_fixed_update()?
+        } else {
+            let is_ready = self
+                .network
+                .as_mut()
+                .unwrap()
+                .update(tick, &mut self.world)?;
+
+            if is_ready {
+                self.simulate_confirmed_tick();
+            }
+
+            is_ready
+        };
+
+        self.check_desync()?;
+
+        Ok(is_simulated)
+    }
+
+    /// Start the match over, from the first tick, with `settings`
+    fn restart_match(&mut self, settings: MatchSettings) {
+        let map = storage::get::<Map>().clone();
+
+        let (world, players) = init_match(&map, &self.player_params, &settings);
+
+        self.world = world;
+        self.players = players;
+        self.checksums = ChecksumState::new(settings.checksum_interval);
+
+        self.rollback = match self.network.as_ref().map(LockstepSession::netcode) {
+            Some(NetcodeMode::Rollback) => Some(RollbackState::new()),
+            _ => None,
+        };
+
+        self.replay_recorder = ReplayRecorder::new(settings, map, &self.player_params);
+    }
+
+    /// Simulate a tick that has final input, meaning that it will not be rolled back, computing
+    /// the checksum of the state before it is simulated, if it is due
+    fn simulate_confirmed_tick(&mut self) {
+        if self.checksums.is_checksum_tick(get_tick()) {
+            let snapshot = self.snapshot();
+            self.update_checksum(&snapshot);
         }
 
-        let updates = updates_builder
-            .with_system(update_player_animations)
-            .with_system(update_animated_sprites)
-            .with_system(update_particle_emitters)
-            .build();
+        self.simulate_tick();
+    }
 
-        let fixed_updates = fixed_updates_builder.build();
+    /// Execute the fixed update scheduler, using the input currently applied to the player
+    /// controllers, and advance the simulation clock by one tick
+    fn simulate_tick(&mut self) {
+        let tick = get_tick();
 
-        let draws = Scheduler::builder()
-            .with_thread_local(draw_drawables)
-            .with_thread_local(draw_weapons_hud)
-            .with_thread_local(draw_particles)
-            .build();
+        let context = self.run_context();
+        self.fixed_updates.execute(&mut self.world, &context);
 
-        #[cfg(debug_assertions)]
-        let debug_draws = Scheduler::builder()
-            .with_thread_local(debug_draw_drawables)
-            .with_thread_local(debug_draw_physics_bodies)
-            .with_thread_local(debug_draw_rigid_bodies)
-            .with_thread_local(debug_draw_active_effects)
-            .build();
+        // When rolling back, input is recorded once it is confirmed, in stead
+        if self.rollback.is_none() {
+            for (_, (player, controller)) in self.world.query_mut::<(&Player, &PlayerController)>()
+            {
+                self.replay_recorder
+                    .record(tick, player.index, controller.input);
+            }
+        }
 
-        let res = Game {
-            world,
-            players,
-            updates,
-            fixed_updates,
-            draws,
-            #[cfg(debug_assertions)]
-            debug_draws,
+        storage::get_mut::<SimulationClock>().advance();
+    }
+
+    /// Send the message written in the chat input, if any, and add received messages, and
+    /// changes to the connection with the remote peer, to the chat log
+    fn update_chat(&mut self) {
+        let (chat, network) = match (&mut self.chat, &mut self.network) {
+            (Some(chat), Some(network)) => (chat, network),
+            _ => return,
         };
 
-        Ok(res)
+        if let Some(text) = chat.update() {
+            if let Some(args) = text.strip_prefix("/mute ") {
+                set_chat_muted(chat, network, args, true);
+            } else if let Some(args) = text.strip_prefix("/unmute ") {
+                set_chat_muted(chat, network, args, false);
+            } else if let Some(&player) = network.local_players().first() {
+                match network.send_chat(player, &text) {
+                    Some(text) => chat.push_message(
+                        &player_chat_name(player),
+                        &player_chat_name(player),
+                        &text,
+                    ),
+                    None => chat.push_system_message("You are sending messages too fast"),
+                }
+            }
+        }
+
+        for (player, text) in network.take_chat_messages() {
+            let name = player_chat_name(player);
+            chat.push_message(&name, &name, &text);
+        }
+
+        let peer_status = network.peer_status();
+
+        if peer_status != self.peer_status {
+            let action = match peer_status {
+                PeerStatus::Connected if self.peer_status == PeerStatus::Reconnecting => {
+                    Some("reconnected")
+                }
+                PeerStatus::Connected => Some("joined"),
+                PeerStatus::Reconnecting => Some("lost the connection"),
+                // The remote players are removed once disconnected, which is shown in the chat
+                // by `remove_remote_players`
+                PeerStatus::Connecting | PeerStatus::Disconnected => None,
+            };
+
+            self.peer_status = peer_status;
+
+            if let Some(action) = action {
+                for player in network.remote_players() {
+                    chat.push_system_message(&format!("{} {}", player_chat_name(player), action));
+                }
+            }
+        }
     }
 
     fn on_update(&mut self) {
-        self.updates.execute(&mut self.world);
+        let context = self.run_context();
+        self.updates.execute(&mut self.world, &context);
+
+        // Escape closes the chat input, in stead of opening the game menu, while it is open
+        let is_chat_open = matches!(&self.chat, Some(chat) if chat.is_input_open());
+
+        self.update_chat();
+
+        // Keys pressed while writing a chat message, or while the game menu is open, should not
+        // move the local players
+        let is_input_blocked =
+            self.is_paused() || matches!(&self.chat, Some(chat) if chat.is_input_open());
+
+        if is_input_blocked {
+            self.local_inputs.clear();
+        } else {
+            collect_local_inputs(&self.world, &mut self.local_inputs);
+        }
+
+        if self.is_spectating() && !is_chat_open {
+            self.update_spectator_input();
+        }
+
+        // The event is handled before the next frame, which removes the game from the scene
+        if let Some(event) = self.match_over_event() {
+            event.dispatch();
+        }
+
+        if let Some(announcer) = &mut self.lan_announcer {
+            let is_connected = self
+                .network
+                .as_ref()
+                .map(|network| network.is_connected())
+                .unwrap_or(true);
+
+            // Once the remote peer has connected, the game is announced as full, which lists it
+            // as running, for spectators
+            let announcement = announcer.announcement();
+
+            if is_connected && announcement.player_count < announcement.capacity {
+                let mut announcement = announcement.clone();
+                announcement.player_count = announcement.capacity;
+
+                announcer.set_announcement(announcement);
+            }
+
+            if let Err(_err) = announcer.update() {
+                #[cfg(debug_assertions)]
+                println!("WARNING: Unable to announce game on LAN: {}", _err);
+            }
+        }
 
         #[cfg(debug_assertions)]
         if is_key_pressed(macroquad::prelude::KeyCode::U) {
             crate::debug::toggle_debug_draw();
         }
 
+        #[cfg(debug_assertions)]
+        if is_key_pressed(macroquad::prelude::KeyCode::I) {
+            crate::profiler::toggle_profiler();
+        }
+
         {
             let gamepad_context = storage::get::<GamepadContext>();
-            if is_key_pressed(macroquad::prelude::KeyCode::Escape)
+            let is_escape_pressed =
+                !is_chat_open && is_key_pressed(macroquad::prelude::KeyCode::Escape);
+
+            if self.is_playtest && is_escape_pressed {
+                return_to_editor();
+            } else if is_escape_pressed
                 || is_gamepad_btn_pressed(Some(&gamepad_context), Button::Start)
             {
-                gui::toggle_game_menu();
+                gui::toggle_game_menu(self.can_restart());
             }
         }
     }
 
     fn on_fixed_update(&mut self) {
-        self.fixed_updates.execute(&mut self.world);
+        if !self.is_paused() {
+            self.fixed_update();
+        }
     }
 
     fn on_draw(&mut self) {
@@ -200,11 +840,19 @@ impl Game {
             map.draw(None, true);
         }
 
-        self.draws.execute(&mut self.world);
+        let context = self.run_context();
+        self.draws.execute(&mut self.world, &context);
 
-        #[cfg(debug_assertions)]
-        if debug::is_debug_draw_enabled() {
-            self.debug_draws.execute(&mut self.world);
+        if self.is_spectating() {
+            self.draw_spectator_overlay();
+        }
+
+        if let Some(chat) = &self.chat {
+            chat.draw();
+        }
+
+        if crate::profiler::is_profiler_enabled() {
+            crate::profiler::draw_profiler_overlay(&self.updates, &self.fixed_updates, &self.draws);
         }
 
         if gui::is_game_menu_open() {
@@ -212,6 +860,7 @@ impl Game {
                 match res.into_usize() {
                     GAME_MENU_RESULT_MAIN_MENU => exit_to_main_menu(),
                     GAME_MENU_RESULT_QUIT => quit_to_desktop(),
+                    GAME_MENU_RESULT_RESTART => restart_match(),
                     _ => {}
                 }
             }
@@ -233,6 +882,82 @@ impl Node for Game {
     }
 }
 
+/// Initialize the simulation state of a match on `map`, storing the resources that are part of it,
+/// and return the world, with the objects of the map and the players spawned, along with the
+/// entities of the players
+fn init_match(
+    map: &Map,
+    player_params: &[PlayerParams],
+    settings: &MatchSettings,
+) -> (World, Vec<Entity>) {
+    let mut world = World::default();
+
+    storage::store(SimulationClock::new());
+    storage::store(MatchState::new(settings.rules));
+
+    // All gameplay systems must draw random numbers from this, in stead of from
+    // `macroquad::rand`, as the simulation has to be reproducible from the match settings.
+    let mut rng = Rng::new(settings.seed);
+
+    let collision_world = create_collision_world(map);
+    storage::store(collision_world);
+
+    spawn_map_objects(&mut world, map).unwrap();
+
+    let mut players = Vec::new();
+    for PlayerParams {
+        index,
+        controller,
+        character,
+    } in player_params.iter().cloned()
+    {
+        let position = settings
+            .spawn_point_overrides
+            .get(&index)
+            .and_then(|&i| map.spawn_points.get(i).copied())
+            .unwrap_or_else(|| map.get_random_spawn_point(&mut rng));
+
+        let player = spawn_player(&mut world, index, position, controller, character);
+
+        players.push(player);
+    }
+
+    storage::store(rng);
+
+    (world, players)
+}
+
+/// The name that the player with the specified index is shown by, in the chat of a network game.
+/// This is also used as the key of its name color, so that it is the same on both peers.
+fn player_chat_name(index: u8) -> String {
+    format!("Player {}", index + 1)
+}
+
+/// Handle the chat command that mutes, or unmutes, the player with the number given in `args`
+fn set_chat_muted(chat: &mut ChatLog, network: &mut LockstepSession, args: &str, is_muted: bool) {
+    if !network.is_host() {
+        chat.push_system_message("Only the host can mute players");
+        return;
+    }
+
+    let player = args
+        .trim()
+        .parse::<u8>()
+        .ok()
+        .and_then(|number| number.checked_sub(1))
+        .filter(|index| network.remote_players().contains(index));
+
+    match player {
+        Some(player) => {
+            network.set_muted(player, is_muted);
+
+            let action = if is_muted { "muted" } else { "unmuted" };
+            chat.push_system_message(&format!("{} was {}", player_chat_name(player), action));
+        }
+        None => chat.push_system_message("Usage: /mute <player number>"),
+    }
+}
+
 pub fn spawn_map_objects(world: &mut World, map: &Map) -> Result<Vec<Entity>> {
     let mut objects = Vec::new();
 
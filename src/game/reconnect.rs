@@ -0,0 +1,144 @@
+//! This implements the `Game` side of a remote peer losing its connection.
+//! While the peer is reconnecting, the `LockstepSession` stalls the simulation, or continues it
+//! with the remote players standing idle, according to the `DisconnectRule` of the match. Once
+//! the peer is back, the host sends it a snapshot of the game at the last confirmed tick, and both
+//! continue from that tick, exchanging the input of every tick after it again. If the peer does
+//! not come back before the reconnect timeout, its players are removed from the match.
+//! If the peer that is lost is the host, the client takes over as host, after removing the
+//! players of the old host at the last confirmed tick, and spectators follow it, continuing from
+//! a snapshot of that tick. The host is elected deterministically, as there is only ever one
+//! client controlling players, which the host tells spectators about, for as long as it is
+//! connected.
+//! The replay of a client that has restored a snapshot from the host will not play back
+//! identically, as the snapshot is not part of it.
+
+use core::error::ErrorKind;
+use core::{formaterr, Result};
+
+use super::{get_tick, player_chat_name, Game, GameMode, GameSnapshot};
+use crate::player::Player;
+
+impl Game {
+    /// Send a snapshot to the remote peer, if it is back after reconnecting, restore the snapshot
+    /// sent by the host, once received, and remove the remote players, if the remote peer has
+    /// disconnected
+    pub(super) fn update_reconnection(&mut self) -> Result<()> {
+        let network = self.network.as_mut().unwrap();
+
+        if network.is_resync_requested() {
+            let tick = self.rewind_to_confirmed_tick()?;
+            let snapshot = serde_json::to_vec(&self.snapshot())?;
+
+            self.network
+                .as_mut()
+                .unwrap()
+                .send_snapshot(tick, &snapshot)?;
+        } else if let Some((tick, bytes)) = network.take_snapshot() {
+            let snapshot = serde_json::from_slice::<GameSnapshot>(&bytes)?;
+
+            self.restore(&snapshot)?;
+
+            if let Some(rollback) = &mut self.rollback {
+                rollback.reset(tick);
+            }
+
+            self.checksums.reset();
+
+            // A client that joined as a spectator only learns the players of the match from the
+            // snapshot
+            let players = self
+                .world
+                .query_mut::<&Player>()
+                .into_iter()
+                .map(|(_, player)| player.index)
+                .collect::<Vec<_>>();
+
+            self.network.as_mut().unwrap().set_players(&players);
+            self.update_controller_kinds();
+        }
+
+        let network = self.network.as_mut().unwrap();
+
+        if network.follow_successor() {
+            if let Some(chat) = &mut self.chat {
+                chat.push_system_message("The host left, joining the new host");
+            }
+        } else if network.is_disconnected() && !network.remote_players().is_empty() {
+            self.remove_remote_players()?;
+        }
+
+        Ok(())
+    }
+
+    /// Remove the players of the remote peer from the match, once it has disconnected
+    fn remove_remote_players(&mut self) -> Result<()> {
+        let tick = self.rewind_to_confirmed_tick()?;
+
+        let players = {
+            let network = self.network.as_mut().unwrap();
+            let players = network.remote_players();
+            network.remove_remote_players(tick);
+            players
+        };
+
+        for index in players {
+            self.remove_player(index);
+            self.replay_recorder.record_removal(tick, index);
+
+            if let Some(chat) = &mut self.chat {
+                chat.push_system_message(&format!("{} left", player_chat_name(index)));
+            }
+        }
+
+        let network = self.network.as_mut().unwrap();
+
+        if network.take_over_as_host() {
+            // The relay is not taken over, as this does not register as the host with it
+            self.mode = GameMode::NetworkHost {
+                port: network.local_addr()?.port(),
+                relay: None,
+            };
+
+            if let Some(chat) = &mut self.chat {
+                chat.push_system_message("The host left, you are now the host");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Restore the state of the last confirmed tick, if ticks after it have been simulated with
+    /// predicted input, and discard all rollback snapshots, so that the game continues from the
+    /// returned tick, without ever rolling back past it
+    fn rewind_to_confirmed_tick(&mut self) -> Result<u64> {
+        let tick = get_tick();
+        let confirmed_tick = self.network.as_ref().unwrap().confirmed_tick().min(tick);
+
+        let rollback = match &mut self.rollback {
+            Some(rollback) => rollback,
+            None => return Ok(tick),
+        };
+
+        let snapshot = if confirmed_tick < tick {
+            let snapshot = rollback.snapshot(confirmed_tick).cloned().ok_or_else(|| {
+                formaterr!(
+                    ErrorKind::Network,
+                    "Unable to rewind to tick {}, as there is no snapshot of it",
+                    confirmed_tick
+                )
+            })?;
+
+            Some(snapshot)
+        } else {
+            None
+        };
+
+        rollback.reset(confirmed_tick);
+
+        if let Some(snapshot) = snapshot {
+            self.restore(&snapshot)?;
+        }
+
+        Ok(confirmed_tick)
+    }
+}
@@ -0,0 +1,280 @@
+//! This implements recording and playback of matches.
+//! As the simulation is deterministic, a replay only has to hold what a match was started with,
+//! meaning the map, the player characters and the match settings, as well as the input of every
+//! player, for every tick. Input is only stored when it changes, to keep replay files small.
+//! The checksums of the simulation state are also stored, so that a replay that does not play
+//! back identically can be detected.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use core::Result;
+
+use crate::player::{PlayerCharacterMetadata, PlayerControllerKind, PlayerParams};
+use crate::{GameInput, Map};
+
+use super::MatchSettings;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayPlayer {
+    pub index: u8,
+    pub character: PlayerCharacterMetadata,
+}
+
+/// The input of a player, from `tick` and until the next entry for the same player
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayInput {
+    pub tick: u64,
+    pub player: u8,
+    pub input: GameInput,
+}
+
+/// The checksum of the simulation state at the start of `tick`
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct ReplayChecksum {
+    pub tick: u64,
+    pub checksum: u64,
+}
+
+/// The removal of a player from the match, before `tick`, like when a remote player has
+/// disconnected
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct ReplayRemoval {
+    pub tick: u64,
+    pub player: u8,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Replay {
+    pub settings: MatchSettings,
+    pub map: Map,
+    pub players: Vec<ReplayPlayer>,
+    /// The amount of ticks that were recorded
+    pub tick_cnt: u64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub inputs: Vec<ReplayInput>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub checksums: Vec<ReplayChecksum>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub removals: Vec<ReplayRemoval>,
+}
+
+impl Replay {
+    pub fn new(settings: MatchSettings, map: Map, player_params: &[PlayerParams]) -> Self {
+        let players = player_params
+            .iter()
+            .map(|params| ReplayPlayer {
+                index: params.index,
+                character: params.character.clone(),
+            })
+            .collect();
+
+        Replay {
+            settings,
+            map,
+            players,
+            tick_cnt: 0,
+            inputs: Vec::new(),
+            checksums: Vec::new(),
+            removals: Vec::new(),
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let bytes = fs::read(path)?;
+        let replay = serde_json::from_slice(&bytes)?;
+
+        Ok(replay)
+    }
+
+    #[cfg(any(target_family = "unix", target_family = "windows"))]
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let json = serde_json::to_string(self)?;
+        fs::write(path, json)?;
+        Ok(())
+    }
+
+    #[cfg(target_family = "wasm")]
+    pub fn save<P: AsRef<Path>>(&self, _: P) -> Result<()> {
+        Ok(())
+    }
+
+    /// Get the params for the players of the replay. The returned players will all have a
+    /// controller of kind `PlayerControllerKind::Replay`.
+    pub fn player_params(&self) -> Vec<PlayerParams> {
+        self.players
+            .iter()
+            .map(|player| PlayerParams {
+                index: player.index,
+                controller: PlayerControllerKind::Replay,
+                character: player.character.clone(),
+            })
+            .collect()
+    }
+}
+
+/// This records the input of all players, for every tick, into a `Replay`.
+pub struct ReplayRecorder {
+    replay: Replay,
+    last_inputs: HashMap<u8, GameInput>,
+}
+
+impl ReplayRecorder {
+    pub fn new(settings: MatchSettings, map: Map, player_params: &[PlayerParams]) -> Self {
+        ReplayRecorder {
+            replay: Replay::new(settings, map, player_params),
+            last_inputs: HashMap::new(),
+        }
+    }
+
+    pub fn replay(&self) -> &Replay {
+        &self.replay
+    }
+
+    pub fn record(&mut self, tick: u64, player: u8, input: GameInput) {
+        let last_input = self.last_inputs.entry(player).or_default();
+
+        if *last_input != input {
+            *last_input = input;
+
+            self.replay.inputs.push(ReplayInput {
+                tick,
+                player,
+                input,
+            });
+        }
+
+        self.replay.tick_cnt = self.replay.tick_cnt.max(tick + 1);
+    }
+
+    pub fn record_checksum(&mut self, tick: u64, checksum: u64) {
+        self.replay
+            .checksums
+            .push(ReplayChecksum { tick, checksum });
+    }
+
+    /// Record that `player` was removed from the match, before `tick` was simulated
+    pub fn record_removal(&mut self, tick: u64, player: u8) {
+        self.replay.removals.push(ReplayRemoval { tick, player });
+    }
+}
+
+/// This holds the replay that is being played back, for the input of controllers of kind
+/// `PlayerControllerKind::Replay` to be read from.
+pub struct ReplayPlayback {
+    replay: Replay,
+    next_input: usize,
+    next_removal: usize,
+    current_inputs: HashMap<u8, GameInput>,
+}
+
+impl ReplayPlayback {
+    pub fn new(replay: Replay) -> Self {
+        ReplayPlayback {
+            replay,
+            next_input: 0,
+            next_removal: 0,
+            current_inputs: HashMap::new(),
+        }
+    }
+
+    pub fn replay(&self) -> &Replay {
+        &self.replay
+    }
+
+    /// Returns `true` if all recorded ticks have been played back
+    pub fn is_finished(&self, tick: u64) -> bool {
+        tick >= self.replay.tick_cnt
+    }
+
+    /// Advance the playback to `tick`. This must be called, with a `tick` that is equal to or
+    /// greater than the previous one, before the input of that tick is read.
+    pub fn advance_to(&mut self, tick: u64) {
+        while let Some(entry) = self.replay.inputs.get(self.next_input) {
+            if entry.tick > tick {
+                break;
+            }
+
+            self.current_inputs.insert(entry.player, entry.input);
+            self.next_input += 1;
+        }
+    }
+
+    /// Returns the players that were removed from the match before `tick`, since this was last
+    /// called
+    pub fn take_removals(&mut self, tick: u64) -> Vec<u8> {
+        let mut res = Vec::new();
+
+        while let Some(removal) = self.replay.removals.get(self.next_removal) {
+            if removal.tick > tick {
+                break;
+            }
+
+            res.push(removal.player);
+            self.next_removal += 1;
+        }
+
+        res
+    }
+
+    /// Get the recorded checksum of the simulation state at the start of `tick`, if any
+    pub fn get_checksum(&self, tick: u64) -> Option<u64> {
+        let checksums = &self.replay.checksums;
+
+        checksums
+            .binary_search_by_key(&tick, |entry| entry.tick)
+            .ok()
+            .map(|i| checksums[i].checksum)
+    }
+
+    /// Get the input of the player with the specified index, at the tick that the playback was
+    /// last advanced to
+    pub fn get_input(&self, player: u8) -> GameInput {
+        self.current_inputs
+            .get(&player)
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game::headless::tests::{create_test_game, test_input};
+    use crate::game::{checksum, simulation_state, HeadlessGame, MatchSettings};
+
+    use super::Replay;
+
+    #[test]
+    fn test_replay_round_trip() {
+        let (guard, mut game) = create_test_game(MatchSettings::new(3));
+
+        for tick in 0..180 {
+            game.step(&test_input(tick));
+        }
+
+        let expected = checksum(&simulation_state(&game.snapshot()).unwrap());
+
+        let json = serde_json::to_string(game.replay()).unwrap();
+        drop(game);
+
+        let replay: Replay = serde_json::from_str(&json).unwrap();
+        assert_eq!(replay.tick_cnt, 180);
+
+        let mut game = HeadlessGame::from_replay(replay).unwrap();
+
+        for _ in 0..180 {
+            game.step(&[]);
+        }
+
+        assert_eq!(game.tick(), 180);
+        assert_eq!(
+            checksum(&simulation_state(&game.snapshot()).unwrap()),
+            expected
+        );
+
+        drop(guard);
+    }
+}
@@ -0,0 +1,204 @@
+//! This implements the `Game` side of `NetcodeMode::Rollback`.
+//! Every tick is simulated as soon as the local input for it is available, with the input of
+//! remote players predicted by the `LockstepSession`, when it has not yet been received. A
+//! snapshot is captured before every tick that is simulated with predicted input, so that, if a
+//! prediction turns out to be wrong, the game can be restored to the first mispredicted tick and
+//! simulated again, up until the current tick, with the input that was actually received.
+
+use std::collections::VecDeque;
+
+use core::error::ErrorKind;
+use core::{formaterr, Result};
+
+use super::{get_tick, Game, GameSnapshot};
+
+#[derive(Default)]
+pub struct RollbackState {
+    /// The snapshots of all ticks that can still be rolled back to, captured before each of
+    /// those ticks was simulated
+    snapshots: VecDeque<GameSnapshot>,
+    /// The next tick that the confirmed input will be recorded to the replay for
+    next_recorded_tick: u64,
+    rollback_cnt: u64,
+}
+
+impl RollbackState {
+    pub fn new() -> Self {
+        RollbackState {
+            snapshots: VecDeque::new(),
+            next_recorded_tick: 0,
+            rollback_cnt: 0,
+        }
+    }
+
+    /// The amount of times the game has been rolled back
+    pub fn rollback_cnt(&self) -> u64 {
+        self.rollback_cnt
+    }
+
+    /// The snapshot captured before `tick` was simulated, if it can still be rolled back to
+    pub(super) fn snapshot(&self, tick: u64) -> Option<&GameSnapshot> {
+        self.snapshots
+            .iter()
+            .find(|snapshot| snapshot.clock.tick() == tick)
+    }
+
+    /// Discard all snapshots, as the game will continue from `tick`, which can not be rolled back
+    /// past, and record the confirmed input from `tick`, if it was ahead of it
+    pub(super) fn reset(&mut self, tick: u64) {
+        self.snapshots.clear();
+        self.next_recorded_tick = self.next_recorded_tick.min(tick);
+    }
+}
+
+impl Game {
+    /// Exchange input with the remote peer, roll back if a misprediction was detected, and
+    /// simulate the current tick. Returns `false` if the tick could not be simulated.
+    pub(super) fn rollback_fixed_update(&mut self) -> Result<bool> {
+        let tick = get_tick();
+
+        let misprediction = {
+            let network = self.network.as_mut().unwrap();
+            network.exchange(tick, &mut self.world)?;
+            network.take_misprediction()
+        };
+
+        if let Some(from_tick) = misprediction {
+            self.roll_back(from_tick, tick)?;
+        }
+
+        let is_simulated = self.simulate_predicted_tick(tick);
+
+        self.discard_confirmed_ticks();
+
+        Ok(is_simulated)
+    }
+
+    /// Exchange input with the remote peer and roll back if a misprediction was detected,
+    /// without simulating the current tick. Returns `true` when the input of every tick that has
+    /// been simulated is confirmed, meaning that the current state will no longer change.
+    pub(super) fn resolve_predictions(&mut self) -> Result<bool> {
+        let tick = get_tick();
+
+        let misprediction = {
+            let network = self.network.as_mut().unwrap();
+            network.exchange(tick, &mut self.world)?;
+            network.take_misprediction()
+        };
+
+        if let Some(from_tick) = misprediction {
+            self.roll_back(from_tick, tick)?;
+        }
+
+        self.discard_confirmed_ticks();
+
+        let is_resolved = self.network.as_ref().unwrap().confirmed_tick() >= tick;
+
+        Ok(is_resolved)
+    }
+
+    /// Record the input of newly confirmed ticks and discard the input and the snapshots of
+    /// ticks that can no longer be rolled back to
+    fn discard_confirmed_ticks(&mut self) {
+        let tick = get_tick();
+
+        self.record_confirmed_input(tick);
+
+        let confirmed_tick = {
+            let network = self.network.as_mut().unwrap();
+            network.discard_confirmed(tick);
+            network.confirmed_tick().min(tick)
+        };
+
+        // The snapshots of confirmed ticks are final, so checksums are computed from these
+        loop {
+            let rollback = self.rollback.as_mut().unwrap();
+
+            let snapshot = match rollback.snapshots.front() {
+                Some(snapshot) if snapshot.clock.tick() < confirmed_tick => {
+                    rollback.snapshots.pop_front().unwrap()
+                }
+                _ => break,
+            };
+
+            if self.checksums.is_checksum_tick(snapshot.clock.tick()) {
+                self.update_checksum(&snapshot);
+            }
+        }
+    }
+
+    /// Restore the snapshot of `from_tick` and simulate every tick up until `to_tick` again
+    fn roll_back(&mut self, from_tick: u64, to_tick: u64) -> Result<()> {
+        let snapshot = {
+            let rollback = self.rollback.as_mut().unwrap();
+
+            let i = rollback
+                .snapshots
+                .iter()
+                .position(|snapshot| snapshot.clock.tick() == from_tick)
+                .ok_or_else(|| {
+                    formaterr!(
+                        ErrorKind::Network,
+                        "Unable to roll back to tick {}, as there is no snapshot of it",
+                        from_tick
+                    )
+                })?;
+
+            rollback.rollback_cnt += 1;
+
+            rollback.snapshots.split_off(i).pop_front().unwrap()
+        };
+
+        self.restore(&snapshot)?;
+
+        for tick in from_tick..to_tick {
+            self.simulate_predicted_tick(tick);
+        }
+
+        Ok(())
+    }
+
+    fn simulate_predicted_tick(&mut self, tick: u64) -> bool {
+        if !self
+            .network
+            .as_mut()
+            .unwrap()
+            .apply_input(tick, &mut self.world)
+        {
+            return false;
+        }
+
+        let snapshot = self.snapshot();
+
+        self.rollback
+            .as_mut()
+            .unwrap()
+            .snapshots
+            .push_back(snapshot);
+
+        self.simulate_tick();
+
+        true
+    }
+
+    /// Record the input of every tick before `tick` that has been confirmed since this was last
+    /// called. Input is only recorded once confirmed, as predicted input might be rolled back.
+    fn record_confirmed_input(&mut self, tick: u64) {
+        let network = self.network.as_ref().unwrap();
+        let rollback = self.rollback.as_mut().unwrap();
+
+        let confirmed_tick = network.confirmed_tick().min(tick);
+
+        while rollback.next_recorded_tick < confirmed_tick {
+            let recorded_tick = rollback.next_recorded_tick;
+
+            if let Some(inputs) = network.confirmed_input(recorded_tick) {
+                for (player, input) in inputs {
+                    self.replay_recorder.record(recorded_tick, player, input);
+                }
+            }
+
+            rollback.next_recorded_tick += 1;
+        }
+    }
+}
@@ -0,0 +1,553 @@
+//! The rules of a match, decide how it is won. A match is played in rounds, and is won by the
+//! first player to win the majority of them. Without a win condition, there is only a single,
+//! endless, round, where dead players just respawn.
+//! The score of the match is kept in the `MatchState` resource, which is part of the simulation,
+//! so it is captured in snapshots and has to be updated deterministically. Kills are recorded by
+//! `update_player_events`, when a player receives damage that is not blocked, and are credited
+//! to the player that gave the damage.
+//! Once the match is over, and its outcome has been shown for `ROUND_END_DELAY`, the game moves
+//! on, as decided by `Game::match_over_event`.
+
+use std::collections::BTreeMap;
+
+use macroquad::experimental::collections::storage;
+use macroquad::prelude::*;
+
+use hecs::World;
+
+use serde::{Deserialize, Serialize};
+
+use core::rng::Rng;
+
+use super::{get_tick, TICK_LENGTH};
+use crate::player::{Player, PlayerState};
+use crate::{Map, PhysicsBody, Transform};
+
+/// The time, in seconds, between the end of a round and the start of the next one
+pub const ROUND_END_DELAY: f32 = 3.0;
+
+/// How a round is won
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "kind")]
+pub enum WinCondition {
+    /// The round never ends, and dead players respawn
+    #[default]
+    Endless,
+    /// The first player to reach the specified amount of kills wins the round
+    Kills { kills: u32 },
+    /// Dead players do not respawn, and the last player alive wins the round
+    LastFishStanding,
+    /// Every player has the specified amount of lives, and the last player with lives left wins
+    /// the round
+    Stock { lives: u32 },
+    /// The player with the most kills, when the time limit, in seconds, is reached, wins the
+    /// round. If several players are tied, the round is a draw.
+    TimeLimit { seconds: u32 },
+}
+
+impl WinCondition {
+    /// The win conditions that can be selected in menus
+    pub const PRESETS: [WinCondition; 5] = [
+        WinCondition::Endless,
+        WinCondition::Kills { kills: 5 },
+        WinCondition::LastFishStanding,
+        WinCondition::Stock { lives: 3 },
+        WinCondition::TimeLimit { seconds: 180 },
+    ];
+
+    /// The preset that follows this win condition, in `PRESETS`, for menus that cycle through
+    /// them
+    pub fn next_preset(&self) -> Self {
+        let i = Self::PRESETS
+            .iter()
+            .position(|preset| std::mem::discriminant(preset) == std::mem::discriminant(self));
+
+        match i {
+            Some(i) => Self::PRESETS[(i + 1) % Self::PRESETS.len()],
+            None => Self::PRESETS[0],
+        }
+    }
+
+    pub fn description(&self) -> String {
+        match self {
+            WinCondition::Endless => "Endless".to_string(),
+            WinCondition::Kills { kills } => format!("First to {} kills", kills),
+            WinCondition::LastFishStanding => "Last fish standing".to_string(),
+            WinCondition::Stock { lives } => format!("{} lives", lives),
+            WinCondition::TimeLimit { seconds } => {
+                format!("{}:{:02} time limit", seconds / 60, seconds % 60)
+            }
+        }
+    }
+}
+
+/// The rules of a match. These are part of `MatchSettings`, so they are identical for all peers
+/// of a network game. They are serialized as JSON in the settings of a lobby.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct MatchRules {
+    #[serde(default)]
+    pub win_condition: WinCondition,
+    /// The amount of rounds in the match, which is won by the first player to win the majority of
+    /// them. Rounds that end in a draw are not counted.
+    #[serde(default = "MatchRules::default_rounds")]
+    pub rounds: u32,
+}
+
+impl MatchRules {
+    /// The round counts that can be selected in menus
+    pub const ROUND_PRESETS: [u32; 3] = [1, 3, 5];
+
+    pub fn new(win_condition: WinCondition, rounds: u32) -> Self {
+        MatchRules {
+            win_condition,
+            rounds,
+        }
+    }
+
+    /// The preset that follows the current round count, in `ROUND_PRESETS`, for menus that cycle
+    /// through them
+    pub fn next_round_preset(&self) -> u32 {
+        Self::ROUND_PRESETS
+            .iter()
+            .copied()
+            .find(|&rounds| rounds > self.rounds)
+            .unwrap_or(Self::ROUND_PRESETS[0])
+    }
+
+    /// The amount of rounds a player has to win, to win the match
+    pub fn rounds_to_win(&self) -> u32 {
+        self.rounds.max(1) / 2 + 1
+    }
+
+    pub fn description(&self) -> String {
+        if self.rounds > 1 && self.win_condition != WinCondition::Endless {
+            format!(
+                "{}, best of {}",
+                self.win_condition.description(),
+                self.rounds
+            )
+        } else {
+            self.win_condition.description()
+        }
+    }
+
+    fn default_rounds() -> u32 {
+        1
+    }
+}
+
+impl Default for MatchRules {
+    fn default() -> Self {
+        Self::new(WinCondition::default(), Self::default_rounds())
+    }
+}
+
+/// The score of a single player
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct PlayerScore {
+    /// The kills in the current round
+    pub kills: u32,
+    /// The deaths in the current round
+    pub deaths: u32,
+    /// The kills in all rounds of the match
+    pub total_kills: u32,
+    pub rounds_won: u32,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "kind")]
+pub enum MatchPhase {
+    Playing,
+    /// The round has ended, and the next one starts at `next_round_tick`. If `winner` is `None`,
+    /// the round was a draw.
+    RoundOver {
+        winner: Option<u8>,
+        next_round_tick: u64,
+    },
+    /// The match has ended, at `end_tick`. If `winner` is `None`, the match was a draw.
+    MatchOver {
+        winner: Option<u8>,
+        end_tick: u64,
+    },
+}
+
+/// The score and round of the match that is being played
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MatchState {
+    rules: MatchRules,
+    /// The current round, starting at `1`
+    round: u32,
+    round_start_tick: u64,
+    phase: MatchPhase,
+    scores: BTreeMap<u8, PlayerScore>,
+}
+
+impl MatchState {
+    pub fn new(rules: MatchRules) -> Self {
+        MatchState {
+            rules,
+            round: 1,
+            round_start_tick: 0,
+            phase: MatchPhase::Playing,
+            scores: BTreeMap::new(),
+        }
+    }
+
+    pub fn rules(&self) -> &MatchRules {
+        &self.rules
+    }
+
+    pub fn round(&self) -> u32 {
+        self.round
+    }
+
+    pub fn phase(&self) -> MatchPhase {
+        self.phase
+    }
+
+    pub fn score(&self, index: u8) -> PlayerScore {
+        self.scores.get(&index).copied().unwrap_or_default()
+    }
+
+    /// The remaining time of the current round, in seconds, if it has a time limit
+    pub fn time_remaining(&self, tick: u64) -> Option<f32> {
+        if let WinCondition::TimeLimit { seconds } = self.rules.win_condition {
+            // Rounds with a time limit only end when the time is up
+            if self.phase != MatchPhase::Playing {
+                return Some(0.0);
+            }
+
+            let elapsed = tick.saturating_sub(self.round_start_tick) as f32 * TICK_LENGTH;
+
+            Some((seconds as f32 - elapsed).max(0.0))
+        } else {
+            None
+        }
+    }
+
+    /// Returns `true` once the match is over, and its outcome has been shown for
+    /// `ROUND_END_DELAY`
+    pub fn is_finished(&self, tick: u64) -> bool {
+        match self.phase {
+            MatchPhase::MatchOver { end_tick, .. } => {
+                tick >= end_tick + (ROUND_END_DELAY / TICK_LENGTH) as u64
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns `true` if the player with the specified index is out of the current round
+    pub fn is_eliminated(&self, index: u8) -> bool {
+        let deaths = self.score(index).deaths;
+
+        match self.rules.win_condition {
+            WinCondition::LastFishStanding => deaths > 0,
+            WinCondition::Stock { lives } => deaths >= lives,
+            _ => false,
+        }
+    }
+
+    /// Returns `true` if the dead player with the specified index can respawn. Players are
+    /// respawned by the match when a new round starts, in stead.
+    pub fn can_respawn(&self, index: u8) -> bool {
+        self.phase == MatchPhase::Playing && !self.is_eliminated(index)
+    }
+
+    /// Returns `true` if players can join the match, which is between rounds, or at any time in
+    /// an endless match
+    pub fn is_joinable(&self) -> bool {
+        match self.phase {
+            MatchPhase::Playing => self.rules.win_condition == WinCondition::Endless,
+            MatchPhase::RoundOver { .. } => true,
+            MatchPhase::MatchOver { .. } => false,
+        }
+    }
+
+    /// Record that the player with index `victim` was killed by the player with index `killer`.
+    /// Deaths are only counted while a round is being played.
+    pub fn on_player_killed(&mut self, victim: u8, killer: Option<u8>) {
+        if self.phase != MatchPhase::Playing {
+            return;
+        }
+
+        self.scores.entry(victim).or_default().deaths += 1;
+
+        if let Some(killer) = killer.filter(|&killer| killer != victim) {
+            let score = self.scores.entry(killer).or_default();
+
+            score.kills += 1;
+            score.total_kills += 1;
+        }
+    }
+
+    /// Check the win condition at `tick`, given the indices of all players in the match, ending
+    /// the round, or the match, if it is met, and start the next round, once it is due.
+    /// Returns `true` if a new round was started, in which case all players should be respawned.
+    pub fn update(&mut self, tick: u64, players: &[u8]) -> bool {
+        match self.phase {
+            MatchPhase::Playing => {
+                if let Some(winner) = self.round_winner(tick, players) {
+                    self.end_round(tick, winner);
+                }
+
+                false
+            }
+            MatchPhase::RoundOver {
+                next_round_tick, ..
+            } if tick >= next_round_tick => {
+                self.round += 1;
+                self.round_start_tick = tick;
+                self.phase = MatchPhase::Playing;
+
+                for score in self.scores.values_mut() {
+                    score.kills = 0;
+                    score.deaths = 0;
+                }
+
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns the outcome of the current round, if it has ended, where a winner of `None` is
+    /// a draw
+    fn round_winner(&self, tick: u64, players: &[u8]) -> Option<Option<u8>> {
+        match self.rules.win_condition {
+            WinCondition::Endless => None,
+            WinCondition::Kills { kills } => players
+                .iter()
+                .copied()
+                .find(|&index| self.score(index).kills >= kills)
+                .map(Some),
+            WinCondition::LastFishStanding | WinCondition::Stock { .. } => {
+                // A player can not be the last one standing on their own
+                if players.len() < 2 {
+                    return None;
+                }
+
+                let mut remaining = players
+                    .iter()
+                    .copied()
+                    .filter(|&index| !self.is_eliminated(index));
+
+                match (remaining.next(), remaining.next()) {
+                    (winner, None) => Some(winner),
+                    _ => None,
+                }
+            }
+            WinCondition::TimeLimit { .. } => {
+                if self.time_remaining(tick) > Some(0.0) {
+                    return None;
+                }
+
+                let most_kills = players
+                    .iter()
+                    .map(|&index| self.score(index).kills)
+                    .max()
+                    .unwrap_or_default();
+
+                let mut leaders = players
+                    .iter()
+                    .copied()
+                    .filter(|&index| self.score(index).kills == most_kills);
+
+                match (leaders.next(), leaders.next()) {
+                    (Some(winner), None) => Some(Some(winner)),
+                    _ => Some(None),
+                }
+            }
+        }
+    }
+
+    fn end_round(&mut self, tick: u64, winner: Option<u8>) {
+        if let Some(winner) = winner {
+            let score = self.scores.entry(winner).or_default();
+            score.rounds_won += 1;
+
+            if score.rounds_won >= self.rules.rounds_to_win() {
+                self.phase = MatchPhase::MatchOver {
+                    winner: Some(winner),
+                    end_tick: tick,
+                };
+
+                return;
+            }
+        }
+
+        self.phase = MatchPhase::RoundOver {
+            winner,
+            next_round_tick: tick + (ROUND_END_DELAY / TICK_LENGTH) as u64,
+        };
+    }
+}
+
+impl Default for MatchState {
+    fn default() -> Self {
+        Self::new(MatchRules::default())
+    }
+}
+
+/// End the round, or the match, when the win condition is met, and respawn all players at random
+/// spawn points, when the next round starts
+pub fn update_match_state(world: &mut World) {
+    let players = world
+        .query_mut::<&Player>()
+        .into_iter()
+        .map(|(_, player)| player.index)
+        .collect::<Vec<_>>();
+
+    let is_new_round = storage::try_get_mut::<MatchState>()
+        .map(|mut state| state.update(get_tick(), &players))
+        .unwrap_or(false);
+
+    if is_new_round {
+        let map = storage::get::<Map>();
+        let mut rng = storage::get_mut::<Rng>();
+
+        for (_, (transform, player, body)) in
+            world.query_mut::<(&mut Transform, &mut Player, &mut PhysicsBody)>()
+        {
+            player.state = PlayerState::None;
+            player.respawn_timer = 0.0;
+            player.incapacitation_timer = 0.0;
+
+            body.velocity = Vec2::ZERO;
+
+            transform.position = map.get_random_spawn_point(&mut rng);
+        }
+    }
+}
+
+/// Draw the score of every player, the remaining time, if the rounds have a time limit, and
+/// the outcome of the round, or match, once it has ended
+pub fn draw_match_hud(world: &mut World) {
+    let state = match storage::try_get::<MatchState>() {
+        Some(state) => state,
+        None => return,
+    };
+
+    if state.rules.win_condition == WinCondition::Endless {
+        return;
+    }
+
+    let mut indices = world
+        .query_mut::<&Player>()
+        .into_iter()
+        .map(|(_, player)| player.index)
+        .collect::<Vec<_>>();
+
+    indices.sort_unstable();
+
+    let mut lines = vec![format!(
+        "Round {} - {}",
+        state.round,
+        state.rules.description()
+    )];
+
+    if let Some(time_remaining) = state.time_remaining(get_tick()) {
+        let seconds = time_remaining.ceil() as u32;
+        lines.push(format!("{}:{:02}", seconds / 60, seconds % 60));
+    }
+
+    for index in indices {
+        let score = state.score(index);
+
+        let mut line = format!(
+            "Player {}: {} kills, {} rounds",
+            index + 1,
+            score.kills,
+            score.rounds_won
+        );
+
+        if let WinCondition::Stock { lives } = state.rules.win_condition {
+            line.push_str(&format!(", {} lives", lives.saturating_sub(score.deaths)));
+        }
+
+        lines.push(line);
+    }
+
+    let banner = match state.phase {
+        MatchPhase::Playing => None,
+        MatchPhase::RoundOver { winner, .. } => Some(match winner {
+            Some(index) => format!("Player {} wins round {}", index + 1, state.round),
+            None => format!("Round {} is a draw", state.round),
+        }),
+        MatchPhase::MatchOver { winner, .. } => Some(match winner {
+            Some(index) => format!("Player {} wins the match!", index + 1),
+            None => "The match is a draw".to_string(),
+        }),
+    };
+
+    push_camera_state();
+    set_default_camera();
+
+    for (i, line) in lines.iter().enumerate() {
+        let size = measure_text(line, None, 24, 1.0);
+        let x = (screen_width() - size.width) / 2.0;
+
+        draw_text(line, x, 32.0 + i as f32 * 24.0, 24.0, WHITE);
+    }
+
+    if let Some(banner) = banner {
+        let size = measure_text(&banner, None, 48, 1.0);
+        let x = (screen_width() - size.width) / 2.0;
+
+        draw_text(&banner, x, screen_height() / 2.0, 48.0, WHITE);
+    }
+
+    pop_camera_state();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_match_state() {
+        let mut state = MatchState::new(MatchRules::new(WinCondition::Stock { lives: 2 }, 3));
+
+        let players = [0, 1];
+
+        state.on_player_killed(1, Some(0));
+        assert!(!state.update(1, &players));
+        assert!(state.can_respawn(1));
+
+        state.on_player_killed(1, Some(0));
+        assert!(!state.update(2, &players));
+        assert!(!state.can_respawn(1));
+        assert_eq!(state.score(0).total_kills, 2);
+
+        // Kills are not counted between rounds
+        state.on_player_killed(0, Some(1));
+        assert_eq!(state.score(0).deaths, 0);
+
+        let next_round_tick = match state.phase() {
+            MatchPhase::RoundOver {
+                winner: Some(0),
+                next_round_tick,
+            } => next_round_tick,
+            phase => panic!("Unexpected phase {:?}", phase),
+        };
+
+        assert!(state.is_joinable());
+        assert!(!state.update(next_round_tick - 1, &players));
+        assert!(state.update(next_round_tick, &players));
+        assert_eq!(state.round(), 2);
+        assert_eq!(state.score(1).deaths, 0);
+
+        state.on_player_killed(1, None);
+        state.on_player_killed(1, None);
+        state.update(next_round_tick + 1, &players);
+
+        assert_eq!(
+            state.phase(),
+            MatchPhase::MatchOver {
+                winner: Some(0),
+                end_tick: next_round_tick + 1,
+            }
+        );
+        assert_eq!(state.score(0).total_kills, 2);
+        assert!(!state.is_finished(next_round_tick + 1));
+    }
+}
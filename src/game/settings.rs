@@ -0,0 +1,123 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::MatchRules;
+
+/// The default for `MatchSettings::max_rollback_ticks`
+pub const DEFAULT_MAX_ROLLBACK_TICKS: u64 = 8;
+
+/// The default for `MatchSettings::checksum_interval`
+pub const DEFAULT_CHECKSUM_INTERVAL: u64 = 60;
+
+/// The default for `MatchSettings::reconnect_timeout`
+pub const DEFAULT_RECONNECT_TIMEOUT: f64 = 30.0;
+
+/// The netcode model used by a network game
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NetcodeMode {
+    /// The simulation is stalled until the input of all players has been received for a tick.
+    /// Local input is delayed, to give remote input time to arrive.
+    #[default]
+    Lockstep,
+    /// Missing remote input is predicted, so the simulation never has to wait for it, and ticks
+    /// are simulated again, from a snapshot, if the prediction turns out to be wrong.
+    Rollback,
+}
+
+impl NetcodeMode {
+    pub fn description(&self) -> &'static str {
+        match self {
+            NetcodeMode::Lockstep => "Lockstep",
+            NetcodeMode::Rollback => "Rollback",
+        }
+    }
+}
+
+/// What happens to a network game while a remote peer is reconnecting
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DisconnectRule {
+    /// The simulation is stalled until the peer has reconnected, or timed out
+    #[default]
+    Pause,
+    /// The simulation goes on, with the players of the peer standing idle
+    Continue,
+}
+
+/// Settings that apply to a single match. These must be identical for all peers in a network
+/// game, so they are decided by the host and shared with the other peers before a match starts.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MatchSettings {
+    /// The seed used for the random number generator of the match
+    pub seed: u64,
+    #[serde(default)]
+    pub netcode: NetcodeMode,
+    /// The maximum amount of ticks that the simulation can run ahead of the last tick that the
+    /// input of all players has been received for, when using `NetcodeMode::Rollback`.
+    /// If this is exceeded, the simulation is stalled until more input arrives.
+    #[serde(default = "MatchSettings::default_max_rollback_ticks")]
+    pub max_rollback_ticks: u64,
+    /// The interval, in ticks, at which a checksum of the simulation state is computed, to
+    /// detect desyncs. Set this to `0` to disable checksums.
+    #[serde(default = "MatchSettings::default_checksum_interval")]
+    pub checksum_interval: u64,
+    #[serde(default)]
+    pub disconnect_rule: DisconnectRule,
+    /// The time, in seconds, that a remote peer that has lost its connection is given to
+    /// reconnect, before its players are removed from the match
+    #[serde(default = "MatchSettings::default_reconnect_timeout")]
+    pub reconnect_timeout: f64,
+    /// If this is set, the slots of a remote peer that has left the match can be taken by a
+    /// spectator
+    #[serde(default)]
+    pub allow_late_join: bool,
+    /// The spawn points, by their index in `Map::spawn_points`, that players start at, by player
+    /// index, in stead of at a random spawn point. This is used when playtesting a map.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub spawn_point_overrides: BTreeMap<u8, usize>,
+    #[serde(default)]
+    pub rules: MatchRules,
+}
+
+impl MatchSettings {
+    pub fn new(seed: u64) -> Self {
+        MatchSettings {
+            seed,
+            netcode: NetcodeMode::default(),
+            max_rollback_ticks: DEFAULT_MAX_ROLLBACK_TICKS,
+            checksum_interval: DEFAULT_CHECKSUM_INTERVAL,
+            disconnect_rule: DisconnectRule::default(),
+            reconnect_timeout: DEFAULT_RECONNECT_TIMEOUT,
+            allow_late_join: false,
+            spawn_point_overrides: BTreeMap::new(),
+            rules: MatchRules::default(),
+        }
+    }
+
+    /// Create settings with a seed derived from the current time, for matches that don't
+    /// have to be reproduced.
+    pub fn with_random_seed() -> Self {
+        let seed = (macroquad::miniquad::date::now() * 1000.0) as u64;
+        Self::new(seed)
+    }
+
+    fn default_max_rollback_ticks() -> u64 {
+        DEFAULT_MAX_ROLLBACK_TICKS
+    }
+
+    fn default_checksum_interval() -> u64 {
+        DEFAULT_CHECKSUM_INTERVAL
+    }
+
+    fn default_reconnect_timeout() -> f64 {
+        DEFAULT_RECONNECT_TIMEOUT
+    }
+}
+
+impl Default for MatchSettings {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
@@ -0,0 +1,502 @@
+//! This implements snapshots of the simulation, that can be serialized and restored into a fresh
+//! `World`, together with the actors of the `CollisionWorld`.
+//! Entities will get new handles when a snapshot is restored, so all components that hold handles
+//! to other entities implement `MapEntities`, and are remapped using the `EntityMap` that is
+//! returned by `WorldSnapshot::restore`.
+//! Entities are restored in the order of their ids in the captured world, so restoring the same
+//! snapshot will always produce the same world, with the same iteration order.
+//! As the handles of entities differ between worlds with identical content, like after a snapshot
+//! has been restored, snapshots should be normalized, using `GameSnapshot::normalized`, before
+//! they are compared.
+
+use macroquad::prelude::*;
+
+use serde::{Deserialize, Serialize};
+
+use hecs::{Component, Entity, EntityBuilder, World};
+
+use core::rng::Rng;
+
+use crate::ecs::{EntityMap, MapEntities};
+use crate::effects::active::projectiles::Projectile;
+use crate::effects::active::triggered::TriggeredEffect;
+use crate::effects::active::DebugCollider;
+use crate::items::Weapon;
+use crate::map::{Decoration, Sproinger};
+use crate::particles::ParticleEmitter;
+use crate::physics::PhysicsBodyParams;
+use crate::player::{
+    Player, PlayerAttributes, PlayerController, PlayerControllerKind, PlayerEventQueue,
+    PlayerInventory,
+};
+use crate::{
+    create_collision_world, json, CollisionWorld, Drawable, GameInput, Item, Map, Owner,
+    PhysicsBody, RigidBody, Transform,
+};
+
+use super::{MatchState, SimulationClock};
+
+/// The serializable state of a `PhysicsBody`. The actor is not stored, as a new one is created in
+/// the `CollisionWorld` when the body is restored, with the sub-pixel remainder of the captured
+/// actor.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PhysicsBodySnapshot {
+    #[serde(with = "json::vec2_def")]
+    pub offset: Vec2,
+    #[serde(with = "json::vec2_def")]
+    pub size: Vec2,
+    #[serde(with = "json::vec2_def")]
+    pub velocity: Vec2,
+    pub is_on_ground: bool,
+    pub was_on_ground: bool,
+    pub is_on_platform: bool,
+    pub has_mass: bool,
+    pub has_friction: bool,
+    pub can_rotate: bool,
+    pub bouncyness: f32,
+    pub is_deactivated: bool,
+    pub gravity: f32,
+    #[serde(with = "json::vec2_def")]
+    pub remainder: Vec2,
+}
+
+impl From<&PhysicsBody> for PhysicsBodySnapshot {
+    fn from(body: &PhysicsBody) -> Self {
+        PhysicsBodySnapshot {
+            offset: body.offset,
+            size: body.size,
+            velocity: body.velocity,
+            is_on_ground: body.is_on_ground,
+            was_on_ground: body.was_on_ground,
+            is_on_platform: body.is_on_platform,
+            has_mass: body.has_mass,
+            has_friction: body.has_friction,
+            can_rotate: body.can_rotate,
+            bouncyness: body.bouncyness,
+            is_deactivated: body.is_deactivated,
+            gravity: body.gravity,
+            remainder: body.remainder,
+        }
+    }
+}
+
+impl PhysicsBodySnapshot {
+    fn restore(&self, collision_world: &mut CollisionWorld, position: Vec2) -> PhysicsBody {
+        let size = self.size.as_i32();
+
+        // The remainder of an actor can only be set by moving it, and a remainder of half a
+        // pixel is rounded into a move of a whole pixel, flipping its sign. Actors with such a
+        // remainder are created a pixel away, so that the move ends at the captured position.
+        let is_half_pixel = self.remainder.abs().cmpeq(Vec2::splat(0.5));
+        let start_offset = Vec2::select(is_half_pixel, self.remainder * 2.0, Vec2::ZERO);
+        let movement = Vec2::select(is_half_pixel, -self.remainder, self.remainder);
+
+        let position = position + self.offset;
+        let actor = collision_world.add_actor(position + start_offset, size.x, size.y);
+
+        if movement.x != 0.0 {
+            collision_world.move_h(actor, movement.x);
+        }
+
+        if movement.y != 0.0 {
+            collision_world.move_v(actor, movement.y);
+        }
+
+        let params = PhysicsBodyParams {
+            size: self.size,
+            offset: self.offset,
+            has_mass: self.has_mass,
+            has_friction: self.has_friction,
+            can_rotate: self.can_rotate,
+            bouncyness: self.bouncyness,
+            gravity: self.gravity,
+        };
+
+        let mut body = PhysicsBody::new(actor, self.velocity, params);
+
+        body.is_on_ground = self.is_on_ground;
+        body.was_on_ground = self.was_on_ground;
+        body.is_on_platform = self.is_on_platform;
+        body.is_deactivated = self.is_deactivated;
+        body.remainder = self.remainder;
+
+        body
+    }
+}
+
+/// The components of a single entity. Components that are not part of the simulation are not
+/// stored, and entities that are not part of it, like those with a `DebugCollider`, are skipped
+/// by `WorldSnapshot::capture`.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct EntitySnapshot {
+    /// The handle of the entity in the world that was captured
+    pub entity: Entity,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transform: Option<Transform>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub physics_body: Option<PhysicsBodySnapshot>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rigid_body: Option<RigidBody>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub player: Option<Player>,
+    /// The input of the player controller. The kind of the controller is not stored, as it is
+    /// local to each machine, so restored controllers will be of kind
+    /// `PlayerControllerKind::External`, until changed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub player_input: Option<GameInput>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub player_attributes: Option<PlayerAttributes>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub player_inventory: Option<PlayerInventory>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub player_events: Option<PlayerEventQueue>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<Owner>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub item: Option<Item>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub weapon: Option<Weapon>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub projectile: Option<Projectile>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub triggered_effect: Option<TriggeredEffect>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sproinger: Option<Sproinger>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decoration: Option<Decoration>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub drawable: Option<Drawable>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub particle_emitters: Option<Vec<ParticleEmitter>>,
+}
+
+fn get_cloned<T: Component + Clone>(world: &World, entity: Entity) -> Option<T> {
+    world
+        .get::<T>(entity)
+        .ok()
+        .map(|component| (*component).clone())
+}
+
+impl EntitySnapshot {
+    pub fn capture(world: &World, entity: Entity) -> Self {
+        EntitySnapshot {
+            entity,
+            transform: get_cloned(world, entity),
+            physics_body: world
+                .get::<PhysicsBody>(entity)
+                .ok()
+                .map(|body| PhysicsBodySnapshot::from(&*body)),
+            rigid_body: get_cloned(world, entity),
+            player: get_cloned(world, entity),
+            player_input: world
+                .get::<PlayerController>(entity)
+                .ok()
+                .map(|controller| controller.input),
+            player_attributes: get_cloned(world, entity),
+            player_inventory: get_cloned(world, entity),
+            player_events: get_cloned(world, entity),
+            owner: get_cloned(world, entity),
+            item: get_cloned(world, entity),
+            weapon: get_cloned(world, entity),
+            projectile: get_cloned(world, entity),
+            triggered_effect: get_cloned(world, entity),
+            sproinger: get_cloned(world, entity),
+            decoration: get_cloned(world, entity),
+            drawable: get_cloned(world, entity),
+            particle_emitters: get_cloned(world, entity),
+        }
+    }
+
+    /// Returns `true` if none of the components of the entity were stored
+    pub fn is_empty(&self) -> bool {
+        self.transform.is_none()
+            && self.physics_body.is_none()
+            && self.rigid_body.is_none()
+            && self.player.is_none()
+            && self.player_input.is_none()
+            && self.player_attributes.is_none()
+            && self.player_inventory.is_none()
+            && self.player_events.is_none()
+            && self.owner.is_none()
+            && self.item.is_none()
+            && self.weapon.is_none()
+            && self.projectile.is_none()
+            && self.triggered_effect.is_none()
+            && self.sproinger.is_none()
+            && self.decoration.is_none()
+            && self.drawable.is_none()
+            && self.particle_emitters.is_none()
+    }
+
+    fn build(&self, collision_world: &mut CollisionWorld, entity_map: &EntityMap) -> EntityBuilder {
+        let mut builder = EntityBuilder::new();
+
+        let position = self
+            .transform
+            .as_ref()
+            .map(|transform| transform.position)
+            .unwrap_or_default();
+
+        if let Some(transform) = &self.transform {
+            builder.add(transform.clone());
+        }
+
+        if let Some(body) = &self.physics_body {
+            let body = body.restore(collision_world, position);
+
+            if let Some(effect) = &self.triggered_effect {
+                if !effect.should_collide_with_platforms {
+                    collision_world.descent(body.actor);
+                }
+            }
+
+            builder.add(body);
+        }
+
+        if let Some(body) = &self.rigid_body {
+            builder.add(body.clone());
+        }
+
+        if let Some(player) = &self.player {
+            let mut player = player.clone();
+            player.map_entities(entity_map);
+
+            for effect in &mut player.passive_effects {
+                effect.resolve_function();
+            }
+
+            builder.add(player);
+        }
+
+        if let Some(input) = self.player_input {
+            let mut controller = PlayerController::from(PlayerControllerKind::External);
+            controller.apply_input(input);
+
+            builder.add(controller);
+        }
+
+        if let Some(attributes) = &self.player_attributes {
+            builder.add(attributes.clone());
+        }
+
+        if let Some(inventory) = &self.player_inventory {
+            let mut inventory = inventory.clone();
+            inventory.map_entities(entity_map);
+
+            builder.add(inventory);
+        }
+
+        if let Some(events) = &self.player_events {
+            let mut events = events.clone();
+            events.map_entities(entity_map);
+
+            builder.add(events);
+        }
+
+        if let Some(owner) = &self.owner {
+            let mut owner = *owner;
+            owner.map_entities(entity_map);
+
+            builder.add(owner);
+        }
+
+        if let Some(item) = &self.item {
+            builder.add(item.clone());
+        }
+
+        if let Some(weapon) = &self.weapon {
+            builder.add(weapon.clone());
+        }
+
+        if let Some(projectile) = &self.projectile {
+            let mut projectile = projectile.clone();
+            projectile.map_entities(entity_map);
+
+            builder.add(projectile);
+        }
+
+        if let Some(effect) = &self.triggered_effect {
+            let mut effect = effect.clone();
+            effect.map_entities(entity_map);
+
+            builder.add(effect);
+        }
+
+        if let Some(sproinger) = &self.sproinger {
+            builder.add(sproinger.clone());
+        }
+
+        if let Some(decoration) = &self.decoration {
+            builder.add(decoration.clone());
+        }
+
+        if let Some(drawable) = &self.drawable {
+            let mut drawable = drawable.clone();
+            drawable.resolve_textures();
+
+            builder.add(drawable);
+        }
+
+        if let Some(particle_emitters) = &self.particle_emitters {
+            builder.add(particle_emitters.clone());
+        }
+
+        builder
+    }
+}
+
+impl MapEntities for EntitySnapshot {
+    fn map_entities(&mut self, entity_map: &EntityMap) {
+        entity_map.map(&mut self.entity);
+
+        if let Some(player) = &mut self.player {
+            player.map_entities(entity_map);
+        }
+
+        if let Some(inventory) = &mut self.player_inventory {
+            inventory.map_entities(entity_map);
+        }
+
+        if let Some(events) = &mut self.player_events {
+            events.map_entities(entity_map);
+        }
+
+        if let Some(owner) = &mut self.owner {
+            owner.map_entities(entity_map);
+        }
+
+        if let Some(projectile) = &mut self.projectile {
+            projectile.map_entities(entity_map);
+        }
+
+        if let Some(effect) = &mut self.triggered_effect {
+            effect.map_entities(entity_map);
+        }
+    }
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct WorldSnapshot {
+    pub entities: Vec<EntitySnapshot>,
+}
+
+impl WorldSnapshot {
+    pub fn capture(world: &World) -> Self {
+        let mut entities = world
+            .iter()
+            .filter(|entity_ref| !entity_ref.has::<DebugCollider>())
+            .map(|entity_ref| EntitySnapshot::capture(world, entity_ref.entity()))
+            .filter(|snapshot| !snapshot.is_empty())
+            .collect::<Vec<_>>();
+
+        entities.sort_by_key(|snapshot| snapshot.entity.id());
+
+        WorldSnapshot { entities }
+    }
+
+    /// Restore the snapshot into a new `World` and a new `CollisionWorld`, created from `map`.
+    /// The returned `EntityMap` maps the handles of the captured world to the handles of the
+    /// restored world.
+    pub fn restore(&self, map: &Map) -> (World, CollisionWorld, EntityMap) {
+        let mut world = World::new();
+        let mut collision_world = create_collision_world(map);
+        let mut entity_map = EntityMap::new();
+
+        for snapshot in &self.entities {
+            let entity = world.spawn(());
+            entity_map.insert(snapshot.entity, entity);
+        }
+
+        for snapshot in &self.entities {
+            let entity = entity_map.get(snapshot.entity).unwrap();
+            let mut builder = snapshot.build(&mut collision_world, &entity_map);
+
+            world.insert(entity, builder.build()).unwrap();
+        }
+
+        (world, collision_world, entity_map)
+    }
+
+    /// Create a map from the handles of the captured entities to handles that only depend on
+    /// the order of the entities in the snapshot
+    fn normalized_entity_map(&self) -> EntityMap {
+        let mut entity_map = EntityMap::new();
+
+        for (i, snapshot) in self.entities.iter().enumerate() {
+            let entity = Entity::from_bits((1 << 32) | i as u64).unwrap();
+            entity_map.insert(snapshot.entity, entity);
+        }
+
+        entity_map
+    }
+}
+
+/// The complete state of the simulation of a `Game`, at a specific tick
+#[derive(Clone, Serialize, Deserialize)]
+pub struct GameSnapshot {
+    pub clock: SimulationClock,
+    pub rng: Rng,
+    pub match_state: MatchState,
+    pub players: Vec<Entity>,
+    pub world: WorldSnapshot,
+}
+
+impl GameSnapshot {
+    /// Returns a copy of the snapshot where all entity handles are replaced by handles that only
+    /// depend on the order of the entities, so that snapshots of identical worlds are equal,
+    /// regardless of how the entities were originally allocated
+    pub fn normalized(&self) -> Self {
+        let entity_map = self.world.normalized_entity_map();
+
+        let mut res = self.clone();
+
+        for entity in &mut res.players {
+            entity_map.map(entity);
+        }
+
+        for snapshot in &mut res.world.entities {
+            snapshot.map_entities(&entity_map);
+        }
+
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game::headless::tests::{create_test_game, test_input};
+    use crate::game::{checksum, simulation_state, MatchSettings};
+
+    use super::GameSnapshot;
+
+    #[test]
+    fn test_restore_snapshot() {
+        let (_guard, mut game) = create_test_game(MatchSettings::new(5));
+
+        for tick in 0..100 {
+            game.step(&test_input(tick));
+        }
+
+        let json = serde_json::to_string(&game.snapshot()).unwrap();
+
+        for tick in 100..160 {
+            game.step(&test_input(tick));
+        }
+
+        let expected = checksum(&simulation_state(&game.snapshot()).unwrap());
+
+        let snapshot: GameSnapshot = serde_json::from_str(&json).unwrap();
+        game.restore(&snapshot).unwrap();
+
+        assert_eq!(game.tick(), 100);
+
+        for tick in 100..160 {
+            game.step(&test_input(tick));
+        }
+
+        assert_eq!(
+            checksum(&simulation_state(&game.snapshot()).unwrap()),
+            expected
+        );
+    }
+}
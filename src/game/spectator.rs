@@ -0,0 +1,246 @@
+//! This implements the `Game` side of spectating a network game.
+//! The host sends every spectator a snapshot of the game at the last tick that it has the
+//! confirmed input of all players for, and the spectator continues from that tick, applying the
+//! confirmed input that the host forwards to it. Spectators do not control any players, so they
+//! control the camera in stead.
+//! If the match allows late joining, a spectator can ask for the slots of a remote peer that has
+//! left, in which case the host spawns the players of those slots again, once the current round
+//! has ended, or right away in an endless match, and the spectator continues from a snapshot,
+//! like a client that has reconnected. The replay of the host does not record players that join
+//! late, so it will not play back identically after that.
+
+use macroquad::experimental::collections::storage;
+use macroquad::prelude::*;
+
+use core::rng::Rng;
+use core::{Id, Result};
+
+use super::{get_tick, CameraMode, Game, GameCamera, GameMode, MatchState};
+use crate::player::{spawn_player, Player, PlayerController, PlayerControllerKind};
+use crate::Map;
+
+/// The speed, in pixels per second, that the camera is moved at, in `CameraMode::Free`
+const FREE_CAMERA_SPEED: f32 = 600.0;
+
+/// The factor that the zoom of the camera is changed by per second, in `CameraMode::Free`
+const FREE_CAMERA_ZOOM_SPEED: f32 = 2.0;
+
+impl Game {
+    /// Returns `true` if this game was joined as a spectator. This stays `true` if the spectator
+    /// has taken the slots of a remote peer, in which case the camera can still be controlled.
+    pub fn is_spectating(&self) -> bool {
+        matches!(self.mode, GameMode::NetworkSpectator { .. })
+    }
+
+    /// Send a snapshot to the spectators that need one, and give the slots of a remote peer that
+    /// has left to a spectator that asks for them, on the host. On a client, make sure that the
+    /// players it has taken the slots of are controlled locally.
+    pub(super) fn update_spectators(&mut self) -> Result<()> {
+        if !self.network.as_ref().unwrap().is_host() {
+            if self.is_spectating() {
+                self.update_controller_kinds();
+            }
+
+            return Ok(());
+        }
+
+        let is_joinable = storage::get::<MatchState>().is_joinable();
+
+        if self.replay().settings.allow_late_join && is_joinable {
+            self.fill_open_slots();
+        }
+
+        let tick = match self.network.as_ref().unwrap().spectator_snapshot_tick() {
+            Some(tick) => tick,
+            None => return Ok(()),
+        };
+
+        // With rollback, the current tick might not be confirmed yet, but the snapshot of the last
+        // confirmed tick is kept, as it can still be rolled back to
+        let snapshot = if tick == get_tick() {
+            Some(self.snapshot())
+        } else {
+            self.rollback
+                .as_ref()
+                .and_then(|rollback| rollback.snapshot(tick))
+                .cloned()
+        };
+
+        match snapshot {
+            Some(snapshot) => {
+                let bytes = serde_json::to_vec(&snapshot)?;
+
+                self.network
+                    .as_mut()
+                    .unwrap()
+                    .send_spectator_snapshot(tick, &bytes)?;
+            }
+            None => {
+                #[cfg(debug_assertions)]
+                println!("WARNING: No snapshot of tick {} for spectators", tick);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Spawn the players of the open slots again, if a spectator has asked for them. The
+    /// spectator becomes the remote peer, which is sent a snapshot once it is resynced.
+    fn fill_open_slots(&mut self) {
+        let players = match self.network.as_mut().unwrap().grant_open_slots() {
+            Some(players) => players,
+            None => return,
+        };
+
+        for index in players {
+            let character = self
+                .replay()
+                .players
+                .iter()
+                .find(|player| player.index == index)
+                .map(|player| player.character.clone());
+
+            let character = match character {
+                Some(character) => character,
+                None => continue,
+            };
+
+            let position = {
+                let map = storage::get::<Map>();
+                let mut rng = storage::get_mut::<Rng>();
+                map.get_random_spawn_point(&mut rng)
+            };
+
+            let controller = PlayerControllerKind::Network(Id::from(index.to_string()));
+            let player = spawn_player(&mut self.world, index, position, controller, character);
+
+            self.players.push(player);
+        }
+    }
+
+    /// Set the kind of the player controllers after a snapshot from the host has been restored,
+    /// as the host does not know which players are controlled by which peer
+    pub(super) fn update_controller_kinds(&mut self) {
+        let input_scheme = match self.mode {
+            GameMode::NetworkSpectator { input_scheme, .. } => input_scheme,
+            _ => return,
+        };
+
+        let network = self.network.as_ref().unwrap();
+        let local_players = network.local_players();
+
+        for (_, (player, controller)) in self.world.query_mut::<(&Player, &mut PlayerController)>()
+        {
+            if local_players.contains(&player.index) {
+                if !controller.kind.is_local() {
+                    controller.kind = PlayerControllerKind::LocalInput(input_scheme);
+                }
+            } else if !matches!(controller.kind, PlayerControllerKind::Network(_)) {
+                controller.kind = PlayerControllerKind::Network(Id::from(player.index.to_string()));
+            }
+        }
+    }
+
+    /// Handle the camera controls, and the request for a slot, of a spectator
+    pub(super) fn update_spectator_input(&mut self) {
+        let mut camera = storage::get_mut::<GameCamera>();
+
+        if is_key_pressed(KeyCode::Tab) {
+            let mut indices = self
+                .world
+                .query_mut::<&Player>()
+                .into_iter()
+                .map(|(_, player)| player.index)
+                .collect::<Vec<_>>();
+
+            indices.sort_unstable();
+
+            let mode = match camera.mode() {
+                CameraMode::Players => indices
+                    .first()
+                    .map(|&index| CameraMode::FollowPlayer(index))
+                    .unwrap_or(CameraMode::Free),
+                CameraMode::FollowPlayer(current) => indices
+                    .into_iter()
+                    .find(|&index| index > current)
+                    .map(CameraMode::FollowPlayer)
+                    .unwrap_or(CameraMode::Free),
+                CameraMode::Free => CameraMode::Players,
+            };
+
+            camera.set_mode(mode);
+        }
+
+        if camera.mode() == CameraMode::Free {
+            let delta_time = get_frame_time();
+
+            let mut direction = Vec2::ZERO;
+
+            if is_key_down(KeyCode::Left) {
+                direction.x -= 1.0;
+            }
+
+            if is_key_down(KeyCode::Right) {
+                direction.x += 1.0;
+            }
+
+            if is_key_down(KeyCode::Up) {
+                direction.y -= 1.0;
+            }
+
+            if is_key_down(KeyCode::Down) {
+                direction.y += 1.0;
+            }
+
+            let mut zoom_scale = 1.0;
+
+            if is_key_down(KeyCode::PageUp) {
+                zoom_scale /= 1.0 + FREE_CAMERA_ZOOM_SPEED * delta_time;
+            }
+
+            if is_key_down(KeyCode::PageDown) {
+                zoom_scale *= 1.0 + FREE_CAMERA_ZOOM_SPEED * delta_time;
+            }
+
+            camera.move_free(direction * FREE_CAMERA_SPEED * delta_time, zoom_scale);
+        }
+
+        let network = self.network.as_mut().unwrap();
+
+        if network.is_spectator() && is_key_pressed(KeyCode::J) {
+            network.request_slot();
+        }
+    }
+
+    /// Draw the camera mode, and the controls, of a spectator
+    pub(super) fn draw_spectator_overlay(&self) {
+        let network = self.network.as_ref().unwrap();
+
+        let mode = match storage::get::<GameCamera>().mode() {
+            CameraMode::Players => "All players".to_string(),
+            CameraMode::FollowPlayer(index) => format!("Following player {}", index + 1),
+            CameraMode::Free => "Free camera (arrows to move, page up/down to zoom)".to_string(),
+        };
+
+        let mut lines = vec![format!("{} - Tab to change", mode)];
+
+        if network.is_spectator() {
+            if network.is_resyncing() {
+                lines.insert(0, "Waiting for the host...".to_string());
+            } else if network.is_slot_requested() {
+                lines.insert(0, "Spectating - waiting for an open slot".to_string());
+            } else {
+                lines.insert(0, "Spectating - J to join when a slot opens".to_string());
+            }
+        }
+
+        push_camera_state();
+        set_default_camera();
+
+        for (i, line) in lines.iter().enumerate() {
+            draw_text(line, 16.0, 32.0 + i as f32 * 24.0, 24.0, WHITE);
+        }
+
+        pop_camera_state();
+    }
+}
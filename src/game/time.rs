@@ -0,0 +1,55 @@
+use macroquad::experimental::collections::storage;
+
+use serde::{Deserialize, Serialize};
+
+/// The length of one simulation tick, in seconds.
+/// This is the same as the fixed frame time used by macroquad for `Node::fixed_update`.
+pub const TICK_LENGTH: f32 = 1.0 / 60.0;
+
+/// This is the clock of the simulation. All gameplay systems must read time from this, through
+/// `get_delta_time` and `get_tick`, in stead of calling `get_frame_time`, so that the outcome of
+/// a match does not depend on the frame rate of the machine it is running on.
+/// It is advanced by `Game` once for every execution of its fixed update scheduler.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SimulationClock {
+    tick: u64,
+}
+
+impl SimulationClock {
+    pub fn new() -> Self {
+        SimulationClock { tick: 0 }
+    }
+
+    /// The number of ticks that have been simulated
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    /// The fixed length of a tick, in seconds
+    pub fn delta_time(&self) -> f32 {
+        TICK_LENGTH
+    }
+
+    /// The simulated time, in seconds
+    pub fn elapsed(&self) -> f64 {
+        self.tick as f64 * TICK_LENGTH as f64
+    }
+
+    pub fn advance(&mut self) {
+        self.tick += 1;
+    }
+}
+
+impl Default for SimulationClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn get_delta_time() -> f32 {
+    storage::get::<SimulationClock>().delta_time()
+}
+
+pub fn get_tick() -> u64 {
+    storage::get::<SimulationClock>().tick()
+}
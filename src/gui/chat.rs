@@ -0,0 +1,217 @@
+//! The chat overlay, that is shown in the lobby room and in network games.
+//! It shows the most recent messages in the bottom left corner of the screen, and has an input
+//! box, that is opened with `CHAT_KEY`, for the local player to write a message.
+
+use std::collections::VecDeque;
+
+use macroquad::prelude::*;
+
+use core::network::{fnv_hash, MAX_CHAT_MESSAGE_LEN};
+
+/// The key that opens the chat input
+pub const CHAT_KEY: KeyCode = KeyCode::T;
+
+/// The amount of messages that are kept in the log
+const MAX_CHAT_LOG_LEN: usize = 8;
+
+/// The time, in seconds, that a message is shown, when the input is closed. The whole log is
+/// shown while the input is open.
+const CHAT_MESSAGE_DURATION: f64 = 10.0;
+
+const CHAT_FONT_SIZE: f32 = 20.0;
+const CHAT_LINE_HEIGHT: f32 = 22.0;
+const CHAT_WIDTH: f32 = 380.0;
+const CHAT_MARGIN: f32 = 12.0;
+
+const CHAT_BG_COLOR: Color = Color::new(0.0, 0.0, 0.0, 0.5);
+const SYSTEM_MESSAGE_COLOR: Color = Color::new(0.75, 0.75, 0.75, 1.0);
+
+/// The colors that player names are drawn in
+const PLAYER_COLORS: &[Color] = &[
+    Color::new(1.0, 0.42, 0.42, 1.0),
+    Color::new(0.42, 0.71, 1.0, 1.0),
+    Color::new(0.45, 0.9, 0.45, 1.0),
+    Color::new(1.0, 0.85, 0.3, 1.0),
+    Color::new(0.85, 0.55, 1.0, 1.0),
+    Color::new(1.0, 0.62, 0.3, 1.0),
+    Color::new(0.4, 0.92, 0.88, 1.0),
+    Color::new(1.0, 0.55, 0.82, 1.0),
+];
+
+/// The color of the name of a player, picked by hashing `key`, which should be something that
+/// identifies the player on all clients, like its id, so that the color is the same everywhere
+pub fn player_color(key: &str) -> Color {
+    let i = fnv_hash(key.as_bytes()) % PLAYER_COLORS.len() as u64;
+
+    PLAYER_COLORS[i as usize]
+}
+
+struct ChatEntry {
+    /// The name of the player that sent the message, and its color. This is `None` for system
+    /// messages.
+    sender: Option<(String, Color)>,
+    text: String,
+    received_at: f64,
+}
+
+pub struct ChatLog {
+    entries: VecDeque<ChatEntry>,
+    input: String,
+    is_input_open: bool,
+}
+
+impl ChatLog {
+    pub fn new() -> Self {
+        ChatLog {
+            entries: VecDeque::new(),
+            input: String::new(),
+            is_input_open: false,
+        }
+    }
+
+    /// Add a message from a player, with a name color picked by `player_color(key)`
+    pub fn push_message(&mut self, name: &str, key: &str, text: &str) {
+        self.push_entry(Some((name.to_string(), player_color(key))), text);
+    }
+
+    /// Add a message that was not sent by a player, like when a player has joined or left
+    pub fn push_system_message(&mut self, text: &str) {
+        self.push_entry(None, text);
+    }
+
+    fn push_entry(&mut self, sender: Option<(String, Color)>, text: &str) {
+        self.entries.push_back(ChatEntry {
+            sender,
+            text: text.to_string(),
+            received_at: get_time(),
+        });
+
+        while self.entries.len() > MAX_CHAT_LOG_LEN {
+            self.entries.pop_front();
+        }
+    }
+
+    /// Returns `true` while the input is open, in which case keyboard input should not be used
+    /// for anything else
+    pub fn is_input_open(&self) -> bool {
+        self.is_input_open
+    }
+
+    /// Handle keyboard input. The input is opened with `CHAT_KEY` and closed with escape, or with
+    /// enter, in which case the message that was written is returned, to be sent.
+    pub fn update(&mut self) -> Option<String> {
+        if !self.is_input_open {
+            if is_key_pressed(CHAT_KEY) {
+                self.is_input_open = true;
+
+                // Discard the character of the key that opened the input
+                while get_char_pressed().is_some() {}
+            }
+
+            return None;
+        }
+
+        if is_key_pressed(KeyCode::Escape) {
+            self.close_input();
+            return None;
+        }
+
+        if is_key_pressed(KeyCode::Enter) || is_key_pressed(KeyCode::KpEnter) {
+            let text = self.input.trim().to_string();
+            self.close_input();
+
+            return if text.is_empty() { None } else { Some(text) };
+        }
+
+        if is_key_pressed(KeyCode::Backspace) {
+            self.input.pop();
+        }
+
+        // Characters are queued in reverse order
+        let mut chars = Vec::new();
+        while let Some(c) = get_char_pressed() {
+            chars.push(c);
+        }
+
+        for c in chars.into_iter().rev() {
+            if !c.is_control() && self.input.chars().count() < MAX_CHAT_MESSAGE_LEN {
+                self.input.push(c);
+            }
+        }
+
+        None
+    }
+
+    fn close_input(&mut self) {
+        self.is_input_open = false;
+        self.input.clear();
+    }
+
+    /// Draw the log and, if it is open, the input, in the bottom left corner of the screen
+    pub fn draw(&self) {
+        let now = get_time();
+
+        let entries = self
+            .entries
+            .iter()
+            .filter(|entry| self.is_input_open || now - entry.received_at < CHAT_MESSAGE_DURATION)
+            .collect::<Vec<_>>();
+
+        if entries.is_empty() && !self.is_input_open {
+            return;
+        }
+
+        push_camera_state();
+        set_default_camera();
+
+        let line_cnt = entries.len() + self.is_input_open as usize;
+        let height = line_cnt as f32 * CHAT_LINE_HEIGHT + CHAT_MARGIN;
+
+        let x = CHAT_MARGIN;
+        let mut y = screen_height() - CHAT_MARGIN - height;
+
+        draw_rectangle(x, y, CHAT_WIDTH, height, CHAT_BG_COLOR);
+
+        let x = x + CHAT_MARGIN / 2.0;
+        y += CHAT_LINE_HEIGHT;
+
+        for entry in entries {
+            let mut text_x = x;
+
+            match &entry.sender {
+                Some((name, color)) => {
+                    let name = format!("{}: ", name);
+                    draw_text(&name, text_x, y, CHAT_FONT_SIZE, *color);
+
+                    text_x += measure_text(&name, None, CHAT_FONT_SIZE as u16, 1.0).width;
+
+                    draw_text(&entry.text, text_x, y, CHAT_FONT_SIZE, WHITE);
+                }
+                None => draw_text(&entry.text, text_x, y, CHAT_FONT_SIZE, SYSTEM_MESSAGE_COLOR),
+            }
+
+            y += CHAT_LINE_HEIGHT;
+        }
+
+        if self.is_input_open {
+            // Show the end of the input, if it is too long to fit
+            let mut input = format!("> {}_", self.input);
+            while input.len() > 2
+                && measure_text(&input, None, CHAT_FONT_SIZE as u16, 1.0).width
+                    > CHAT_WIDTH - CHAT_MARGIN
+            {
+                input.remove(2);
+            }
+
+            draw_text(&input, x, y, CHAT_FONT_SIZE, WHITE);
+        }
+
+        pop_camera_state();
+    }
+}
+
+impl Default for ChatLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -9,28 +9,38 @@ const MENU_WIDTH: f32 = 300.0;
 
 pub const GAME_MENU_RESULT_MAIN_MENU: usize = 0;
 pub const GAME_MENU_RESULT_QUIT: usize = 1;
+pub const GAME_MENU_RESULT_RESTART: usize = 2;
 
 static mut GAME_MENU_INSTANCE: Option<Menu> = None;
 
-pub fn open_game_menu() {
+/// Open the game menu. If `can_restart` is `true`, the menu has an entry that restarts the
+/// match, which is only possible in local matches.
+pub fn open_game_menu(can_restart: bool) {
     unsafe {
         if GAME_MENU_INSTANCE.is_none() {
-            let menu = Menu::new(
-                hash!(),
-                MENU_WIDTH,
-                &[
-                    MenuEntry {
-                        index: GAME_MENU_RESULT_MAIN_MENU,
-                        title: "Main Menu".to_string(),
-                        ..Default::default()
-                    },
-                    MenuEntry {
-                        index: GAME_MENU_RESULT_QUIT,
-                        title: "Quit".to_string(),
-                        ..Default::default()
-                    },
-                ],
-            );
+            let mut entries = Vec::new();
+
+            if can_restart {
+                entries.push(MenuEntry {
+                    index: GAME_MENU_RESULT_RESTART,
+                    title: "Restart".to_string(),
+                    ..Default::default()
+                });
+            }
+
+            entries.push(MenuEntry {
+                index: GAME_MENU_RESULT_MAIN_MENU,
+                title: "Main Menu".to_string(),
+                ..Default::default()
+            });
+
+            entries.push(MenuEntry {
+                index: GAME_MENU_RESULT_QUIT,
+                title: "Quit".to_string(),
+                ..Default::default()
+            });
+
+            let menu = Menu::new(hash!(), MENU_WIDTH, &entries);
 
             GAME_MENU_INSTANCE = Some(menu);
         }
@@ -44,7 +54,7 @@ pub fn close_game_menu() {
 pub fn draw_game_menu(ui: &mut Ui) -> Option<MenuResult> {
     let menu = unsafe {
         if GAME_MENU_INSTANCE.is_none() {
-            open_game_menu();
+            open_game_menu(false);
         }
 
         GAME_MENU_INSTANCE.as_mut().unwrap()
@@ -64,12 +74,12 @@ pub fn is_game_menu_open() -> bool {
 }
 
 /// Toggle game menu and return state after toggle
-pub fn toggle_game_menu() -> bool {
+pub fn toggle_game_menu(can_restart: bool) -> bool {
     if is_game_menu_open() {
         close_game_menu();
         false
     } else {
-        open_game_menu();
+        open_game_menu(can_restart);
         true
     }
 }
@@ -0,0 +1,1074 @@
+//! The lobby browser and the lobby room, that are shown when selecting network game in the main
+//! menu. Lobbies are managed through `Api`, so it has to be initialized before these are shown.
+//! Games that are announced on the local network are listed in the browser as well, and are
+//! joined directly, without a lobby room. The games of online lobbies use the UDP socket of the
+//! lobby service as their relay, unless another relay is set in the config.
+
+use std::net::{Ipv4Addr, SocketAddr};
+
+use macroquad::{
+    experimental::collections::storage,
+    prelude::*,
+    ui::{hash, widgets, Ui},
+};
+
+use core::network::{
+    Api, ClientState, Lobby, LobbyFilter, LobbyPrivacy, LobbySettings, LobbyState, NetworkEvent,
+    Player, DEFAULT_PORT,
+};
+use core::Id;
+
+use serde::{Deserialize, Serialize};
+
+use super::{ChatLog, Checkbox, GuiResources, MainMenuResult, Panel};
+
+use crate::game::{MatchRules, NetcodeMode, WinCondition};
+use crate::network::{LanAnnouncement, LanBrowser, RelayConfig};
+use crate::player::{PlayerControllerKind, PlayerParams};
+use crate::{gui, Config, GameInputScheme, Map, Resources};
+
+const PANEL_WIDTH: f32 = 500.0;
+const PANEL_HEIGHT: f32 = 560.0;
+
+const INPUT_WIDTH: f32 = 275.0;
+const INPUT_HEIGHT: f32 = 25.0;
+
+/// The amount of lobbies that are listed in the browser
+const MAX_LISTED_LOBBIES: usize = 8;
+
+/// The interval, in seconds, at which the lobby list is refreshed
+const REFRESH_INTERVAL: f64 = 5.0;
+
+/// Matches are played between two peers, by `LockstepSession`, so this is the capacity of all
+/// lobbies that are created
+const LOBBY_CAPACITY: i32 = 2;
+
+/// The input scheme of the local player in network games, which is also used by spectators that
+/// take the slots of a remote peer
+const LOCAL_INPUT_SCHEME: GameInputScheme = GameInputScheme::KeyboardLeft;
+
+pub enum NetworkUiResult {
+    Cancel,
+    Start(Box<MainMenuResult>),
+}
+
+/// An action that was taken in the ui, that requires a request to be made through `Api`
+pub enum NetworkUiAction {
+    Refresh,
+    Create,
+    JoinByCode,
+    Join(Id),
+    JoinLan(Box<Lobby>),
+    /// Watch the running game of a lobby, from the service or the local network
+    Spectate(Box<Lobby>),
+    HostLan,
+    Back,
+    Leave,
+    SetReady(bool),
+    SelectCharacter(String),
+    SelectMap,
+    SetRules(LobbyRules),
+    SetAddress,
+    Kick(Id),
+    SetMuted(Id, bool),
+    SendChat(String),
+    Start,
+}
+
+/// The rules of the match of a lobby, which are stored in `LobbySettings::rules`, as JSON
+#[derive(Debug, Default, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LobbyRules {
+    #[serde(flatten)]
+    pub rules: MatchRules,
+    /// If this is set, spectators can take the slots of a player that has left the match
+    #[serde(default)]
+    pub allow_late_join: bool,
+    #[serde(default)]
+    pub netcode: NetcodeMode,
+}
+
+impl LobbyRules {
+    /// The rules of `lobby`, or the default rules, if they are not set, or invalid
+    pub fn from_lobby(lobby: &Lobby) -> Self {
+        lobby
+            .settings
+            .rules
+            .as_ref()
+            .and_then(|json| serde_json::from_str(json).ok())
+            .unwrap_or_default()
+    }
+}
+
+pub struct LobbyBrowserState {
+    lobbies: Vec<Lobby>,
+    filter: LobbyFilter,
+    filter_name: String,
+    lobby_name: String,
+    is_private: bool,
+    code: String,
+    last_refresh: Option<f64>,
+    lan: Option<LanBrowser>,
+    lan_lobbies: Vec<Lobby>,
+    error: Option<String>,
+}
+
+impl LobbyBrowserState {
+    pub fn new() -> Self {
+        LobbyBrowserState {
+            lobbies: Vec::new(),
+            filter: LobbyFilter::default(),
+            filter_name: String::new(),
+            lobby_name: "New Lobby".to_string(),
+            is_private: false,
+            code: String::new(),
+            last_refresh: None,
+            lan: LanBrowser::bind()
+                .map_err(|err| println!("WARNING: Unable to browse LAN games: {}", err))
+                .ok(),
+            lan_lobbies: Vec::new(),
+            error: None,
+        }
+    }
+
+    fn with_error(error: &str) -> Self {
+        LobbyBrowserState {
+            error: Some(error.to_string()),
+            ..Self::new()
+        }
+    }
+}
+
+impl Default for LobbyBrowserState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct LobbyRoomState {
+    lobby: Lobby,
+    local_player_id: Id,
+    address: String,
+    chat: ChatLog,
+    error: Option<String>,
+}
+
+impl LobbyRoomState {
+    pub fn new(lobby: Lobby, local_player_id: Id) -> Self {
+        let address = lobby
+            .settings
+            .address
+            .unwrap_or_else(|| (Ipv4Addr::LOCALHOST, DEFAULT_PORT).into())
+            .to_string();
+
+        LobbyRoomState {
+            lobby,
+            local_player_id,
+            address,
+            chat: ChatLog::new(),
+            error: None,
+        }
+    }
+
+    fn is_admin(&self) -> bool {
+        self.lobby.admin_player_id == self.local_player_id
+    }
+
+    fn local_player_state(&self) -> ClientState {
+        self.lobby
+            .players
+            .iter()
+            .find(|player| player.id == self.local_player_id)
+            .map(|player| player.state)
+            .unwrap_or(ClientState::None)
+    }
+}
+
+pub enum NetworkUiState {
+    Browser(LobbyBrowserState),
+    Room(LobbyRoomState),
+}
+
+impl NetworkUiState {
+    pub fn new() -> Self {
+        NetworkUiState::Browser(LobbyBrowserState::new())
+    }
+
+    /// Draw the current screen and return the action that was taken, if any
+    pub fn ui(&mut self, ui: &mut Ui) -> Option<NetworkUiAction> {
+        let size = vec2(PANEL_WIDTH, PANEL_HEIGHT);
+        let position = (vec2(screen_width(), screen_height()) - size) / 2.0;
+
+        let mut res = None;
+
+        match self {
+            NetworkUiState::Browser(state) => {
+                Panel::new(hash!("lobby_browser"), size, position)
+                    .with_title("Lobbies", false)
+                    .ui(ui, |ui, _| {
+                        {
+                            let gui_resources = storage::get::<GuiResources>();
+                            ui.push_skin(&gui_resources.skins.menu);
+                        }
+
+                        res = lobby_browser_ui(ui, state);
+
+                        ui.pop_skin();
+                    });
+            }
+            NetworkUiState::Room(state) => {
+                let title = state.lobby.name.clone();
+
+                Panel::new(hash!("lobby_room"), size, position)
+                    .with_title(&title, false)
+                    .ui(ui, |ui, _| {
+                        {
+                            let gui_resources = storage::get::<GuiResources>();
+                            ui.push_skin(&gui_resources.skins.menu);
+                        }
+
+                        res = lobby_room_ui(ui, state);
+
+                        ui.pop_skin();
+                    });
+
+                if let Some(text) = state.chat.update() {
+                    res = Some(NetworkUiAction::SendChat(text));
+                }
+
+                state.chat.draw();
+            }
+        }
+
+        res
+    }
+
+    /// Handle the events received through `Api`, and the action taken in the ui, if any.
+    /// This will return `NetworkUiResult::Start` when the game of the lobby has been started.
+    pub async fn update(&mut self, action: Option<NetworkUiAction>) -> Option<NetworkUiResult> {
+        let mut is_started = false;
+        let mut relay_host_token = None;
+
+        let events = match Api::poll_events() {
+            Ok(events) => events,
+            Err(err) => {
+                let error = format!("Lost connection to the lobby service: {}", err);
+
+                // Without its events, the lobby can not be followed, so it is left
+                match self {
+                    NetworkUiState::Browser(state) => state.error = Some(error),
+                    NetworkUiState::Room(_) => {
+                        *self = NetworkUiState::Browser(LobbyBrowserState::with_error(&error));
+                    }
+                }
+
+                Vec::new()
+            }
+        };
+
+        for event in events {
+            if let NetworkUiState::Room(state) = self {
+                match event {
+                    NetworkEvent::LobbyChanged { lobby } if lobby.id == state.lobby.id => {
+                        state.lobby = lobby;
+                    }
+                    NetworkEvent::PlayerKicked { player_id }
+                        if player_id == state.local_player_id =>
+                    {
+                        *self = NetworkUiState::Browser(LobbyBrowserState::with_error(
+                            "You were kicked from the lobby",
+                        ));
+                    }
+                    NetworkEvent::GameStarted {
+                        lobby_id,
+                        relay_host_token: token,
+                    } if lobby_id == state.lobby.id => {
+                        is_started = true;
+                        relay_host_token = token;
+                    }
+                    NetworkEvent::ChatMessage {
+                        player_id,
+                        username,
+                        text,
+                    } => {
+                        state
+                            .chat
+                            .push_message(&username, player_id.as_str(), &text);
+                    }
+                    NetworkEvent::SystemMessage { text } => {
+                        state.chat.push_system_message(&text);
+                    }
+                    NetworkEvent::PlayerReconnecting { player_id } => {
+                        let player = state
+                            .lobby
+                            .players
+                            .iter()
+                            .find(|player| player.id == player_id);
+
+                        if let Some(player) = player {
+                            state.chat.push_system_message(&format!(
+                                "{} is reconnecting",
+                                player.username
+                            ));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        match self {
+            NetworkUiState::Browser(state) => {
+                if let Some(lan) = &mut state.lan {
+                    lan.update();
+
+                    state.lan_lobbies = lan
+                        .lobbies()
+                        .into_iter()
+                        .filter(|lobby| state.filter.matches(lobby))
+                        .collect();
+                }
+
+                let is_refresh_due = state
+                    .last_refresh
+                    .map(|time| get_time() - time >= REFRESH_INTERVAL)
+                    .unwrap_or(true);
+
+                let action = match action {
+                    Some(NetworkUiAction::Back) => return Some(NetworkUiResult::Cancel),
+                    Some(NetworkUiAction::JoinLan(lobby)) => {
+                        match join_lan_game(*lobby) {
+                            Ok(res) => return Some(NetworkUiResult::Start(Box::new(res))),
+                            Err(err) => state.error = Some(err),
+                        }
+
+                        None
+                    }
+                    Some(NetworkUiAction::Spectate(lobby)) => {
+                        match spectate_game(&lobby) {
+                            Ok(res) => return Some(NetworkUiResult::Start(Box::new(res))),
+                            Err(err) => state.error = Some(err),
+                        }
+
+                        None
+                    }
+                    Some(NetworkUiAction::HostLan) => {
+                        match host_lan_game(state.lobby_name.trim()).await {
+                            Ok(res) => return Some(NetworkUiResult::Start(Box::new(res))),
+                            Err(err) => state.error = Some(err),
+                        }
+
+                        None
+                    }
+                    None if is_refresh_due => Some(NetworkUiAction::Refresh),
+                    action => action,
+                };
+
+                if let Some(action) = action {
+                    if let Some(next_state) = update_lobby_browser(state, action).await {
+                        *self = next_state;
+                    }
+                }
+            }
+            NetworkUiState::Room(state) => {
+                if is_started {
+                    let res = build_network_game(
+                        &state.lobby,
+                        &state.local_player_id,
+                        relay_host_token,
+                        None,
+                    );
+
+                    match res {
+                        Ok(res) => return Some(NetworkUiResult::Start(Box::new(res))),
+                        Err(err) => {
+                            println!("WARNING: Unable to start network game: {}", err);
+                            state.error = Some(err);
+                        }
+                    }
+                } else if let Some(action) = action {
+                    if let Some(next_state) = update_lobby_room(state, action).await {
+                        *self = next_state;
+                    }
+                }
+            }
+        }
+
+        None
+    }
+}
+
+impl Default for NetworkUiState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn lobby_browser_ui(ui: &mut Ui, state: &mut LobbyBrowserState) -> Option<NetworkUiAction> {
+    let mut res = None;
+
+    let input_size = vec2(INPUT_WIDTH, INPUT_HEIGHT);
+
+    widgets::InputText::new(hash!("lobby_browser", "filter_name"))
+        .size(input_size)
+        .ratio(1.0)
+        .label("Search")
+        .ui(ui, &mut state.filter_name);
+
+    {
+        let mut include_full = state.filter.include_full;
+
+        Checkbox::new(
+            hash!("lobby_browser", "include_full"),
+            None,
+            "Show full lobbies",
+        )
+        .ui(ui, &mut include_full);
+
+        state.filter.include_full = include_full;
+    }
+
+    if ui.button(None, "Refresh") {
+        res = Some(NetworkUiAction::Refresh);
+    }
+
+    ui.separator();
+
+    if state.lobbies.is_empty() && state.lan_lobbies.is_empty() {
+        ui.label(None, "No lobbies found");
+    }
+
+    for lobby in state.lobbies.iter().take(MAX_LISTED_LOBBIES) {
+        let label = format!("{} ({}/{})", lobby.name, lobby.player_count, lobby.capacity);
+
+        ui.label(None, &label);
+        ui.same_line(PANEL_WIDTH - 120.0);
+
+        if lobby.player_count < lobby.capacity
+            && lobby.state.is_joinable()
+            && ui.button(None, "Join")
+        {
+            res = Some(NetworkUiAction::Join(lobby.id.clone()));
+        }
+
+        if is_spectatable(lobby) && ui.button(None, "Spectate") {
+            res = Some(NetworkUiAction::Spectate(Box::new(lobby.clone())));
+        }
+    }
+
+    for lobby in state.lan_lobbies.iter().take(MAX_LISTED_LOBBIES) {
+        let label = format!(
+            "[LAN] {} ({}/{})",
+            lobby.name, lobby.player_count, lobby.capacity
+        );
+
+        ui.label(None, &label);
+        ui.same_line(PANEL_WIDTH - 120.0);
+
+        if lobby.state.is_joinable() && ui.button(None, "Join") {
+            res = Some(NetworkUiAction::JoinLan(Box::new(lobby.clone())));
+        }
+
+        if is_spectatable(lobby) && ui.button(None, "Spectate") {
+            res = Some(NetworkUiAction::Spectate(Box::new(lobby.clone())));
+        }
+    }
+
+    ui.separator();
+
+    widgets::InputText::new(hash!("lobby_browser", "lobby_name"))
+        .size(input_size)
+        .ratio(1.0)
+        .label("Name")
+        .ui(ui, &mut state.lobby_name);
+
+    Checkbox::new(hash!("lobby_browser", "is_private"), None, "Private")
+        .ui(ui, &mut state.is_private);
+
+    if ui.button(None, "Create") {
+        res = Some(NetworkUiAction::Create);
+    }
+
+    ui.same_line(0.0);
+    if ui.button(None, "Host LAN game") {
+        res = Some(NetworkUiAction::HostLan);
+    }
+
+    ui.separator();
+
+    widgets::InputText::new(hash!("lobby_browser", "code"))
+        .size(input_size)
+        .ratio(1.0)
+        .label("Code")
+        .ui(ui, &mut state.code);
+
+    if ui.button(None, "Join by code") {
+        res = Some(NetworkUiAction::JoinByCode);
+    }
+
+    ui.separator();
+
+    if let Some(error) = &state.error {
+        ui.label(None, error);
+    }
+
+    if ui.button(None, "Back") || is_key_pressed(KeyCode::Escape) {
+        res = Some(NetworkUiAction::Back);
+    }
+
+    res
+}
+
+fn lobby_room_ui(ui: &mut Ui, state: &mut LobbyRoomState) -> Option<NetworkUiAction> {
+    let mut res = None;
+
+    let is_admin = state.is_admin();
+    let is_ready = state.local_player_state() == ClientState::Ready;
+
+    let resources = storage::get::<Resources>();
+
+    ui.label(None, &format!("Code: {}", state.lobby.id.as_str()));
+
+    ui.separator();
+
+    let characters = sorted_character_ids(&resources);
+
+    for (i, player) in state.lobby.players.iter().enumerate() {
+        let character = player
+            .character
+            .as_ref()
+            .or_else(|| characters.get(i % characters.len().max(1)))
+            .and_then(|id| resources.player_characters.get(id))
+            .map(|meta| meta.name.clone())
+            .unwrap_or_default();
+
+        let is_local = player.id == state.local_player_id;
+
+        let label = format!(
+            "{}{} - {} - {}",
+            player.username,
+            if player.id == state.lobby.admin_player_id {
+                " (host)"
+            } else {
+                ""
+            },
+            client_state_label(player.state),
+            character,
+        );
+
+        ui.label(None, &label);
+
+        if is_local && !is_ready && !characters.is_empty() {
+            let current = characters
+                .iter()
+                .position(|id| Some(id) == player.character.as_ref())
+                .unwrap_or(i % characters.len());
+
+            ui.same_line(PANEL_WIDTH - 120.0);
+            if ui.button(None, "<") {
+                let i = (current + characters.len() - 1) % characters.len();
+                res = Some(NetworkUiAction::SelectCharacter(characters[i].clone()));
+            }
+
+            ui.same_line(0.0);
+            if ui.button(None, ">") {
+                let i = (current + 1) % characters.len();
+                res = Some(NetworkUiAction::SelectCharacter(characters[i].clone()));
+            }
+        } else if is_admin && !is_local {
+            let is_muted = state.lobby.muted_player_ids.contains(&player.id);
+
+            ui.same_line(PANEL_WIDTH - 180.0);
+            if ui.button(None, if is_muted { "Unmute" } else { "Mute" }) {
+                res = Some(NetworkUiAction::SetMuted(player.id.clone(), !is_muted));
+            }
+
+            ui.same_line(0.0);
+            if ui.button(None, "Kick") {
+                res = Some(NetworkUiAction::Kick(player.id.clone()));
+            }
+        }
+    }
+
+    ui.separator();
+
+    {
+        let map_name = state
+            .lobby
+            .settings
+            .map
+            .as_ref()
+            .and_then(|path| {
+                resources
+                    .maps
+                    .iter()
+                    .find(|map_resource| map_resource.meta.path == *path)
+            })
+            .map(|map_resource| map_resource.meta.name.clone())
+            .unwrap_or_else(|| "None".to_string());
+
+        ui.label(None, &format!("Map: {}", map_name));
+
+        if is_admin {
+            ui.same_line(PANEL_WIDTH - 120.0);
+            if ui.button(None, "Select") {
+                res = Some(NetworkUiAction::SelectMap);
+            }
+        }
+    }
+
+    {
+        let lobby_rules = LobbyRules::from_lobby(&state.lobby);
+        let rules = lobby_rules.rules;
+
+        ui.label(
+            None,
+            &format!("Rules: {}", rules.win_condition.description()),
+        );
+
+        if is_admin {
+            ui.same_line(PANEL_WIDTH - 120.0);
+            if ui.button(None, "Change") {
+                let rules = MatchRules {
+                    win_condition: rules.win_condition.next_preset(),
+                    ..rules
+                };
+
+                res = Some(NetworkUiAction::SetRules(LobbyRules {
+                    rules,
+                    ..lobby_rules
+                }));
+            }
+        }
+
+        if rules.win_condition != WinCondition::Endless {
+            ui.label(None, &format!("Rounds: best of {}", rules.rounds));
+
+            if is_admin {
+                ui.same_line(PANEL_WIDTH - 120.0);
+                if ui.button(None, "Change") {
+                    let rules = MatchRules {
+                        rounds: rules.next_round_preset(),
+                        ..rules
+                    };
+
+                    res = Some(NetworkUiAction::SetRules(LobbyRules {
+                        rules,
+                        ..lobby_rules
+                    }));
+                }
+            }
+        }
+
+        if is_admin {
+            let mut allow_late_join = lobby_rules.allow_late_join;
+
+            Checkbox::new(
+                hash!("lobby_room", "allow_late_join"),
+                None,
+                "Allow late join",
+            )
+            .ui(ui, &mut allow_late_join);
+
+            if allow_late_join != lobby_rules.allow_late_join {
+                res = Some(NetworkUiAction::SetRules(LobbyRules {
+                    allow_late_join,
+                    ..lobby_rules
+                }));
+            }
+        } else if lobby_rules.allow_late_join {
+            ui.label(None, "Late join: allowed");
+        } else {
+            ui.label(None, "Late join: not allowed");
+        }
+
+        ui.label(
+            None,
+            &format!("Netcode: {}", lobby_rules.netcode.description()),
+        );
+
+        if is_admin {
+            ui.same_line(PANEL_WIDTH - 120.0);
+            if ui.button(None, "Change") {
+                let netcode = match lobby_rules.netcode {
+                    NetcodeMode::Lockstep => NetcodeMode::Rollback,
+                    NetcodeMode::Rollback => NetcodeMode::Lockstep,
+                };
+
+                res = Some(NetworkUiAction::SetRules(LobbyRules {
+                    netcode,
+                    ..lobby_rules
+                }));
+            }
+        }
+    }
+
+    if is_admin {
+        widgets::InputText::new(hash!("lobby_room", "address"))
+            .size(vec2(INPUT_WIDTH, INPUT_HEIGHT))
+            .ratio(1.0)
+            .label("Address")
+            .ui(ui, &mut state.address);
+
+        if ui.button(None, "Set address") {
+            res = Some(NetworkUiAction::SetAddress);
+        }
+    } else {
+        let address = state
+            .lobby
+            .settings
+            .address
+            .map(|address| address.to_string())
+            .unwrap_or_else(|| "None".to_string());
+
+        ui.label(None, &format!("Address: {}", address));
+    }
+
+    ui.separator();
+
+    if let Some(error) = &state.error {
+        ui.label(None, error);
+    }
+
+    if ui.button(None, if is_ready { "Not Ready" } else { "Ready" }) {
+        res = Some(NetworkUiAction::SetReady(!is_ready));
+    }
+
+    if is_admin && state.lobby.state == LobbyState::LobbyReady {
+        ui.same_line(0.0);
+        if ui.button(None, "Start") {
+            res = Some(NetworkUiAction::Start);
+        }
+    }
+
+    // Escape closes the chat input, when it is open
+    ui.same_line(0.0);
+    if ui.button(None, "Leave") || (!state.chat.is_input_open() && is_key_pressed(KeyCode::Escape))
+    {
+        res = Some(NetworkUiAction::Leave);
+    }
+
+    res
+}
+
+/// Handle `action`, taken in the lobby browser. This returns the next state, if it changed.
+async fn update_lobby_browser(
+    state: &mut LobbyBrowserState,
+    action: NetworkUiAction,
+) -> Option<NetworkUiState> {
+    let lobby = match action {
+        NetworkUiAction::Refresh => {
+            let name = state.filter_name.trim();
+            state.filter.name = if name.is_empty() {
+                None
+            } else {
+                Some(name.to_string())
+            };
+
+            state.last_refresh = Some(get_time());
+
+            match Api::list_lobbies(&state.filter).await {
+                Ok(lobbies) => state.lobbies = lobbies,
+                Err(err) => state.error = Some(err.to_string()),
+            }
+
+            return None;
+        }
+        NetworkUiAction::Create => {
+            let privacy = if state.is_private {
+                LobbyPrivacy::Private
+            } else {
+                LobbyPrivacy::Public
+            };
+
+            create_lobby(state.lobby_name.trim(), privacy).await
+        }
+        NetworkUiAction::JoinByCode => Api::join_lobby(&Id::from(state.code.trim())).await,
+        NetworkUiAction::Join(id) => Api::join_lobby(&id).await,
+        _ => return None,
+    };
+
+    match (lobby, Api::local_player_id()) {
+        (Ok(lobby), Some(local_player_id)) => Some(NetworkUiState::Room(LobbyRoomState::new(
+            lobby,
+            local_player_id,
+        ))),
+        (Err(err), _) => {
+            state.error = Some(err.to_string());
+            None
+        }
+        (_, None) => {
+            state.error = Some("Not signed in to the lobby service".to_string());
+            None
+        }
+    }
+}
+
+/// Create a lobby, with the first available map and the default address
+async fn create_lobby(name: &str, privacy: LobbyPrivacy) -> core::Result<Lobby> {
+    let lobby = Api::create_lobby(name, privacy, LOBBY_CAPACITY).await?;
+
+    let map = {
+        let resources = storage::get::<Resources>();
+        resources
+            .maps
+            .first()
+            .map(|map_resource| map_resource.meta.path.clone())
+    };
+
+    let settings = LobbySettings {
+        map,
+        address: Some((Ipv4Addr::LOCALHOST, DEFAULT_PORT).into()),
+        ..Default::default()
+    };
+
+    Api::update_lobby_settings(&lobby.id, settings).await?;
+
+    Api::get_lobby(&lobby.id).await
+}
+
+/// Handle `action`, taken in the lobby room. This returns the next state, if it changed.
+async fn update_lobby_room(
+    state: &mut LobbyRoomState,
+    action: NetworkUiAction,
+) -> Option<NetworkUiState> {
+    let lobby_id = state.lobby.id.clone();
+
+    let res = match action {
+        NetworkUiAction::Leave => {
+            if let Err(err) = Api::leave_lobby(&lobby_id).await {
+                println!("WARNING: Unable to leave lobby: {}", err);
+            }
+
+            return Some(NetworkUiState::Browser(LobbyBrowserState::new()));
+        }
+        NetworkUiAction::SetReady(is_ready) => Api::set_ready(&lobby_id, is_ready).await,
+        NetworkUiAction::SelectCharacter(character) => {
+            Api::set_character(&lobby_id, &character).await
+        }
+        NetworkUiAction::SelectMap => {
+            let map_resource = gui::show_select_map_menu().await;
+
+            let settings = LobbySettings {
+                map: Some(map_resource.meta.path),
+                ..state.lobby.settings.clone()
+            };
+
+            Api::update_lobby_settings(&lobby_id, settings).await
+        }
+        NetworkUiAction::SetRules(rules) => {
+            let settings = LobbySettings {
+                rules: serde_json::to_string(&rules).ok(),
+                ..state.lobby.settings.clone()
+            };
+
+            Api::update_lobby_settings(&lobby_id, settings).await
+        }
+        NetworkUiAction::SetAddress => match state.address.trim().parse::<SocketAddr>() {
+            Ok(address) => {
+                let settings = LobbySettings {
+                    address: Some(address),
+                    ..state.lobby.settings.clone()
+                };
+
+                Api::update_lobby_settings(&lobby_id, settings).await
+            }
+            Err(_) => {
+                state.error = Some("Invalid address".to_string());
+                return None;
+            }
+        },
+        NetworkUiAction::Kick(player_id) => Api::kick_player(&lobby_id, &player_id).await,
+        NetworkUiAction::SetMuted(player_id, is_muted) => {
+            Api::set_player_muted(&lobby_id, &player_id, is_muted).await
+        }
+        NetworkUiAction::SendChat(text) => {
+            // The message is shown once it is received back from the service
+            if let Err(err) = Api::send_chat_message(&lobby_id, &text).await {
+                state
+                    .chat
+                    .push_system_message(&format!("Unable to send message: {}", err));
+            }
+
+            return None;
+        }
+        NetworkUiAction::Start => Api::start_game(&lobby_id).await,
+        _ => return None,
+    };
+
+    state.error = res.err().map(|err| err.to_string());
+
+    None
+}
+
+/// Join the game announced on the local network, that is listed as `lobby`
+fn join_lan_game(mut lobby: Lobby) -> Result<MainMenuResult, String> {
+    let local_player_id = Id::from("lan-local");
+
+    lobby.players.push(Player::new(&local_player_id, "Player"));
+
+    build_network_game(&lobby, &local_player_id, None, None)
+}
+
+/// Returns `true` if the game of `lobby` is running, and can be watched as a spectator
+fn is_spectatable(lobby: &Lobby) -> bool {
+    lobby.state == LobbyState::Running && lobby.settings.address.is_some()
+}
+
+/// Watch the running game of `lobby`, as a spectator
+fn spectate_game(lobby: &Lobby) -> Result<MainMenuResult, String> {
+    let resources = storage::get::<Resources>();
+
+    let map = lobby_map(&resources, lobby)?;
+
+    let address = lobby
+        .settings
+        .address
+        .ok_or_else(|| "The lobby has no host address".to_string())?;
+
+    Ok(MainMenuResult::SpectateGame {
+        address,
+        map,
+        relay: lobby_relay(lobby, None),
+        input_scheme: LOCAL_INPUT_SCHEME,
+    })
+}
+
+/// Host a game on the local network, named `name`, on a map selected by the local player
+async fn host_lan_game(name: &str) -> Result<MainMenuResult, String> {
+    let map_resource = gui::show_select_map_menu().await;
+
+    let announcement = LanAnnouncement::new(
+        name,
+        &map_resource.meta.path,
+        1,
+        LOBBY_CAPACITY,
+        DEFAULT_PORT,
+    );
+
+    let mut lobby = announcement.to_lobby((Ipv4Addr::UNSPECIFIED, DEFAULT_PORT).into());
+    let local_player_id = lobby.admin_player_id.clone();
+
+    lobby
+        .players
+        .push(Player::new(&Id::from("lan-peer"), "Player"));
+
+    build_network_game(&lobby, &local_player_id, None, Some(announcement))
+}
+
+/// Build the network game of `lobby`, once it has started
+fn build_network_game(
+    lobby: &Lobby,
+    local_player_id: &Id,
+    relay_host_token: Option<u64>,
+    announcement: Option<LanAnnouncement>,
+) -> Result<MainMenuResult, String> {
+    let resources = storage::get::<Resources>();
+
+    let map = lobby_map(&resources, lobby)?;
+
+    let address = lobby
+        .settings
+        .address
+        .ok_or_else(|| "The lobby has no host address".to_string())?;
+
+    let characters = sorted_character_ids(&resources);
+
+    let LobbyRules {
+        rules,
+        allow_late_join,
+        netcode,
+    } = LobbyRules::from_lobby(lobby);
+
+    let mut players = Vec::new();
+
+    for (i, player) in lobby.players.iter().enumerate() {
+        let controller = if player.id == *local_player_id {
+            PlayerControllerKind::LocalInput(LOCAL_INPUT_SCHEME)
+        } else {
+            PlayerControllerKind::Network(player.id.clone())
+        };
+
+        let character = player
+            .character
+            .as_ref()
+            .or_else(|| characters.get(i % characters.len().max(1)))
+            .and_then(|id| resources.player_characters.get(id))
+            .cloned()
+            .ok_or_else(|| format!("No character available for '{}'", player.username))?;
+
+        players.push(PlayerParams {
+            index: i as u8,
+            controller,
+            character,
+        });
+    }
+
+    Ok(MainMenuResult::NetworkGame {
+        is_host: lobby.admin_player_id == *local_player_id,
+        address,
+        map,
+        players,
+        rules,
+        allow_late_join,
+        netcode,
+        announcement,
+        relay: lobby_relay(lobby, relay_host_token),
+    })
+}
+
+/// The relay of the game of `lobby`. Games on the local network do not use a relay, as their
+/// lobbies are not shared by the peers, so they could not agree on a session. The host registers
+/// the game with `relay_host_token`, which the lobby service only gives to the admin.
+fn lobby_relay(lobby: &Lobby, relay_host_token: Option<u64>) -> Option<RelayConfig> {
+    let (relay_server, is_forced) = storage::try_get::<Config>()
+        .map(|config| (config.relay_server, config.force_relay))
+        .unwrap_or_default();
+
+    lobby.server.as_ref().map(|server| RelayConfig {
+        server: relay_server.unwrap_or(server.udp),
+        session: lobby.relay_session(),
+        is_forced,
+        host_token: relay_host_token,
+    })
+}
+
+/// The map of `lobby`, if it is available locally
+fn lobby_map(resources: &Resources, lobby: &Lobby) -> Result<Map, String> {
+    lobby
+        .settings
+        .map
+        .as_ref()
+        .and_then(|path| {
+            resources
+                .maps
+                .iter()
+                .find(|map_resource| map_resource.meta.path == *path)
+        })
+        .map(|map_resource| map_resource.map.clone())
+        .ok_or_else(|| "The map of the lobby is not available".to_string())
+}
+
+/// The ids of all player characters, sorted, so that the default character of a player is the
+/// same on all clients
+fn sorted_character_ids(resources: &Resources) -> Vec<String> {
+    let mut res = resources
+        .player_characters
+        .keys()
+        .cloned()
+        .collect::<Vec<_>>();
+
+    res.sort();
+
+    res
+}
+
+fn client_state_label(state: ClientState) -> &'static str {
+    match state {
+        ClientState::None => "Connecting",
+        ClientState::Joined => "Not Ready",
+        ClientState::Ready => "Ready",
+        ClientState::Playing => "Playing",
+        ClientState::Left => "Left",
+        ClientState::Done => "Done",
+    }
+}
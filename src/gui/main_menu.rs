@@ -1,4 +1,5 @@
 use std::borrow::BorrowMut;
+use std::net::SocketAddr;
 
 use macroquad::{
     experimental::collections::storage,
@@ -8,11 +9,14 @@ use macroquad::{
 
 use fishsticks::{Button, GamepadContext};
 
-use core::Id;
+use core::network::Api;
 
+use super::lobby::{NetworkUiResult, NetworkUiState};
 use super::{draw_main_menu_background, GuiResources, Menu, MenuEntry, MenuResult, Panel};
 
+use crate::game::{MatchRules, NetcodeMode, WinCondition};
 use crate::input::update_gamepad_context;
+use crate::network::{init_api, LanAnnouncement, RelayConfig};
 use crate::player::{PlayerControllerKind, PlayerParams};
 use crate::{gui, is_gamepad_btn_pressed, EditorInputScheme, GameInputScheme, Map, Resources};
 
@@ -21,17 +25,40 @@ const MENU_WIDTH: f32 = 300.0;
 const HEADER_TEXTURE_ID: &str = "main_menu_header";
 
 const LOCAL_GAME_MENU_WIDTH: f32 = 400.0;
-const LOCAL_GAME_MENU_HEIGHT: f32 = 200.0;
+const LOCAL_GAME_MENU_HEIGHT: f32 = 264.0;
 
 pub enum MainMenuResult {
     LocalGame {
         map: Map,
         players: Vec<PlayerParams>,
+        rules: MatchRules,
     },
     NetworkGame {
         is_host: bool,
+        /// The address of the host. When hosting, only the port is used.
+        address: SocketAddr,
         map: Map,
         players: Vec<PlayerParams>,
+        /// The rules decided by the admin of the lobby
+        rules: MatchRules,
+        /// If this is set, spectators can take the slots of a remote peer that has left, when
+        /// hosting
+        allow_late_join: bool,
+        /// The netcode model decided by the admin of the lobby
+        netcode: NetcodeMode,
+        /// When hosting a game on the local network, this is announced for as long as the
+        /// game is running
+        announcement: Option<LanAnnouncement>,
+        /// The relay used if the host can not be reached directly
+        relay: Option<RelayConfig>,
+    },
+    /// Watch the network game hosted at `address`, as a spectator
+    SpectateGame {
+        address: SocketAddr,
+        map: Map,
+        relay: Option<RelayConfig>,
+        /// The input scheme used if the spectator takes the slots of a remote peer
+        input_scheme: GameInputScheme,
     },
     Editor {
         input_scheme: EditorInputScheme,
@@ -46,7 +73,7 @@ pub enum MainMenuResult {
 enum MainMenuState {
     Root(Menu),
     LocalGame,
-    NetworkGame,
+    NetworkGame(Box<NetworkUiState>),
     Settings,
     Editor(Menu),
     Credits,
@@ -138,6 +165,7 @@ pub async fn show_main_menu() -> MainMenuResult {
     let mut menu_state = MainMenuState::Root(build_main_menu());
 
     let mut player_input = Vec::new();
+    let mut rules = MatchRules::default();
 
     loop {
         update_gamepad_context(None).unwrap();
@@ -169,7 +197,17 @@ pub async fn show_main_menu() -> MainMenuResult {
                             menu_state = MainMenuState::LocalGame;
                         }
                         ROOT_OPTION_NETWORK_GAME => {
-                            menu_state = MainMenuState::NetworkGame;
+                            // Without a configured lobby service, the mock backend is used
+                            if !Api::is_initialized() {
+                                if let Err(err) = init_api("player_one_token").await {
+                                    println!("WARNING: Unable to initialize api: {}", err);
+                                }
+                            }
+
+                            if Api::is_initialized() {
+                                menu_state =
+                                    MainMenuState::NetworkGame(Box::new(NetworkUiState::new()));
+                            }
                         }
                         ROOT_OPTION_EDITOR => {
                             menu_state = MainMenuState::Editor(build_editor_menu());
@@ -188,7 +226,7 @@ pub async fn show_main_menu() -> MainMenuResult {
                 }
             }
             MainMenuState::LocalGame => {
-                let res = local_game_ui(&mut *root_ui(), &mut player_input);
+                let res = local_game_ui(&mut *root_ui(), &mut player_input, &mut rules);
                 if let Some(res) = res {
                     match res.into_usize() {
                         LOCAL_GAME_OPTION_SUBMIT => {
@@ -223,6 +261,7 @@ pub async fn show_main_menu() -> MainMenuResult {
                             return MainMenuResult::LocalGame {
                                 map: map_resource.map,
                                 players,
+                                rules,
                             };
                         }
                         Menu::CANCEL_INDEX => {
@@ -232,9 +271,15 @@ pub async fn show_main_menu() -> MainMenuResult {
                     }
                 }
             }
-            MainMenuState::NetworkGame => {
-                if let Some(res) = network_game_ui(&mut *root_ui(), &mut NetworkUiState::new()) {
-                    return res;
+            MainMenuState::NetworkGame(state) => {
+                let action = state.ui(&mut *root_ui());
+
+                match state.update(action).await {
+                    Some(NetworkUiResult::Start(res)) => return *res,
+                    Some(NetworkUiResult::Cancel) => {
+                        menu_state = MainMenuState::Root(build_main_menu());
+                    }
+                    None => {}
                 }
             }
             MainMenuState::Editor(menu_instance) => {
@@ -271,7 +316,11 @@ pub async fn show_main_menu() -> MainMenuResult {
     }
 }
 
-fn local_game_ui(ui: &mut ui::Ui, player_input: &mut Vec<GameInputScheme>) -> Option<MenuResult> {
+fn local_game_ui(
+    ui: &mut ui::Ui,
+    player_input: &mut Vec<GameInputScheme>,
+    rules: &mut MatchRules,
+) -> Option<MenuResult> {
     if player_input.len() == 2 {
         return Some(LOCAL_GAME_OPTION_SUBMIT.into());
     } else {
@@ -332,72 +381,36 @@ fn local_game_ui(ui: &mut ui::Ui, player_input: &mut Vec<GameInputScheme>) -> Op
         }
 
         {
-            let position = vec2(12.0, 108.0);
+            let position = vec2(12.0, 92.0);
 
-            ui.label(position, "Press B or ESC to cancel");
+            ui.label(
+                position,
+                &format!("Rules: {}", rules.win_condition.description()),
+            );
+
+            if ui.button(vec2(LOCAL_GAME_MENU_WIDTH - 96.0, 92.0), "Change") {
+                rules.win_condition = rules.win_condition.next_preset();
+            }
         }
 
-        ui.pop_skin();
-    });
+        if rules.win_condition != WinCondition::Endless {
+            let position = vec2(12.0, 124.0);
 
-    None
-}
+            ui.label(position, &format!("Rounds: best of {}", rules.rounds));
 
-#[allow(dead_code)]
-struct NetworkUiState {
-    input_scheme: Option<GameInputScheme>,
-}
+            if ui.button(vec2(LOCAL_GAME_MENU_WIDTH - 96.0, 124.0), "Change") {
+                rules.rounds = rules.next_round_preset();
+            }
+        }
 
-impl NetworkUiState {
-    pub fn new() -> Self {
-        NetworkUiState { input_scheme: None }
-    }
-}
+        {
+            let position = vec2(12.0, 172.0);
 
-fn network_game_ui(ui: &mut ui::Ui, _state: &mut NetworkUiState) -> Option<MainMenuResult> {
-    let mut res = None;
-
-    if ui.button(None, "Host") {
-        let resources = storage::get::<Resources>();
-        let map_resource = resources.maps.first().cloned().unwrap();
-        res = Some(MainMenuResult::NetworkGame {
-            is_host: true,
-            map: map_resource.map,
-            players: vec![
-                PlayerParams {
-                    index: 0,
-                    controller: PlayerControllerKind::LocalInput(GameInputScheme::KeyboardLeft),
-                    character: resources.player_characters.get("pescy").cloned().unwrap(),
-                },
-                PlayerParams {
-                    index: 1,
-                    controller: PlayerControllerKind::Network(Id::from("2")),
-                    character: resources.player_characters.get("sharky").cloned().unwrap(),
-                },
-            ],
-        });
-    }
+            ui.label(position, "Press B or ESC to cancel");
+        }
 
-    if ui.button(None, "Join") {
-        let resources = storage::get::<Resources>();
-        let map_resource = resources.maps.first().cloned().unwrap();
-        res = Some(MainMenuResult::NetworkGame {
-            is_host: false,
-            map: map_resource.map,
-            players: vec![
-                PlayerParams {
-                    index: 0,
-                    controller: PlayerControllerKind::Network(Id::from("1")),
-                    character: resources.player_characters.get("pescy").cloned().unwrap(),
-                },
-                PlayerParams {
-                    index: 1,
-                    controller: PlayerControllerKind::LocalInput(GameInputScheme::KeyboardLeft),
-                    character: resources.player_characters.get("sharky").cloned().unwrap(),
-                },
-            ],
-        });
-    }
+        ui.pop_skin();
+    });
 
-    res
+    None
 }
@@ -1,8 +1,10 @@
 mod background;
+mod chat;
 mod checkbox;
 mod create_map;
 mod credits;
 mod game_menu;
+mod lobby;
 mod main_menu;
 mod menu;
 mod panel;
@@ -18,12 +20,13 @@ pub use style::{
 };
 
 pub use background::{draw_main_menu_background, Background};
+pub use chat::{player_color, ChatLog, CHAT_KEY};
 pub use checkbox::Checkbox;
 pub use create_map::show_create_map_menu;
 pub use credits::show_game_credits;
 pub use game_menu::{
     close_game_menu, draw_game_menu, is_game_menu_open, open_game_menu, toggle_game_menu,
-    GAME_MENU_RESULT_MAIN_MENU, GAME_MENU_RESULT_QUIT,
+    GAME_MENU_RESULT_MAIN_MENU, GAME_MENU_RESULT_QUIT, GAME_MENU_RESULT_RESTART,
 };
 pub use main_menu::{show_main_menu, MainMenuResult};
 pub use menu::{Menu, MenuEntry, MenuResult};
@@ -46,3 +49,9 @@ impl GuiResources {
         }
     }
 }
+
+impl Default for GuiResources {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -929,3 +929,9 @@ impl SkinCollection {
         }
     }
 }
+
+impl Default for SkinCollection {
+    fn default() -> Self {
+        Self::new()
+    }
+}
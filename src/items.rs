@@ -2,7 +2,7 @@
 //! Proto-mods, eventually some of the items will move to some sort of a wasm runtime
 
 use hecs::{Entity, World};
-use macroquad::audio::{play_sound_once, Sound};
+use macroquad::audio::play_sound_once;
 use macroquad::experimental::collections::storage;
 use macroquad::prelude::*;
 
@@ -88,12 +88,14 @@ pub struct ItemParams {
     pub deplete_behavior: ItemDepleteBehavior,
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Item {
     pub id: String,
     pub name: String,
     pub effects: Vec<PassiveEffectMetadata>,
     pub uses: Option<u32>,
     pub duration: Option<f32>,
+    #[serde(with = "json::vec2_def")]
     pub mount_offset: Vec2,
     pub drop_behavior: ItemDropBehavior,
     pub deplete_behavior: ItemDepleteBehavior,
@@ -239,11 +241,6 @@ pub fn spawn_item(world: &mut World, position: Vec2, meta: MapItemMetadata) -> R
         MapItemKind::Weapon { meta } => {
             let effect_offset = meta.effect_offset;
 
-            let mut sound_effect = None;
-            if let Some(id) = meta.sound_effect_id.as_ref() {
-                sound_effect = storage::get::<Resources>().sounds.get(id).copied();
-            }
-
             if let Some(effect_sprite) = meta.effect_sprite {
                 let animations = effect_sprite
                     .animations
@@ -278,7 +275,7 @@ pub fn spawn_item(world: &mut World, position: Vec2, meta: MapItemMetadata) -> R
                 name,
                 effects: meta.effects,
                 uses,
-                sound_effect,
+                sound_effect_id: meta.sound_effect_id,
                 mount_offset,
                 effect_offset,
                 drop_behavior,
@@ -306,7 +303,7 @@ pub struct WeaponParams {
     pub name: String,
     pub effects: Vec<ActiveEffectMetadata>,
     pub uses: Option<u32>,
-    pub sound_effect: Option<Sound>,
+    pub sound_effect_id: Option<String>,
     pub mount_offset: Vec2,
     pub effect_offset: Vec2,
     pub drop_behavior: ItemDropBehavior,
@@ -319,7 +316,7 @@ impl Default for WeaponParams {
             name: "".to_string(),
             effects: Vec::new(),
             uses: None,
-            sound_effect: None,
+            sound_effect_id: None,
             mount_offset: Vec2::ZERO,
             effect_offset: Vec2::ZERO,
             drop_behavior: Default::default(),
@@ -328,16 +325,19 @@ impl Default for WeaponParams {
     }
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Weapon {
     pub id: String,
     pub name: String,
     pub effects: Vec<ActiveEffectMetadata>,
-    pub sound_effect: Option<Sound>,
+    pub sound_effect_id: Option<String>,
     pub recoil: f32,
     pub cooldown: f32,
     pub attack_duration: f32,
     pub uses: Option<u32>,
+    #[serde(with = "json::vec2_def")]
     pub mount_offset: Vec2,
+    #[serde(with = "json::vec2_def")]
     pub effect_offset: Vec2,
     pub drop_behavior: ItemDropBehavior,
     pub deplete_behavior: ItemDepleteBehavior,
@@ -361,7 +361,7 @@ impl Weapon {
             cooldown,
             uses: params.uses,
             attack_duration,
-            sound_effect: params.sound_effect,
+            sound_effect_id: params.sound_effect_id,
             mount_offset: params.mount_offset,
             effect_offset: params.effect_offset,
             drop_behavior: params.drop_behavior,
@@ -413,8 +413,11 @@ pub fn fire_weapon(world: &mut World, entity: Entity, owner: Entity) -> Result<(
 
             weapon.cooldown_timer = 0.0;
 
-            if let Some(sound) = weapon.sound_effect {
-                play_sound_once(sound);
+            if let Some(id) = &weapon.sound_effect_id {
+                let resources = storage::get::<Resources>();
+                if let Some(sound) = resources.sounds.get(id) {
+                    play_sound_once(*sound);
+                }
             }
 
             let mut drawable = world.get_mut::<Drawable>(entity).unwrap();
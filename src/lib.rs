@@ -0,0 +1,85 @@
+use fishsticks::GamepadContext;
+
+pub mod config;
+pub mod debug;
+pub mod ecs;
+pub mod editor;
+pub mod effects;
+pub mod events;
+pub mod game;
+pub mod gui;
+pub mod input;
+mod items;
+pub mod json;
+pub mod map;
+pub mod network;
+mod noise;
+pub mod particles;
+pub mod physics;
+pub mod player;
+pub mod profiler;
+pub mod resources;
+
+mod channel;
+mod drawables;
+mod transform;
+
+pub use drawables::*;
+pub use input::*;
+pub use physics::*;
+pub use transform::*;
+
+pub use editor::{Editor, EditorCamera, EditorInputScheme, EditorState};
+
+pub use map::{Map, MapLayerKind, MapObjectKind};
+
+pub use channel::Channel;
+
+pub use config::Config;
+pub use items::Item;
+
+pub use events::{dispatch_application_event, ApplicationEvent};
+
+pub use game::{
+    collect_local_input, start_music, stop_music, Game, GameCamera, GameInput, GameInputScheme,
+};
+
+pub use resources::Resources;
+
+pub use player::PlayerEvent;
+
+pub use ecs::Owner;
+
+pub use effects::{
+    ActiveEffectKind, ActiveEffectMetadata, PassiveEffectInstance, PassiveEffectMetadata,
+};
+
+pub type CollisionWorld = macroquad_platformer::World;
+
+pub const ASSETS_DIR_ENV_VAR: &str = "FISHFIGHT_ASSETS";
+pub const MODS_DIR_ENV_VAR: &str = "FISHFIGHT_MODS";
+
+/// Exit to main menu
+pub fn exit_to_main_menu() {
+    ApplicationEvent::MainMenu.dispatch();
+}
+
+/// Quit to desktop
+pub fn quit_to_desktop() {
+    ApplicationEvent::Quit.dispatch()
+}
+
+/// Reload resources
+pub fn reload_resources() {
+    ApplicationEvent::ReloadResources.dispatch()
+}
+
+/// Restart the current local match
+pub fn restart_match() {
+    ApplicationEvent::RestartMatch.dispatch()
+}
+
+/// Return to the editor that the current match was started from
+pub fn return_to_editor() {
+    ApplicationEvent::ReturnToEditor.dispatch()
+}
@@ -6,86 +6,30 @@ use std::path::PathBuf;
 use macroquad::experimental::collections::storage;
 use macroquad::prelude::*;
 
-pub mod config;
-pub mod debug;
-pub mod ecs;
-pub mod editor;
-pub mod effects;
-pub mod events;
-pub mod game;
-mod gui;
-pub mod input;
-mod items;
-pub mod json;
-pub mod map;
-pub mod network;
-mod noise;
-pub mod particles;
-pub mod physics;
-pub mod player;
-pub mod resources;
-
-mod channel;
-mod drawables;
-mod transform;
-
-pub use drawables::*;
-pub use input::*;
-pub use physics::*;
-pub use transform::*;
-
-use editor::{Editor, EditorCamera, EditorInputScheme};
-
-use map::{Map, MapLayerKind, MapObjectKind};
-
-pub use channel::Channel;
-
-pub use config::Config;
-pub use items::Item;
-
-pub use events::{dispatch_application_event, ApplicationEvent};
-
-pub use game::{
-    collect_local_input, start_music, stop_music, Game, GameCamera, GameInput, GameInputScheme,
+use fishfight::effects::passive::init_passive_effects;
+use fishfight::events::{self, ApplicationEvent};
+use fishfight::game::{GameMode, MatchSettings, Replay};
+use fishfight::gui::{self, MainMenuResult};
+use fishfight::network::init_http_api;
+use fishfight::particles::Particles;
+use fishfight::player::PlayerParams;
+use fishfight::profiler::{self, TRACE_FILE_ENV_VAR};
+use fishfight::resources::{load_resources, MapResource};
+use fishfight::{
+    quit_to_desktop, reload_resources, start_music, stop_music, Config, Editor, EditorCamera,
+    EditorInputScheme, EditorState, Game, Map, Resources, ASSETS_DIR_ENV_VAR, MODS_DIR_ENV_VAR,
 };
 
-pub use resources::Resources;
-
-pub use player::PlayerEvent;
-
-pub use ecs::Owner;
-
-use crate::effects::passive::init_passive_effects;
-use crate::game::GameMode;
-use crate::particles::Particles;
-use crate::resources::load_resources;
-pub use effects::{
-    ActiveEffectKind, ActiveEffectMetadata, PassiveEffectInstance, PassiveEffectMetadata,
-};
-
-pub type CollisionWorld = macroquad_platformer::World;
-
 const CONFIG_FILE_ENV_VAR: &str = "FISHFIGHT_CONFIG";
-const ASSETS_DIR_ENV_VAR: &str = "FISHFIGHT_ASSETS";
-const MODS_DIR_ENV_VAR: &str = "FISHFIGHT_MODS";
+/// If this is set, a replay of the current match will be saved to the specified path, when
+/// exiting to the main menu or to desktop
+const REPLAY_FILE_ENV_VAR: &str = "FISHFIGHT_REPLAY_FILE";
+/// If this is set, the replay at the specified path will be played back on startup, instead of
+/// showing the main menu
+const PLAYBACK_FILE_ENV_VAR: &str = "FISHFIGHT_PLAYBACK_FILE";
 
 const WINDOW_TITLE: &str = "Fish Fight";
 
-/// Exit to main menu
-pub fn exit_to_main_menu() {
-    ApplicationEvent::MainMenu.dispatch();
-}
-
-/// Quit to desktop
-pub fn quit_to_desktop() {
-    ApplicationEvent::Quit.dispatch()
-}
-
-/// Reload resources
-pub fn reload_resources() {
-    ApplicationEvent::ReloadResources.dispatch()
-}
-
 fn window_conf() -> Conf {
     let config = Config::load(
         env::var(CONFIG_FILE_ENV_VAR)
@@ -113,9 +57,6 @@ fn window_conf() -> Conf {
 
 #[macroquad::main(window_conf)]
 async fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
-    use events::iter_events;
-    use gui::MainMenuResult;
-
     let assets_dir = env::var(ASSETS_DIR_ENV_VAR).unwrap_or_else(|_| "./assets".to_string());
     let mods_dir = env::var(MODS_DIR_ENV_VAR).unwrap_or_else(|_| "./mods".to_string());
 
@@ -123,6 +64,10 @@ async fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
 
     load_resources(&assets_dir, &mods_dir).await?;
 
+    if env::var(TRACE_FILE_ENV_VAR).is_ok() {
+        profiler::start_trace();
+    }
+
     {
         let gamepad_system = fishsticks::GamepadContext::init().unwrap();
         storage::store(gamepad_system);
@@ -137,93 +82,379 @@ async fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
 
     // init_api("player_one_token").await?;
 
-    'outer: loop {
-        match gui::show_main_menu().await {
-            MainMenuResult::LocalGame { map, players } => {
-                let game = Game::new(GameMode::Local, map, &players)?;
-                scene::add_node(game);
+    {
+        let config = storage::get::<Config>().clone();
+
+        if let Some(address) = config.lobby_server {
+            let token = config
+                .lobby_token
+                .unwrap_or_else(|| format!("local-{}", (miniquad::date::now() * 1000.0) as u64));
 
-                start_music("fish_tide");
+            if let Err(err) = init_http_api(address, &token).await {
+                println!("WARNING: Unable to connect to lobby service: {}", err);
             }
-            MainMenuResult::NetworkGame {
-                is_host,
+        }
+    }
+
+    let state = match env::var(PLAYBACK_FILE_ENV_VAR) {
+        Ok(path) => {
+            start_replay(Replay::load(path)?)?;
+
+            AppState::Replay
+        }
+        Err(_) => AppState::MainMenu,
+    };
+
+    let res = run_main_loop(state).await;
+
+    // The trace covers the whole session, across matches, so it is saved however the main loop
+    // exits, including on an error
+    save_profiler_trace()?;
+
+    // Api::close().await?;
+
+    res
+}
+
+/// Run frames, handling application events, until `ApplicationEvent::Quit` is handled
+async fn run_main_loop(mut state: AppState) -> Result<(), Box<dyn std::error::Error>> {
+    loop {
+        if let AppState::MainMenu = state {
+            state = show_main_menu().await?;
+        }
+
+        for event in events::iter_events() {
+            state = handle_event(state, event).await?;
+        }
+
+        match state {
+            AppState::Quit => break,
+            // The main menu runs its own frames
+            AppState::MainMenu => continue,
+            _ => {}
+        }
+
+        {
+            let mut gamepad_system = storage::get_mut::<GamepadContext>();
+            gamepad_system.update()?;
+        }
+
+        next_frame().await;
+    }
+
+    Ok(())
+}
+
+/// The state of the main loop, which changes as application events are handled
+#[allow(clippy::large_enum_variant)]
+enum AppState {
+    MainMenu,
+    /// A local match is running. This holds what is needed to restart it, and the editor that
+    /// it was started from, if any.
+    LocalGame {
+        map: Map,
+        players: Vec<PlayerParams>,
+        settings: MatchSettings,
+        editor: Option<EditorState>,
+    },
+    /// A network match is running, either as a peer or as a spectator
+    NetworkGame,
+    /// A replay is being played back
+    Replay,
+    Editor,
+    Quit,
+}
+
+/// Show the main menu, returning the state that the selected option leads to. Options that are
+/// handled by the main loop are dispatched as events, in which case this returns
+/// `AppState::MainMenu`, so that the main menu is shown again, if the event does not lead
+/// anywhere else.
+async fn show_main_menu() -> Result<AppState, Box<dyn std::error::Error>> {
+    match gui::show_main_menu().await {
+        MainMenuResult::LocalGame {
+            map,
+            players,
+            rules,
+        } => {
+            ApplicationEvent::StartLocalGame {
                 map,
                 players,
-            } => {
-                let mode = if is_host {
-                    GameMode::NetworkHost
-                } else {
-                    GameMode::NetworkClient
-                };
+                settings: MatchSettings {
+                    rules,
+                    ..MatchSettings::with_random_seed()
+                },
+            }
+            .dispatch();
+        }
+        MainMenuResult::NetworkGame {
+            is_host,
+            address,
+            map,
+            players,
+            rules,
+            allow_late_join,
+            netcode,
+            announcement,
+            relay,
+        } => {
+            let mode = if is_host {
+                GameMode::NetworkHost {
+                    port: address.port(),
+                    relay,
+                }
+            } else {
+                GameMode::NetworkClient {
+                    host: address,
+                    relay,
+                }
+            };
+
+            // Clients adopt the settings of the host when it welcomes them, so these only
+            // apply when hosting
+            let settings = MatchSettings {
+                netcode,
+                rules,
+                allow_late_join,
+                ..MatchSettings::with_random_seed()
+            };
+
+            let mut game = Game::new(mode, map, &players, settings)?;
+
+            if let Some(announcement) = announcement {
+                if let Err(err) = game.announce_on_lan(announcement) {
+                    println!("WARNING: Unable to announce game on LAN: {}", err);
+                }
+            }
 
-                let game = Game::new(mode, map, &players)?;
-                scene::add_node(game);
+            scene::add_node(game);
 
-                start_music("fish_tide");
-            }
-            MainMenuResult::Editor {
+            start_music("fish_tide");
+
+            return Ok(AppState::NetworkGame);
+        }
+        MainMenuResult::SpectateGame {
+            address,
+            map,
+            relay,
+            input_scheme,
+        } => {
+            let mode = GameMode::NetworkSpectator {
+                host: address,
+                relay,
                 input_scheme,
-                is_new_map,
-            } => {
-                let map_resource = if is_new_map {
-                    let res = gui::show_create_map_menu().await?;
-                    if res.is_none() {
-                        continue 'outer;
-                    }
+            };
 
-                    res.unwrap()
-                } else {
-                    gui::show_select_map_menu().await
-                };
+            // The state of the game, and with it the players, is received from the host
+            let game = Game::new(mode, map, &[], MatchSettings::default())?;
+            scene::add_node(game);
 
-                let position = map_resource.map.get_size() * 0.5;
+            start_music("fish_tide");
 
-                scene::add_node(EditorCamera::new(position));
-                scene::add_node(Editor::new(input_scheme, map_resource));
-            }
-            MainMenuResult::ReloadResources => {
-                reload_resources();
-                continue 'outer;
+            return Ok(AppState::NetworkGame);
+        }
+        MainMenuResult::Editor {
+            input_scheme,
+            is_new_map,
+        } => {
+            let map_resource = if is_new_map {
+                gui::show_create_map_menu().await?
+            } else {
+                Some(gui::show_select_map_menu().await)
+            };
+
+            if let Some(map) = map_resource {
+                ApplicationEvent::OpenEditor { input_scheme, map }.dispatch();
             }
-            MainMenuResult::Credits => {
-                let resources = storage::get::<Resources>();
-                start_music("thanks_for_all_the_fished");
-                gui::show_game_credits(&resources.assets_dir).await;
-                stop_music();
-                continue 'outer;
+        }
+        MainMenuResult::ReloadResources => {
+            reload_resources();
+        }
+        MainMenuResult::Credits => {
+            let resources = storage::get::<Resources>();
+            start_music("thanks_for_all_the_fished");
+            gui::show_game_credits(&resources.assets_dir).await;
+            stop_music();
+        }
+        MainMenuResult::Quit => {
+            quit_to_desktop();
+        }
+    }
+
+    Ok(AppState::MainMenu)
+}
+
+async fn handle_event(
+    state: AppState,
+    event: ApplicationEvent,
+) -> Result<AppState, Box<dyn std::error::Error>> {
+    let res = match event {
+        ApplicationEvent::ReloadResources => {
+            let resources = storage::get::<Resources>();
+            load_resources(&resources.assets_dir, &resources.mods_dir).await?;
+
+            state
+        }
+        ApplicationEvent::MainMenu => {
+            close_scene()?;
+
+            AppState::MainMenu
+        }
+        ApplicationEvent::Quit => {
+            close_scene()?;
+
+            AppState::Quit
+        }
+        ApplicationEvent::StartLocalGame {
+            map,
+            players,
+            settings,
+        } => {
+            let editor = match state {
+                AppState::Editor => {
+                    scene::find_node_by_type::<Editor>().map(|mut editor| editor.take_state())
+                }
+                AppState::LocalGame { editor, .. } => editor,
+                _ => None,
+            };
+
+            close_scene()?;
+            start_local_game(map.clone(), &players, settings.clone(), editor.is_some())?;
+
+            AppState::LocalGame {
+                map,
+                players,
+                settings,
+                editor,
             }
-            MainMenuResult::Quit => {
-                quit_to_desktop();
+        }
+        ApplicationEvent::OpenEditor { input_scheme, map } => {
+            close_scene()?;
+            open_editor(input_scheme, map);
+
+            AppState::Editor
+        }
+        ApplicationEvent::RestartMatch => {
+            debug_assert!(
+                matches!(state, AppState::LocalGame { .. }),
+                "Only local matches can be restarted"
+            );
+
+            match state {
+                AppState::LocalGame {
+                    map,
+                    players,
+                    settings,
+                    editor,
+                } => {
+                    let settings = MatchSettings {
+                        seed: MatchSettings::with_random_seed().seed,
+                        ..settings
+                    };
+
+                    close_scene()?;
+                    start_local_game(map.clone(), &players, settings.clone(), editor.is_some())?;
+
+                    AppState::LocalGame {
+                        map,
+                        players,
+                        settings,
+                        editor,
+                    }
+                }
+                state => state,
             }
-        };
-
-        'inner: loop {
-            #[allow(clippy::never_loop)]
-            for event in iter_events() {
-                match event {
-                    ApplicationEvent::ReloadResources => {
-                        let resources = storage::get::<Resources>();
-                        load_resources(&resources.assets_dir, &resources.mods_dir).await?;
+        }
+        ApplicationEvent::ReturnToEditor => {
+            debug_assert!(
+                matches!(
+                    state,
+                    AppState::LocalGame {
+                        editor: Some(_),
+                        ..
                     }
-                    ApplicationEvent::MainMenu => break 'inner,
-                    ApplicationEvent::Quit => break 'outer,
+                ),
+                "The current match was not started from the editor"
+            );
+
+            match state {
+                AppState::LocalGame {
+                    editor: Some(editor),
+                    ..
+                } => {
+                    close_scene()?;
+                    editor.restore();
+
+                    AppState::Editor
                 }
+                state => state,
             }
+        }
+    };
 
-            {
-                let mut gamepad_system = storage::get_mut::<GamepadContext>();
-                gamepad_system.update()?;
-            }
+    Ok(res)
+}
 
-            next_frame().await;
-        }
+/// Start a local match. If `is_playtest` is `true`, the match can be ended, returning to the
+/// editor, by pressing escape.
+fn start_local_game(
+    map: Map,
+    players: &[PlayerParams],
+    settings: MatchSettings,
+    is_playtest: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut game = Game::new(GameMode::Local, map, players, settings)?;
+    game.set_playtest(is_playtest);
+
+    scene::add_node(game);
+
+    start_music("fish_tide");
+
+    Ok(())
+}
 
-        scene::clear();
+fn start_replay(replay: Replay) -> Result<(), Box<dyn std::error::Error>> {
+    let game = Game::from_replay(replay)?;
 
-        stop_music();
+    scene::add_node(game);
+
+    start_music("fish_tide");
+
+    Ok(())
+}
+
+fn open_editor(input_scheme: EditorInputScheme, map_resource: MapResource) {
+    let position = map_resource.map.get_size() * 0.5;
+
+    scene::add_node(EditorCamera::new(position));
+    scene::add_node(Editor::new(input_scheme, map_resource));
+}
+
+/// Remove the current game, or editor, from the scene, saving a replay of the match, if one was
+/// being played
+fn close_scene() -> Result<(), Box<dyn std::error::Error>> {
+    save_match_replay()?;
+
+    scene::clear();
+
+    stop_music();
+
+    Ok(())
+}
+
+fn save_profiler_trace() -> Result<(), Box<dyn std::error::Error>> {
+    if let Ok(path) = env::var(TRACE_FILE_ENV_VAR) {
+        profiler::save_trace(path)?;
     }
 
-    // Api::close().await?;
+    Ok(())
+}
+
+fn save_match_replay() -> Result<(), Box<dyn std::error::Error>> {
+    if let Ok(path) = env::var(REPLAY_FILE_ENV_VAR) {
+        if let Some(game) = scene::find_node_by_type::<Game>() {
+            game.replay().save(path)?;
+        }
+    }
 
     Ok(())
 }
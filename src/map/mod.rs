@@ -11,6 +11,7 @@ pub use decoration::*;
 pub use sproinger::*;
 
 use core::math::URect;
+use core::rng::Rng;
 use core::text::ToStringHelper;
 use core::Result;
 
@@ -382,8 +383,8 @@ impl Map {
         Ok(())
     }
 
-    pub fn get_random_spawn_point(&self) -> Vec2 {
-        let i = rand::gen_range(0, self.spawn_points.len()) as usize;
+    pub fn get_random_spawn_point(&self, rng: &mut Rng) -> Vec2 {
+        let i = rng.gen_range(0, self.spawn_points.len());
         self.spawn_points[i]
     }
 }
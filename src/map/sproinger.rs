@@ -4,8 +4,11 @@ use macroquad::prelude::*;
 
 use hecs::{Entity, World};
 
+use serde::{Deserialize, Serialize};
+
 use core::Result;
 
+use crate::game::get_delta_time;
 use crate::{Animation, Drawable, PhysicsBody, QueuedAnimationAction, Resources, Transform};
 
 const SPROINGER_DRAW_ORDER: u32 = 2;
@@ -25,7 +28,7 @@ const TRIGGER_HEIGHT: f32 = 8.0;
 
 const FORCE: f32 = 35.0;
 
-#[derive(Default)]
+#[derive(Default, Clone, Serialize, Deserialize)]
 pub struct Sproinger {
     pub cooldown_timer: f32,
 }
@@ -78,7 +81,7 @@ pub fn spawn_sproinger(world: &mut World, position: Vec2) -> Result<Entity> {
 }
 
 pub fn fixed_update_sproingers(world: &mut World) {
-    let dt = get_frame_time();
+    let dt = get_delta_time();
 
     let bodies = world
         .query::<(&Transform, &PhysicsBody)>()
@@ -101,7 +104,7 @@ pub fn fixed_update_sproingers(world: &mut World) {
 
         let sound = {
             let resources = storage::get::<Resources>();
-            resources.sounds[SOUND_EFFECT_ID]
+            resources.sounds.get(SOUND_EFFECT_ID).copied()
         };
 
         if sproinger.cooldown_timer >= COOLDOWN {
@@ -123,7 +126,9 @@ pub fn fixed_update_sproingers(world: &mut World) {
                         CONTRACT_ANIMATION_ID.to_string(),
                     ));
 
-                    play_sound_once(sound);
+                    if let Some(sound) = sound {
+                        play_sound_once(sound);
+                    }
 
                     continue 'sproingers;
                 }
@@ -0,0 +1,272 @@
+//! Discovery of network games on the local network, without a lobby service.
+//! Hosts broadcast a `LanAnnouncement` at a fixed interval, using a `LanAnnouncer`, while they
+//! wait for a remote peer, and clients collect those with a `LanBrowser`, which lists them as
+//! lobbies, so that they can be shown alongside the lobbies of a lobby service.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+
+use macroquad::miniquad::date;
+
+use serde::{Deserialize, Serialize};
+
+use core::error::{Error, ErrorKind};
+use core::network::{
+    ClientState, Lobby, LobbyPrivacy, LobbySettings, LobbyState, Player, PROTOCOL_VERSION,
+};
+use core::{Id, Result};
+
+/// The port that announcements are broadcast to
+pub const DISCOVERY_PORT: u16 = 9001;
+
+/// The interval, in seconds, at which hosts broadcast their announcement
+pub const ANNOUNCE_INTERVAL: f64 = 1.0;
+
+/// The time, in seconds, after which a host is no longer listed, if no announcement has been
+/// received from it
+pub const HOST_TIMEOUT: f64 = 3.5;
+
+/// This is prepended to all announcements, so that other datagrams sent to the discovery port
+/// are ignored
+const ANNOUNCEMENT_MAGIC: &[u8] = b"FISHFIGHT_LAN";
+
+const MAX_ANNOUNCEMENT_SIZE: usize = 1024;
+
+/// The announcement that is broadcast by a host on the local network
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LanAnnouncement {
+    /// Hosts with a protocol version that is different from `PROTOCOL_VERSION` are not listed
+    pub protocol_version: u16,
+    /// The name of the host, which is listed as the name of the game
+    pub host_name: String,
+    /// The path of the map, like in `LobbySettings`
+    pub map: String,
+    pub player_count: i32,
+    pub capacity: i32,
+    /// The port that the host accepts game traffic on
+    pub port: u16,
+}
+
+impl LanAnnouncement {
+    pub fn new(host_name: &str, map: &str, player_count: i32, capacity: i32, port: u16) -> Self {
+        LanAnnouncement {
+            protocol_version: PROTOCOL_VERSION,
+            host_name: host_name.to_string(),
+            map: map.to_string(),
+            player_count,
+            capacity,
+            port,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut res = ANNOUNCEMENT_MAGIC.to_vec();
+        res.extend(serde_json::to_vec(self)?);
+
+        Ok(res)
+    }
+
+    /// Returns `None` if `bytes` is not an announcement
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        bytes
+            .strip_prefix(ANNOUNCEMENT_MAGIC)
+            .and_then(|json| serde_json::from_slice(json).ok())
+    }
+
+    /// Create a lobby for the game announced by the host at `address`. The host is the only
+    /// player in it, and the admin, and the address of the game is set in the settings of the
+    /// lobby.
+    pub fn to_lobby(&self, address: SocketAddr) -> Lobby {
+        let address = SocketAddr::new(address.ip(), self.port);
+
+        let host_id = Id::from(format!("lan-host-{}", address));
+
+        let mut host = Player::new(&host_id, &self.host_name);
+        host.state = ClientState::Ready;
+
+        let state = if self.player_count < self.capacity {
+            LobbyState::NotStarted
+        } else {
+            LobbyState::Running
+        };
+
+        Lobby {
+            id: Id::from(format!("lan-{}", address)),
+            name: self.host_name.clone(),
+            creator_player_id: host_id.clone(),
+            admin_player_id: host_id,
+            player_count: self.player_count,
+            capacity: self.capacity,
+            server: None,
+            privacy: LobbyPrivacy::Public,
+            state,
+            players: vec![host],
+            settings: LobbySettings {
+                map: Some(self.map.clone()),
+                address: Some(address),
+                ..Default::default()
+            },
+            muted_player_ids: Vec::new(),
+        }
+    }
+}
+
+/// Broadcasts an announcement on the local network, at `ANNOUNCE_INTERVAL`, while `update` is
+/// called
+pub struct LanAnnouncer {
+    socket: UdpSocket,
+    announcement: LanAnnouncement,
+    last_announced: Option<f64>,
+}
+
+impl LanAnnouncer {
+    pub fn new(announcement: LanAnnouncement) -> Result<Self> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+            .and_then(|socket| {
+                socket.set_broadcast(true)?;
+                socket.set_nonblocking(true)?;
+                Ok(socket)
+            })
+            .map_err(|err| Error::new(ErrorKind::Network, err))?;
+
+        Ok(LanAnnouncer {
+            socket,
+            announcement,
+            last_announced: None,
+        })
+    }
+
+    pub fn announcement(&self) -> &LanAnnouncement {
+        &self.announcement
+    }
+
+    /// Replace the announcement, like when a player has joined. It is broadcast on the next call
+    /// to `update`.
+    pub fn set_announcement(&mut self, announcement: LanAnnouncement) {
+        self.announcement = announcement;
+        self.last_announced = None;
+    }
+
+    pub fn update(&mut self) -> Result<()> {
+        let now = date::now();
+
+        if let Some(last_announced) = self.last_announced {
+            if now - last_announced < ANNOUNCE_INTERVAL {
+                return Ok(());
+            }
+        }
+
+        self.last_announced = Some(now);
+
+        let bytes = self.announcement.to_bytes()?;
+
+        // Broadcasting fails when not connected to a network, in which case the game can still be
+        // joined from the same machine, so it is announced over loopback, in stead
+        let res = self
+            .socket
+            .send_to(&bytes, (Ipv4Addr::BROADCAST, DISCOVERY_PORT))
+            .or_else(|_| {
+                self.socket
+                    .send_to(&bytes, (Ipv4Addr::LOCALHOST, DISCOVERY_PORT))
+            });
+
+        match res {
+            Err(err) if err.kind() != io::ErrorKind::WouldBlock => {
+                Err(Error::new(ErrorKind::Network, err))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Collects the announcements of hosts on the local network
+pub struct LanBrowser {
+    socket: UdpSocket,
+    /// The announcements, and the time they were received, by the address of their host
+    hosts: HashMap<SocketAddr, (LanAnnouncement, f64)>,
+}
+
+impl LanBrowser {
+    /// Bind to `DISCOVERY_PORT`. This will fail if another browser is already bound to it, on the
+    /// same machine.
+    pub fn bind() -> Result<Self> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, DISCOVERY_PORT))
+            .and_then(|socket| {
+                socket.set_nonblocking(true)?;
+                Ok(socket)
+            })
+            .map_err(|err| Error::new(ErrorKind::Network, err))?;
+
+        Ok(LanBrowser {
+            socket,
+            hosts: HashMap::new(),
+        })
+    }
+
+    /// Receive pending announcements and remove hosts that have timed out
+    pub fn update(&mut self) {
+        let now = date::now();
+
+        let mut buf = [0; MAX_ANNOUNCEMENT_SIZE];
+
+        loop {
+            match self.socket.recv_from(&mut buf) {
+                Ok((len, addr)) => {
+                    if let Some(announcement) = LanAnnouncement::from_bytes(&buf[..len]) {
+                        if announcement.protocol_version == PROTOCOL_VERSION {
+                            self.hosts.insert(addr, (announcement, now));
+                        }
+                    }
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                // Errors caused by earlier datagrams, like `ConnectionReset` on Windows, are
+                // reported here, so these are skipped
+                Err(_) => continue,
+            }
+        }
+
+        self.hosts
+            .retain(|_, (_, received_at)| now - *received_at < HOST_TIMEOUT);
+    }
+
+    /// The games announced on the local network, as lobbies
+    pub fn lobbies(&self) -> Vec<Lobby> {
+        let mut res = self
+            .hosts
+            .iter()
+            .map(|(addr, (announcement, _))| announcement.to_lobby(*addr))
+            .collect::<Vec<_>>();
+
+        res.sort_by(|a, b| a.name.cmp(&b.name));
+
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_announcement_to_lobby() {
+        let announcement = LanAnnouncement::new("Host", "maps/level_01.json", 1, 2, 9000);
+
+        let bytes = announcement.to_bytes().unwrap();
+        assert_eq!(
+            LanAnnouncement::from_bytes(&bytes),
+            Some(announcement.clone())
+        );
+        assert_eq!(LanAnnouncement::from_bytes(&bytes[1..]), None);
+
+        let lobby = announcement.to_lobby(([192, 168, 0, 2], 50000).into());
+
+        assert_eq!(
+            lobby.settings.address,
+            Some(([192, 168, 0, 2], 9000).into()),
+            "The address of the game should be on the announced port"
+        );
+        assert_eq!(lobby.settings.map.as_deref(), Some("maps/level_01.json"));
+        assert_eq!(lobby.state, LobbyState::NotStarted);
+    }
+}
@@ -0,0 +1,486 @@
+//! A self-hostable lobby service, that implements the API used by `HttpApiBackend`.
+//! It serves the HTTP API, pushes events to connected clients over TCP, and runs a `RelayServer`
+//! on its UDP socket, which is used as the relay of the games of its lobbies. The relay only
+//! accepts the hosts of games that have been started through the service.
+//!
+//! Any bearer token is accepted, and a player is created for a token when it is first seen, so
+//! this should only be used for local play and testing.
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, TcpListener, TcpStream, UdpSocket};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use core::error::{Error, ErrorKind};
+use core::network::{
+    CreateLobbyRequest, KickPlayerRequest, LobbyFilter, LobbyRegistry, LobbySettings,
+    MutePlayerRequest, RegistryResult, RequestStatus, SendChatRequest, Server, SetCharacterRequest,
+    SetReadyRequest,
+};
+use core::{formaterr, Id, Result};
+
+use super::{Relay, RelayServer};
+
+/// The interval at which queued events are pushed to clients
+const EVENT_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The interval at which an empty line is sent over idle event streams, to detect clients that
+/// have disconnected
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The maximum size of a request body
+const MAX_BODY_SIZE: usize = 64 * 1024;
+
+pub struct LobbyServer {
+    server: Server,
+    registry: Arc<Mutex<LobbyRegistry>>,
+    http: TcpListener,
+    tcp: TcpListener,
+    udp: UdpSocket,
+}
+
+impl LobbyServer {
+    /// Bind the HTTP API to `port` on `ip`, and the TCP event stream and the UDP socket to the
+    /// two following ports. If `port` is `0`, all three are bound to unused ports.
+    pub fn bind(ip: IpAddr, port: u16) -> Result<Self> {
+        let port_at = |offset: u16| if port == 0 { 0 } else { port + offset };
+
+        let http = TcpListener::bind((ip, port_at(0)))
+            .map_err(|err| Error::new(ErrorKind::Network, err))?;
+        let tcp = TcpListener::bind((ip, port_at(1)))
+            .map_err(|err| Error::new(ErrorKind::Network, err))?;
+        let udp =
+            UdpSocket::bind((ip, port_at(2))).map_err(|err| Error::new(ErrorKind::Network, err))?;
+
+        let server = Server {
+            http: http
+                .local_addr()
+                .map_err(|err| Error::new(ErrorKind::Network, err))?,
+            tcp: tcp
+                .local_addr()
+                .map_err(|err| Error::new(ErrorKind::Network, err))?,
+            udp: udp
+                .local_addr()
+                .map_err(|err| Error::new(ErrorKind::Network, err))?,
+        };
+
+        let registry = Arc::new(Mutex::new(LobbyRegistry::new(Some(server.clone()))));
+
+        Ok(LobbyServer {
+            server,
+            registry,
+            http,
+            tcp,
+            udp,
+        })
+    }
+
+    /// The addresses that the service is bound to
+    pub fn server(&self) -> &Server {
+        &self.server
+    }
+
+    /// Run the service until the HTTP listener or the relay fails, and return the error. The
+    /// HTTP API, the event stream and the UDP socket are served on separate threads, and every
+    /// connection is handled on a thread of its own.
+    pub fn run(self) -> Result<()> {
+        let (error_tx, error_rx) = mpsc::channel();
+
+        {
+            let registry = self.registry.clone();
+            let tcp = self.tcp;
+
+            thread::spawn(move || {
+                for stream in tcp.incoming().flatten() {
+                    let registry = registry.clone();
+                    thread::spawn(move || serve_events(stream, registry));
+                }
+            });
+        }
+
+        {
+            let udp = self.udp;
+            let registry = self.registry.clone();
+
+            // Only the admin of a lobby is given the host token of its game
+            let relay = Relay::with_host_tokens(Box::new(move |session| {
+                registry.lock().unwrap().relay_host_token(session)
+            }));
+
+            let error_tx = error_tx.clone();
+
+            thread::spawn(move || {
+                let _ = error_tx.send(RelayServer::with_relay(udp, relay).run());
+            });
+        }
+
+        {
+            let http = self.http;
+            let registry = self.registry;
+            let server = self.server;
+
+            thread::spawn(move || {
+                for stream in http.incoming() {
+                    let stream = match stream {
+                        Ok(stream) => stream,
+                        Err(err) => {
+                            let _ = error_tx.send(Err(Error::new(ErrorKind::Network, err)));
+                            return;
+                        }
+                    };
+
+                    let registry = registry.clone();
+                    let server = server.clone();
+
+                    thread::spawn(move || serve_request(stream, &server, &registry));
+                }
+            });
+        }
+
+        error_rx
+            .recv()
+            .unwrap_or_else(|_| Err(formaterr!(ErrorKind::Network, "The service stopped")))
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    headers: HashMap<String, String>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn read(stream: &mut TcpStream) -> Result<Self> {
+        let mut reader = BufReader::new(stream);
+
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+
+        let mut parts = line.split_whitespace();
+        let method = parts.next().unwrap_or_default().to_string();
+        let path = parts.next().unwrap_or_default().to_string();
+
+        let mut headers = HashMap::new();
+
+        loop {
+            let mut line = String::new();
+
+            if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+                break;
+            }
+
+            if let Some((name, value)) = line.split_once(':') {
+                headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+            }
+        }
+
+        let len = headers
+            .get("content-length")
+            .and_then(|len| len.parse::<usize>().ok())
+            .unwrap_or(0)
+            .min(MAX_BODY_SIZE);
+
+        let mut body = vec![0; len];
+        reader.read_exact(&mut body)?;
+
+        Ok(Request {
+            method,
+            path,
+            headers,
+            body,
+        })
+    }
+
+    fn token(&self) -> Option<&str> {
+        self.headers
+            .get("authorization")
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim)
+            .filter(|token| !token.is_empty())
+    }
+}
+
+/// Respond to the request on `stream`. A request that can not be read is responded to with
+/// `RequestStatus::BadRequest`. If the response can not be written, the client is gone, and
+/// there is no one left to report the error to.
+fn serve_request(mut stream: TcpStream, server: &Server, registry: &Mutex<LobbyRegistry>) {
+    let res = stream
+        .set_read_timeout(Some(REQUEST_TIMEOUT))
+        .map_err(Error::from)
+        .and_then(|_| Request::read(&mut stream))
+        .map_err(|_| RequestStatus::BadRequest)
+        .and_then(|req| handle_request(&req, server, &mut registry.lock().unwrap()));
+
+    let (status, body) = match res {
+        Ok(body) => (RequestStatus::Ok, body),
+        Err(status) => (status, "null".to_string()),
+    };
+
+    let res = format!(
+        "HTTP/1.1 {} {}\r\n\
+         Content-Type: application/json\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n\
+         {}",
+        status.as_code(),
+        status.as_str(),
+        body.len(),
+        body
+    );
+
+    let _ = stream.write_all(res.as_bytes());
+}
+
+/// Handle `req` and return the body of the response, as JSON
+fn handle_request(
+    req: &Request,
+    server: &Server,
+    registry: &mut LobbyRegistry,
+) -> RegistryResult<String> {
+    let token = req.token().ok_or(RequestStatus::Unauthorized)?;
+
+    let player_id = match registry.authenticate(token) {
+        Ok(id) => id,
+        Err(_) => {
+            let username = format!("Player {}", registry.player_cnt() + 1);
+            registry.add_player(token, &username).id
+        }
+    };
+
+    let segments = req
+        .path
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect::<Vec<_>>();
+
+    match (req.method.as_str(), segments.as_slice()) {
+        ("GET", ["server"]) => to_json(server),
+        ("GET", ["players", "me"]) => to_json(&registry.get_player(&player_id)?),
+        ("GET", ["players", id]) => to_json(&registry.get_player(&Id::from(*id))?),
+        ("POST", ["lobbies", "search"]) => {
+            let filter: LobbyFilter = from_json(&req.body)?;
+
+            to_json(&registry.list_lobbies(&filter))
+        }
+        ("POST", ["lobbies"]) => {
+            let body: CreateLobbyRequest = from_json(&req.body)?;
+
+            let lobby =
+                registry.create_lobby(&player_id, &body.name, body.privacy, body.capacity)?;
+
+            to_json(&lobby)
+        }
+        ("GET", ["lobbies", id]) => to_json(&registry.get_lobby(&Id::from(*id))?),
+        ("POST", ["lobbies", id, "join"]) => {
+            to_json(&registry.join_lobby(&player_id, &Id::from(*id))?)
+        }
+        ("POST", ["lobbies", id, "leave"]) => {
+            to_json(&registry.leave_lobby(&player_id, &Id::from(*id))?)
+        }
+        ("POST", ["lobbies", id, "ready"]) => {
+            let body: SetReadyRequest = from_json(&req.body)?;
+
+            to_json(&registry.set_ready(&player_id, &Id::from(*id), body.is_ready)?)
+        }
+        ("POST", ["lobbies", id, "character"]) => {
+            let body: SetCharacterRequest = from_json(&req.body)?;
+
+            to_json(&registry.set_character(&player_id, &Id::from(*id), &body.character)?)
+        }
+        ("POST", ["lobbies", id, "kick"]) => {
+            let body: KickPlayerRequest = from_json(&req.body)?;
+
+            to_json(&registry.kick_player(&player_id, &Id::from(*id), &body.player_id)?)
+        }
+        ("POST", ["lobbies", id, "start"]) => {
+            registry.start_game(&player_id, &Id::from(*id))?;
+
+            // There is no game server to wait for, so the game is running as soon as it starts
+            to_json(&registry.set_game_running(&Id::from(*id))?)
+        }
+        ("PUT", ["lobbies", id, "settings"]) => {
+            let body: LobbySettings = from_json(&req.body)?;
+
+            to_json(&registry.update_lobby_settings(&player_id, &Id::from(*id), body)?)
+        }
+        ("POST", ["lobbies", id, "chat"]) => {
+            let body: SendChatRequest = from_json(&req.body)?;
+
+            to_json(&registry.send_chat_message(&player_id, &Id::from(*id), &body.text)?)
+        }
+        ("POST", ["lobbies", id, "mute"]) => {
+            let body: MutePlayerRequest = from_json(&req.body)?;
+
+            to_json(&registry.set_muted(
+                &player_id,
+                &Id::from(*id),
+                &body.player_id,
+                body.is_muted,
+            )?)
+        }
+        _ => Err(RequestStatus::NotFound),
+    }
+}
+
+fn to_json<T: Serialize>(value: &T) -> RegistryResult<String> {
+    serde_json::to_string(value).map_err(|_| RequestStatus::InternalServerError)
+}
+
+fn from_json<T: serde::de::DeserializeOwned>(body: &[u8]) -> RegistryResult<T> {
+    serde_json::from_slice(body).map_err(|_| RequestStatus::BadRequest)
+}
+
+/// Push the events of the player that authenticates on `stream`, until it disconnects, after
+/// which it is removed from the lobby that it is in
+fn serve_events(stream: TcpStream, registry: Arc<Mutex<LobbyRegistry>>) {
+    let mut token = String::new();
+
+    let player_id = {
+        let _ = stream.set_read_timeout(Some(REQUEST_TIMEOUT));
+
+        if BufReader::new(&stream).read_line(&mut token).is_err() {
+            return;
+        }
+
+        match registry.lock().unwrap().authenticate(token.trim()) {
+            Ok(id) => id,
+            Err(_) => return,
+        }
+    };
+
+    let mut stream = stream;
+    let mut last_write = Instant::now();
+
+    loop {
+        thread::sleep(EVENT_INTERVAL);
+
+        let events = registry.lock().unwrap().take_events(&player_id);
+
+        let mut lines = String::new();
+
+        for event in events {
+            if let Ok(line) = serde_json::to_string(&event) {
+                lines.push_str(&line);
+                lines.push('\n');
+            }
+        }
+
+        if lines.is_empty() && last_write.elapsed() >= HEARTBEAT_INTERVAL {
+            lines.push('\n');
+        }
+
+        if !lines.is_empty() {
+            if stream.write_all(lines.as_bytes()).is_err() {
+                break;
+            }
+
+            last_write = Instant::now();
+        }
+    }
+
+    let _ = registry.lock().unwrap().leave_current_lobby(&player_id);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::net::Ipv4Addr;
+    use std::task::{Context, Poll, Wake, Waker};
+
+    use core::network::{ApiBackend, HttpApiBackend, LobbyPrivacy, LobbyState, NetworkEvent};
+
+    use super::*;
+
+    struct NoopWaker;
+
+    impl Wake for NoopWaker {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    /// The requests of `HttpApiBackend` are blocking, so its futures are ready when first polled
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let waker = Waker::from(Arc::new(NoopWaker));
+
+        match Box::pin(future)
+            .as_mut()
+            .poll(&mut Context::from_waker(&waker))
+        {
+            Poll::Ready(res) => res,
+            Poll::Pending => panic!("Request did not complete"),
+        }
+    }
+
+    fn connect(server: &Server, token: &str) -> HttpApiBackend {
+        let mut backend = HttpApiBackend::new(server.http);
+        block_on(backend.init(token)).unwrap();
+        backend
+    }
+
+    /// Poll the events of `backend` until `f` returns `Some` for one of them
+    fn wait_for_event<T>(backend: &mut HttpApiBackend, f: impl Fn(NetworkEvent) -> Option<T>) -> T {
+        for _ in 0..500 {
+            if let Some(res) = backend.poll_events().unwrap().into_iter().find_map(&f) {
+                return res;
+            }
+
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        panic!("Event was not received");
+    }
+
+    #[test]
+    fn test_lobby_server() {
+        let service = LobbyServer::bind(Ipv4Addr::LOCALHOST.into(), 0).unwrap();
+        let server = service.server().clone();
+
+        thread::spawn(move || service.run());
+
+        let mut admin = connect(&server, "admin");
+        let mut other = connect(&server, "other");
+
+        let lobby_id = block_on(admin.create_lobby("Lobby", LobbyPrivacy::Public, 2))
+            .unwrap()
+            .id;
+
+        let lobby = block_on(other.join_lobby(&lobby_id)).unwrap();
+        assert_eq!(lobby.player_count, 2);
+        assert_eq!(lobby.admin_player_id, admin.local_player_id().unwrap());
+
+        assert!(
+            block_on(admin.start_game(&lobby_id)).is_err(),
+            "Game should not start before all players are ready"
+        );
+
+        block_on(admin.set_ready(&lobby_id, true)).unwrap();
+        block_on(other.set_ready(&lobby_id, true)).unwrap();
+
+        assert!(
+            block_on(other.start_game(&lobby_id)).is_err(),
+            "Only the admin should be able to start the game"
+        );
+
+        block_on(admin.start_game(&lobby_id)).unwrap();
+
+        let started = |event| match event {
+            NetworkEvent::GameStarted {
+                lobby_id: id,
+                relay_host_token,
+            } if id == lobby_id => Some(relay_host_token),
+            _ => None,
+        };
+
+        assert!(wait_for_event(&mut admin, started).is_some());
+        assert!(wait_for_event(&mut other, started).is_none());
+
+        let lobby = block_on(other.get_lobby(&lobby_id)).unwrap();
+        assert_eq!(lobby.state, LobbyState::Running);
+    }
+}
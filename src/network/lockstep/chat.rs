@@ -0,0 +1,142 @@
+//! Chat between the players of the peers. Messages are numbered, and sent again until they are
+//! acknowledged, and both local and remote messages are rate limited.
+
+use std::collections::{HashSet, VecDeque};
+
+use macroquad::miniquad::date;
+
+use core::network::{sanitize_chat_message, ChatRateLimiter, Message};
+
+use super::LockstepSession;
+
+/// The interval, in seconds, at which chat messages are sent again, until they are acknowledged
+const CHAT_RESEND_INTERVAL: f64 = 0.2;
+
+pub(super) struct Chat {
+    /// Local chat messages that have not been acknowledged, as `(seq, player, text)`
+    pub outgoing: VecDeque<(u32, u8, String)>,
+    pub next_seq: u32,
+    pub last_send_time: f64,
+    /// The sequence number of the next chat message expected from the remote peer
+    pub next_remote_seq: u32,
+    pub remote_limiter: ChatRateLimiter,
+    pub local_limiter: ChatRateLimiter,
+    /// Received chat messages, as `(player, text)`, that have not been taken
+    pub messages: Vec<(u8, String)>,
+    pub muted_players: HashSet<u8>,
+}
+
+impl Chat {
+    pub fn new() -> Self {
+        Chat {
+            outgoing: VecDeque::new(),
+            next_seq: 0,
+            last_send_time: f64::NEG_INFINITY,
+            next_remote_seq: 0,
+            remote_limiter: ChatRateLimiter::new(),
+            local_limiter: ChatRateLimiter::new(),
+            messages: Vec::new(),
+            muted_players: HashSet::new(),
+        }
+    }
+
+    /// Chat messages are numbered from the start, for every peer
+    pub fn reset(&mut self) {
+        self.outgoing.clear();
+        self.next_seq = 0;
+        self.next_remote_seq = 0;
+        self.remote_limiter = ChatRateLimiter::new();
+    }
+
+    /// Handle chat message `seq` from the remote player `player`, which is discarded if the
+    /// player is not a remote player, or is muted. Returns the sequence number to acknowledge.
+    pub fn receive(&mut self, seq: u32, player: u8, text: &str, is_remote_player: bool) -> u32 {
+        // Messages are only accepted in order, so that every message is rate limited once, no
+        // matter how many times it is received
+        if seq != self.next_remote_seq {
+            return self.next_remote_seq;
+        }
+
+        self.next_remote_seq += 1;
+
+        if is_remote_player && !self.muted_players.contains(&player) {
+            if let Some(text) = sanitize_chat_message(text) {
+                if self.remote_limiter.try_send(date::now()) {
+                    self.messages.push((player, text));
+                }
+            }
+        }
+
+        self.next_remote_seq
+    }
+
+    /// Handle the acknowledgement of all local messages before `seq`
+    pub fn ack(&mut self, seq: u32) {
+        while matches!(self.outgoing.front(), Some((other, _, _)) if *other < seq) {
+            self.outgoing.pop_front();
+        }
+    }
+
+    /// Add the unacknowledged local messages to `messages`, if the resend interval has passed
+    pub fn push_messages(&mut self, now: f64, messages: &mut Vec<Message>) {
+        if self.outgoing.is_empty() || now - self.last_send_time < CHAT_RESEND_INTERVAL {
+            return;
+        }
+
+        self.last_send_time = now;
+
+        for (seq, player, text) in &self.outgoing {
+            messages.push(Message::Chat {
+                seq: *seq,
+                player: *player,
+                text: text.clone(),
+            });
+        }
+    }
+}
+
+impl LockstepSession {
+    /// Send a chat message from the local player `player` to the remote peer. The message is
+    /// sanitized and returned, for it to be shown locally, or `None` is returned if it is empty,
+    /// or if it exceeds the chat rate limit.
+    pub fn send_chat(&mut self, player: u8, text: &str) -> Option<String> {
+        let text = sanitize_chat_message(text)?;
+
+        if !self.chat.local_limiter.try_send(date::now()) {
+            return None;
+        }
+
+        self.chat
+            .outgoing
+            .push_back((self.chat.next_seq, player, text.clone()));
+        self.chat.next_seq += 1;
+
+        // Send it with the next call to `send`
+        self.chat.last_send_time = f64::NEG_INFINITY;
+
+        Some(text)
+    }
+
+    /// Returns the chat messages, as `(player, text)`, received since this was last called
+    pub fn take_chat_messages(&mut self) -> Vec<(u8, String)> {
+        std::mem::take(&mut self.chat.messages)
+    }
+
+    /// Mute, or unmute, the remote player `player`. Messages from muted players are discarded,
+    /// on receive. Only the host is allowed to mute players, so this is ignored on clients.
+    pub fn set_muted(&mut self, player: u8, is_muted: bool) {
+        if !self.is_host {
+            return;
+        }
+
+        if is_muted {
+            self.chat.muted_players.insert(player);
+        } else {
+            self.chat.muted_players.remove(&player);
+        }
+    }
+
+    pub fn is_muted(&self, player: u8) -> bool {
+        self.chat.muted_players.contains(&player)
+    }
+}
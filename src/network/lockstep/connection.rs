@@ -0,0 +1,129 @@
+//! The connection with the remote peer, over a `Transport`, and the round trip time that is
+//! measured on it, with pings.
+
+use std::net::SocketAddr;
+
+use macroquad::miniquad::date;
+
+use core::network::{DisconnectReason, Message};
+use core::Result;
+
+use crate::game::NetcodeMode;
+use crate::network::Transport;
+
+use super::LockstepSession;
+
+/// The interval, in seconds, between pings sent to measure round trip time
+pub(super) const PING_INTERVAL: f64 = 0.25;
+
+/// The weight of a new round trip time sample, in the smoothed round trip time
+const RTT_SMOOTHING: f64 = 0.1;
+
+pub(super) const MAX_DATAGRAM_SIZE: usize = 1024;
+
+pub(super) struct Connection {
+    pub transport: Box<dyn Transport>,
+    pub peer: Option<SocketAddr>,
+    /// This is set when the handshake has completed
+    pub is_connected: bool,
+    pub disconnect_reason: Option<DisconnectReason>,
+    pub last_receive_time: Option<f64>,
+    pub rtt: Option<f64>,
+    pub jitter: f64,
+    pub last_ping_time: f64,
+}
+
+impl Connection {
+    pub fn new(transport: Box<dyn Transport>, peer: Option<SocketAddr>) -> Self {
+        Connection {
+            transport,
+            peer,
+            is_connected: false,
+            disconnect_reason: None,
+            last_receive_time: None,
+            rtt: None,
+            jitter: 0.0,
+            last_ping_time: f64::NEG_INFINITY,
+        }
+    }
+
+    /// Send `message` to the remote peer, if its address is known
+    pub fn send(&mut self, message: &Message) -> Result<()> {
+        if let Some(peer) = self.peer {
+            self.transport.send_to(&message.encode(), peer)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn send_to(&mut self, message: &Message, addr: SocketAddr) -> Result<()> {
+        self.transport.send_to(&message.encode(), addr)
+    }
+
+    pub fn refuse(&mut self, addr: SocketAddr, reason: DisconnectReason) -> Result<()> {
+        self.send_to(&Message::Disconnect { reason }, addr)
+    }
+
+    /// The time, in seconds, since anything was last received from the remote peer
+    pub fn silence(&self) -> Option<f64> {
+        self.last_receive_time.map(|time| date::now() - time)
+    }
+
+    /// Add a ping to `messages`, if the ping interval has passed since the last one
+    pub fn push_ping(&mut self, now: f64, messages: &mut Vec<Message>) {
+        if now - self.last_ping_time >= PING_INTERVAL {
+            self.last_ping_time = now;
+            messages.push(Message::Ping { time: now });
+        }
+    }
+
+    /// Add a round trip time sample to the smoothed round trip time and jitter, and return the
+    /// new smoothed round trip time
+    pub fn add_rtt_sample(&mut self, sample: f64) -> f64 {
+        let rtt = match self.rtt {
+            Some(rtt) => {
+                self.jitter += ((sample - rtt).abs() - self.jitter) * RTT_SMOOTHING;
+                rtt + (sample - rtt) * RTT_SMOOTHING
+            }
+            None => sample,
+        };
+
+        self.rtt = Some(rtt);
+
+        rtt
+    }
+}
+
+impl LockstepSession {
+    /// The smoothed round trip time, in seconds, if it has been measured
+    pub fn rtt(&self) -> Option<f64> {
+        self.connection.rtt
+    }
+
+    /// Returns `true` when the handshake with the remote peer has completed
+    pub fn is_connected(&self) -> bool {
+        self.connection.is_connected
+    }
+
+    /// The reason that the remote peer gave for disconnecting, if any
+    pub fn disconnect_reason(&self) -> Option<DisconnectReason> {
+        self.connection.disconnect_reason
+    }
+
+    /// The address that the session is bound to
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        self.connection.transport.local_addr()
+    }
+
+    pub(super) fn update_rtt(&mut self, sample: f64) {
+        let rtt = self.connection.add_rtt_sample(sample);
+
+        // When rolling back, remote input does not have to arrive before the tick it is stamped
+        // with is simulated, so local input is not delayed more than required
+        if self.netcode == NetcodeMode::Rollback {
+            return;
+        }
+
+        self.inputs.adapt_delay(rtt, self.connection.jitter);
+    }
+}
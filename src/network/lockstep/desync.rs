@@ -0,0 +1,158 @@
+//! Desync detection. Peers send the checksums of their most recent simulation states along with
+//! their input, and the first tick where a checksum differs is reported. After that, the peers
+//! can exchange their simulation states, to find out where they differ.
+
+use std::collections::BTreeMap;
+
+use core::error::ErrorKind;
+use core::network::{Message, MAX_STATE_CHUNK_SIZE};
+use core::{formaterr, Result};
+
+use super::LockstepSession;
+
+/// The amount of checksums that are kept, for both peers, to be compared
+const CHECKSUM_HISTORY_LEN: usize = 16;
+
+/// The amount of the most recent local checksums that are sent with every message, as redundancy
+const CHECKSUMS_PER_SEND: usize = 3;
+
+pub(super) struct Checksums {
+    pub local: BTreeMap<u64, u64>,
+    pub remote: BTreeMap<u64, u64>,
+    pub is_desynced: bool,
+    pub desync: Option<u64>,
+    /// The chunks of the simulation state of the remote peer, at the tick of the desync, as they
+    /// are received
+    pub remote_state_chunks: Option<(u64, Vec<Option<Vec<u8>>>)>,
+    pub remote_state: Option<(u64, Vec<u8>)>,
+}
+
+impl Checksums {
+    pub fn new() -> Self {
+        Checksums {
+            local: BTreeMap::new(),
+            remote: BTreeMap::new(),
+            is_desynced: false,
+            desync: None,
+            remote_state_chunks: None,
+            remote_state: None,
+        }
+    }
+
+    pub fn add_remote(&mut self, tick: u64, checksum: u64) {
+        self.remote.insert(tick, checksum);
+
+        while self.remote.len() > CHECKSUM_HISTORY_LEN {
+            self.remote.pop_first();
+        }
+
+        self.compare(tick);
+    }
+
+    /// Discard the remote checksums, and any desync that was detected, as the peers continue
+    /// from the same snapshot
+    pub fn reset_remote(&mut self) {
+        self.remote.clear();
+        self.is_desynced = false;
+        self.desync = None;
+    }
+
+    /// Add the most recent local checksums to `messages`
+    pub fn push_messages(&self, messages: &mut Vec<Message>) {
+        for (&tick, &checksum) in self.local.iter().rev().take(CHECKSUMS_PER_SEND) {
+            messages.push(Message::Checksum { tick, checksum });
+        }
+    }
+
+    fn compare(&mut self, tick: u64) {
+        if self.is_desynced {
+            return;
+        }
+
+        if let (Some(local), Some(remote)) = (self.local.get(&tick), self.remote.get(&tick)) {
+            if local != remote {
+                self.is_desynced = true;
+                self.desync = Some(tick);
+            }
+        }
+    }
+
+    pub fn add_state_chunk(&mut self, tick: u64, index: u16, chunk_cnt: u16, data: Vec<u8>) {
+        let is_new_transfer = !matches!(
+            &self.remote_state_chunks,
+            Some((other_tick, chunks)) if *other_tick == tick && chunks.len() == chunk_cnt as usize
+        );
+
+        if is_new_transfer {
+            self.remote_state_chunks = Some((tick, vec![None; chunk_cnt as usize]));
+        }
+
+        let (_, chunks) = self.remote_state_chunks.as_mut().unwrap();
+
+        if let Some(chunk) = chunks.get_mut(index as usize) {
+            *chunk = Some(data);
+        }
+
+        if chunks.iter().all(|chunk| chunk.is_some()) {
+            let (tick, chunks) = self.remote_state_chunks.take().unwrap();
+            let state = chunks.into_iter().flatten().flatten().collect();
+
+            self.remote_state = Some((tick, state));
+        }
+    }
+}
+
+impl LockstepSession {
+    /// Store the checksum of the local simulation state at the start of `tick`, to be sent to and
+    /// compared with the checksum of the remote peer
+    pub fn add_checksum(&mut self, tick: u64, checksum: u64) {
+        self.checksums.local.insert(tick, checksum);
+
+        while self.checksums.local.len() > CHECKSUM_HISTORY_LEN {
+            self.checksums.local.pop_first();
+        }
+
+        self.checksums.compare(tick);
+    }
+
+    /// Returns the tick of the first checksum that did not match the checksum of the remote peer,
+    /// once it has been detected. Only the first desync is reported, as the states will most
+    /// likely differ for the rest of the match.
+    pub fn take_desync(&mut self) -> Option<u64> {
+        self.checksums.desync.take()
+    }
+
+    /// Send the local simulation state at the start of `tick` to the remote peer, so that it can
+    /// be compared with its own, after a desync
+    pub fn send_state(&mut self, tick: u64, state: &[u8]) -> Result<()> {
+        let chunk_cnt = ((state.len() + MAX_STATE_CHUNK_SIZE - 1) / MAX_STATE_CHUNK_SIZE).max(1);
+
+        if chunk_cnt > u16::MAX as usize {
+            return Err(formaterr!(
+                ErrorKind::Network,
+                "State of tick {} is too large to be sent",
+                tick
+            ));
+        }
+
+        for index in 0..chunk_cnt {
+            let start = index * MAX_STATE_CHUNK_SIZE;
+            let end = (start + MAX_STATE_CHUNK_SIZE).min(state.len());
+
+            self.connection.send(&Message::StateChunk {
+                tick,
+                index: index as u16,
+                chunk_cnt: chunk_cnt as u16,
+                data: state[start..end].to_vec(),
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the simulation state sent by the remote peer, and the tick it is from, once all of
+    /// it has been received
+    pub fn take_remote_state(&mut self) -> Option<(u64, Vec<u8>)> {
+        self.checksums.remote_state.take()
+    }
+}
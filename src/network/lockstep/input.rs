@@ -0,0 +1,338 @@
+//! The input of every player, by tick, which is what peers exchange in delayed lockstep.
+//! Local input is captured for the current tick plus the input delay, which is adjusted to the
+//! round trip time and jitter of the connection, and the simulation can only advance to a tick
+//! once the input of all players has been received for it.
+
+use std::collections::{HashMap, VecDeque};
+
+use hecs::World;
+
+use core::network::{Message, MAX_INPUTS_PER_MESSAGE};
+use core::Result;
+
+use crate::game::{DisconnectRule, TICK_LENGTH};
+use crate::player::{Player, PlayerController};
+use crate::GameInput;
+
+use super::LockstepSession;
+
+/// The minimum amount of ticks that local input is delayed by
+pub const MIN_INPUT_DELAY: u64 = 2;
+/// The maximum amount of ticks that local input is delayed by
+pub const MAX_INPUT_DELAY: u64 = 20;
+
+/// This holds the input of a single player, by tick
+#[derive(Debug, Default, Clone)]
+pub struct InputBuffer {
+    start_tick: u64,
+    inputs: VecDeque<Option<GameInput>>,
+}
+
+impl InputBuffer {
+    pub fn new() -> Self {
+        InputBuffer {
+            start_tick: 0,
+            inputs: VecDeque::new(),
+        }
+    }
+
+    /// Insert the input for `tick`. This is ignored if `tick` has already been discarded.
+    pub fn insert(&mut self, tick: u64, input: GameInput) {
+        if tick < self.start_tick {
+            return;
+        }
+
+        let i = (tick - self.start_tick) as usize;
+
+        if i >= self.inputs.len() {
+            self.inputs.resize(i + 1, None);
+        }
+
+        self.inputs[i] = Some(input);
+    }
+
+    pub fn get(&self, tick: u64) -> Option<GameInput> {
+        if tick < self.start_tick {
+            return None;
+        }
+
+        self.inputs
+            .get((tick - self.start_tick) as usize)
+            .copied()
+            .flatten()
+    }
+
+    /// The first tick, that has not been discarded, that there is no input for
+    pub fn next_missing_tick(&self) -> u64 {
+        let cnt = self
+            .inputs
+            .iter()
+            .position(|input| input.is_none())
+            .unwrap_or(self.inputs.len());
+
+        self.start_tick + cnt as u64
+    }
+
+    /// Get the consecutive inputs starting at `tick`, up to a maximum of `max_cnt`
+    pub fn get_from(&self, tick: u64, max_cnt: usize) -> Vec<GameInput> {
+        (tick..)
+            .map_while(|tick| self.get(tick))
+            .take(max_cnt)
+            .collect()
+    }
+
+    /// Discard all input and start over, with `tick` as the first tick
+    pub fn reset(&mut self, tick: u64) {
+        self.start_tick = tick;
+        self.inputs.clear();
+    }
+
+    /// Discard all input for ticks before `tick`
+    pub fn discard_before(&mut self, tick: u64) {
+        while self.start_tick < tick {
+            if self.inputs.pop_front().is_none() {
+                self.start_tick = tick;
+                break;
+            }
+
+            self.start_tick += 1;
+        }
+    }
+}
+
+/// The input of the local and the remote players, and what is needed to exchange it
+pub(super) struct Inputs {
+    pub local: HashMap<u8, InputBuffer>,
+    pub remote: HashMap<u8, InputBuffer>,
+    /// The next tick that local input will be captured for
+    pub next_local_tick: u64,
+    pub delay: u64,
+    /// All local input before this tick has been acknowledged by the remote peer
+    pub remote_ack: u64,
+    pub stalled_tick_cnt: u64,
+}
+
+impl Inputs {
+    pub fn new(local_players: &[u8], remote_players: &[u8]) -> Self {
+        Inputs {
+            local: local_players
+                .iter()
+                .map(|&index| (index, InputBuffer::new()))
+                .collect(),
+            remote: remote_players
+                .iter()
+                .map(|&index| (index, InputBuffer::new()))
+                .collect(),
+            next_local_tick: 0,
+            delay: MIN_INPUT_DELAY,
+            remote_ack: 0,
+            stalled_tick_cnt: 0,
+        }
+    }
+
+    /// The indices of the players whose input is received from the remote peer
+    pub fn remote_players(&self) -> Vec<u8> {
+        let mut res = self.remote.keys().copied().collect::<Vec<_>>();
+        res.sort_unstable();
+        res
+    }
+
+    /// Returns `true` if the input of all players has been received for `tick`
+    pub fn is_complete(&self, tick: u64) -> bool {
+        self.local
+            .values()
+            .chain(self.remote.values())
+            .all(|buffer| buffer.get(tick).is_some())
+    }
+
+    /// The input of all players has been received for every tick before this
+    pub fn confirmed_tick(&self) -> u64 {
+        self.local
+            .values()
+            .chain(self.remote.values())
+            .map(|buffer| buffer.next_missing_tick())
+            .min()
+            .unwrap_or_default()
+    }
+
+    /// The input of all players for `tick`, ordered by player index, if it has been received
+    pub fn confirmed_input(&self, tick: u64) -> Option<Vec<(u8, GameInput)>> {
+        let mut res = self
+            .local
+            .iter()
+            .chain(self.remote.iter())
+            .map(|(&index, buffer)| buffer.get(tick).map(|input| (index, input)))
+            .collect::<Option<Vec<_>>>()?;
+
+        res.sort_by_key(|&(index, _)| index);
+
+        Some(res)
+    }
+
+    /// Store the current input of the local players for every tick up until `tick` plus the
+    /// input delay. If the delay has grown since the last capture, the input is repeated for
+    /// the ticks in between. If it has shrunk, nothing is captured until `tick` catches up, as
+    /// input that has already been sent can not be taken back.
+    pub fn capture_local(&mut self, tick: u64, world: &mut World) {
+        let last_tick = tick + self.delay;
+
+        if self.next_local_tick > last_tick {
+            return;
+        }
+
+        for (_, (player, controller)) in world.query_mut::<(&Player, &PlayerController)>() {
+            if let Some(buffer) = self.local.get_mut(&player.index) {
+                for tick in self.next_local_tick..=last_tick {
+                    buffer.insert(tick, controller.input);
+                }
+            }
+        }
+
+        self.next_local_tick = last_tick + 1;
+    }
+
+    /// Adjust the input delay to the smoothed round trip time and jitter of the connection
+    pub fn adapt_delay(&mut self, rtt: f64, jitter: f64) {
+        // The input has to arrive at the remote peer, which is simulating roughly the same tick
+        // as us, before the tick it is stamped with is simulated. One tick is added, as input is
+        // only sent once per tick.
+        let latency = rtt / 2.0 + jitter * 2.0;
+        let delay = (latency / TICK_LENGTH as f64).ceil() as u64 + 1;
+
+        self.delay = delay.clamp(MIN_INPUT_DELAY, MAX_INPUT_DELAY);
+    }
+
+    /// Add the messages that send the local input that has not been acknowledged, and that
+    /// acknowledge the remote input, to `messages`
+    pub fn push_messages(&self, messages: &mut Vec<Message>) {
+        for (&player, buffer) in &self.local {
+            let start_tick = self.remote_ack;
+            let inputs = buffer.get_from(start_tick, MAX_INPUTS_PER_MESSAGE);
+
+            if !inputs.is_empty() {
+                messages.push(Message::Input {
+                    player,
+                    start_tick,
+                    inputs: inputs.iter().map(GameInput::to_bits).collect(),
+                });
+            }
+        }
+
+        let ack_tick = self
+            .remote
+            .values()
+            .map(|buffer| buffer.next_missing_tick())
+            .min()
+            .unwrap_or_default();
+
+        messages.push(Message::Ack { tick: ack_tick });
+    }
+}
+
+impl LockstepSession {
+    /// The amount of ticks that local input is currently delayed by
+    pub fn input_delay(&self) -> u64 {
+        self.inputs.delay
+    }
+
+    /// The amount of times the simulation has been stalled, waiting for remote input
+    pub fn stalled_tick_cnt(&self) -> u64 {
+        self.inputs.stalled_tick_cnt
+    }
+
+    /// The indices of the players whose input is sent to the remote peer
+    pub fn local_players(&self) -> Vec<u8> {
+        let mut res = self.inputs.local.keys().copied().collect::<Vec<_>>();
+        res.sort_unstable();
+        res
+    }
+
+    /// The indices of the players whose input is received from the remote peer
+    pub fn remote_players(&self) -> Vec<u8> {
+        self.inputs.remote_players()
+    }
+
+    /// Returns `true` if the remote peer has acknowledged all the local input that it needs to
+    /// simulate up until `tick`
+    pub fn is_synced(&self, tick: u64) -> bool {
+        self.inputs.remote_ack >= tick
+    }
+
+    /// The input of all players has been received for every tick before this
+    pub fn confirmed_tick(&self) -> u64 {
+        self.inputs.confirmed_tick()
+    }
+
+    /// The input of all players for `tick`, ordered by player index, if it has been received
+    pub fn confirmed_input(&self, tick: u64) -> Option<Vec<(u8, GameInput)>> {
+        self.inputs.confirmed_input(tick)
+    }
+
+    /// Capture the input of the local players, exchange messages with the remote peer and, if the
+    /// input of all players is available for `tick`, apply it to the player controllers.
+    /// Returns `false` if input is missing, in which case the simulation must be stalled.
+    pub fn update(&mut self, tick: u64, world: &mut World) -> Result<bool> {
+        self.exchange(tick, world)?;
+
+        let is_ready = self.reconnect.resync.is_none() && self.inputs.is_complete(tick);
+
+        if !is_ready {
+            self.inputs.stalled_tick_cnt += 1;
+            return Ok(false);
+        }
+
+        self.keep_spectated_input(tick + 1);
+
+        for (_, (player, controller)) in world.query_mut::<(&Player, &mut PlayerController)>() {
+            let input = self
+                .inputs
+                .local
+                .get(&player.index)
+                .or_else(|| self.inputs.remote.get(&player.index))
+                .and_then(|buffer| buffer.get(tick));
+
+            if let Some(input) = input {
+                controller.apply_input(input);
+            }
+        }
+
+        for buffer in self.inputs.remote.values_mut() {
+            buffer.discard_before(tick + 1);
+        }
+
+        let discard_tick = self.inputs.remote_ack.min(tick + 1);
+        for buffer in self.inputs.local.values_mut() {
+            buffer.discard_before(discard_tick);
+        }
+
+        Ok(true)
+    }
+
+    /// Capture the input of the local players, for `tick` plus the input delay, and exchange
+    /// messages with the remote peer
+    pub fn exchange(&mut self, tick: u64, world: &mut World) -> Result<()> {
+        self.receive()?;
+
+        // Only the host continues without the remote peer, as the host is the one that provides
+        // the snapshot that both continue from, once the peer is back
+        if self.is_host
+            && self.reconnect.disconnect_rule == DisconnectRule::Continue
+            && self.is_reconnecting()
+        {
+            self.insert_idle_remote_input(tick);
+        }
+
+        self.inputs.capture_local(tick, world);
+
+        self.send()
+    }
+
+    /// Insert the input of the remote player `player` for `tick`, and check it against the input
+    /// that was predicted for it, if any
+    pub(super) fn insert_remote_input(&mut self, player: u8, tick: u64, input: GameInput) {
+        if let Some(buffer) = self.inputs.remote.get_mut(&player) {
+            buffer.insert(tick, input);
+            self.predictions.check(tick, player, input);
+        }
+    }
+}
@@ -0,0 +1,101 @@
+//! Host migration. The host tells every spectator the address of the client, as the successor,
+//! and if the host is lost, the client takes over as host, while the spectators follow it.
+
+use std::net::SocketAddr;
+
+use macroquad::miniquad::date;
+
+use core::network::{DisconnectReason, Message};
+
+use super::connection::PING_INTERVAL;
+use super::{LockstepSession, Resync};
+
+pub(super) struct Migration {
+    /// The address of the peer that takes over as host, if the host leaves, on a spectator
+    pub successor: Option<SocketAddr>,
+    pub last_send_time: f64,
+}
+
+impl Migration {
+    pub fn new() -> Self {
+        Migration {
+            successor: None,
+            last_send_time: f64::NEG_INFINITY,
+        }
+    }
+}
+
+impl LockstepSession {
+    /// Returns `true` if the host has left, or has not come back before the reconnect timeout, on
+    /// a client. Peers that were refused by the host are not considered to have lost it.
+    pub fn is_host_lost(&self) -> bool {
+        !self.is_host
+            && self.connection.last_receive_time.is_some()
+            && matches!(
+                self.connection.disconnect_reason,
+                None | Some(DisconnectReason::Quit)
+            )
+            && self.is_disconnected()
+    }
+
+    /// Take over as host, once the host has been lost and its players have been removed, with
+    /// `remove_remote_players`, on a client that controls players. The session stays bound to
+    /// the same address, which the host has told spectators about, and the slots of the players
+    /// of the old host are open. Returns `false` if this session is not the successor.
+    pub fn take_over_as_host(&mut self) -> bool {
+        if self.is_spectator || !self.is_host_lost() || !self.inputs.remote.is_empty() {
+            return false;
+        }
+
+        self.is_host = true;
+        self.connection.peer = None;
+        self.connection.is_connected = false;
+        self.connection.disconnect_reason = None;
+        self.connection.last_receive_time = None;
+        self.chat.reset();
+
+        true
+    }
+
+    /// Connect to the successor that the host told about, as a spectator, once the host has been
+    /// lost. The spectator continues from a snapshot sent by the new host. Returns `false` if this
+    /// session is not a spectator, or if the host did not tell about a successor.
+    pub fn follow_successor(&mut self) -> bool {
+        if !self.is_spectator || !self.is_host_lost() {
+            return false;
+        }
+
+        let successor = match self.migration.successor.take() {
+            Some(successor) => successor,
+            None => return false,
+        };
+
+        self.connection.peer = Some(successor);
+        self.connection.is_connected = false;
+        self.connection.disconnect_reason = None;
+        // The successor is given the same time as the host, to answer
+        self.connection.last_receive_time = Some(date::now());
+        self.inputs.remote.clear();
+        self.reconnect.resync = Some(Resync::Awaiting);
+        self.reconnect.last_resync_tick = None;
+        self.chat.reset();
+
+        true
+    }
+
+    /// The message that tells spectators about the successor, on the host, if it is time to send
+    /// it again. The client takes over as host if the host leaves, so spectators are told where
+    /// to go.
+    pub(super) fn successor_message(&mut self, now: f64) -> Option<Message> {
+        match self.connection.peer {
+            Some(peer)
+                if self.connection.is_connected
+                    && now - self.migration.last_send_time >= PING_INTERVAL =>
+            {
+                self.migration.last_send_time = now;
+                Some(Message::Successor { addr: peer })
+            }
+            _ => None,
+        }
+    }
+}
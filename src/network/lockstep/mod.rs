@@ -0,0 +1,1212 @@
+//! This implements delayed lockstep, as described in `book/src/netcode.md`.
+//! Peers only exchange the input of their local players, stamped with the tick that it should be
+//! applied on. Local input is delayed by a number of ticks, so that it has time to reach the
+//! remote peer before that tick is simulated, and the simulation is stalled if the input of any
+//! player is missing for the current tick.
+//! The input delay is adjusted to the measured round trip time and jitter, so that it stays as
+//! short as possible, without the simulation having to stall.
+//!
+//! When using `NetcodeMode::Rollback`, the same messages are exchanged, but the input delay is
+//! kept at its minimum and `Game` simulates ahead of the remote input, using the input predicted
+//! by `LockstepSession::apply_input`. If a prediction turns out to be wrong, the session reports
+//! the first mispredicted tick, for `Game` to roll back to.
+//!
+//! If nothing is received from the remote peer for `DISCONNECT_TIMEOUT`, it is considered to be
+//! reconnecting, and the simulation is either stalled or continued with its players standing
+//! idle, depending on the `DisconnectRule` of the match. When the peer comes back, either because
+//! its messages arrive again, or because it connects to the host anew, the host sends it a
+//! snapshot of the game, and both continue from the tick of that snapshot, as the states of the
+//! peers might have drifted apart while they were not connected. If the peer does not come back
+//! before the reconnect timeout of the match, it is disconnected, and `Game` removes its players.
+//!
+//! The host also accepts up to `MAX_SPECTATORS` spectators, which join the match while it is
+//! running. These are sent a snapshot of the game, followed by the confirmed input of all
+//! players, from the tick of the snapshot, but send no input of their own. Once the players of the
+//! remote peer have been removed, a spectator that has asked for it can be given their slots, in
+//! which case it becomes the remote peer, and continues from a snapshot, like after reconnecting.
+//!
+//! If the host leaves, or does not come back before the reconnect timeout, the client takes over
+//! as host, from the last tick that it has the confirmed input of all players for, after the
+//! players of the host have been removed. The host tells every spectator the address of the
+//! client, as the successor, so that spectators follow the new host, and continue from a
+//! snapshot of that tick.
+//!
+//! The session is split into a component per concern, which each live in their own module, along
+//! with the methods of `LockstepSession` that use them:
+//! `connection` holds the transport and measures the round trip time, `protocol` does the
+//! handshake, `input` exchanges the input of the players, `rollback` predicts missing input,
+//! `desync` compares checksums, `chat` exchanges chat messages, `reconnect` resyncs a peer that
+//! comes back, `spectators` serves the spectators, and `migration` moves the host to the client.
+
+mod chat;
+mod connection;
+mod desync;
+mod input;
+mod migration;
+mod protocol;
+mod reconnect;
+mod rollback;
+mod spectators;
+
+pub use input::{InputBuffer, MAX_INPUT_DELAY, MIN_INPUT_DELAY};
+pub use reconnect::{PeerStatus, DISCONNECT_TIMEOUT};
+
+use std::net::{Ipv4Addr, SocketAddr};
+
+use macroquad::miniquad::date;
+
+use core::network::{DisconnectReason, HandshakeInfo, Message, PROTOCOL_VERSION};
+use core::Result;
+
+use crate::game::{MatchSettings, NetcodeMode};
+use crate::player::{PlayerControllerKind, PlayerParams};
+use crate::GameInput;
+
+use super::relay::{RelayConfig, RelayTransport};
+use super::transport::{Transport, UdpTransport};
+
+use chat::Chat;
+use connection::{Connection, MAX_DATAGRAM_SIZE};
+use desync::Checksums;
+use input::Inputs;
+use migration::Migration;
+use protocol::Handshake;
+use reconnect::{Reconnect, Resync, SnapshotTransfer};
+use rollback::Predictions;
+use spectators::Spectators;
+
+/// The state of a network game, with a single remote peer.
+/// The host binds to a known port and learns the address of the remote peer from the handshake
+/// of the first client that connects, while the client sends to the address of the host, from
+/// the start. Peers with a `HandshakeInfo` that differs from the local one are refused.
+/// The host decides the settings of the match, which it sends to every client that it welcomes.
+pub struct LockstepSession {
+    netcode: NetcodeMode,
+    is_host: bool,
+    /// This is set if this session is a spectator, that only receives input
+    is_spectator: bool,
+    connection: Connection,
+    handshake: Handshake,
+    inputs: Inputs,
+    predictions: Predictions,
+    checksums: Checksums,
+    chat: Chat,
+    reconnect: Reconnect,
+    spectators: Spectators,
+    migration: Migration,
+}
+
+impl LockstepSession {
+    /// Create a session that waits for the remote peer on the specified port, and through
+    /// `relay`, if specified. Players with a controller of kind `PlayerControllerKind::Network`
+    /// are considered remote and all other players local.
+    pub fn host(
+        port: u16,
+        relay: Option<RelayConfig>,
+        handshake: HandshakeInfo,
+        player_params: &[PlayerParams],
+        settings: &MatchSettings,
+    ) -> Result<Self> {
+        let mut transport: Box<dyn Transport> =
+            Box::new(UdpTransport::bind((Ipv4Addr::UNSPECIFIED, port).into())?);
+
+        if let Some(relay) = relay {
+            transport = Box::new(RelayTransport::host(transport, relay));
+        }
+
+        Self::with_transport(transport, None, handshake, player_params, settings)
+    }
+
+    /// Create a session that connects to the host at the specified address, falling back to
+    /// `relay`, if specified and the host can not be reached directly. Players with a controller
+    /// of kind `PlayerControllerKind::Network` are considered remote and all other players local.
+    pub fn connect(
+        host: SocketAddr,
+        relay: Option<RelayConfig>,
+        handshake: HandshakeInfo,
+        player_params: &[PlayerParams],
+        settings: &MatchSettings,
+    ) -> Result<Self> {
+        let local_addr = match host {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (std::net::Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+
+        let mut transport: Box<dyn Transport> = Box::new(UdpTransport::bind(local_addr)?);
+
+        if let Some(relay) = relay {
+            transport = Box::new(RelayTransport::client(transport, relay, host));
+        }
+
+        Self::with_transport(transport, Some(host), handshake, player_params, settings)
+    }
+
+    /// Create a session that exchanges messages over `transport`. If `peer` is `None`, the
+    /// address of the remote peer is learned from the first message that is received.
+    pub fn with_transport(
+        transport: Box<dyn Transport>,
+        peer: Option<SocketAddr>,
+        handshake: HandshakeInfo,
+        player_params: &[PlayerParams],
+        settings: &MatchSettings,
+    ) -> Result<Self> {
+        let (remote, local): (Vec<_>, Vec<_>) = player_params
+            .iter()
+            .partition(|params| matches!(params.controller, PlayerControllerKind::Network(_)));
+
+        Self::with_players(
+            transport,
+            peer,
+            handshake,
+            &local.iter().map(|params| params.index).collect::<Vec<_>>(),
+            &remote.iter().map(|params| params.index).collect::<Vec<_>>(),
+            settings,
+        )
+    }
+
+    /// Create a session that exchanges messages over `transport`, with the players of the
+    /// specified indices as local and remote players. On the host, `settings` are sent to the
+    /// clients, while a client adopts the settings of the host, unless `handshake` holds a seed
+    /// and settings hash, in which case the host refuses it if they differ from its own.
+    /// Hosting fails if `settings` are too large to be sent to the clients.
+    pub fn with_players(
+        transport: Box<dyn Transport>,
+        peer: Option<SocketAddr>,
+        handshake: HandshakeInfo,
+        local_players: &[u8],
+        remote_players: &[u8],
+        settings: &MatchSettings,
+    ) -> Result<Self> {
+        let is_host = peer.is_none();
+
+        Ok(LockstepSession {
+            netcode: settings.netcode,
+            is_host,
+            is_spectator: false,
+            connection: Connection::new(transport, peer),
+            handshake: Handshake::new(handshake, settings, is_host)?,
+            inputs: Inputs::new(local_players, remote_players),
+            predictions: Predictions::new(settings.max_rollback_ticks),
+            checksums: Checksums::new(),
+            chat: Chat::new(),
+            reconnect: Reconnect::new(settings.disconnect_rule, settings.reconnect_timeout),
+            spectators: Spectators::new(),
+            migration: Migration::new(),
+        })
+    }
+
+    /// Create a session that watches the match hosted at the specified address, as a spectator.
+    /// The players of the match are only known once the snapshot sent by the host has been
+    /// restored, after which they have to be set with `set_players`.
+    pub fn spectate(
+        host: SocketAddr,
+        relay: Option<RelayConfig>,
+        handshake: HandshakeInfo,
+        settings: &MatchSettings,
+    ) -> Result<Self> {
+        Ok(Self::connect(host, relay, handshake, &[], settings)?.into_spectator())
+    }
+
+    pub fn netcode(&self) -> NetcodeMode {
+        self.netcode
+    }
+
+    pub fn is_host(&self) -> bool {
+        self.is_host
+    }
+
+    /// Returns `true` if this session is a spectator, that only receives input
+    pub fn is_spectator(&self) -> bool {
+        self.is_spectator
+    }
+
+    /// Tell the remote peer, and any spectators, that we are leaving the game
+    pub fn disconnect(&mut self) -> Result<()> {
+        for spectator in self.spectators.peers.drain(..) {
+            self.connection.send_to(
+                &Message::Disconnect {
+                    reason: DisconnectReason::Quit,
+                },
+                spectator.addr,
+            )?;
+        }
+
+        if self.connection.is_connected {
+            self.connection.is_connected = false;
+            self.connection.send(&Message::Disconnect {
+                reason: DisconnectReason::Quit,
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Receive and send messages, without capturing any input. This should be called regularly,
+    /// if the simulation is no longer advanced, for the remote peer to receive the remaining input.
+    pub fn poll(&mut self) -> Result<()> {
+        self.receive()?;
+        self.send()
+    }
+
+    fn receive(&mut self) -> Result<()> {
+        let mut buf = [0; MAX_DATAGRAM_SIZE];
+
+        // The first message received from a peer that was reconnecting means that it is back
+        let was_reconnecting = self.is_reconnecting();
+
+        loop {
+            let (len, addr) = match self.connection.transport.recv_from(&mut buf)? {
+                Some(res) => res,
+                None => break,
+            };
+
+            let bytes = &buf[..len];
+
+            let message = match Message::decode(bytes) {
+                Ok(message) => message,
+                Err(_err) => {
+                    #[cfg(debug_assertions)]
+                    println!("WARNING: Invalid message from '{}': {}", addr, _err);
+
+                    if self.is_host && Message::protocol_version(bytes) != Some(PROTOCOL_VERSION) {
+                        self.connection
+                            .refuse(addr, DisconnectReason::ProtocolMismatch)?;
+                    }
+
+                    continue;
+                }
+            };
+
+            if was_reconnecting && self.connection.peer == Some(addr) {
+                self.request_resync();
+            }
+
+            let is_spectator = self.spectators.contains(addr);
+
+            match message {
+                Message::Hello(remote) if self.is_host => {
+                    self.handle_hello(addr, remote, was_reconnecting)?
+                }
+                Message::Spectate(remote) if self.is_host => self.handle_spectate(addr, remote)?,
+                message if is_spectator => self.handle_spectator_message(addr, message)?,
+                _ if self.connection.peer != Some(addr) => continue,
+                Message::Welcome { settings } if !self.is_host => self.handle_welcome(settings)?,
+                // A client that rejoins from another address is refused until the host notices
+                // that it has lost its connection, so this is ignored once connected
+                Message::Disconnect {
+                    reason: DisconnectReason::SessionFull,
+                } if self.connection.is_connected => {}
+                Message::Disconnect { reason } => {
+                    if self.connection.disconnect_reason.is_none() {
+                        #[cfg(debug_assertions)]
+                        println!("WARNING: Disconnected from '{}': {}", addr, reason);
+                    }
+
+                    self.connection.is_connected = false;
+                    self.connection.disconnect_reason = Some(reason);
+                }
+                message if self.connection.is_connected => self.handle_message(message)?,
+                _ => {}
+            }
+
+            if self.connection.peer == Some(addr) {
+                self.connection.last_receive_time = Some(date::now());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle_message(&mut self, message: Message) -> Result<()> {
+        match message {
+            // While resyncing, the input of the peers is not exchanged, as it is from before the
+            // tick of the snapshot that both will continue from
+            Message::Input { .. } | Message::Ack { .. } | Message::Checksum { .. }
+                if self.reconnect.resync.is_some() => {}
+            Message::Input {
+                player,
+                start_tick,
+                inputs,
+            } => {
+                // Input from a peer that took open slots means that it knows about them
+                self.spectators.granted_slots = None;
+
+                for (i, bits) in inputs.into_iter().enumerate() {
+                    self.insert_remote_input(
+                        player,
+                        start_tick + i as u64,
+                        GameInput::from_bits(bits),
+                    );
+                }
+            }
+            Message::Ack { tick } => {
+                self.inputs.remote_ack = self.inputs.remote_ack.max(tick);
+            }
+            Message::Ping { time } => {
+                self.connection.send(&Message::Pong { time })?;
+            }
+            Message::Pong { time } => {
+                self.update_rtt(date::now() - time);
+            }
+            Message::Checksum { tick, checksum } => {
+                self.checksums.add_remote(tick, checksum);
+            }
+            Message::StateChunk {
+                tick,
+                index,
+                chunk_cnt,
+                data,
+            } => {
+                self.checksums.add_state_chunk(tick, index, chunk_cnt, data);
+            }
+            Message::Chat { seq, player, text } => {
+                let is_remote_player = self.inputs.remote.contains_key(&player);
+                let seq = self.chat.receive(seq, player, &text, is_remote_player);
+
+                self.connection.send(&Message::ChatAck { seq })?;
+            }
+            Message::ChatAck { seq } => self.chat.ack(seq),
+            Message::SnapshotChunk {
+                tick,
+                index,
+                chunk_cnt,
+                data,
+            } => {
+                self.add_snapshot_chunk(tick, index, chunk_cnt, data)?;
+            }
+            Message::SnapshotAck { tick, index } => {
+                self.ack_snapshot_chunks(tick, index);
+            }
+            Message::SlotGranted { players } => self.take_granted_slots(players),
+            Message::Successor { addr } if self.is_spectator => {
+                self.migration.successor = Some(addr)
+            }
+            Message::Successor { .. } => {}
+            // The handshake is handled on receive, and slots are requested by spectators
+            Message::Hello(_)
+            | Message::Spectate(_)
+            | Message::Welcome { .. }
+            | Message::Disconnect { .. }
+            | Message::SlotRequest => {}
+        }
+
+        Ok(())
+    }
+
+    fn send(&mut self) -> Result<()> {
+        let now = date::now();
+
+        if self.is_host {
+            self.send_to_spectators(now)?;
+        }
+
+        if self.connection.peer.is_none() || self.connection.disconnect_reason.is_some() {
+            return Ok(());
+        }
+
+        if !self.connection.is_connected {
+            if !self.is_host {
+                self.connection
+                    .send(&self.handshake.message(self.is_spectator))?;
+            }
+
+            return Ok(());
+        }
+
+        let mut messages = Vec::new();
+
+        // The address of the client might have changed, in which case it has to rejoin
+        if !self.is_host && self.is_reconnecting() {
+            messages.push(self.handshake.message(self.is_spectator));
+        }
+
+        if let Some(players) = &self.spectators.granted_slots {
+            messages.push(Message::SlotGranted {
+                players: players.clone(),
+            });
+        }
+
+        if self.spectators.is_slot_requested {
+            messages.push(Message::SlotRequest);
+        }
+
+        if self.reconnect.resync.is_none() {
+            self.inputs.push_messages(&mut messages);
+
+            // Spectators are not checked for desyncs, as they only ever apply confirmed input
+            if !self.is_spectator {
+                self.checksums.push_messages(&mut messages);
+            }
+        } else if let Some(Resync::Sending(transfer)) = &mut self.reconnect.resync {
+            transfer.push_chunks(now, &mut messages);
+        }
+
+        self.connection.push_ping(now, &mut messages);
+        self.chat.push_messages(now, &mut messages);
+
+        for message in &messages {
+            self.connection.send(message)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for LockstepSession {
+    fn drop(&mut self) {
+        let _ = self.disconnect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use macroquad::prelude::vec2;
+
+    use hecs::World;
+
+    use core::network::fnv_hash;
+
+    use super::*;
+    use crate::game::{MatchRules, WinCondition};
+    use crate::network::{LinkConditions, LoopbackNetwork};
+    use crate::player::{Player, PlayerController};
+
+    fn handshake() -> HandshakeInfo {
+        HandshakeInfo {
+            game_version: "0.0.0".to_string(),
+            mods_hash: 1,
+            map_hash: 2,
+            seed: None,
+            settings_hash: None,
+        }
+    }
+
+    struct Peer {
+        session: LockstepSession,
+        world: World,
+        tick: u64,
+        local_player: u8,
+        /// The input that was applied to every player, for every tick that has been simulated
+        applied_inputs: Vec<Vec<GameInput>>,
+        /// The amount of times the peer has rolled back, when using `NetcodeMode::Rollback`
+        rollback_cnt: u64,
+    }
+
+    impl Peer {
+        fn new(session: LockstepSession, local_player: u8) -> Self {
+            let mut world = World::new();
+
+            for index in 0..2 {
+                world.spawn((
+                    Player::new(index, vec2(0.0, 0.0)),
+                    PlayerController::from(PlayerControllerKind::External),
+                ));
+            }
+
+            Peer {
+                session,
+                world,
+                tick: 0,
+                local_player,
+                applied_inputs: Vec::new(),
+                rollback_cnt: 0,
+            }
+        }
+
+        fn set_local_input(&mut self) {
+            for (_, (player, controller)) in
+                self.world.query_mut::<(&Player, &mut PlayerController)>()
+            {
+                if player.index == self.local_player {
+                    controller.input = scripted_input(player.index, self.tick);
+                }
+            }
+        }
+
+        fn push_applied_inputs(&mut self) {
+            let mut inputs = self
+                .world
+                .query_mut::<(&Player, &PlayerController)>()
+                .into_iter()
+                .map(|(_, (player, controller))| (player.index, controller.input))
+                .collect::<Vec<_>>();
+
+            inputs.sort_by_key(|(index, _)| *index);
+
+            self.applied_inputs
+                .push(inputs.into_iter().map(|(_, input)| input).collect());
+        }
+
+        fn update(&mut self) {
+            self.set_local_input();
+
+            if self.session.update(self.tick, &mut self.world).unwrap() {
+                self.push_applied_inputs();
+                self.tick += 1;
+            }
+        }
+
+        /// Update the peer like `Game` does with `NetcodeMode::Rollback`, where the applied
+        /// inputs are the simulated state. On a misprediction, the inputs of the mispredicted
+        /// ticks are discarded, like restoring a snapshot, and applied again.
+        fn update_rollback(&mut self) {
+            self.set_local_input();
+            self.resolve_predictions();
+
+            if self.session.apply_input(self.tick, &mut self.world) {
+                self.push_applied_inputs();
+                self.tick += 1;
+            }
+
+            self.session.discard_confirmed(self.tick);
+        }
+
+        fn resolve_predictions(&mut self) {
+            self.session.exchange(self.tick, &mut self.world).unwrap();
+
+            if let Some(from_tick) = self.session.take_misprediction() {
+                self.rollback_cnt += 1;
+                self.applied_inputs.truncate(from_tick as usize);
+
+                for tick in from_tick..self.tick {
+                    assert!(self.session.apply_input(tick, &mut self.world));
+                    self.push_applied_inputs();
+                }
+            }
+        }
+
+        /// A checksum of the simulated state
+        fn checksum(&self) -> u64 {
+            let bytes = self
+                .applied_inputs
+                .iter()
+                .flatten()
+                .map(|input| input.to_bits())
+                .collect::<Vec<_>>();
+
+            fnv_hash(&bytes)
+        }
+    }
+
+    fn scripted_input(player: u8, tick: u64) -> GameInput {
+        GameInput {
+            left: (tick / 7 + player as u64) % 2 == 0,
+            right: (tick / 7 + player as u64) % 2 == 1,
+            jump: tick % 5 == 0,
+            fire: tick % 11 == player as u64,
+            ..GameInput::default()
+        }
+    }
+
+    /// Run two peers over `network` until both have simulated `tick_cnt` ticks
+    fn run_peers(network: &LoopbackNetwork, tick_cnt: u64) -> (Peer, Peer) {
+        let (mut host, mut client) = create_peers(network, &MatchSettings::default());
+
+        run_until(&mut host, &mut client, tick_cnt);
+
+        (host, client)
+    }
+
+    fn create_peers(network: &LoopbackNetwork, settings: &MatchSettings) -> (Peer, Peer) {
+        let host_transport = network.bind_any().unwrap();
+        let host_addr = host_transport.local_addr().unwrap();
+        let client_transport = network.bind_any().unwrap();
+
+        let host = Peer::new(
+            LockstepSession::with_players(
+                Box::new(host_transport),
+                None,
+                handshake(),
+                &[0],
+                &[1],
+                settings,
+            )
+            .unwrap(),
+            0,
+        );
+
+        let client = Peer::new(
+            LockstepSession::with_players(
+                Box::new(client_transport),
+                Some(host_addr),
+                handshake(),
+                &[1],
+                &[0],
+                settings,
+            )
+            .unwrap(),
+            1,
+        );
+
+        (host, client)
+    }
+
+    fn run_until(host: &mut Peer, client: &mut Peer, tick_cnt: u64) {
+        for _ in 0..20_000 {
+            if host.tick >= tick_cnt && client.tick >= tick_cnt {
+                break;
+            }
+
+            if host.tick < tick_cnt {
+                host.update();
+            } else {
+                host.session.poll().unwrap();
+            }
+
+            if client.tick < tick_cnt {
+                client.update();
+            } else {
+                client.session.poll().unwrap();
+            }
+
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        assert_eq!(host.tick, tick_cnt, "Host did not reach the final tick");
+        assert_eq!(client.tick, tick_cnt, "Client did not reach the final tick");
+    }
+
+    #[test]
+    fn test_lockstep_perfect_link() {
+        let network = LoopbackNetwork::new(0);
+
+        let (host, client) = run_peers(&network, 60);
+
+        assert_eq!(host.applied_inputs, client.applied_inputs);
+    }
+
+    #[test]
+    fn test_lockstep_bad_link() {
+        let network = LoopbackNetwork::new(1);
+
+        // Any round trip on this link is long enough for the input delay to grow
+        network.set_conditions(LinkConditions {
+            latency: 0.05,
+            jitter: 0.01,
+            loss: 0.25,
+            reordering: 0.1,
+            reorder_delay: 0.03,
+        });
+
+        let (mut host, mut client) = run_peers(&network, 120);
+
+        assert_eq!(host.applied_inputs, client.applied_inputs);
+
+        // Pings are lost as well, so the round trip time might not have been measured yet
+        for _ in 0..5_000 {
+            if host.session.rtt().is_some() {
+                break;
+            }
+
+            host.session.poll().unwrap();
+            client.session.poll().unwrap();
+
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        assert!(host.session.input_delay() > MIN_INPUT_DELAY);
+    }
+
+    #[test]
+    fn test_rollback_bad_link() {
+        let network = LoopbackNetwork::new(2);
+
+        // The latency is longer than the input delay, so remote input has to be predicted
+        network.set_conditions(LinkConditions {
+            latency: 0.05,
+            jitter: 0.02,
+            loss: 0.0,
+            reordering: 0.2,
+            reorder_delay: 0.03,
+        });
+
+        let settings = MatchSettings {
+            netcode: NetcodeMode::Rollback,
+            ..MatchSettings::default()
+        };
+
+        let (mut host, mut client) = create_peers(&network, &settings);
+
+        let tick_cnt = 120;
+
+        for _ in 0..20_000 {
+            if host.tick >= tick_cnt && client.tick >= tick_cnt {
+                break;
+            }
+
+            for peer in [&mut host, &mut client] {
+                if peer.tick < tick_cnt {
+                    peer.update_rollback();
+                } else {
+                    peer.resolve_predictions();
+                }
+            }
+
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        assert_eq!(host.tick, tick_cnt, "Host did not reach the final tick");
+        assert_eq!(client.tick, tick_cnt, "Client did not reach the final tick");
+
+        // Wait for the remaining predictions to be confirmed, or rolled back
+        for _ in 0..5_000 {
+            if host.session.confirmed_tick() >= tick_cnt
+                && client.session.confirmed_tick() >= tick_cnt
+            {
+                break;
+            }
+
+            host.resolve_predictions();
+            client.resolve_predictions();
+
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        host.resolve_predictions();
+        client.resolve_predictions();
+
+        assert_eq!(host.checksum(), client.checksum());
+        assert!(host.rollback_cnt + client.rollback_cnt > 0);
+    }
+
+    #[test]
+    fn test_resync_bad_link() {
+        let network = LoopbackNetwork::new(3);
+
+        network.set_conditions(LinkConditions {
+            latency: 0.01,
+            jitter: 0.005,
+            loss: 0.25,
+            reordering: 0.1,
+            reorder_delay: 0.02,
+        });
+
+        let (mut host, mut client) = run_peers(&network, 30);
+
+        // Large enough to be sent in more than one window
+        let snapshot = (0..40_000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+
+        host.session.send_snapshot(30, &snapshot).unwrap();
+
+        let mut received = None;
+
+        for _ in 0..10_000 {
+            host.update();
+
+            if host.session.is_resyncing() {
+                assert_eq!(host.tick, 30, "The host should stall while resyncing");
+            }
+
+            client.update();
+
+            // The client might have simulated ahead of the host, before the snapshot arrived
+            if let Some((tick, snapshot)) = client.session.take_snapshot() {
+                client.tick = tick;
+                client.applied_inputs.truncate(tick as usize);
+
+                received = Some((tick, snapshot));
+            }
+
+            if received.is_some() && !host.session.is_resyncing() {
+                break;
+            }
+
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        assert_eq!(received, Some((30, snapshot)));
+
+        run_until(&mut host, &mut client, 60);
+
+        assert_eq!(host.applied_inputs, client.applied_inputs);
+    }
+
+    #[test]
+    fn test_spectator_bad_link() {
+        let network = LoopbackNetwork::new(4);
+
+        network.set_conditions(LinkConditions {
+            latency: 0.01,
+            jitter: 0.005,
+            loss: 0.2,
+            reordering: 0.1,
+            reorder_delay: 0.02,
+        });
+
+        let (mut host, mut client) = run_peers(&network, 20);
+        let mut spectator = create_spectator(&network, &host);
+
+        for _ in 0..20_000 {
+            if host.tick >= 90 && client.tick >= 90 && spectator.tick >= 90 {
+                break;
+            }
+
+            for peer in [&mut host, &mut client] {
+                if peer.tick < 90 {
+                    peer.update();
+                } else {
+                    peer.session.poll().unwrap();
+                }
+            }
+
+            update_spectator(&mut host, &mut spectator, &[0, 1], 90);
+
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        assert_eq!(spectator.tick, 90, "Spectator did not reach the final tick");
+        assert_eq!(host.session.spectator_cnt(), 1);
+        assert_eq!(host.applied_inputs, client.applied_inputs);
+        assert_eq!(host.applied_inputs, spectator.applied_inputs);
+    }
+
+    #[test]
+    fn test_host_migration() {
+        let network = LoopbackNetwork::new(5);
+
+        let (mut host, mut client) = run_peers(&network, 20);
+        let mut spectator = create_spectator(&network, &host);
+
+        for _ in 0..20_000 {
+            if spectator.tick >= 40 {
+                break;
+            }
+
+            for peer in [&mut host, &mut client] {
+                if peer.tick < 40 {
+                    peer.update();
+                } else {
+                    peer.session.poll().unwrap();
+                }
+            }
+
+            update_spectator(&mut host, &mut spectator, &[0, 1], 40);
+
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        host.session.disconnect().unwrap();
+        drop(host);
+
+        for _ in 0..1_000 {
+            client.session.poll().unwrap();
+            spectator.session.poll().unwrap();
+
+            if client.session.is_host_lost() && spectator.session.is_host_lost() {
+                break;
+            }
+
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        client.session.remove_remote_players(client.tick);
+
+        assert!(client.session.take_over_as_host());
+        assert!(spectator.session.follow_successor());
+
+        for _ in 0..20_000 {
+            if client.tick >= 80 && spectator.tick >= 80 {
+                break;
+            }
+
+            if client.tick < 80 {
+                client.update();
+            } else {
+                client.session.poll().unwrap();
+            }
+
+            update_spectator(&mut client, &mut spectator, &[1], 80);
+
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        assert_eq!(spectator.tick, 80, "Spectator did not reach the final tick");
+        assert!(client.session.is_host());
+
+        let client_inputs = client.applied_inputs.iter().map(|inputs| inputs[1]);
+        let spectator_inputs = spectator.applied_inputs.iter().map(|inputs| inputs[1]);
+
+        assert!(client_inputs.eq(spectator_inputs));
+    }
+
+    fn create_spectator(network: &LoopbackNetwork, host: &Peer) -> Peer {
+        let transport = network.bind_any().unwrap();
+        let host_addr = host.session.local_addr().unwrap();
+
+        Peer::new(
+            LockstepSession::with_players(
+                Box::new(transport),
+                Some(host_addr),
+                handshake(),
+                &[],
+                &[],
+                &MatchSettings::default(),
+            )
+            .unwrap()
+            .into_spectator(),
+            u8::MAX,
+        )
+    }
+
+    /// Send a snapshot from `host` to the spectators that need one, and update `spectator`, until
+    /// it has simulated `tick_cnt` ticks. A snapshot is restored by taking the inputs that the
+    /// host applied before it.
+    fn update_spectator(host: &mut Peer, spectator: &mut Peer, players: &[u8], tick_cnt: u64) {
+        if let Some(tick) = host.session.spectator_snapshot_tick() {
+            assert_eq!(tick, host.tick);
+
+            host.session
+                .send_spectator_snapshot(tick, &tick.to_le_bytes())
+                .unwrap();
+        }
+
+        if let Some((tick, snapshot)) = spectator.session.take_snapshot() {
+            assert_eq!(snapshot, tick.to_le_bytes());
+
+            spectator.session.set_players(players);
+            spectator.tick = tick;
+            spectator.applied_inputs = host.applied_inputs[..tick as usize].to_vec();
+        }
+
+        if spectator.tick < tick_cnt {
+            spectator.update();
+        } else {
+            spectator.session.poll().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_chat_bad_link() {
+        let network = LoopbackNetwork::new(2);
+
+        network.set_conditions(LinkConditions {
+            latency: 0.01,
+            jitter: 0.0,
+            loss: 0.5,
+            reordering: 0.2,
+            reorder_delay: 0.02,
+        });
+
+        let settings = MatchSettings::default();
+
+        let host_transport = network.bind_any().unwrap();
+        let host_addr = host_transport.local_addr().unwrap();
+        let client_transport = network.bind_any().unwrap();
+
+        let mut host = LockstepSession::with_players(
+            Box::new(host_transport),
+            None,
+            handshake(),
+            &[0],
+            &[1],
+            &settings,
+        )
+        .unwrap();
+
+        let mut client = LockstepSession::with_players(
+            Box::new(client_transport),
+            Some(host_addr),
+            handshake(),
+            &[1],
+            &[0],
+            &settings,
+        )
+        .unwrap();
+
+        let sent = ["one", "two", "three"];
+
+        for text in sent {
+            assert!(client.send_chat(1, text).is_some());
+        }
+
+        let mut received = Vec::new();
+
+        for _ in 0..5_000 {
+            client.poll().unwrap();
+            host.poll().unwrap();
+
+            received.extend(host.take_chat_messages());
+
+            if received.len() == sent.len() {
+                break;
+            }
+
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        assert_eq!(
+            received,
+            sent.iter()
+                .map(|text| (1, text.to_string()))
+                .collect::<Vec<_>>(),
+            "Chat messages should be received once, in order"
+        );
+
+        host.set_muted(1, true);
+        client.send_chat(1, "muted");
+
+        for _ in 0..500 {
+            client.poll().unwrap();
+            host.poll().unwrap();
+
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        assert!(host.take_chat_messages().is_empty());
+    }
+
+    #[test]
+    fn test_handshake_mismatch() {
+        let network = LoopbackNetwork::new(0);
+        let settings = MatchSettings::default();
+
+        let host_transport = network.bind_any().unwrap();
+        let host_addr = host_transport.local_addr().unwrap();
+        let client_transport = network.bind_any().unwrap();
+
+        let mut host = LockstepSession::with_players(
+            Box::new(host_transport),
+            None,
+            handshake(),
+            &[0],
+            &[1],
+            &settings,
+        )
+        .unwrap();
+
+        let mut client = LockstepSession::with_players(
+            Box::new(client_transport),
+            Some(host_addr),
+            HandshakeInfo {
+                map_hash: 3,
+                ..handshake()
+            },
+            &[1],
+            &[0],
+            &settings,
+        )
+        .unwrap();
+
+        for _ in 0..3 {
+            client.poll().unwrap();
+            host.poll().unwrap();
+        }
+
+        assert!(!host.is_connected());
+        assert!(client.is_disconnected());
+        assert_eq!(
+            client.disconnect_reason(),
+            Some(DisconnectReason::MapMismatch)
+        );
+    }
+
+    #[test]
+    fn test_handshake_settings_too_large() {
+        let network = LoopbackNetwork::new(0);
+
+        // The settings are sent in a single datagram, so hosting with more fails
+        let settings = MatchSettings {
+            spawn_point_overrides: (0..=u8::MAX).map(|index| (index, usize::MAX)).collect(),
+            ..MatchSettings::new(7)
+        };
+
+        assert!(LockstepSession::with_players(
+            Box::new(network.bind_any().unwrap()),
+            None,
+            handshake(),
+            &[0],
+            &[1],
+            &settings,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_handshake_settings() {
+        let network = LoopbackNetwork::new(0);
+
+        let host_transport = network.bind_any().unwrap();
+        let host_addr = host_transport.local_addr().unwrap();
+
+        let rules = MatchRules::new(WinCondition::Stock { lives: 3 }, 5);
+
+        let mut host = LockstepSession::with_players(
+            Box::new(host_transport),
+            None,
+            handshake(),
+            &[0],
+            &[1],
+            &MatchSettings {
+                netcode: NetcodeMode::Rollback,
+                rules,
+                ..MatchSettings::new(7)
+            },
+        )
+        .unwrap();
+
+        // A client that does not know the settings adopts those of the host
+        let mut client = LockstepSession::with_players(
+            Box::new(network.bind_any().unwrap()),
+            Some(host_addr),
+            handshake(),
+            &[1],
+            &[0],
+            &MatchSettings::default(),
+        )
+        .unwrap();
+
+        for _ in 0..3 {
+            client.poll().unwrap();
+            host.poll().unwrap();
+        }
+
+        assert!(client.is_connected());
+        assert_eq!(client.netcode(), NetcodeMode::Rollback);
+
+        let host_settings = client.take_host_settings().unwrap();
+        assert_eq!(host_settings.seed, 7);
+        assert_eq!(host_settings.rules, rules);
+        assert!(client.take_host_settings().is_none());
+
+        // A client that expects other settings is refused
+        let host_transport = network.bind_any().unwrap();
+        let host_addr = host_transport.local_addr().unwrap();
+
+        let mut host = LockstepSession::with_players(
+            Box::new(host_transport),
+            None,
+            handshake(),
+            &[0],
+            &[1],
+            &MatchSettings::new(7),
+        )
+        .unwrap();
+
+        let mut other = LockstepSession::with_players(
+            Box::new(network.bind_any().unwrap()),
+            Some(host_addr),
+            HandshakeInfo {
+                seed: Some(8),
+                ..host.handshake.info.clone()
+            },
+            &[1],
+            &[0],
+            &MatchSettings::default(),
+        )
+        .unwrap();
+
+        for _ in 0..3 {
+            other.poll().unwrap();
+            host.poll().unwrap();
+        }
+
+        assert!(other.is_disconnected());
+        assert_eq!(
+            other.disconnect_reason(),
+            Some(DisconnectReason::SettingsMismatch)
+        );
+    }
+}
@@ -0,0 +1,136 @@
+//! The handshake between peers. A client sends its `HandshakeInfo` until the host answers it,
+//! and the host welcomes the clients whose handshake matches its own, with the settings of the
+//! match, which the clients adopt.
+
+use std::net::SocketAddr;
+
+use core::error::ErrorKind;
+use core::network::{fnv_hash, DisconnectReason, HandshakeInfo, Message, MAX_SETTINGS_SIZE};
+use core::{formaterr, Result};
+
+use crate::game::MatchSettings;
+
+use super::LockstepSession;
+
+pub(super) struct Handshake {
+    pub info: HandshakeInfo,
+    /// The serialized settings of the match, as sent by the host in `Message::Welcome`
+    pub settings: Vec<u8>,
+    /// The settings received from the host, on a client, until they are taken by `Game`
+    pub host_settings: Option<MatchSettings>,
+}
+
+impl Handshake {
+    /// On the host, the seed and settings hash of `info` are set from `settings`. This fails on
+    /// the host if the serialized settings do not fit in a single `Message::Welcome`.
+    pub fn new(mut info: HandshakeInfo, settings: &MatchSettings, is_host: bool) -> Result<Self> {
+        let settings_bytes = serde_json::to_vec(settings)?;
+
+        if is_host {
+            if settings_bytes.len() > MAX_SETTINGS_SIZE {
+                return Err(formaterr!(
+                    ErrorKind::Network,
+                    "The match settings are too large to be sent ({} bytes, at most {})",
+                    settings_bytes.len(),
+                    MAX_SETTINGS_SIZE
+                ));
+            }
+
+            info.seed = Some(settings.seed);
+            info.settings_hash = Some(fnv_hash(&settings_bytes));
+        }
+
+        Ok(Handshake {
+            info,
+            settings: settings_bytes,
+            host_settings: None,
+        })
+    }
+
+    pub fn welcome_message(&self) -> Message {
+        Message::Welcome {
+            settings: self.settings.clone(),
+        }
+    }
+
+    /// The handshake that is sent to the host, until it is answered
+    pub fn message(&self, is_spectator: bool) -> Message {
+        if is_spectator {
+            Message::Spectate(self.info.clone())
+        } else {
+            Message::Hello(self.info.clone())
+        }
+    }
+}
+
+impl LockstepSession {
+    /// Returns the settings of the match that were received from the host, on a client, once
+    /// they have been received. The match has to be started over with these, as the client only
+    /// learns them when it is welcomed by the host.
+    pub fn take_host_settings(&mut self) -> Option<MatchSettings> {
+        self.handshake.host_settings.take()
+    }
+
+    /// Accept the client at `addr`, if its handshake matches the local one and no other client
+    /// has connected. The handshake is sent until it is answered, so it might be received again.
+    /// If `is_rejoin` is `true`, the client that was connected is reconnecting, and a client at
+    /// another address is accepted in its place, as its address might have changed.
+    pub(super) fn handle_hello(
+        &mut self,
+        addr: SocketAddr,
+        remote: HandshakeInfo,
+        is_rejoin: bool,
+    ) -> Result<()> {
+        let is_other_peer = matches!(self.connection.peer, Some(peer) if peer != addr);
+
+        if is_other_peer && !is_rejoin {
+            return self.connection.refuse(addr, DisconnectReason::SessionFull);
+        }
+
+        if let Some(reason) = self.handshake.info.check(&remote) {
+            #[cfg(debug_assertions)]
+            println!(
+                "WARNING: Refused connection from '{}' (version {}): {}",
+                addr, remote.game_version, reason
+            );
+
+            return self.connection.refuse(addr, reason);
+        }
+
+        self.connection.peer = Some(addr);
+        self.connection.is_connected = true;
+
+        if is_other_peer {
+            self.request_resync();
+        }
+
+        self.connection.send(&self.handshake.welcome_message())
+    }
+
+    /// Adopt the settings of the match that the host sent, unless they are already known, as is
+    /// the case when rejoining, or when the host has checked that they match the local ones
+    pub(super) fn handle_welcome(&mut self, settings: Vec<u8>) -> Result<()> {
+        if self.handshake.info.settings_hash.is_none() {
+            let host_settings: MatchSettings = serde_json::from_slice(&settings)?;
+
+            self.handshake.info.seed = Some(host_settings.seed);
+            self.handshake.info.settings_hash = Some(fnv_hash(&settings));
+
+            // Spectators never roll back, as they only apply confirmed input
+            if !self.is_spectator {
+                self.netcode = host_settings.netcode;
+            }
+
+            self.predictions.max_rollback_ticks = host_settings.max_rollback_ticks;
+            self.reconnect.disconnect_rule = host_settings.disconnect_rule;
+            self.reconnect.timeout = host_settings.reconnect_timeout;
+
+            self.handshake.settings = settings;
+            self.handshake.host_settings = Some(host_settings);
+        }
+
+        self.connection.is_connected = true;
+
+        Ok(())
+    }
+}
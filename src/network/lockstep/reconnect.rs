@@ -0,0 +1,377 @@
+//! Reconnection of the remote peer. A peer that has not been heard from for `DISCONNECT_TIMEOUT`
+//! is reconnecting, until the reconnect timeout of the match has passed as well. Once it is back,
+//! the host sends it a snapshot of the game, in chunks, and both continue from its tick.
+
+use core::error::ErrorKind;
+use core::network::{Message, MAX_STATE_CHUNK_SIZE};
+use core::{formaterr, Result};
+
+use crate::game::DisconnectRule;
+use crate::GameInput;
+
+use super::LockstepSession;
+
+/// If nothing has been received from the remote peer for this many seconds, it is considered to
+/// be reconnecting, and, after the reconnect timeout of the match has passed as well, to be
+/// disconnected
+pub const DISCONNECT_TIMEOUT: f64 = 5.0;
+
+/// The interval, in seconds, at which the unacknowledged chunks of a snapshot are sent again
+const SNAPSHOT_RESEND_INTERVAL: f64 = 0.1;
+
+/// The maximum amount of unacknowledged snapshot chunks that are sent at a time
+const SNAPSHOT_WINDOW: usize = 32;
+
+/// The state of the connection with the remote peer
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PeerStatus {
+    /// The handshake has not yet completed
+    Connecting,
+    Connected,
+    /// Nothing has been received from the peer for `DISCONNECT_TIMEOUT`, but it still has time
+    /// to reconnect
+    Reconnecting,
+    Disconnected,
+}
+
+/// A snapshot that is sent in chunks, a window at a time, until all are acknowledged
+pub(super) struct SnapshotTransfer {
+    tick: u64,
+    chunks: Vec<Vec<u8>>,
+    acked_cnt: usize,
+    sent_cnt: usize,
+    last_send_time: f64,
+}
+
+impl SnapshotTransfer {
+    pub fn new(tick: u64, snapshot: &[u8]) -> Result<Self> {
+        let mut chunks = snapshot
+            .chunks(MAX_STATE_CHUNK_SIZE)
+            .map(|chunk| chunk.to_vec())
+            .collect::<Vec<_>>();
+
+        if chunks.is_empty() {
+            chunks.push(Vec::new());
+        }
+
+        if chunks.len() > u16::MAX as usize {
+            return Err(formaterr!(
+                ErrorKind::Network,
+                "Snapshot of tick {} is too large to be sent",
+                tick
+            ));
+        }
+
+        Ok(SnapshotTransfer {
+            tick,
+            chunks,
+            acked_cnt: 0,
+            sent_cnt: 0,
+            last_send_time: f64::NEG_INFINITY,
+        })
+    }
+
+    /// Handle the acknowledgement of all chunks of the snapshot of `tick` before `index`.
+    /// Returns `true` once all chunks have been acknowledged.
+    pub fn ack(&mut self, tick: u64, index: u16) -> bool {
+        if tick == self.tick {
+            self.acked_cnt = self.acked_cnt.max(index as usize);
+        }
+
+        self.acked_cnt >= self.chunks.len()
+    }
+
+    /// Add the next window of unacknowledged chunks to `messages`, if the previous window has
+    /// been acknowledged, or the resend interval has passed
+    pub fn push_chunks(&mut self, now: f64, messages: &mut Vec<Message>) {
+        if self.acked_cnt < self.sent_cnt && now - self.last_send_time < SNAPSHOT_RESEND_INTERVAL {
+            return;
+        }
+
+        self.last_send_time = now;
+        self.sent_cnt = (self.acked_cnt + SNAPSHOT_WINDOW).min(self.chunks.len());
+
+        for index in self.acked_cnt..self.sent_cnt {
+            messages.push(Message::SnapshotChunk {
+                tick: self.tick,
+                index: index as u16,
+                chunk_cnt: self.chunks.len() as u16,
+                data: self.chunks[index].clone(),
+            });
+        }
+    }
+}
+
+/// The transfer of a snapshot from the host to a client that has reconnected, or that has just
+/// joined as a spectator
+pub(super) enum Resync {
+    /// The client has reconnected, and the host has to provide a snapshot, through
+    /// `LockstepSession::send_snapshot`
+    Requested,
+    /// The host is sending the snapshot to the remote peer
+    Sending(SnapshotTransfer),
+    /// The client is waiting for the host to start sending a snapshot
+    Awaiting,
+    /// The client is receiving the chunks of the snapshot of `tick`
+    Receiving {
+        tick: u64,
+        chunks: Vec<Option<Vec<u8>>>,
+    },
+    /// The client has received the whole snapshot of `tick`, for `Game` to restore
+    Received { tick: u64, snapshot: Vec<u8> },
+}
+
+pub(super) struct Reconnect {
+    pub disconnect_rule: DisconnectRule,
+    /// The reconnect timeout of the match, in seconds
+    pub timeout: f64,
+    pub resync: Option<Resync>,
+    /// The tick of the last snapshot that was restored, so that chunks of it that are received
+    /// again can still be acknowledged
+    pub last_resync_tick: Option<u64>,
+}
+
+impl Reconnect {
+    pub fn new(disconnect_rule: DisconnectRule, timeout: f64) -> Self {
+        Reconnect {
+            disconnect_rule,
+            timeout,
+            resync: None,
+            last_resync_tick: None,
+        }
+    }
+}
+
+impl LockstepSession {
+    /// Returns `true` if the remote peer has left, or refused the connection, or if nothing has
+    /// been received from it for the duration of `DISCONNECT_TIMEOUT` plus the reconnect
+    /// timeout. The timeouts do not apply until the first message is received.
+    pub fn is_disconnected(&self) -> bool {
+        self.connection.disconnect_reason.is_some()
+            || matches!(self.connection.silence(), Some(silence) if silence > self.disconnect_timeout())
+    }
+
+    /// Returns `true` if the remote peer has lost its connection, but still has time to
+    /// reconnect
+    pub fn is_reconnecting(&self) -> bool {
+        self.connection.is_connected
+            && self.connection.disconnect_reason.is_none()
+            && matches!(
+                self.connection.silence(),
+                Some(silence) if silence > DISCONNECT_TIMEOUT && silence <= self.disconnect_timeout()
+            )
+    }
+
+    /// The time, in seconds, that the remote peer has left to reconnect, if it is reconnecting
+    pub fn reconnect_time_left(&self) -> Option<f64> {
+        if self.is_reconnecting() {
+            self.connection
+                .silence()
+                .map(|silence| self.disconnect_timeout() - silence)
+        } else {
+            None
+        }
+    }
+
+    pub fn peer_status(&self) -> PeerStatus {
+        if self.is_disconnected() {
+            PeerStatus::Disconnected
+        } else if self.is_reconnecting() {
+            PeerStatus::Reconnecting
+        } else if self.connection.is_connected {
+            PeerStatus::Connected
+        } else {
+            PeerStatus::Connecting
+        }
+    }
+
+    fn disconnect_timeout(&self) -> f64 {
+        DISCONNECT_TIMEOUT + self.reconnect.timeout.max(0.0)
+    }
+
+    /// Stop waiting for the input of the remote players, like when they have been removed from
+    /// the match, after the remote peer disconnected. The game must continue from `tick`, which
+    /// can not be after the last confirmed tick.
+    pub fn remove_remote_players(&mut self, tick: u64) {
+        if self.is_host {
+            self.keep_spectated_input(tick);
+        }
+
+        self.spectators.open_slots = self.remote_players();
+
+        self.inputs.remote.clear();
+        self.predictions.clear();
+        self.reconnect.resync = None;
+        self.spectators.granted_slots = None;
+
+        // The players of the snapshots that spectators have received no longer match
+        self.spectators.require_snapshots(tick);
+    }
+
+    /// Returns `true` if the remote peer has reconnected and the host has to send it a snapshot
+    /// of the game, using `send_snapshot`
+    pub fn is_resync_requested(&self) -> bool {
+        matches!(self.reconnect.resync, Some(Resync::Requested))
+    }
+
+    /// Returns `true` while a snapshot is being transferred to a client that has reconnected,
+    /// during which the simulation is stalled
+    pub fn is_resyncing(&self) -> bool {
+        self.reconnect.resync.is_some()
+    }
+
+    /// Send the snapshot of the game at the start of `tick` to the remote peer, which has
+    /// reconnected. Both peers will continue from `tick`, once the snapshot has been received.
+    pub fn send_snapshot(&mut self, tick: u64, snapshot: &[u8]) -> Result<()> {
+        let transfer = SnapshotTransfer::new(tick, snapshot)?;
+
+        self.reset_to_tick(tick);
+
+        self.reconnect.resync = Some(Resync::Sending(transfer));
+
+        Ok(())
+    }
+
+    /// Returns the snapshot sent by the host, and the tick it is from, once all of it has been
+    /// received. The session will continue from that tick, so the snapshot has to be restored.
+    pub fn take_snapshot(&mut self) -> Option<(u64, Vec<u8>)> {
+        let (tick, snapshot) = match self.reconnect.resync.take() {
+            Some(Resync::Received { tick, snapshot }) => (tick, snapshot),
+            resync => {
+                self.reconnect.resync = resync;
+                return None;
+            }
+        };
+
+        self.reset_to_tick(tick);
+
+        for buffer in self.inputs.local.values_mut() {
+            buffer.reset(tick);
+        }
+
+        self.inputs.next_local_tick = tick;
+        self.checksums.local.clear();
+        self.reconnect.last_resync_tick = Some(tick);
+
+        Some((tick, snapshot))
+    }
+
+    /// Discard the remote input, and everything else that was received, for ticks from `tick`
+    /// and forward, and start sending the local input from `tick` again, to continue from the
+    /// snapshot of `tick`
+    fn reset_to_tick(&mut self, tick: u64) {
+        for buffer in self.inputs.remote.values_mut() {
+            buffer.reset(tick);
+        }
+
+        self.inputs.remote_ack = tick;
+        self.predictions.clear();
+        self.checksums.reset_remote();
+
+        // The input of the ticks before `tick` might no longer be available, so spectators that
+        // are behind have to continue from a new snapshot, as do all spectators, if players have
+        // been added for a peer that took open slots
+        if self.spectators.next_tick < tick || self.spectators.granted_slots.is_some() {
+            self.spectators.require_snapshots(tick);
+        }
+    }
+
+    /// Insert idle input for the remote players, for every tick up until `tick` that their input
+    /// is missing for, so that the simulation can continue while the remote peer is reconnecting
+    pub(super) fn insert_idle_remote_input(&mut self, tick: u64) {
+        let players = self.remote_players();
+
+        for player in players {
+            let start_tick = self.inputs.remote[&player].next_missing_tick();
+
+            for tick in start_tick..=tick {
+                self.insert_remote_input(player, tick, GameInput::default());
+            }
+        }
+    }
+
+    /// Have the host send a snapshot to the remote peer, which is back after reconnecting
+    pub(super) fn request_resync(&mut self) {
+        if self.is_host
+            && self.reconnect.resync.is_none()
+            && self.connection.disconnect_reason.is_none()
+        {
+            self.reconnect.resync = Some(Resync::Requested);
+        }
+    }
+
+    /// Add a chunk of the snapshot that is sent by the host, and acknowledge the chunks that have
+    /// been received
+    pub(super) fn add_snapshot_chunk(
+        &mut self,
+        tick: u64,
+        index: u16,
+        chunk_cnt: u16,
+        data: Vec<u8>,
+    ) -> Result<()> {
+        if self.is_host {
+            return Ok(());
+        }
+
+        let is_received = self.reconnect.last_resync_tick == Some(tick)
+            || matches!(&self.reconnect.resync, Some(Resync::Received { tick: other, .. }) if *other == tick);
+
+        if is_received {
+            return self.connection.send(&Message::SnapshotAck {
+                tick,
+                index: chunk_cnt,
+            });
+        }
+
+        let is_new_transfer = !matches!(
+            &self.reconnect.resync,
+            Some(Resync::Receiving { tick: other, chunks })
+                if *other == tick && chunks.len() == chunk_cnt as usize
+        );
+
+        if is_new_transfer {
+            self.reconnect.resync = Some(Resync::Receiving {
+                tick,
+                chunks: vec![None; chunk_cnt as usize],
+            });
+        }
+
+        let chunks = match &mut self.reconnect.resync {
+            Some(Resync::Receiving { chunks, .. }) => chunks,
+            _ => unreachable!(),
+        };
+
+        if let Some(chunk) = chunks.get_mut(index as usize) {
+            *chunk = Some(data);
+        }
+
+        let next_index = chunks
+            .iter()
+            .position(|chunk| chunk.is_none())
+            .unwrap_or(chunks.len());
+
+        if next_index == chunks.len() {
+            let snapshot = std::mem::take(chunks)
+                .into_iter()
+                .flatten()
+                .flatten()
+                .collect();
+            self.reconnect.resync = Some(Resync::Received { tick, snapshot });
+        }
+
+        self.connection.send(&Message::SnapshotAck {
+            tick,
+            index: next_index as u16,
+        })
+    }
+
+    /// Handle the acknowledgement of the chunks of the snapshot that is sent to the remote peer.
+    /// The resync is complete once all chunks are acknowledged.
+    pub(super) fn ack_snapshot_chunks(&mut self, tick: u64, index: u16) {
+        if let Some(Resync::Sending(transfer)) = &mut self.reconnect.resync {
+            if transfer.ack(tick, index) {
+                self.reconnect.resync = None;
+            }
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! Prediction of missing remote input, for `NetcodeMode::Rollback`. The predictions are checked
+//! against the input that is received later, and the first mispredicted tick is reported, for
+//! `Game` to roll back to.
+
+use std::collections::{BTreeMap, HashMap};
+
+use hecs::World;
+
+use crate::player::{Player, PlayerController};
+use crate::GameInput;
+
+use super::LockstepSession;
+
+pub(super) struct Predictions {
+    pub max_rollback_ticks: u64,
+    /// The input that was predicted for remote players, by tick, for ticks that the actual input
+    /// has not yet been received for
+    pub inputs: BTreeMap<u64, HashMap<u8, GameInput>>,
+    /// The first tick where received input did not match the prediction
+    pub misprediction: Option<u64>,
+}
+
+impl Predictions {
+    pub fn new(max_rollback_ticks: u64) -> Self {
+        Predictions {
+            max_rollback_ticks,
+            inputs: BTreeMap::new(),
+            misprediction: None,
+        }
+    }
+
+    pub fn insert(&mut self, tick: u64, player: u8, input: GameInput) {
+        self.inputs.entry(tick).or_default().insert(player, input);
+    }
+
+    /// Check the received input of `player` for `tick` against the input that was predicted for
+    /// it, if any
+    pub fn check(&mut self, tick: u64, player: u8, input: GameInput) {
+        let prediction = self
+            .inputs
+            .get_mut(&tick)
+            .and_then(|predictions| predictions.remove(&player));
+
+        if matches!(prediction, Some(prediction) if prediction != input) {
+            self.misprediction = Some(self.misprediction.map_or(tick, |other| other.min(tick)));
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.inputs.clear();
+        self.misprediction = None;
+    }
+}
+
+impl LockstepSession {
+    /// Returns the first tick, since this was last called, where the received input of a remote
+    /// player did not match the input that was predicted for it
+    pub fn take_misprediction(&mut self) -> Option<u64> {
+        self.predictions.misprediction.take()
+    }
+
+    /// Apply the input for `tick` to the player controllers. Missing remote input is predicted
+    /// to be the same as the last input that was received for the player.
+    /// Returns `false` if local input is missing, or if `tick` is too far ahead of the last
+    /// confirmed tick, in which case the simulation must be stalled.
+    pub fn apply_input(&mut self, tick: u64, world: &mut World) -> bool {
+        let is_ready = self.reconnect.resync.is_none()
+            && tick < self.confirmed_tick() + self.predictions.max_rollback_ticks
+            && self
+                .inputs
+                .local
+                .values()
+                .all(|buffer| buffer.get(tick).is_some());
+
+        if !is_ready {
+            self.inputs.stalled_tick_cnt += 1;
+            return false;
+        }
+
+        for (_, (player, controller)) in world.query_mut::<(&Player, &mut PlayerController)>() {
+            if let Some(buffer) = self.inputs.local.get(&player.index) {
+                controller.apply_input(buffer.get(tick).unwrap());
+            } else if let Some(buffer) = self.inputs.remote.get(&player.index) {
+                let input = match buffer.get(tick) {
+                    Some(input) => input,
+                    None => {
+                        let input = buffer
+                            .next_missing_tick()
+                            .checked_sub(1)
+                            .and_then(|tick| buffer.get(tick))
+                            .unwrap_or_default();
+
+                        self.predictions.insert(tick, player.index, input);
+
+                        input
+                    }
+                };
+
+                controller.apply_input(input);
+            }
+        }
+
+        true
+    }
+
+    /// Discard all input and predictions that can no longer be rolled back to, when `tick` is
+    /// the next tick to be simulated. The input for the last confirmed tick is kept, as it is
+    /// used for predictions.
+    pub fn discard_confirmed(&mut self, tick: u64) {
+        self.keep_spectated_input(self.confirmed_tick().min(tick));
+
+        let discard_tick = self.confirmed_tick().min(tick).saturating_sub(1);
+
+        for buffer in self.inputs.remote.values_mut() {
+            buffer.discard_before(discard_tick);
+        }
+
+        let local_discard_tick = self.inputs.remote_ack.min(discard_tick);
+        for buffer in self.inputs.local.values_mut() {
+            buffer.discard_before(local_discard_tick);
+        }
+
+        self.predictions.inputs = self.predictions.inputs.split_off(&discard_tick);
+    }
+}
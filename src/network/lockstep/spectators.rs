@@ -0,0 +1,407 @@
+//! Spectators, which join the match while it is running. The host sends every spectator a
+//! snapshot, followed by the confirmed input of all players, from the tick of the snapshot. A
+//! spectator can ask for the slots of the players of a remote peer that has been removed, in
+//! which case it becomes the remote peer.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::net::SocketAddr;
+
+use macroquad::miniquad::date;
+
+use core::error::ErrorKind;
+use core::network::{DisconnectReason, HandshakeInfo, Message, MAX_INPUTS_PER_MESSAGE};
+use core::{formaterr, Result};
+
+use crate::game::NetcodeMode;
+use crate::GameInput;
+
+use super::{
+    InputBuffer, LockstepSession, PeerStatus, Resync, SnapshotTransfer, DISCONNECT_TIMEOUT,
+};
+
+/// The maximum amount of spectators that the host accepts
+const MAX_SPECTATORS: usize = 8;
+
+/// A client that watches the match, on the host
+pub(super) struct Spectator {
+    pub addr: SocketAddr,
+    /// This is set until `LockstepSession::send_spectator_snapshot` is called
+    is_snapshot_required: bool,
+    /// The snapshot that is being sent, until all of it has been acknowledged
+    snapshot: Option<SnapshotTransfer>,
+    /// All input before this tick has been acknowledged
+    ack_tick: u64,
+    last_receive_time: f64,
+    /// This is set if the spectator wants to take the slot of a player that has left
+    is_slot_requested: bool,
+}
+
+impl Spectator {
+    fn new(addr: SocketAddr) -> Self {
+        Spectator {
+            addr,
+            is_snapshot_required: true,
+            snapshot: None,
+            ack_tick: 0,
+            last_receive_time: date::now(),
+            is_slot_requested: false,
+        }
+    }
+}
+
+pub(super) struct Spectators {
+    pub peers: Vec<Spectator>,
+    /// The confirmed input of all players, for every tick from `start_tick`, that has not been
+    /// acknowledged by every spectator
+    pub inputs: VecDeque<Vec<(u8, GameInput)>>,
+    pub start_tick: u64,
+    /// The confirmed input has been added to `inputs` for every tick before this
+    pub next_tick: u64,
+    /// The indices of the players of a remote peer that has been removed, on the host
+    pub open_slots: Vec<u8>,
+    /// The slots that were given to the remote peer, which are sent to it until it has received
+    /// its snapshot, as it joined as a spectator
+    pub granted_slots: Option<Vec<u8>>,
+    /// This is set on a spectator that wants to take an open slot
+    pub is_slot_requested: bool,
+}
+
+impl Spectators {
+    pub fn new() -> Self {
+        Spectators {
+            peers: Vec::new(),
+            inputs: VecDeque::new(),
+            start_tick: 0,
+            next_tick: 0,
+            open_slots: Vec::new(),
+            granted_slots: None,
+            is_slot_requested: false,
+        }
+    }
+
+    pub fn contains(&self, addr: SocketAddr) -> bool {
+        self.peers.iter().any(|spectator| spectator.addr == addr)
+    }
+
+    /// Make every spectator continue from a new snapshot of `tick`, discarding the input that was
+    /// kept for them
+    pub fn require_snapshots(&mut self, tick: u64) {
+        for spectator in &mut self.peers {
+            spectator.is_snapshot_required = true;
+            spectator.snapshot = None;
+        }
+
+        self.inputs.clear();
+        self.start_tick = tick;
+        self.next_tick = tick;
+    }
+}
+
+impl LockstepSession {
+    /// Make a session without players a spectator, that waits for a snapshot from the host.
+    /// Spectators only apply confirmed input, so they never roll back.
+    pub(super) fn into_spectator(mut self) -> Self {
+        self.is_spectator = true;
+        self.netcode = NetcodeMode::Lockstep;
+        self.reconnect.resync = Some(Resync::Awaiting);
+
+        self
+    }
+
+    /// The amount of spectators that are connected to the host
+    pub fn spectator_cnt(&self) -> usize {
+        self.spectators.peers.len()
+    }
+
+    /// Returns the tick that spectators that have joined need a snapshot of, if any. The
+    /// snapshot has to be provided through `send_spectator_snapshot`.
+    pub fn spectator_snapshot_tick(&self) -> Option<u64> {
+        self.spectators
+            .peers
+            .iter()
+            .any(|spectator| spectator.is_snapshot_required)
+            .then_some(self.spectators.next_tick)
+    }
+
+    /// Send the snapshot of the game at the start of `tick`, which must be the tick returned by
+    /// `spectator_snapshot_tick`, to the spectators that need one
+    pub fn send_spectator_snapshot(&mut self, tick: u64, snapshot: &[u8]) -> Result<()> {
+        if tick != self.spectators.next_tick {
+            return Err(formaterr!(
+                ErrorKind::Network,
+                "Spectators need a snapshot of tick {}, not of tick {}",
+                self.spectators.next_tick,
+                tick
+            ));
+        }
+
+        for spectator in &mut self.spectators.peers {
+            if spectator.is_snapshot_required {
+                spectator.is_snapshot_required = false;
+                spectator.snapshot = Some(SnapshotTransfer::new(tick, snapshot)?);
+                spectator.ack_tick = tick;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Set the players of the match, once the snapshot sent by the host has been restored, on a
+    /// client. All players that are not local are remote. This is required on a client that
+    /// joined as a spectator, as it does not know the players of the match until then.
+    pub fn set_players(&mut self, players: &[u8]) {
+        let tick = self.reconnect.last_resync_tick.unwrap_or_default();
+
+        self.inputs.remote = players
+            .iter()
+            .filter(|index| !self.inputs.local.contains_key(index))
+            .map(|&index| {
+                let mut buffer = InputBuffer::new();
+                buffer.reset(tick);
+
+                (index, buffer)
+            })
+            .collect();
+    }
+
+    /// Ask the host for the slot of a player that has left the match, on a spectator. The
+    /// request stays open until it is granted.
+    pub fn request_slot(&mut self) {
+        self.spectators.is_slot_requested = self.is_spectator;
+    }
+
+    pub fn is_slot_requested(&self) -> bool {
+        self.spectators.is_slot_requested
+    }
+
+    /// The indices of the players that have been removed from the match, along with the remote
+    /// peer, on the host
+    pub fn open_slots(&self) -> &[u8] {
+        &self.spectators.open_slots
+    }
+
+    /// Give the open slots to the first spectator that has asked for them, making it the remote
+    /// peer, if there are any. Returns the indices of the players of the slots, which have to be
+    /// spawned again before the snapshot, that the new peer continues from, is sent.
+    pub fn grant_open_slots(&mut self) -> Option<Vec<u8>> {
+        if self.spectators.open_slots.is_empty() || self.peer_status() == PeerStatus::Reconnecting {
+            return None;
+        }
+
+        let i = self
+            .spectators
+            .peers
+            .iter()
+            .position(|spectator| spectator.is_slot_requested)?;
+
+        let spectator = self.spectators.peers.remove(i);
+        let players = std::mem::take(&mut self.spectators.open_slots);
+
+        self.connection.peer = Some(spectator.addr);
+        self.connection.is_connected = true;
+        self.connection.disconnect_reason = None;
+        self.connection.last_receive_time = Some(spectator.last_receive_time);
+
+        self.inputs.remote = players
+            .iter()
+            .map(|&index| (index, InputBuffer::new()))
+            .collect();
+
+        self.chat.reset();
+
+        self.spectators.granted_slots = Some(players.clone());
+        self.reconnect.resync = Some(Resync::Requested);
+
+        Some(players)
+    }
+
+    /// Keep the confirmed input of every tick before `tick`, that has not yet been kept, for the
+    /// spectators, and discard the input that every spectator has acknowledged
+    pub(super) fn keep_spectated_input(&mut self, tick: u64) {
+        if !self.is_host {
+            return;
+        }
+
+        while self.spectators.next_tick < tick {
+            let inputs = match self.confirmed_input(self.spectators.next_tick) {
+                Some(inputs) => inputs,
+                None => break,
+            };
+
+            if !self.spectators.peers.is_empty() {
+                self.spectators.inputs.push_back(inputs);
+            }
+
+            self.spectators.next_tick += 1;
+        }
+
+        let discard_tick = self
+            .spectators
+            .peers
+            .iter()
+            .filter(|spectator| !spectator.is_snapshot_required)
+            .map(|spectator| spectator.ack_tick)
+            .min()
+            .unwrap_or(self.spectators.next_tick);
+
+        while self.spectators.start_tick < discard_tick {
+            if self.spectators.inputs.pop_front().is_none() {
+                self.spectators.start_tick = self.spectators.next_tick;
+                break;
+            }
+
+            self.spectators.start_tick += 1;
+        }
+    }
+
+    /// Accept the spectator at `addr`, if its handshake matches the local one and there is room
+    /// for it. The handshake is sent until it is answered, so it might be received again.
+    pub(super) fn handle_spectate(
+        &mut self,
+        addr: SocketAddr,
+        remote: HandshakeInfo,
+    ) -> Result<()> {
+        if !self.spectators.contains(addr) {
+            if let Some(reason) = self.handshake.info.check(&remote) {
+                #[cfg(debug_assertions)]
+                println!(
+                    "WARNING: Refused spectator '{}' (version {}): {}",
+                    addr, remote.game_version, reason
+                );
+
+                return self.connection.refuse(addr, reason);
+            }
+
+            if self.spectators.peers.len() >= MAX_SPECTATORS || self.connection.peer == Some(addr) {
+                return self.connection.refuse(addr, DisconnectReason::SessionFull);
+            }
+
+            self.spectators.peers.push(Spectator::new(addr));
+
+            // Tell the new spectator about the successor right away, as the host might leave
+            // before it would otherwise be told
+            self.migration.last_send_time = f64::NEG_INFINITY;
+        }
+
+        self.connection
+            .send_to(&self.handshake.welcome_message(), addr)
+    }
+
+    /// Handle a message from the spectator at `addr`, on the host
+    pub(super) fn handle_spectator_message(
+        &mut self,
+        addr: SocketAddr,
+        message: Message,
+    ) -> Result<()> {
+        let i = match self
+            .spectators
+            .peers
+            .iter()
+            .position(|spectator| spectator.addr == addr)
+        {
+            Some(i) => i,
+            None => return Ok(()),
+        };
+
+        let spectator = &mut self.spectators.peers[i];
+        spectator.last_receive_time = date::now();
+
+        match message {
+            Message::Ack { tick } if spectator.snapshot.is_none() => {
+                spectator.ack_tick = spectator.ack_tick.max(tick);
+            }
+            Message::SnapshotAck { tick, index } => {
+                if let Some(snapshot) = &mut spectator.snapshot {
+                    if snapshot.ack(tick, index) {
+                        spectator.snapshot = None;
+                    }
+                }
+            }
+            Message::SlotRequest => spectator.is_slot_requested = true,
+            Message::Ping { time } => {
+                self.connection.send_to(&Message::Pong { time }, addr)?;
+            }
+            Message::Disconnect { .. } => {
+                self.spectators.peers.remove(i);
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Make a spectator the remote peer, controlling the players of the granted slots. The
+    /// spectator continues from the snapshot that the host sends after granting the slots, which
+    /// might already have been restored, if this is received late.
+    pub(super) fn take_granted_slots(&mut self, players: Vec<u8>) {
+        if !self.is_spectator {
+            return;
+        }
+
+        self.is_spectator = false;
+        self.spectators.is_slot_requested = false;
+
+        let tick = self.reconnect.last_resync_tick.unwrap_or_default();
+
+        for index in players {
+            let mut buffer = InputBuffer::new();
+            buffer.reset(tick);
+
+            self.inputs.remote.remove(&index);
+            self.inputs.local.insert(index, buffer);
+        }
+
+        self.inputs.next_local_tick = tick;
+
+        if self.reconnect.resync.is_none() {
+            self.reconnect.resync = Some(Resync::Awaiting);
+        }
+    }
+
+    /// Send the snapshot, or the confirmed input that has not been acknowledged, to every
+    /// spectator, on the host, and drop the spectators that have not been heard from in a while
+    pub(super) fn send_to_spectators(&mut self, now: f64) -> Result<()> {
+        self.spectators
+            .peers
+            .retain(|spectator| now - spectator.last_receive_time < DISCONNECT_TIMEOUT);
+
+        let successor = self.successor_message(now);
+
+        for spectator in &mut self.spectators.peers {
+            let mut messages = successor.iter().cloned().collect::<Vec<_>>();
+
+            if let Some(snapshot) = &mut spectator.snapshot {
+                snapshot.push_chunks(now, &mut messages);
+            } else if !spectator.is_snapshot_required {
+                let start_tick = spectator.ack_tick.max(self.spectators.start_tick);
+                let mut inputs = BTreeMap::<u8, Vec<_>>::new();
+
+                for tick_inputs in self
+                    .spectators
+                    .inputs
+                    .iter()
+                    .skip((start_tick - self.spectators.start_tick) as usize)
+                    .take(MAX_INPUTS_PER_MESSAGE)
+                {
+                    for (player, input) in tick_inputs {
+                        inputs.entry(*player).or_default().push(input.to_bits());
+                    }
+                }
+
+                for (player, inputs) in inputs {
+                    messages.push(Message::Input {
+                        player,
+                        start_tick,
+                        inputs,
+                    });
+                }
+            }
+
+            for message in &messages {
+                self.connection.send_to(message, spectator.addr)?;
+            }
+        }
+
+        Ok(())
+    }
+}
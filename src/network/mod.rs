@@ -0,0 +1,77 @@
+//! This module holds the networking core, used by network games.
+//! Matches are played using delayed lockstep, implemented by `LockstepSession`, over one of the
+//! transports in `transport`. Games on the local network can be found without a lobby service,
+//! through `discovery`, and peers that can not reach each other directly can play through a
+//! `relay`.
+
+mod discovery;
+mod lobby_server;
+mod lockstep;
+mod relay;
+mod transport;
+
+pub use discovery::{
+    LanAnnouncement, LanAnnouncer, LanBrowser, ANNOUNCE_INTERVAL, DISCOVERY_PORT, HOST_TIMEOUT,
+};
+pub use lobby_server::LobbyServer;
+pub use lockstep::{
+    InputBuffer, LockstepSession, PeerStatus, DISCONNECT_TIMEOUT, MAX_INPUT_DELAY, MIN_INPUT_DELAY,
+};
+pub use relay::{
+    HostTokens, Relay, RelayConfig, RelayServer, RelayTransport, RELAY_FALLBACK_TIMEOUT,
+};
+pub use transport::{LinkConditions, LoopbackNetwork, LoopbackTransport, Transport, UdpTransport};
+
+use std::net::SocketAddr;
+
+use macroquad::experimental::collections::storage;
+
+use core::network::{fnv_hash, Api, HandshakeInfo, HttpApiBackend};
+use core::Result;
+
+use crate::{Map, Resources};
+
+/// The version of the game. Peers will only connect to peers running the same version.
+pub const GAME_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Create the handshake info of a network game played on `map`, with the mods that are loaded.
+/// The seed and settings hash are set by `LockstepSession`, on the host, and are adopted from the
+/// host, on a client.
+pub fn local_handshake(map: &Map) -> Result<HandshakeInfo> {
+    let mods = storage::try_get::<Resources>()
+        .map(|resources| {
+            resources
+                .loaded_mods
+                .iter()
+                .map(|meta| format!("{}@{}\n", meta.id, meta.version))
+                .collect::<String>()
+        })
+        .unwrap_or_default();
+
+    // The map is hashed as a JSON value, as the keys of those are sorted
+    let map = serde_json::to_value(map)?.to_string();
+
+    Ok(HandshakeInfo {
+        game_version: GAME_VERSION.to_string(),
+        mods_hash: fnv_hash(mods.as_bytes()),
+        map_hash: fnv_hash(map.as_bytes()),
+        seed: None,
+        settings_hash: None,
+    })
+}
+
+#[cfg(feature = "ultimate")]
+pub async fn init_api(token: &str) -> Result<()> {
+    Api::init::<ultimate::UltimateApiBackend>(token).await
+}
+
+#[cfg(not(feature = "ultimate"))]
+pub async fn init_api(token: &str) -> Result<()> {
+    Api::init::<core::network::MockApiBackend>(token).await
+}
+
+/// Init the api with a backend that talks to the lobby service at `address`, like one run by
+/// `fishfight-lobby-server`
+pub async fn init_http_api(address: SocketAddr, token: &str) -> Result<()> {
+    Api::init_with(HttpApiBackend::new(address), token).await
+}
@@ -0,0 +1,562 @@
+//! A relay, that forwards the datagrams of a network game between peers that can not reach each
+//! other directly, like when the host is behind a NAT that does not allow incoming datagrams.
+//! `RelayServer` runs a `Relay` on a UDP socket, either on its own, through
+//! `fishfight-relay-server`, or on the UDP socket of a `LobbyServer`. Datagrams that are not relay
+//! packets are dropped, so that the relay can not be used to reflect traffic at others, and
+//! clients measure their latency to it with `RelayPacket::Ping`, in stead.
+//!
+//! Peers use a relay through `RelayTransport`, which wraps the transport of a `LockstepSession`.
+//! The host registers the session with the relay, and accepts datagrams both directly and through
+//! the relay, while a client only falls back to the relay if nothing is received from the host
+//! directly for `RELAY_FALLBACK_TIMEOUT`, unless the relay is forced. Both report datagrams that
+//! were relayed as sent by the actual sender, so the session does not have to know about it.
+//!
+//! Sessions are identified by an id that the peers agree on, like `Lobby::relay_session`, and only
+//! a peer that has the host token of a session can register as its host. A relay that runs on a
+//! lobby service checks the token against the one that the service gave to the admin of the lobby,
+//! when the game was started, while a relay that runs on its own lets the first peer to register
+//! a session claim it, and then only accepts the same token, until the session expires.
+//! Host migration is not supported through a relay, as the client that takes over does not have
+//! the host token of the session.
+
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use macroquad::miniquad::date;
+
+use core::error::{Error, ErrorKind};
+use core::network::{RelayPacket, MAX_RELAY_OVERHEAD};
+use core::Result;
+
+use super::transport::{is_transient, Transport};
+
+/// If nothing is received from the host of a session, or from one of its members, for this
+/// long, it is removed from the relay
+const SESSION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The maximum amount of sessions that a relay holds at once
+const MAX_SESSIONS: usize = 1024;
+
+/// The maximum amount of peers, other than the host, that a session can hold
+const MAX_SESSION_MEMBERS: usize = 16;
+
+/// The interval, in seconds, at which the host registers with the relay
+const REGISTER_INTERVAL: f64 = 1.0;
+
+/// If a client has not received anything from the host directly for this many seconds, it
+/// falls back to the relay
+pub const RELAY_FALLBACK_TIMEOUT: f64 = 3.0;
+
+const MAX_DATAGRAM_SIZE: usize = 1024 + MAX_RELAY_OVERHEAD;
+
+/// The relay that a network game uses, if any
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct RelayConfig {
+    /// The address of the relay server
+    pub server: SocketAddr,
+    /// The id of the session on the relay, which must be the same for all peers of the game
+    pub session: u64,
+    /// If this is `true`, a client sends everything through the relay, in stead of trying to
+    /// reach the host directly first
+    pub is_forced: bool,
+    /// The token that the host registers the session with, which is only set on the host
+    pub host_token: Option<u64>,
+}
+
+/// Returns the host token of a session, as given out by a lobby service, if the session is known
+pub type HostTokens = Box<dyn Fn(u64) -> Option<u64> + Send>;
+
+struct RelaySession {
+    host: SocketAddr,
+    host_token: u64,
+    last_register_time: Instant,
+    /// The peers that have sent datagrams to the host, by the time that they last did
+    members: HashMap<SocketAddr, Instant>,
+}
+
+/// The state of a relay, which is independent of the socket that it runs on
+pub struct Relay {
+    sessions: HashMap<u64, RelaySession>,
+    /// The host tokens given out by a lobby service, if the relay runs on one
+    host_tokens: Option<HostTokens>,
+}
+
+impl Relay {
+    /// Create a relay where the first peer to register a session claims it
+    pub fn new() -> Self {
+        Relay {
+            sessions: HashMap::new(),
+            host_tokens: None,
+        }
+    }
+
+    /// Create a relay where peers can only register a session with the token returned by
+    /// `host_tokens`
+    pub fn with_host_tokens(host_tokens: HostTokens) -> Self {
+        Relay {
+            sessions: HashMap::new(),
+            host_tokens: Some(host_tokens),
+        }
+    }
+
+    /// Handle a datagram received from `from`, returning the datagram to send in response, and
+    /// where to send it, if any. Datagrams that are not relay packets are dropped.
+    pub fn handle(
+        &mut self,
+        bytes: &[u8],
+        from: SocketAddr,
+        now: Instant,
+    ) -> Option<(Vec<u8>, SocketAddr)> {
+        if !RelayPacket::is_relay_packet(bytes) {
+            return None;
+        }
+
+        self.remove_expired(now);
+
+        match RelayPacket::decode(bytes).ok()? {
+            RelayPacket::Register { session, token } => {
+                let is_authorized = match &self.host_tokens {
+                    Some(host_tokens) => host_tokens(session) == Some(token),
+                    None => self
+                        .sessions
+                        .get(&session)
+                        .map(|session| session.host_token == token)
+                        .unwrap_or(true),
+                };
+
+                if !is_authorized {
+                    return None;
+                }
+
+                if !self.sessions.contains_key(&session) && self.sessions.len() >= MAX_SESSIONS {
+                    return None;
+                }
+
+                let session = self
+                    .sessions
+                    .entry(session)
+                    .or_insert_with(|| RelaySession {
+                        host: from,
+                        host_token: token,
+                        last_register_time: now,
+                        members: HashMap::new(),
+                    });
+
+                session.host = from;
+                session.host_token = token;
+                session.last_register_time = now;
+                session.members.remove(&from);
+
+                None
+            }
+            RelayPacket::Send {
+                session,
+                to: Some(to),
+                payload,
+            } => {
+                let session = self.sessions.get(&session)?;
+
+                if session.host != from || !session.members.contains_key(&to) {
+                    return None;
+                }
+
+                let packet = RelayPacket::Forward {
+                    from,
+                    is_from_host: true,
+                    payload,
+                };
+
+                Some((packet.encode(), to))
+            }
+            RelayPacket::Send {
+                session,
+                to: None,
+                payload,
+            } => {
+                let session = self.sessions.get_mut(&session)?;
+
+                if session.host == from {
+                    return None;
+                }
+
+                if !session.members.contains_key(&from)
+                    && session.members.len() >= MAX_SESSION_MEMBERS
+                {
+                    return None;
+                }
+
+                session.members.insert(from, now);
+
+                let packet = RelayPacket::Forward {
+                    from,
+                    is_from_host: false,
+                    payload,
+                };
+
+                Some((packet.encode(), session.host))
+            }
+            RelayPacket::Ping { nonce } => Some((RelayPacket::Pong { nonce }.encode(), from)),
+            RelayPacket::Forward { .. } | RelayPacket::Pong { .. } => None,
+        }
+    }
+
+    /// The amount of sessions that are held by the relay
+    pub fn session_cnt(&self) -> usize {
+        self.sessions.len()
+    }
+
+    fn remove_expired(&mut self, now: Instant) {
+        self.sessions.retain(|_, session| {
+            session
+                .members
+                .retain(|_, last_time| now.duration_since(*last_time) < SESSION_TIMEOUT);
+
+            now.duration_since(session.last_register_time) < SESSION_TIMEOUT
+        });
+    }
+}
+
+impl Default for Relay {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Runs a `Relay` on a UDP socket
+pub struct RelayServer {
+    socket: UdpSocket,
+    relay: Relay,
+}
+
+impl RelayServer {
+    /// Bind the relay to `port` on `ip`. If `port` is `0`, it is bound to an unused port.
+    pub fn bind(ip: IpAddr, port: u16) -> Result<Self> {
+        let socket =
+            UdpSocket::bind((ip, port)).map_err(|err| Error::new(ErrorKind::Network, err))?;
+
+        Ok(Self::from_socket(socket))
+    }
+
+    /// Run the relay on a socket that is already bound
+    pub fn from_socket(socket: UdpSocket) -> Self {
+        Self::with_relay(socket, Relay::new())
+    }
+
+    /// Run `relay` on a socket that is already bound
+    pub fn with_relay(socket: UdpSocket, relay: Relay) -> Self {
+        RelayServer { socket, relay }
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        self.socket
+            .local_addr()
+            .map_err(|err| Error::new(ErrorKind::Network, err))
+    }
+
+    /// Run the relay until the socket fails. This blocks the calling thread.
+    pub fn run(mut self) -> Result<()> {
+        let mut buf = [0; MAX_DATAGRAM_SIZE];
+
+        loop {
+            let (len, from) = match self.socket.recv_from(&mut buf) {
+                Ok(res) => res,
+                Err(err) if is_transient(&err) => continue,
+                Err(err) => return Err(Error::new(ErrorKind::Network, err)),
+            };
+
+            if let Some((bytes, to)) = self.relay.handle(&buf[..len], from, Instant::now()) {
+                if let Err(err) = self.socket.send_to(&bytes, to) {
+                    if !is_transient(&err) {
+                        return Err(Error::new(ErrorKind::Network, err));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A transport that sends datagrams through a relay, when the remote peer can not be reached
+/// directly. Datagrams are exchanged directly over the wrapped transport otherwise.
+pub struct RelayTransport {
+    transport: Box<dyn Transport>,
+    relay: RelayConfig,
+    /// The address of the host, on a client
+    host: Option<SocketAddr>,
+    /// The peers that datagrams were received from through the relay, on the host. Datagrams
+    /// sent to these are relayed as well.
+    relayed_peers: HashSet<SocketAddr>,
+    /// This is set, on a client, when it sends everything through the relay
+    is_relayed: bool,
+    /// This is set, on a client, when a datagram has been received from the host directly
+    has_direct_contact: bool,
+    start_time: f64,
+    last_register_time: Option<f64>,
+    buf: Vec<u8>,
+}
+
+impl RelayTransport {
+    /// Wrap the transport of the host of a session
+    pub fn host(transport: Box<dyn Transport>, relay: RelayConfig) -> Self {
+        Self::new(transport, relay, None)
+    }
+
+    /// Wrap the transport of a client of the session hosted at `host`
+    pub fn client(transport: Box<dyn Transport>, relay: RelayConfig, host: SocketAddr) -> Self {
+        Self::new(transport, relay, Some(host))
+    }
+
+    fn new(transport: Box<dyn Transport>, relay: RelayConfig, host: Option<SocketAddr>) -> Self {
+        RelayTransport {
+            transport,
+            relay,
+            host,
+            relayed_peers: HashSet::new(),
+            is_relayed: host.is_some() && relay.is_forced,
+            has_direct_contact: false,
+            start_time: date::now(),
+            last_register_time: None,
+            buf: vec![0; MAX_DATAGRAM_SIZE],
+        }
+    }
+
+    /// Returns `true` if datagrams to the host are sent through the relay, on a client
+    pub fn is_relayed(&self) -> bool {
+        self.is_relayed
+    }
+
+    /// Register with the relay at an interval, on the host, and fall back to the relay, if the
+    /// host has not been reached directly in time, on a client
+    fn update(&mut self) -> Result<()> {
+        let now = date::now();
+
+        if self.host.is_none() {
+            let is_due = self
+                .last_register_time
+                .map(|time| now - time >= REGISTER_INTERVAL)
+                .unwrap_or(true);
+
+            // The relay would not accept the session without the host token
+            if let Some(token) = self.relay.host_token.filter(|_| is_due) {
+                self.last_register_time = Some(now);
+
+                let packet = RelayPacket::Register {
+                    session: self.relay.session,
+                    token,
+                };
+
+                self.transport
+                    .send_to(&packet.encode(), self.relay.server)?;
+            }
+        } else if !self.is_relayed
+            && !self.has_direct_contact
+            && now - self.start_time >= RELAY_FALLBACK_TIMEOUT
+        {
+            #[cfg(debug_assertions)]
+            println!("WARNING: The host can not be reached directly, falling back to the relay");
+
+            self.is_relayed = true;
+        }
+
+        Ok(())
+    }
+}
+
+impl Transport for RelayTransport {
+    fn send_to(&mut self, bytes: &[u8], addr: SocketAddr) -> Result<()> {
+        self.update()?;
+
+        let to = if self.host == Some(addr) && self.is_relayed {
+            None
+        } else if self.relayed_peers.contains(&addr) {
+            Some(addr)
+        } else {
+            return self.transport.send_to(bytes, addr);
+        };
+
+        let packet = RelayPacket::Send {
+            session: self.relay.session,
+            to,
+            payload: bytes.to_vec(),
+        };
+
+        self.transport.send_to(&packet.encode(), self.relay.server)
+    }
+
+    fn recv_from(&mut self, buf: &mut [u8]) -> Result<Option<(usize, SocketAddr)>> {
+        self.update()?;
+
+        while let Some((len, from)) = self.transport.recv_from(&mut self.buf)? {
+            let bytes = &self.buf[..len];
+
+            if from != self.relay.server {
+                if Some(from) == self.host {
+                    self.has_direct_contact = true;
+                }
+
+                let len = len.min(buf.len());
+                buf[..len].copy_from_slice(&bytes[..len]);
+
+                return Ok(Some((len, from)));
+            }
+
+            // Anything else from the relay, like the answer to a ping, is dropped
+            let (from, payload) = match RelayPacket::decode(bytes) {
+                Ok(RelayPacket::Forward {
+                    from,
+                    is_from_host,
+                    payload,
+                }) => match self.host {
+                    Some(host) if is_from_host => (host, payload),
+                    None if !is_from_host => {
+                        self.relayed_peers.insert(from);
+                        (from, payload)
+                    }
+                    _ => continue,
+                },
+                _ => continue,
+            };
+
+            let len = payload.len().min(buf.len());
+            buf[..len].copy_from_slice(&payload[..len]);
+
+            return Ok(Some((len, from)));
+        }
+
+        Ok(None)
+    }
+
+    fn local_addr(&self) -> Result<SocketAddr> {
+        self.transport.local_addr()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::network::{LoopbackNetwork, LoopbackTransport};
+
+    /// Pass all datagrams that have been sent to the relay through `relay`
+    fn update_relay(relay: &mut Relay, transport: &mut LoopbackTransport) {
+        let mut buf = [0; MAX_DATAGRAM_SIZE];
+
+        while let Some((len, from)) = transport.recv_from(&mut buf).unwrap() {
+            if let Some((bytes, to)) = relay.handle(&buf[..len], from, Instant::now()) {
+                transport.send_to(&bytes, to).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_forced_relay() {
+        let network = LoopbackNetwork::new(0);
+
+        let mut relay = Relay::new();
+        let mut relay_transport = network.bind_any().unwrap();
+
+        let config = RelayConfig {
+            server: relay_transport.local_addr().unwrap(),
+            session: 1,
+            is_forced: true,
+            host_token: None,
+        };
+
+        let host_config = RelayConfig {
+            host_token: Some(1),
+            ..config
+        };
+
+        let mut host = RelayTransport::host(Box::new(network.bind_any().unwrap()), host_config);
+        let host_addr = host.local_addr().unwrap();
+
+        let mut client =
+            RelayTransport::client(Box::new(network.bind_any().unwrap()), config, host_addr);
+        let client_addr = client.local_addr().unwrap();
+
+        let mut buf = [0; 16];
+
+        // The host registers the session on its first update
+        assert_eq!(host.recv_from(&mut buf).unwrap(), None);
+        update_relay(&mut relay, &mut relay_transport);
+        assert_eq!(relay.session_cnt(), 1);
+
+        client.send_to(&[1], host_addr).unwrap();
+        update_relay(&mut relay, &mut relay_transport);
+
+        assert_eq!(host.recv_from(&mut buf).unwrap(), Some((1, client_addr)));
+        assert_eq!(buf[0], 1);
+
+        host.send_to(&[2], client_addr).unwrap();
+        update_relay(&mut relay, &mut relay_transport);
+
+        // The reply is reported as sent by the host, even though it came from the relay
+        assert_eq!(client.recv_from(&mut buf).unwrap(), Some((1, host_addr)));
+        assert_eq!(buf[0], 2);
+
+        // Only the host can send to the members of a session
+        let mut other = network.bind_any().unwrap();
+        let packet = RelayPacket::Send {
+            session: 1,
+            to: Some(client_addr),
+            payload: vec![3],
+        };
+
+        other.send_to(&packet.encode(), config.server).unwrap();
+        update_relay(&mut relay, &mut relay_transport);
+
+        assert_eq!(client.recv_from(&mut buf).unwrap(), None);
+
+        // Nor can another peer take over the session without the host token
+        let packet = RelayPacket::Register {
+            session: 1,
+            token: 2,
+        };
+
+        other.send_to(&packet.encode(), config.server).unwrap();
+        update_relay(&mut relay, &mut relay_transport);
+
+        client.send_to(&[4], host_addr).unwrap();
+        update_relay(&mut relay, &mut relay_transport);
+
+        assert_eq!(host.recv_from(&mut buf).unwrap(), Some((1, client_addr)));
+        assert_eq!(buf[0], 4);
+    }
+
+    #[test]
+    fn test_relay_host_tokens() {
+        let mut relay = Relay::with_host_tokens(Box::new(|session| (session == 1).then_some(7)));
+
+        let host = "127.0.0.1:9000".parse().unwrap();
+        let now = Instant::now();
+
+        let register = |session, token| RelayPacket::Register { session, token }.encode();
+
+        assert_eq!(relay.handle(&register(1, 8), host, now), None);
+        assert_eq!(relay.handle(&register(2, 7), host, now), None);
+        assert_eq!(
+            relay.session_cnt(),
+            0,
+            "Only the token of the session should be accepted"
+        );
+
+        relay.handle(&register(1, 7), host, now);
+        assert_eq!(relay.session_cnt(), 1);
+
+        assert_eq!(
+            relay.handle(&[1, 2, 3], host, now),
+            None,
+            "Datagrams that are not relay packets should not be answered"
+        );
+
+        let ping = RelayPacket::Ping { nonce: 3 }.encode();
+        let (pong, to) = relay.handle(&ping, host, now).unwrap();
+
+        assert_eq!(to, host);
+        assert_eq!(pong.len(), ping.len());
+        assert_eq!(
+            RelayPacket::decode(&pong).unwrap(),
+            RelayPacket::Pong { nonce: 3 }
+        );
+    }
+}
@@ -0,0 +1,372 @@
+//! The transports that datagrams are exchanged over, in a network game.
+//! `UdpTransport` is used by actual network games, while `LoopbackTransport` exchanges datagrams
+//! with other transports in the same process, through a `LoopbackNetwork`. The loopback network
+//! can simulate latency, jitter, packet loss and reordering, so that the netcode can be tested,
+//! and bad connections reproduced, without a real network.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use macroquad::miniquad::date;
+
+use core::error::{Error, ErrorKind};
+use core::rng::Rng;
+use core::{formaterr, Result};
+
+/// This is implemented by the transports that `LockstepSession` can exchange datagrams over.
+/// Implementations should never block.
+pub trait Transport {
+    /// Send `bytes`, as a single datagram, to `addr`. Datagrams might be lost on the way, so
+    /// failing to deliver one is not considered an error.
+    fn send_to(&mut self, bytes: &[u8], addr: SocketAddr) -> Result<()>;
+
+    /// Receive the next datagram into `buf`, returning its length and the address of the sender,
+    /// or `None` if no datagram is available
+    fn recv_from(&mut self, buf: &mut [u8]) -> Result<Option<(usize, SocketAddr)>>;
+
+    fn local_addr(&self) -> Result<SocketAddr>;
+}
+
+/// A transport over a non-blocking UDP socket
+pub struct UdpTransport {
+    socket: UdpSocket,
+}
+
+impl UdpTransport {
+    pub fn bind(addr: SocketAddr) -> Result<Self> {
+        let socket = UdpSocket::bind(addr).map_err(|err| Error::new(ErrorKind::Network, err))?;
+
+        socket
+            .set_nonblocking(true)
+            .map_err(|err| Error::new(ErrorKind::Network, err))?;
+
+        Ok(UdpTransport { socket })
+    }
+}
+
+impl Transport for UdpTransport {
+    fn send_to(&mut self, bytes: &[u8], addr: SocketAddr) -> Result<()> {
+        match self.socket.send_to(bytes, addr) {
+            Err(err) if !is_transient(&err) => Err(Error::new(ErrorKind::Network, err)),
+            _ => Ok(()),
+        }
+    }
+
+    fn recv_from(&mut self, buf: &mut [u8]) -> Result<Option<(usize, SocketAddr)>> {
+        loop {
+            match self.socket.recv_from(buf) {
+                Ok(res) => return Ok(Some(res)),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+                Err(err) if is_transient(&err) => continue,
+                Err(err) => return Err(Error::new(ErrorKind::Network, err)),
+            }
+        }
+    }
+
+    fn local_addr(&self) -> Result<SocketAddr> {
+        self.socket
+            .local_addr()
+            .map_err(|err| Error::new(ErrorKind::Network, err))
+    }
+}
+
+/// Errors that should not end the session. Connection errors can be reported by some platforms,
+/// for datagrams that did not reach the remote peer, like when it has not yet started.
+pub(super) fn is_transient(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::WouldBlock
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionRefused
+    )
+}
+
+/// The conditions that are simulated for datagrams sent over a `LoopbackNetwork`
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct LinkConditions {
+    /// The time, in seconds, it takes for a datagram to be delivered
+    pub latency: f64,
+    /// The maximum amount of seconds that is randomly added to, or subtracted from, the latency
+    /// of each datagram
+    pub jitter: f64,
+    /// The probability, from `0.0` to `1.0`, that a datagram is lost
+    pub loss: f32,
+    /// The probability, from `0.0` to `1.0`, that a datagram is held back by `reorder_delay`, so
+    /// that it is delivered after datagrams that were sent after it
+    pub reordering: f32,
+    /// The amount of seconds that reordered datagrams are held back by
+    pub reorder_delay: f64,
+}
+
+impl LinkConditions {
+    /// Conditions where every datagram is delivered immediately, in order
+    pub fn perfect() -> Self {
+        Self::default()
+    }
+}
+
+struct Datagram {
+    from: SocketAddr,
+    bytes: Vec<u8>,
+    delivery_time: f64,
+}
+
+struct LoopbackState {
+    rng: Rng,
+    conditions: LinkConditions,
+    /// Conditions that override `conditions` for datagrams sent from, and to, specific addresses
+    link_conditions: HashMap<(SocketAddr, SocketAddr), LinkConditions>,
+    /// The datagrams in flight, by the address of the receiving transport
+    queues: HashMap<SocketAddr, Vec<Datagram>>,
+    next_port: u16,
+}
+
+/// An in-process network that `LoopbackTransport`s are bound to. This is cheap to clone, as all
+/// clones share the same network. The simulated conditions are driven by a seeded `Rng`, so the
+/// same datagrams will be lost and reordered, given the same seed and the same timing.
+#[derive(Clone)]
+pub struct LoopbackNetwork {
+    state: Arc<Mutex<LoopbackState>>,
+}
+
+impl LoopbackNetwork {
+    /// The first port that is assigned to transports that are bound to port `0`
+    const FIRST_EPHEMERAL_PORT: u16 = 49152;
+
+    pub fn new(seed: u64) -> Self {
+        LoopbackNetwork {
+            state: Arc::new(Mutex::new(LoopbackState {
+                rng: Rng::new(seed),
+                conditions: LinkConditions::perfect(),
+                link_conditions: HashMap::new(),
+                queues: HashMap::new(),
+                next_port: Self::FIRST_EPHEMERAL_PORT,
+            })),
+        }
+    }
+
+    /// Set the conditions of all links that have not been given conditions with
+    /// `set_link_conditions`
+    pub fn set_conditions(&self, conditions: LinkConditions) {
+        self.lock().conditions = conditions;
+    }
+
+    /// Set the conditions for datagrams sent from `from` to `to`. This only applies in that
+    /// direction, so a link can be made asymmetric.
+    pub fn set_link_conditions(
+        &self,
+        from: SocketAddr,
+        to: SocketAddr,
+        conditions: LinkConditions,
+    ) {
+        self.lock().link_conditions.insert((from, to), conditions);
+    }
+
+    /// Bind a transport to `addr`. If the port of `addr` is `0`, an unused port is assigned.
+    pub fn bind(&self, mut addr: SocketAddr) -> Result<LoopbackTransport> {
+        let mut state = self.lock();
+
+        if addr.port() == 0 {
+            while state
+                .queues
+                .contains_key(&SocketAddr::new(addr.ip(), state.next_port))
+            {
+                state.next_port = state.next_port.wrapping_add(1).max(1);
+            }
+
+            addr.set_port(state.next_port);
+            state.next_port = state.next_port.wrapping_add(1).max(1);
+        }
+
+        if state.queues.contains_key(&addr) {
+            return Err(formaterr!(
+                ErrorKind::Network,
+                "Address '{}' is already in use",
+                addr
+            ));
+        }
+
+        state.queues.insert(addr, Vec::new());
+
+        Ok(LoopbackTransport {
+            network: self.clone(),
+            addr,
+        })
+    }
+
+    /// Bind a transport to an unused port on the loopback address
+    pub fn bind_any(&self) -> Result<LoopbackTransport> {
+        self.bind((Ipv4Addr::LOCALHOST, 0).into())
+    }
+
+    /// The amount of datagrams that are in flight, to any transport
+    pub fn in_flight_cnt(&self) -> usize {
+        self.lock().queues.values().map(Vec::len).sum()
+    }
+
+    fn lock(&self) -> MutexGuard<LoopbackState> {
+        // A panic while the state is locked can not leave it inconsistent, so poisoning is ignored
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Default for LoopbackNetwork {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+/// A transport bound to a `LoopbackNetwork`. Datagrams sent to addresses that no transport is
+/// bound to are dropped, like they would be by UDP.
+pub struct LoopbackTransport {
+    network: LoopbackNetwork,
+    addr: SocketAddr,
+}
+
+impl Transport for LoopbackTransport {
+    fn send_to(&mut self, bytes: &[u8], addr: SocketAddr) -> Result<()> {
+        let mut state = self.network.lock();
+        let state = &mut *state;
+
+        let conditions = state
+            .link_conditions
+            .get(&(self.addr, addr))
+            .copied()
+            .unwrap_or(state.conditions);
+
+        if conditions.loss > 0.0 && state.rng.next_f32() < conditions.loss {
+            return Ok(());
+        }
+
+        let mut delay = conditions.latency;
+
+        if conditions.jitter > 0.0 {
+            delay += conditions.jitter * (state.rng.next_f32() as f64 * 2.0 - 1.0);
+        }
+
+        if conditions.reordering > 0.0 && state.rng.next_f32() < conditions.reordering {
+            delay += conditions.reorder_delay;
+        }
+
+        if let Some(queue) = state.queues.get_mut(&addr) {
+            queue.push(Datagram {
+                from: self.addr,
+                bytes: bytes.to_vec(),
+                delivery_time: date::now() + delay.max(0.0),
+            });
+        }
+
+        Ok(())
+    }
+
+    fn recv_from(&mut self, buf: &mut [u8]) -> Result<Option<(usize, SocketAddr)>> {
+        let mut state = self.network.lock();
+
+        let queue = match state.queues.get_mut(&self.addr) {
+            Some(queue) => queue,
+            None => return Ok(None),
+        };
+
+        let now = date::now();
+
+        // The queue is in the order that datagrams were sent, so the first of the earliest
+        // delivery times is picked, to keep datagrams with equal delays in order
+        let next = queue
+            .iter()
+            .enumerate()
+            .filter(|(_, datagram)| datagram.delivery_time <= now)
+            .min_by(|(_, a), (_, b)| a.delivery_time.total_cmp(&b.delivery_time))
+            .map(|(i, _)| i);
+
+        match next {
+            Some(i) => {
+                let datagram = queue.remove(i);
+
+                // Like UDP, datagrams that are larger than the buffer are truncated
+                let len = datagram.bytes.len().min(buf.len());
+                buf[..len].copy_from_slice(&datagram.bytes[..len]);
+
+                Ok(Some((len, datagram.from)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.addr)
+    }
+}
+
+impl Drop for LoopbackTransport {
+    fn drop(&mut self) {
+        self.network.lock().queues.remove(&self.addr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_loopback_loss() {
+        let network = LoopbackNetwork::new(0);
+
+        let mut a = network.bind_any().unwrap();
+        let mut b = network.bind_any().unwrap();
+
+        network.set_conditions(LinkConditions {
+            loss: 1.0,
+            ..LinkConditions::perfect()
+        });
+
+        a.send_to(&[1], b.local_addr().unwrap()).unwrap();
+
+        let mut buf = [0; 16];
+        assert_eq!(b.recv_from(&mut buf).unwrap(), None);
+        assert_eq!(network.in_flight_cnt(), 0);
+    }
+
+    #[test]
+    fn test_loopback_reordering() {
+        let network = LoopbackNetwork::new(0);
+
+        let mut a = network.bind_any().unwrap();
+        let mut b = network.bind_any().unwrap();
+
+        let a_addr = a.local_addr().unwrap();
+        let b_addr = b.local_addr().unwrap();
+
+        network.set_link_conditions(
+            a_addr,
+            b_addr,
+            LinkConditions {
+                reordering: 1.0,
+                reorder_delay: 0.05,
+                ..LinkConditions::perfect()
+            },
+        );
+
+        a.send_to(&[1], b_addr).unwrap();
+
+        network.set_link_conditions(a_addr, b_addr, LinkConditions::perfect());
+
+        a.send_to(&[2], b_addr).unwrap();
+
+        let mut buf = [0; 16];
+        assert_eq!(b.recv_from(&mut buf).unwrap(), Some((1, a_addr)));
+        assert_eq!(buf[0], 2);
+        assert_eq!(b.recv_from(&mut buf).unwrap(), None);
+
+        thread::sleep(Duration::from_millis(60));
+
+        assert_eq!(b.recv_from(&mut buf).unwrap(), Some((1, a_addr)));
+        assert_eq!(buf[0], 1);
+    }
+}
@@ -10,6 +10,7 @@ use serde::{Deserialize, Serialize};
 
 use core::math::IsZero;
 
+use crate::ecs::RunContext;
 use crate::json;
 use crate::{AnimatedSpriteMetadata, Resources, Transform};
 
@@ -57,8 +58,10 @@ impl Default for ParticleEmitterMetadata {
     }
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct ParticleEmitter {
     pub particle_effect_id: String,
+    #[serde(with = "json::vec2_def")]
     pub offset: Vec2,
     pub delay: f32,
     pub emissions: Option<u32>,
@@ -67,6 +70,10 @@ pub struct ParticleEmitter {
     pub delay_timer: f32,
     pub interval_timer: f32,
     pub is_active: bool,
+    /// The positions that the emitter has emitted at, since the particles were last spawned by
+    /// `spawn_emitted_particles`
+    #[serde(skip)]
+    pub emitted_positions: Vec<Vec2>,
 }
 
 impl ParticleEmitter {
@@ -81,6 +88,7 @@ impl ParticleEmitter {
             delay_timer: 0.0,
             interval_timer: meta.interval,
             is_active: meta.should_autostart,
+            emitted_positions: Vec::new(),
         }
     }
 
@@ -116,9 +124,8 @@ pub fn update_one_particle_emitter(
     mut position: Vec2,
     rotation: f32,
     emitter: &mut ParticleEmitter,
+    dt: f32,
 ) {
-    let dt = get_frame_time();
-
     if emitter.is_active {
         emitter.delay_timer += dt;
 
@@ -146,13 +153,7 @@ pub fn update_one_particle_emitter(
                 );
             }
 
-            let mut particles = storage::get_mut::<Particles>();
-            let cache = particles
-                .cache_map
-                .get_mut(&emitter.particle_effect_id)
-                .unwrap();
-
-            cache.spawn(position);
+            emitter.emitted_positions.push(position);
 
             if let Some(emissions) = emitter.emissions {
                 emitter.emission_cnt += 1;
@@ -165,14 +166,54 @@ pub fn update_one_particle_emitter(
     }
 }
 
-pub fn update_particle_emitters(world: &mut World) {
-    for (_, (transform, emitter)) in world.query_mut::<(&Transform, &mut ParticleEmitter)>() {
-        update_one_particle_emitter(transform.position, transform.rotation, emitter);
+/// This only updates the timers of the emitters, as the particle caches are in `storage`, which
+/// can not be accessed from a worker thread. The particles are spawned by `spawn_emitted_particles`.
+pub fn update_particle_emitters(world: &World, context: &RunContext) {
+    for (_, (transform, emitter)) in world.query::<(&Transform, &mut ParticleEmitter)>().iter() {
+        update_one_particle_emitter(
+            transform.position,
+            transform.rotation,
+            emitter,
+            context.frame_time,
+        );
+    }
+
+    for (_, (transform, emitters)) in world
+        .query::<(&Transform, &mut Vec<ParticleEmitter>)>()
+        .iter()
+    {
+        for emitter in emitters.iter_mut() {
+            update_one_particle_emitter(
+                transform.position,
+                transform.rotation,
+                emitter,
+                context.frame_time,
+            );
+        }
+    }
+}
+
+pub fn spawn_emitted_particles(world: &mut World) {
+    let mut particles = storage::get_mut::<Particles>();
+
+    let mut spawn = |emitter: &mut ParticleEmitter| {
+        let cache = particles
+            .cache_map
+            .get_mut(&emitter.particle_effect_id)
+            .unwrap();
+
+        for position in emitter.emitted_positions.drain(..) {
+            cache.spawn(position);
+        }
+    };
+
+    for (_, emitter) in world.query_mut::<&mut ParticleEmitter>() {
+        spawn(emitter);
     }
 
-    for (_, (transform, emitters)) in world.query_mut::<(&Transform, &mut Vec<ParticleEmitter>)>() {
+    for (_, emitters) in world.query_mut::<&mut Vec<ParticleEmitter>>() {
         for emitter in emitters.iter_mut() {
-            update_one_particle_emitter(transform.position, transform.rotation, emitter);
+            spawn(emitter);
         }
     }
 }
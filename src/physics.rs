@@ -83,6 +83,7 @@ impl Default for PhysicsBodyParams {
 /// Regular simulated physics bodies.
 /// Note that rotation is abstract, only set on the transform to be used for draws. The colliders
 /// are axis-aligned and will not be affected by rotation.
+#[derive(Clone)]
 pub struct PhysicsBody {
     pub actor: Actor,
     pub offset: Vec2,
@@ -99,6 +100,10 @@ pub struct PhysicsBody {
     pub bouncyness: f32,
     pub is_deactivated: bool,
     pub gravity: f32,
+    /// The sub-pixel movement that the actor has accumulated, but not yet applied to its
+    /// position. This mirrors the remainder kept by the `CollisionWorld`, which it does not
+    /// expose, so that it can be captured in snapshots.
+    pub remainder: Vec2,
 }
 
 impl PhysicsBody {
@@ -123,6 +128,7 @@ impl PhysicsBody {
             bouncyness: params.bouncyness,
             is_deactivated: false,
             gravity: params.gravity,
+            remainder: Vec2::ZERO,
         }
     }
 
@@ -137,6 +143,7 @@ pub fn fixed_update_physics_bodies(world: &mut World) {
 
     for (_, (transform, body)) in world.query_mut::<(&mut Transform, &mut PhysicsBody)>() {
         collision_world.set_actor_position(body.actor, transform.position + body.offset);
+        body.remainder = Vec2::ZERO;
 
         if !body.is_deactivated {
             let position = collision_world.actor_pos(body.actor);
@@ -166,6 +173,8 @@ pub fn fixed_update_physics_bodies(world: &mut World) {
                 }
             }
 
+            body.remainder = subpixel_remainder(body.remainder + body.velocity);
+
             if !collision_world.move_h(body.actor, body.velocity.x) {
                 body.velocity.x *= -body.bouncyness;
             }
@@ -190,6 +199,12 @@ pub fn fixed_update_physics_bodies(world: &mut World) {
     }
 }
 
+/// Get what remains of `movement`, after the whole pixels have been applied, the same way that
+/// the `CollisionWorld` does when an actor is moved
+fn subpixel_remainder(movement: Vec2) -> Vec2 {
+    movement - movement.round()
+}
+
 pub fn debug_draw_physics_bodies(world: &mut World) {
     for (_, (transform, body)) in world.query::<(&Transform, &PhysicsBody)>().iter() {
         if !body.is_deactivated {
@@ -231,9 +246,13 @@ impl Default for RigidBodyParams {
 /// Simple physics bodies that has a velocity and optional rotation.
 /// Note that rotation is abstract, only set on the transform to be used for draws. The colliders
 /// are axis-aligned and will not be affected by rotation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RigidBody {
+    #[serde(with = "json::vec2_def")]
     pub offset: Vec2,
+    #[serde(with = "json::vec2_def")]
     pub size: Vec2,
+    #[serde(with = "json::vec2_def")]
     pub velocity: Vec2,
     pub can_rotate: bool,
 }
@@ -4,6 +4,7 @@ use hecs::World;
 
 use serde::{Deserialize, Serialize};
 
+use crate::ecs::RunContext;
 use crate::player::{
     Player, PlayerState, CROUCH_ANIMATION_ID, DEATH_BACK_ANIMATION_ID, DEATH_FORWARD_ANIMATION_ID,
     FALL_ANIMATION_ID, IDLE_ANIMATION_ID, JUMP_ANIMATION_ID, MOVE_ANIMATION_ID, SLIDE_ANIMATION_ID,
@@ -248,8 +249,10 @@ impl PlayerAnimations {
     }
 }
 
-pub fn update_player_animations(world: &mut World) {
-    for (_, (player, body, drawable)) in world.query_mut::<(&Player, &PhysicsBody, &mut Drawable)>()
+pub fn update_player_animations(world: &World, _context: &RunContext) {
+    for (_, (player, body, drawable)) in world
+        .query::<(&Player, &PhysicsBody, &mut Drawable)>()
+        .iter()
     {
         let sprite_set = drawable.get_animated_sprite_set_mut().unwrap();
 
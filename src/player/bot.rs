@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+
+use hecs::{Entity, World};
+use macroquad::prelude::*;
+
+use crate::player::{Player, PlayerController, PlayerControllerKind, PlayerInventory, PlayerState};
+use crate::{GameInput, PhysicsBody, Transform};
+
+/// The horizontal distance, from the closest opponent, that a bot stops moving towards it at
+const BOT_CLOSE_DISTANCE: f32 = 32.0;
+/// The horizontal distance, from the closest opponent, that a bot attacks it within
+const BOT_ATTACK_DISTANCE: f32 = 320.0;
+/// The vertical distance, from the closest opponent, that a bot attacks it within
+const BOT_ATTACK_HEIGHT: f32 = 24.0;
+/// The height that an opponent has to be above a bot, for the bot to jump
+const BOT_JUMP_HEIGHT: f32 = 48.0;
+
+/// Compute the input of all players with a controller of kind `PlayerControllerKind::Bot`.
+/// Bots move towards the closest opponent that is alive, jumping when it is above them, or
+/// when they are blocked, and attack it when it is in front of them, picking up anything that
+/// they run into, while they are not holding a weapon.
+pub fn collect_bot_inputs(world: &World) -> HashMap<Entity, GameInput> {
+    let opponents = world
+        .query::<(&Player, &Transform)>()
+        .iter()
+        .filter(|(_, (player, _))| player.state != PlayerState::Dead)
+        .map(|(_, (player, transform))| (player.index, transform.position))
+        .collect::<Vec<_>>();
+
+    let mut res = HashMap::new();
+
+    for (entity, (player, transform, body, inventory, controller)) in world
+        .query::<(
+            &Player,
+            &Transform,
+            &PhysicsBody,
+            &PlayerInventory,
+            &PlayerController,
+        )>()
+        .iter()
+    {
+        if let PlayerControllerKind::Bot = controller.kind {
+            let position = transform.position;
+
+            let target = opponents
+                .iter()
+                .filter(|(index, _)| *index != player.index)
+                .map(|(_, target)| *target)
+                .min_by(|a, b| {
+                    let a = a.distance_squared(position);
+                    let b = b.distance_squared(position);
+
+                    a.total_cmp(&b)
+                });
+
+            let input = match target {
+                Some(target) => bot_input(player, position, body, inventory, target),
+                None => GameInput::default(),
+            };
+
+            res.insert(entity, input);
+        }
+    }
+
+    res
+}
+
+fn bot_input(
+    player: &Player,
+    position: Vec2,
+    body: &PhysicsBody,
+    inventory: &PlayerInventory,
+    target: Vec2,
+) -> GameInput {
+    let mut input = GameInput::default();
+
+    let offset = target - position;
+
+    if offset.x.abs() > BOT_CLOSE_DISTANCE {
+        input.left = offset.x < 0.0;
+        input.right = offset.x > 0.0;
+    }
+
+    let is_blocked = (input.left || input.right) && body.velocity.x == 0.0;
+
+    if body.is_on_ground && (offset.y < -BOT_JUMP_HEIGHT || is_blocked) {
+        input.jump = true;
+    }
+
+    input.float = !body.is_on_ground && offset.y < 0.0;
+
+    let is_facing_target = player.is_facing_left == (offset.x < 0.0);
+
+    input.fire = inventory.weapon.is_some()
+        && is_facing_target
+        && offset.x.abs() <= BOT_ATTACK_DISTANCE
+        && offset.y.abs() <= BOT_ATTACK_HEIGHT;
+
+    input.pickup = inventory.weapon.is_none();
+
+    input
+}
@@ -1,14 +1,25 @@
+use std::collections::HashMap;
+
 use hecs::World;
 use macroquad::prelude::*;
 
 use core::Id;
 
+use crate::player::{collect_bot_inputs, Player};
 use crate::{collect_local_input, GameInput, GameInputScheme};
 
 #[derive(Debug, Clone)]
 pub enum PlayerControllerKind {
     LocalInput(GameInputScheme),
+    /// Input is received from a remote peer and applied by the `LockstepSession` of `Game`
     Network(Id),
+    /// Input is applied by the code driving the game, through `Game::apply_player_input`, like
+    /// when the game is running headless
+    External,
+    /// Input is applied by `Game`, from the replay that is being played back
+    Replay,
+    /// Input is computed by `collect_bot_inputs`, from the state of the game
+    Bot,
 }
 
 impl PlayerControllerKind {
@@ -24,6 +35,9 @@ impl PlayerControllerKind {
 pub struct PlayerController {
     pub kind: PlayerControllerKind,
 
+    /// The input that was last applied to the controller
+    pub input: GameInput,
+
     /// No vertical movement is possible now but you never know what the future holds :)
     pub move_direction: Vec2,
 
@@ -39,6 +53,7 @@ impl From<PlayerControllerKind> for PlayerController {
     fn from(kind: PlayerControllerKind) -> Self {
         PlayerController {
             kind,
+            input: GameInput::default(),
             move_direction: Vec2::ZERO,
             should_crouch: false,
             should_jump: false,
@@ -52,6 +67,7 @@ impl From<PlayerControllerKind> for PlayerController {
 
 impl PlayerController {
     pub fn clear(&mut self) {
+        self.input = GameInput::default();
         self.move_direction = Vec2::ZERO;
         self.should_crouch = false;
         self.should_jump = false;
@@ -64,6 +80,8 @@ impl PlayerController {
     pub fn apply_input(&mut self, input: GameInput) {
         self.clear();
 
+        self.input = input;
+
         if input.left {
             self.move_direction.x -= 1.0;
         }
@@ -81,16 +99,40 @@ impl PlayerController {
     }
 }
 
-pub fn update_player_controllers(world: &mut World) {
-    for (_, controller) in world.query_mut::<&mut PlayerController>() {
+/// Collect the input of the local players, latching it into `local_inputs`, by player index. This
+/// is done every frame, as presses would be missed on frames where no tick is simulated, if input
+/// was only collected by `update_player_controllers`.
+pub fn collect_local_inputs(world: &World, local_inputs: &mut HashMap<u8, GameInput>) {
+    for (_, (player, controller)) in world.query::<(&Player, &PlayerController)>().iter() {
+        if let PlayerControllerKind::LocalInput(input_scheme) = controller.kind {
+            let input = collect_local_input(input_scheme);
+
+            local_inputs.entry(player.index).or_default().latch(input);
+        }
+    }
+}
+
+/// Apply the input of local players, collected by `collect_local_inputs`, and of bots, to the
+/// player controllers. The presses of local players are consumed, so that they only apply to one
+/// tick.
+pub fn update_player_controllers(world: &mut World, local_inputs: &mut HashMap<u8, GameInput>) {
+    let mut bot_inputs = collect_bot_inputs(world);
+
+    for (entity, (player, controller)) in world.query_mut::<(&Player, &mut PlayerController)>() {
         match &controller.kind {
-            PlayerControllerKind::LocalInput(input_scheme) => {
-                let input = collect_local_input(*input_scheme);
-                controller.apply_input(input);
+            PlayerControllerKind::LocalInput(_) => {
+                let input = local_inputs.entry(player.index).or_default();
+
+                controller.apply_input(*input);
+                input.clear_presses();
             }
-            PlayerControllerKind::Network(_account_id) => {
-                // TODO: Network input
+            PlayerControllerKind::Bot => {
+                let input = bot_inputs.remove(&entity).unwrap_or_default();
+                controller.apply_input(input);
             }
+            PlayerControllerKind::Network(_)
+            | PlayerControllerKind::External
+            | PlayerControllerKind::Replay => {}
         }
     }
 }
@@ -1,10 +1,13 @@
+use macroquad::experimental::collections::storage;
+
 use hecs::{Entity, World};
-use macroquad::time::get_frame_time;
 
+use crate::ecs::{EntityMap, MapEntities};
+use crate::game::{get_delta_time, MatchState};
 use crate::player::{Player, PlayerState};
 use serde::{Deserialize, Serialize};
 
-#[derive(Default)]
+#[derive(Default, Clone, Serialize, Deserialize)]
 pub struct PlayerEventQueue {
     pub queue: Vec<PlayerEvent>,
 }
@@ -15,7 +18,23 @@ impl PlayerEventQueue {
     }
 }
 
-#[derive(Clone)]
+impl MapEntities for PlayerEventQueue {
+    fn map_entities(&mut self, entity_map: &EntityMap) {
+        for event in &mut self.queue {
+            match event {
+                PlayerEvent::ReceiveDamage { damage_from, .. } => entity_map.map_opt(damage_from),
+                PlayerEvent::GiveDamage { damage_to } => entity_map.map_opt(damage_to),
+                PlayerEvent::Incapacitated { incapacitated_by } => {
+                    entity_map.map_opt(incapacitated_by)
+                }
+                PlayerEvent::Collision { collision_with, .. } => entity_map.map(collision_with),
+                PlayerEvent::Update { .. } | PlayerEvent::DamageBlocked { .. } => {}
+            }
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub enum PlayerEvent {
     Update {
         dt: f32,
@@ -66,9 +85,13 @@ impl From<&PlayerEvent> for PlayerEventKind {
     }
 }
 
+/// Handle the events of all players, killing players that received damage that was not blocked.
+/// Kills are recorded in the `MatchState`, if there is one.
 pub fn update_player_events(world: &mut World) {
+    let mut kills = Vec::new();
+
     for (_, (player, events)) in world.query_mut::<(&mut Player, &mut PlayerEventQueue)>() {
-        let dt = get_frame_time();
+        let dt = get_delta_time();
 
         events.queue.push(PlayerEvent::Update { dt });
 
@@ -83,13 +106,32 @@ pub fn update_player_events(world: &mut World) {
         }
 
         while let Some(event) = events.queue.pop() {
-            if let PlayerEvent::ReceiveDamage { is_from_left, .. } = event {
+            if let PlayerEvent::ReceiveDamage {
+                is_from_left,
+                damage_from,
+            } = event
+            {
                 if (is_from_left && !damage_blocked_left)
                     || (!is_from_left && !damage_blocked_right)
                 {
+                    // Damage received by a dead player does not count as a kill
+                    if player.state != PlayerState::Dead {
+                        kills.push((player.index, damage_from));
+                    }
+
                     player.state = PlayerState::Dead;
                 }
             }
         }
     }
+
+    if let Some(mut state) = storage::try_get_mut::<MatchState>() {
+        for (victim, damage_from) in kills {
+            let killer = damage_from
+                .and_then(|entity| world.get::<Player>(entity).ok())
+                .map(|player| player.index);
+
+            state.on_player_killed(victim, killer);
+        }
+    }
 }
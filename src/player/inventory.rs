@@ -2,18 +2,23 @@ use macroquad::prelude::*;
 
 use hecs::{Entity, With, Without, World};
 
+use serde::{Deserialize, Serialize};
+
+use crate::ecs::{EntityMap, MapEntities};
+use crate::game::get_delta_time;
 use crate::items::{
     fire_weapon, ItemDepleteBehavior, ItemDropBehavior, Weapon, EFFECT_ANIMATED_SPRITE_ID,
     GROUND_ANIMATION_ID, ITEMS_DRAW_ORDER, SPRITE_ANIMATED_SPRITE_ID,
 };
 use crate::particles::ParticleEmitter;
 use crate::player::{Player, PlayerController, PlayerState, IDLE_ANIMATION_ID, PICKUP_GRACE_TIME};
-use crate::{Drawable, Item, Owner, PassiveEffectInstance, PhysicsBody, Transform};
+use crate::{json, Drawable, Item, Owner, PassiveEffectInstance, PhysicsBody, Transform};
 
 const THROW_FORCE: f32 = 5.0;
 
-#[derive(Default)]
+#[derive(Default, Clone, Serialize, Deserialize)]
 pub struct PlayerInventory {
+    #[serde(with = "json::vec2_def")]
     pub weapon_mount: Vec2,
     pub weapon: Option<Entity>,
     pub items: Vec<Entity>,
@@ -29,6 +34,18 @@ impl From<Vec2> for PlayerInventory {
     }
 }
 
+impl MapEntities for PlayerInventory {
+    fn map_entities(&mut self, entity_map: &EntityMap) {
+        entity_map.map_opt(&mut self.weapon);
+
+        self.items = self
+            .items
+            .iter()
+            .filter_map(|&entity| entity_map.get(entity))
+            .collect();
+    }
+}
+
 impl PlayerInventory {
     pub fn get_weapon_mount(&self, is_facing_left: bool, is_upside_down: bool) -> Vec2 {
         flip_offset(self.weapon_mount, None, is_facing_left, is_upside_down)
@@ -137,7 +154,7 @@ pub fn update_player_inventory(world: &mut World) {
             if let Some(weapon_entity) = inventory.weapon {
                 let mut weapon = world.get_mut::<Weapon>(weapon_entity).unwrap();
 
-                weapon.cooldown_timer += get_frame_time();
+                weapon.cooldown_timer += get_delta_time();
 
                 let mut weapon_transform = world.get_mut::<Transform>(weapon_entity).unwrap();
 
@@ -215,7 +232,7 @@ pub fn update_player_inventory(world: &mut World) {
 
                 let mut item = world.get_mut::<Item>(item_entity).unwrap();
 
-                item.duration_timer += get_frame_time();
+                item.duration_timer += get_delta_time();
 
                 let mut is_depleted = false;
 
@@ -294,85 +311,108 @@ pub fn update_player_inventory(world: &mut World) {
     }
 
     for entity in to_drop {
-        world.remove_one::<Owner>(entity).unwrap();
-
-        let mut should_destroy = false;
+        drop_item(world, entity);
+    }
 
-        if let Ok(mut weapon) = world.get_mut::<Weapon>(entity) {
-            match weapon.drop_behavior {
-                ItemDropBehavior::ClearState => {
-                    weapon.use_cnt = 0;
-                    weapon.cooldown_timer = weapon.cooldown;
-                }
-                ItemDropBehavior::Destroy => {
-                    should_destroy = true;
-                }
-                _ => {}
-            }
-        } else if let Ok(mut item) = world.get_mut::<Item>(entity) {
-            match item.drop_behavior {
-                ItemDropBehavior::ClearState => {
-                    item.use_cnt = 0;
-                    item.duration_timer = 0.0;
-                }
-                ItemDropBehavior::PersistState => {
-                    if let Some(uses) = item.uses {
-                        should_destroy = item.use_cnt >= uses;
-                    }
-                }
-                ItemDropBehavior::Destroy => {
-                    should_destroy = true;
-                }
-            }
+    for (entity, owner) in to_fire.drain(0..) {
+        if let Err(err) = fire_weapon(world, entity, owner) {
+            #[cfg(debug_assertions)]
+            println!("WARNING: {}", err);
         }
+    }
 
-        if should_destroy {
-            if let Err(err) = world.despawn(entity) {
-                #[cfg(debug_assertions)]
-                println!("WARNING: {}", err);
-            }
-        } else {
-            let mut drawable = world.get_mut::<Drawable>(entity).unwrap();
-            drawable.draw_order = ITEMS_DRAW_ORDER;
-
-            let mut body = world.get_mut::<PhysicsBody>(entity).unwrap();
+    for entity in to_destroy {
+        if let Err(err) = world.despawn(entity) {
+            #[cfg(debug_assertions)]
+            println!("WARNING: {}", err);
+        }
+    }
+}
 
-            body.is_deactivated = false;
+/// Drop all the items and the weapon held by the player `player_entity`, like when the player is
+/// removed from the game
+pub fn drop_inventory(world: &mut World, player_entity: Entity) {
+    let to_drop = match world.get_mut::<PlayerInventory>(player_entity) {
+        Ok(mut inventory) => {
+            let mut res = inventory.items.drain(0..).collect::<Vec<_>>();
+            res.extend(inventory.weapon.take());
+            res
+        }
+        Err(_) => return,
+    };
 
-            let sprite_set = drawable.get_animated_sprite_set_mut().unwrap();
+    for entity in to_drop {
+        drop_item(world, entity);
+    }
+}
 
-            sprite_set.restart_all();
+/// Release an item, or a weapon, from its owner, leaving it on the ground, or destroying it,
+/// according to its drop behavior
+fn drop_item(world: &mut World, entity: Entity) {
+    world.remove_one::<Owner>(entity).unwrap();
 
-            let sprite = sprite_set.map.get_mut(SPRITE_ANIMATED_SPRITE_ID).unwrap();
+    let mut should_destroy = false;
 
-            if sprite
-                .animations
-                .iter()
-                .any(|a| a.id == *GROUND_ANIMATION_ID)
-            {
-                sprite.set_animation(GROUND_ANIMATION_ID, true);
-            } else {
-                sprite.set_animation(IDLE_ANIMATION_ID, true);
+    if let Ok(mut weapon) = world.get_mut::<Weapon>(entity) {
+        match weapon.drop_behavior {
+            ItemDropBehavior::ClearState => {
+                weapon.use_cnt = 0;
+                weapon.cooldown_timer = weapon.cooldown;
             }
-
-            if let Some(sprite) = sprite_set.map.get_mut(EFFECT_ANIMATED_SPRITE_ID) {
-                sprite.is_deactivated = true;
+            ItemDropBehavior::Destroy => {
+                should_destroy = true;
             }
+            _ => {}
         }
-    }
-
-    for (entity, owner) in to_fire.drain(0..) {
-        if let Err(err) = fire_weapon(world, entity, owner) {
-            #[cfg(debug_assertions)]
-            println!("WARNING: {}", err);
+    } else if let Ok(mut item) = world.get_mut::<Item>(entity) {
+        match item.drop_behavior {
+            ItemDropBehavior::ClearState => {
+                item.use_cnt = 0;
+                item.duration_timer = 0.0;
+            }
+            ItemDropBehavior::PersistState => {
+                if let Some(uses) = item.uses {
+                    should_destroy = item.use_cnt >= uses;
+                }
+            }
+            ItemDropBehavior::Destroy => {
+                should_destroy = true;
+            }
         }
     }
 
-    for entity in to_destroy {
+    if should_destroy {
         if let Err(err) = world.despawn(entity) {
             #[cfg(debug_assertions)]
             println!("WARNING: {}", err);
         }
+    } else {
+        let mut drawable = world.get_mut::<Drawable>(entity).unwrap();
+        drawable.draw_order = ITEMS_DRAW_ORDER;
+
+        let mut body = world.get_mut::<PhysicsBody>(entity).unwrap();
+
+        body.is_deactivated = false;
+
+        let sprite_set = drawable.get_animated_sprite_set_mut().unwrap();
+
+        sprite_set.restart_all();
+
+        let sprite = sprite_set.map.get_mut(SPRITE_ANIMATED_SPRITE_ID).unwrap();
+
+        if sprite
+            .animations
+            .iter()
+            .any(|a| a.id == *GROUND_ANIMATION_ID)
+        {
+            sprite.set_animation(GROUND_ANIMATION_ID, true);
+        } else {
+            sprite.set_animation(IDLE_ANIMATION_ID, true);
+        }
+
+        if let Some(sprite) = sprite_set.map.get_mut(EFFECT_ANIMATED_SPRITE_ID) {
+            sprite.is_deactivated = true;
+        }
     }
 }
 
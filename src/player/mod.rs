@@ -3,12 +3,18 @@ use macroquad::prelude::*;
 
 use hecs::{Entity, World};
 
+use serde::{Deserialize, Serialize};
+
+use crate::ecs::{EntityMap, MapEntities};
 use crate::{
     AnimatedSprite, AnimatedSpriteMetadata, AnimatedSpriteParams, CollisionWorld, Drawable,
     GameCamera, PassiveEffectInstance, PhysicsBody, Resources, Transform,
 };
 
+use crate::json;
+
 mod animation;
+mod bot;
 mod character;
 mod controller;
 mod events;
@@ -16,6 +22,7 @@ mod inventory;
 mod state;
 
 pub use animation::*;
+pub use bot::*;
 pub use character::*;
 pub use controller::*;
 pub use events::*;
@@ -50,6 +57,7 @@ pub struct PlayerParams {
     pub character: PlayerCharacterMetadata,
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Player {
     pub index: u8,
     pub state: PlayerState,
@@ -61,6 +69,7 @@ pub struct Player {
     pub incapacitation_timer: f32,
     pub attack_timer: f32,
     pub respawn_timer: f32,
+    #[serde(with = "json::RectDef")]
     pub camera_box: Rect,
     pub passive_effects: Vec<PassiveEffectInstance>,
 }
@@ -86,6 +95,14 @@ impl Player {
     }
 }
 
+impl MapEntities for Player {
+    fn map_entities(&mut self, entity_map: &EntityMap) {
+        for effect in &mut self.passive_effects {
+            effect.map_entities(entity_map);
+        }
+    }
+}
+
 pub fn update_player_camera_box(world: &mut World) {
     for (_, (transform, player)) in world.query_mut::<(&Transform, &mut Player)>() {
         let rect = Rect::new(transform.position.x, transform.position.y, 32.0, 60.0);
@@ -107,14 +124,15 @@ pub fn update_player_camera_box(world: &mut World) {
         }
 
         let mut camera = storage::get_mut::<GameCamera>();
-        camera.add_player_rect(player.camera_box);
+        camera.add_player_rect(player.index, player.camera_box);
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PlayerAttributes {
     pub head_threshold: f32,
     pub legs_threshold: f32,
+    #[serde(with = "json::vec2_def")]
     pub weapon_mount: Vec2,
     pub jump_force: f32,
     pub move_speed: f32,
@@ -211,3 +229,14 @@ pub fn spawn_player(
         PhysicsBody::new(actor, None, body_params),
     ))
 }
+
+/// Remove the player `entity` from the game, dropping its weapon and items, like when a remote
+/// player has disconnected from a network game
+pub fn despawn_player(world: &mut World, entity: Entity) {
+    drop_inventory(world, entity);
+
+    if let Err(err) = world.despawn(entity) {
+        #[cfg(debug_assertions)]
+        println!("WARNING: {}", err);
+    }
+}
@@ -4,6 +4,11 @@ use macroquad::prelude::*;
 
 use hecs::{Entity, World};
 
+use serde::{Deserialize, Serialize};
+
+use core::rng::Rng;
+
+use crate::game::{get_delta_time, MatchState};
 use crate::player::{
     Player, PlayerAttributes, PlayerController, PlayerEventQueue, JUMP_SOUND_ID, LAND_SOUND_ID,
     RESPAWN_DELAY,
@@ -14,7 +19,7 @@ const SLIDE_STOP_THRESHOLD: f32 = 2.0;
 const JUMP_FRAME_COUNT: u16 = 8;
 const PLATFORM_JUMP_FORCE_MULTIPLIER: f32 = 0.2;
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub enum PlayerState {
     None,
     Jumping,
@@ -41,7 +46,7 @@ pub fn update_player_states(world: &mut World) {
     )>();
     for (_, (transform, player, controller, attributes, body)) in query {
         // Timers
-        let dt = get_frame_time();
+        let dt = get_delta_time();
 
         player.attack_timer -= dt;
         if player.attack_timer <= 0.0 {
@@ -57,12 +62,17 @@ pub fn update_player_states(world: &mut World) {
 
             player.passive_effects.clear();
 
-            if player.respawn_timer >= RESPAWN_DELAY {
+            let can_respawn = storage::try_get::<MatchState>()
+                .map(|state| state.can_respawn(player.index))
+                .unwrap_or(true);
+
+            if can_respawn && player.respawn_timer >= RESPAWN_DELAY {
                 player.state = PlayerState::None;
                 player.respawn_timer = 0.0;
 
                 let map = storage::get::<Map>();
-                transform.position = map.get_random_spawn_point();
+                let mut rng = storage::get_mut::<Rng>();
+                transform.position = map.get_random_spawn_point(&mut rng);
             }
         } else if player.state == PlayerState::Incapacitated {
             player.incapacitation_timer += dt;
@@ -139,9 +149,9 @@ pub fn update_player_states(world: &mut World) {
                     player.state = PlayerState::Jumping;
 
                     let resources = storage::get::<Resources>();
-                    let sound = resources.sounds[JUMP_SOUND_ID];
-
-                    play_sound_once(sound);
+                    if let Some(sound) = resources.sounds.get(JUMP_SOUND_ID) {
+                        play_sound_once(*sound);
+                    }
                 } else if player.state == PlayerState::Jumping {
                     player.jump_frame_counter += 1;
 
@@ -176,9 +186,9 @@ pub fn update_player_states(world: &mut World) {
                 body.has_mass = true;
 
                 let resources = storage::get::<Resources>();
-                let sound = resources.sounds[LAND_SOUND_ID];
-
-                play_sound_once(sound);
+                if let Some(sound) = resources.sounds.get(LAND_SOUND_ID) {
+                    play_sound_once(*sound);
+                }
             }
         }
     }
@@ -188,7 +198,7 @@ pub fn update_player_passive_effects(world: &mut World) {
     let mut function_calls = Vec::new();
 
     for (entity, (player, events)) in world.query::<(&mut Player, &mut PlayerEventQueue)>().iter() {
-        let dt = get_frame_time();
+        let dt = get_delta_time();
 
         for effect in &mut player.passive_effects {
             effect.duration_timer += dt;
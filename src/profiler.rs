@@ -0,0 +1,352 @@
+//! Timing of the systems that are executed by a `Scheduler`.
+//! While the profiler is enabled, every scheduler keeps the timings of its last
+//! `PROFILER_HISTORY_LEN` executions, which are shown by `draw_profiler_overlay`.
+//! While a trace is recorded, the timings of every execution are kept as well, so that they can
+//! be saved as a Chrome trace, which can be opened in `chrome://tracing`, or in Perfetto.
+
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use macroquad::prelude::*;
+
+use serde::Serialize;
+
+use core::Result;
+
+use crate::ecs::Scheduler;
+
+/// The amount of executions that the timings are kept for, by every scheduler
+pub const PROFILER_HISTORY_LEN: usize = 120;
+
+/// If this is set, a trace is recorded from the start, across all matches, and it is saved to the
+/// specified path when the main loop exits, either by quitting to desktop, or on an error
+pub const TRACE_FILE_ENV_VAR: &str = "FISHFIGHT_TRACE_FILE";
+
+/// The amount of systems shown by the profiler overlay
+const OVERLAY_SYSTEM_CNT: usize = 8;
+
+/// The maximum amount of events that a trace is recorded for, to limit the memory used, if a
+/// trace is left running
+const MAX_TRACE_EVENT_CNT: usize = 1_000_000;
+
+static IS_PROFILER_ENABLED: AtomicBool = AtomicBool::new(false);
+
+static TRACE: Mutex<Option<Vec<TraceEvent>>> = Mutex::new(None);
+
+pub fn is_profiler_enabled() -> bool {
+    IS_PROFILER_ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn enable_profiler() {
+    IS_PROFILER_ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn disable_profiler() {
+    IS_PROFILER_ENABLED.store(false, Ordering::Relaxed);
+}
+
+pub fn toggle_profiler() {
+    IS_PROFILER_ENABLED.fetch_xor(true, Ordering::Relaxed);
+}
+
+/// The current time, in seconds, that timings are measured in
+pub fn now() -> f64 {
+    macroquad::miniquad::date::now()
+}
+
+/// The time that a system took, in one execution of its scheduler
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SystemTiming {
+    pub name: &'static str,
+    pub start: f64,
+    pub duration: f64,
+    /// This is `0` for the thread that executed the scheduler, and the index of the thread,
+    /// starting at `1`, for parallel systems that were executed on another thread
+    pub thread_index: usize,
+}
+
+/// The timings of one execution of a scheduler. The duration of an execution can be less than
+/// the sum of the durations of its systems, if systems were executed in parallel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExecutionProfile {
+    pub start: f64,
+    pub duration: f64,
+    pub systems: Vec<SystemTiming>,
+}
+
+/// The timings of the last `PROFILER_HISTORY_LEN` executions of a scheduler
+#[derive(Debug, Default, Clone)]
+pub struct SchedulerProfile {
+    history: VecDeque<ExecutionProfile>,
+}
+
+impl SchedulerProfile {
+    pub fn new() -> Self {
+        SchedulerProfile {
+            history: VecDeque::new(),
+        }
+    }
+
+    pub fn push(&mut self, execution: ExecutionProfile) {
+        if self.history.len() >= PROFILER_HISTORY_LEN {
+            self.history.pop_front();
+        }
+
+        self.history.push_back(execution);
+    }
+
+    pub fn clear(&mut self) {
+        self.history.clear();
+    }
+
+    /// The executions in the history, oldest first
+    pub fn history(&self) -> impl Iterator<Item = &ExecutionProfile> {
+        self.history.iter()
+    }
+
+    /// The average duration of an execution, in seconds
+    pub fn average_duration(&self) -> f64 {
+        if self.history.is_empty() {
+            return 0.0;
+        }
+
+        let total = self
+            .history
+            .iter()
+            .map(|execution| execution.duration)
+            .sum::<f64>();
+
+        total / self.history.len() as f64
+    }
+
+    /// The average duration of each system, per execution, in seconds, slowest first. The
+    /// durations of systems that share a name are added up.
+    pub fn average_system_durations(&self) -> Vec<(&'static str, f64)> {
+        if self.history.is_empty() {
+            return Vec::new();
+        }
+
+        let mut totals = HashMap::new();
+
+        for timing in self.history.iter().flat_map(|execution| &execution.systems) {
+            *totals.entry(timing.name).or_insert(0.0) += timing.duration;
+        }
+
+        let mut res = totals
+            .into_iter()
+            .map(|(name, total)| (name, total / self.history.len() as f64))
+            .collect::<Vec<_>>();
+
+        res.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(b.0)));
+
+        res
+    }
+}
+
+/// An event in the Trace Event Format, that is read by `chrome://tracing`. Times are in
+/// microseconds.
+#[derive(Debug, Clone, Serialize)]
+struct TraceEvent {
+    name: &'static str,
+    #[serde(rename = "cat")]
+    category: &'static str,
+    #[serde(rename = "ph")]
+    phase: &'static str,
+    #[serde(rename = "ts")]
+    timestamp: f64,
+    #[serde(rename = "dur")]
+    duration: f64,
+    pid: u32,
+    tid: usize,
+}
+
+impl TraceEvent {
+    fn new(
+        name: &'static str,
+        category: &'static str,
+        start: f64,
+        duration: f64,
+        tid: usize,
+    ) -> Self {
+        TraceEvent {
+            name,
+            category,
+            phase: "X",
+            timestamp: start * 1_000_000.0,
+            duration: duration * 1_000_000.0,
+            pid: 0,
+            tid,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Trace<'a> {
+    #[serde(rename = "traceEvents")]
+    events: &'a [TraceEvent],
+    #[serde(rename = "displayTimeUnit")]
+    display_time_unit: &'static str,
+}
+
+/// Start recording a trace, discarding any trace that is already being recorded
+pub fn start_trace() {
+    *TRACE.lock().unwrap_or_else(|err| err.into_inner()) = Some(Vec::new());
+}
+
+pub fn is_tracing() -> bool {
+    TRACE
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .is_some()
+}
+
+/// Add an execution of the scheduler with the specified name to the trace, if one is being
+/// recorded
+pub fn record_trace(scheduler: &'static str, execution: &ExecutionProfile) {
+    let mut trace = TRACE.lock().unwrap_or_else(|err| err.into_inner());
+
+    if let Some(events) = trace.as_mut() {
+        if events.len() + execution.systems.len() >= MAX_TRACE_EVENT_CNT {
+            return;
+        }
+
+        events.push(TraceEvent::new(
+            scheduler,
+            "scheduler",
+            execution.start,
+            execution.duration,
+            0,
+        ));
+
+        for timing in &execution.systems {
+            events.push(TraceEvent::new(
+                timing.name,
+                scheduler,
+                timing.start,
+                timing.duration,
+                timing.thread_index,
+            ));
+        }
+    }
+}
+
+/// Stop recording the trace and save it to `path`, as JSON. If no trace is being recorded, an
+/// empty trace is saved.
+pub fn save_trace<P: AsRef<Path>>(path: P) -> Result<()> {
+    let events = TRACE
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .take()
+        .unwrap_or_default();
+
+    let trace = Trace {
+        events: &events,
+        display_time_unit: "ms",
+    };
+
+    let json = serde_json::to_string(&trace)?;
+    std::fs::write(path, json)?;
+
+    Ok(())
+}
+
+/// Draw the average durations of the update, fixed update and draw schedulers of a game, and of
+/// the slowest systems in them, over the last `PROFILER_HISTORY_LEN` executions
+pub fn draw_profiler_overlay(updates: &Scheduler, fixed_updates: &Scheduler, draws: &Scheduler) {
+    let schedulers = [
+        ("update", updates),
+        ("fixed update", fixed_updates),
+        ("draw", draws),
+    ];
+
+    let mut lines = vec![format!(
+        "Frame: {:.2} ms ({} FPS)",
+        get_frame_time() * 1000.0,
+        get_fps()
+    )];
+
+    for (name, scheduler) in &schedulers {
+        lines.push(format!(
+            "{}: {:.3} ms",
+            name,
+            scheduler.profile().average_duration() * 1000.0
+        ));
+    }
+
+    let mut systems = schedulers
+        .iter()
+        .flat_map(|(name, scheduler)| {
+            scheduler
+                .profile()
+                .average_system_durations()
+                .into_iter()
+                .map(move |(system, duration)| (*name, system, duration))
+        })
+        .collect::<Vec<_>>();
+
+    systems.sort_by(|a, b| b.2.total_cmp(&a.2));
+
+    lines.push("Slowest systems:".to_string());
+
+    for (scheduler, system, duration) in systems.into_iter().take(OVERLAY_SYSTEM_CNT) {
+        lines.push(format!(
+            "{:.3} ms - {} ({})",
+            duration * 1000.0,
+            system,
+            scheduler
+        ));
+    }
+
+    push_camera_state();
+    set_default_camera();
+
+    let x = screen_width() - 480.0;
+
+    for (i, line) in lines.iter().enumerate() {
+        draw_text(line, x, 32.0 + i as f32 * 20.0, 20.0, WHITE);
+    }
+
+    pop_camera_state();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn execution(systems: &[(&'static str, f64)]) -> ExecutionProfile {
+        ExecutionProfile {
+            start: 0.0,
+            duration: systems.iter().map(|(_, duration)| duration).sum(),
+            systems: systems
+                .iter()
+                .map(|&(name, duration)| SystemTiming {
+                    name,
+                    start: 0.0,
+                    duration,
+                    thread_index: 0,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_scheduler_profile() {
+        let mut profile = SchedulerProfile::new();
+
+        profile.push(execution(&[("physics", 4.0), ("effects", 1.0)]));
+
+        for _ in 0..PROFILER_HISTORY_LEN {
+            profile.push(execution(&[("physics", 1.0), ("effects", 2.0)]));
+        }
+
+        // The first execution is no longer in the history
+        assert_eq!(profile.history().count(), PROFILER_HISTORY_LEN);
+        assert_eq!(profile.average_duration(), 3.0);
+        assert_eq!(
+            profile.average_system_durations(),
+            [("effects", 2.0), ("physics", 1.0)]
+        );
+    }
+}
@@ -1,3 +1,6 @@
+use std::future::Future;
+use std::io::Read;
+use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
 use std::{collections::HashMap, fs, path::Path};
 
 use macroquad::{
@@ -8,9 +11,9 @@ use macroquad::{
 
 use ff_particles::EmitterConfig;
 
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
-use core::data::{deserialize_json_bytes, deserialize_json_file};
+use core::data::deserialize_json_bytes;
 use core::error::ErrorKind;
 use core::text::ToStringHelper;
 use core::{formaterr, Result};
@@ -19,7 +22,7 @@ use crate::gui::GuiResources;
 use crate::map::DecorationMetadata;
 
 use crate::player::PlayerCharacterMetadata;
-use crate::{items::MapItemMetadata, json, map::Map};
+use crate::{items::MapItemMetadata, json, json::TiledMap, map::Map};
 
 const PARTICLE_EFFECTS_DIR: &str = "particle_effects";
 const SOUNDS_FILE: &str = "sounds";
@@ -140,8 +143,90 @@ pub struct MapResource {
     pub meta: MapMetadata,
 }
 
+/// The mode that resources are loaded in. Windowed games load all assets, through macroquad, while
+/// headless games only load data files, synchronously, from the file system, as they have no
+/// graphics or audio context. Sounds, music and images are skipped, in headless mode, and textures
+/// are empty placeholders, only holding metadata, like size.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum LoadMode {
+    Windowed,
+    Headless,
+}
+
+impl LoadMode {
+    async fn read_file(self, path: &Path) -> Result<Vec<u8>> {
+        let bytes = match self {
+            LoadMode::Windowed => load_file(&path.to_string_helper()).await?,
+            LoadMode::Headless => fs::read(path)?,
+        };
+
+        Ok(bytes)
+    }
+
+    async fn read_json_file<T: DeserializeOwned>(self, path: &Path) -> Result<T> {
+        let bytes = self.read_file(path).await?;
+        let res = deserialize_json_bytes(&bytes)?;
+
+        Ok(res)
+    }
+}
+
+/// This reads the size of a PNG file from its header, so that headless games do not have to
+/// decode every texture, just to get its size.
+fn read_png_size(path: &Path) -> Result<Vec2> {
+    const SIGNATURE: &[u8] = &[0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+    let mut header = [0; 24];
+    fs::File::open(path)?.read_exact(&mut header)?;
+
+    if &header[0..8] != SIGNATURE || &header[12..16] != b"IHDR" {
+        return Err(formaterr!(
+            ErrorKind::Parsing,
+            "Resources: The texture '{}' is not a valid PNG file",
+            path.to_string_helper(),
+        ));
+    }
+
+    let width = u32::from_be_bytes([header[16], header[17], header[18], header[19]]);
+    let height = u32::from_be_bytes([header[20], header[21], header[22], header[23]]);
+
+    Ok(vec2(width as f32, height as f32))
+}
+
+/// This polls a future that is expected to complete without ever yielding, like loading resources
+/// in headless mode, where all files are read synchronously. This is used instead of an executor,
+/// as there is none available outside of the macroquad main loop.
+fn poll_ready<F: Future>(future: F) -> F::Output {
+    fn noop_raw_waker() -> RawWaker {
+        fn clone(_: *const ()) -> RawWaker {
+            noop_raw_waker()
+        }
+
+        fn noop(_: *const ()) {}
+
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+
+        RawWaker::new(std::ptr::null(), &VTABLE)
+    }
+
+    // SAFETY: The vtable functions do not use the data pointer, so it does not matter that it is null
+    let waker = unsafe { Waker::from_raw(noop_raw_waker()) };
+    let mut cx = Context::from_waker(&waker);
+
+    let mut future = Box::pin(future);
+
+    match future.as_mut().poll(&mut cx) {
+        Poll::Ready(res) => res,
+        Poll::Pending => panic!("Resources: A synchronous load yielded"),
+    }
+}
+
 // TODO: Add an optional requirement for all resource files (for when loading games main resources)
-async fn load_resources_from<P: AsRef<Path>>(path: P, resources: &mut Resources) -> Result<()> {
+async fn load_resources_from<P: AsRef<Path>>(
+    path: P,
+    resources: &mut Resources,
+    mode: LoadMode,
+) -> Result<()> {
     let path = path.as_ref();
 
     {
@@ -149,20 +234,20 @@ async fn load_resources_from<P: AsRef<Path>>(path: P, resources: &mut Resources)
             .join(PARTICLE_EFFECTS_DIR)
             .with_extension(RESOURCE_FILES_EXTENSION);
 
-        if let Ok(bytes) = load_file(&particle_effects_file_path.to_string_helper()).await {
+        if let Ok(bytes) = mode.read_file(&particle_effects_file_path).await {
             let metadata: Vec<ParticleEffectMetadata> = deserialize_json_bytes(&bytes)?;
 
             for meta in metadata {
                 let file_path = path.join(&meta.path);
 
-                let cfg: EmitterConfig = deserialize_json_file(&file_path).await?;
+                let cfg: EmitterConfig = mode.read_json_file(&file_path).await?;
 
                 resources.particle_effects.insert(meta.id, cfg);
             }
         }
     }
 
-    {
+    if mode == LoadMode::Windowed {
         let sounds_file_path = path
             .join(SOUNDS_FILE)
             .with_extension(RESOURCE_FILES_EXTENSION);
@@ -180,7 +265,7 @@ async fn load_resources_from<P: AsRef<Path>>(path: P, resources: &mut Resources)
         }
     }
 
-    {
+    if mode == LoadMode::Windowed {
         let music_file_path = path
             .join(MUSIC_FILE)
             .with_extension(RESOURCE_FILES_EXTENSION);
@@ -203,16 +288,21 @@ async fn load_resources_from<P: AsRef<Path>>(path: P, resources: &mut Resources)
             .join(TEXTURES_FILE)
             .with_extension(RESOURCE_FILES_EXTENSION);
 
-        if let Ok(bytes) = load_file(&textures_file_path.to_string_helper()).await {
+        if let Ok(bytes) = mode.read_file(&textures_file_path).await {
             let metadata: Vec<TextureMetadata> = deserialize_json_bytes(&bytes)?;
 
             for meta in metadata {
                 let file_path = path.join(&meta.path);
 
-                let texture = load_texture(&file_path.to_string_helper()).await?;
-                texture.set_filter(meta.filter_mode);
+                let (texture, size) = match mode {
+                    LoadMode::Windowed => {
+                        let texture = load_texture(&file_path.to_string_helper()).await?;
+                        texture.set_filter(meta.filter_mode);
 
-                let size = vec2(texture.width(), texture.height());
+                        (texture, vec2(texture.width(), texture.height()))
+                    }
+                    LoadMode::Headless => (Texture2D::empty(), read_png_size(&file_path)?),
+                };
 
                 let key = meta.id.clone();
 
@@ -236,7 +326,7 @@ async fn load_resources_from<P: AsRef<Path>>(path: P, resources: &mut Resources)
         }
     }
 
-    {
+    if mode == LoadMode::Windowed {
         let images_file_path = path
             .join(IMAGES_FILE)
             .with_extension(RESOURCE_FILES_EXTENSION);
@@ -268,20 +358,26 @@ async fn load_resources_from<P: AsRef<Path>>(path: P, resources: &mut Resources)
             .join(MAPS_FILE)
             .with_extension(RESOURCE_FILES_EXTENSION);
 
-        if let Ok(bytes) = load_file(&maps_file_path.to_string_helper()).await {
+        if let Ok(bytes) = mode.read_file(&maps_file_path).await {
             let metadata: Vec<MapMetadata> = deserialize_json_bytes(&bytes)?;
 
             for meta in metadata {
                 let map_path = path.join(&meta.path);
-                let preview_path = path.join(&meta.preview_path);
 
                 let map = if meta.is_tiled_map {
-                    Map::load_tiled(map_path, None).await?
+                    let tiled_map: TiledMap = mode.read_json_file(&map_path).await?;
+                    tiled_map.into_map()
                 } else {
-                    Map::load(map_path).await?
+                    mode.read_json_file(&map_path).await?
                 };
 
-                let preview = load_texture(&preview_path.to_string_helper()).await?;
+                let preview = match mode {
+                    LoadMode::Windowed => {
+                        let preview_path = path.join(&meta.preview_path);
+                        load_texture(&preview_path.to_string_helper()).await?
+                    }
+                    LoadMode::Headless => Texture2D::empty(),
+                };
 
                 let res = MapResource { map, preview, meta };
 
@@ -295,13 +391,13 @@ async fn load_resources_from<P: AsRef<Path>>(path: P, resources: &mut Resources)
             .join(DECORATION_FILE)
             .with_extension(RESOURCE_FILES_EXTENSION);
 
-        if let Ok(bytes) = load_file(&decoration_file_path.to_string_helper()).await {
+        if let Ok(bytes) = mode.read_file(&decoration_file_path).await {
             let decoration_paths: Vec<String> = deserialize_json_bytes(&bytes)?;
 
             for decoration_path in decoration_paths {
                 let path = path.join(&decoration_path);
 
-                let params: DecorationMetadata = deserialize_json_file(&path).await?;
+                let params: DecorationMetadata = mode.read_json_file(&path).await?;
 
                 resources.decoration.insert(params.id.clone(), params);
             }
@@ -313,13 +409,13 @@ async fn load_resources_from<P: AsRef<Path>>(path: P, resources: &mut Resources)
             .join(ITEMS_FILE)
             .with_extension(RESOURCE_FILES_EXTENSION);
 
-        if let Ok(bytes) = load_file(&items_file_path.to_string_helper()).await {
+        if let Ok(bytes) = mode.read_file(&items_file_path).await {
             let item_paths: Vec<String> = deserialize_json_bytes(&bytes)?;
 
             for item_path in item_paths {
                 let path = path.join(&item_path);
 
-                let params: MapItemMetadata = deserialize_json_file(&path).await?;
+                let params: MapItemMetadata = mode.read_json_file(&path).await?;
 
                 resources.items.insert(params.id.clone(), params);
             }
@@ -331,7 +427,7 @@ async fn load_resources_from<P: AsRef<Path>>(path: P, resources: &mut Resources)
             .join(PLAYER_CHARACTERS_FILE)
             .with_extension(RESOURCE_FILES_EXTENSION);
 
-        if let Ok(bytes) = load_file(&path.to_string_helper()).await {
+        if let Ok(bytes) = mode.read_file(&path).await {
             let metadata: Vec<PlayerCharacterMetadata> = deserialize_json_bytes(&bytes)?;
 
             for meta in metadata {
@@ -365,7 +461,36 @@ impl Resources {
         let assets_dir = assets_dir.as_ref();
         let mods_dir = mods_dir.as_ref();
 
-        let mut resources = Resources {
+        let mut resources = Resources::new_empty(assets_dir, mods_dir);
+
+        load_resources_from(assets_dir, &mut resources, LoadMode::Windowed).await?;
+
+        load_mods(mods_dir, &mut resources, LoadMode::Windowed).await?;
+
+        Ok(resources)
+    }
+
+    /// Load the resources required to run a headless game.
+    /// Refer to `LoadMode` for details on what is loaded.
+    pub fn new_headless<P: AsRef<Path>>(assets_dir: P, mods_dir: P) -> Result<Resources> {
+        let assets_dir = assets_dir.as_ref();
+        let mods_dir = mods_dir.as_ref();
+
+        let mut resources = Resources::new_empty(assets_dir, mods_dir);
+
+        poll_ready(load_resources_from(
+            assets_dir,
+            &mut resources,
+            LoadMode::Headless,
+        ))?;
+
+        poll_ready(load_mods(mods_dir, &mut resources, LoadMode::Headless))?;
+
+        Ok(resources)
+    }
+
+    fn new_empty(assets_dir: &Path, mods_dir: &Path) -> Resources {
+        Resources {
             assets_dir: assets_dir.to_string_helper(),
             mods_dir: mods_dir.to_string_helper(),
             loaded_mods: Vec::new(),
@@ -378,13 +503,7 @@ impl Resources {
             maps: Vec::new(),
             items: HashMap::new(),
             player_characters: HashMap::new(),
-        };
-
-        load_resources_from(assets_dir, &mut resources).await?;
-
-        load_mods(mods_dir, &mut resources).await?;
-
-        Ok(resources)
+        }
     }
 
     pub fn create_map(
@@ -606,14 +725,18 @@ impl Default for ModKind {
     }
 }
 
-async fn load_mods<P: AsRef<Path>>(mods_dir: P, resources: &mut Resources) -> Result<()> {
+async fn load_mods<P: AsRef<Path>>(
+    mods_dir: P,
+    resources: &mut Resources,
+    mode: LoadMode,
+) -> Result<()> {
     let mods_dir = mods_dir.as_ref();
 
     let active_mods_file_path = mods_dir
         .join(ACTIVE_MODS_FILE_NAME)
         .with_extension(RESOURCE_FILES_EXTENSION);
 
-    let mod_dirs: Vec<String> = deserialize_json_file(active_mods_file_path).await?;
+    let mod_dirs: Vec<String> = mode.read_json_file(&active_mods_file_path).await?;
 
     for mod_dir in mod_dirs.iter() {
         let mod_dir_path = mods_dir.join(mod_dir);
@@ -622,38 +745,38 @@ async fn load_mods<P: AsRef<Path>>(mods_dir: P, resources: &mut Resources) -> Re
             .join(MOD_FILE_NAME)
             .with_extension(RESOURCE_FILES_EXTENSION);
 
-        let meta: ModMetadata = deserialize_json_file(mod_file_path).await?;
+        let meta: ModMetadata = mode.read_json_file(&mod_file_path).await?;
 
-        let mut has_unmet_dependencies = false;
+        if !has_unmet_dependencies(&meta, resources) {
+            load_resources_from(mod_dir_path, resources, mode).await?;
 
-        for dependency in &meta.dependencies {
-            let res = resources
-                .loaded_mods
-                .iter()
-                .find(|&meta| meta.id == dependency.id && meta.version == dependency.version);
-
-            if res.is_none() {
-                has_unmet_dependencies = true;
-
-                #[cfg(debug_assertions)]
-                println!(
-                    "Loading mod {} (v{}) failed: Unmet dependency {} (v{})",
-                    &meta.id, &meta.version, &dependency.id, &dependency.version
-                );
+            #[cfg(debug_assertions)]
+            println!("Loaded mod {} (v{})", &meta.id, &meta.version);
 
-                break;
-            }
+            resources.loaded_mods.push(meta);
         }
+    }
 
-        if !has_unmet_dependencies {
-            load_resources_from(mod_dir_path, resources).await?;
+    Ok(())
+}
 
+fn has_unmet_dependencies(meta: &ModMetadata, resources: &Resources) -> bool {
+    for dependency in &meta.dependencies {
+        let res = resources
+            .loaded_mods
+            .iter()
+            .find(|&meta| meta.id == dependency.id && meta.version == dependency.version);
+
+        if res.is_none() {
             #[cfg(debug_assertions)]
-            println!("Loaded mod {} (v{})", &meta.id, &meta.version);
+            println!(
+                "Loading mod {} (v{}) failed: Unmet dependency {} (v{})",
+                &meta.id, &meta.version, &dependency.id, &dependency.version
+            );
 
-            resources.loaded_mods.push(meta);
+            return true;
         }
     }
 
-    Ok(())
+    false
 }
@@ -1,7 +1,12 @@
 use macroquad::prelude::*;
 
-#[derive(Debug, Default)]
+use serde::{Deserialize, Serialize};
+
+use crate::json;
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct Transform {
+    #[serde(with = "json::vec2_def")]
     pub position: Vec2,
     pub rotation: f32,
 }